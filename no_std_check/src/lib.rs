@@ -0,0 +1,11 @@
+// Builds src/portable.rs without std, so the module stays usable on embedded targets. It is a
+// workspace member, so `cargo build --workspace` fails when the module picks up a std path; CI
+// also builds it for a bare-metal target that has no std at all.
+#![no_std]
+
+extern crate alloc;
+
+// The module's tests compare against the std filters, so they run in the main crate
+#[cfg(not(test))]
+#[path = "../../src/portable.rs"]
+pub mod portable;
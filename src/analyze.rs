@@ -0,0 +1,182 @@
+use image::{GrayImage, RgbImage};
+use crate::filter::grayscale;
+
+// Steps larger than this are treated as real edges rather than quantization bands.
+const MAX_BAND_STEP: u8 = 6;
+// Runs shorter than this are texture or noise, not flat bands.
+const MIN_BAND_RUN: usize = 4;
+// Fraction of the image covered by banded runs above which banding is considered visible.
+pub const VISIBLE_BANDING: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandingReport {
+    // Share of pixels that sit in smooth areas (small neighbour differences).
+    pub smooth_fraction: f32,
+    // Share of pixels inside flat runs bordered by a small step.
+    pub banded_fraction: f32,
+    // Average width of those runs in pixels.
+    pub band_width: f32,
+    // Average luminance step between neighbouring bands.
+    pub step_size: f32,
+}
+
+#[derive(Default)]
+struct Tally {
+    pixels: usize,
+    smooth: usize,
+    banded: usize,
+    runs: usize,
+    run_pixels: usize,
+    steps: usize,
+    step_total: usize,
+}
+
+fn scan_line(values: &[u8], tally: &mut Tally) {
+    tally.pixels += values.len();
+    tally.smooth += values.windows(2).filter(|pair| pair[0].abs_diff(pair[1]) <= MAX_BAND_STEP).count();
+
+    let mut runs: Vec<(u8, usize)> = Vec::new();
+    for &value in values {
+        match runs.last_mut() {
+            Some((last, length)) if *last == value => *length += 1,
+            _ => runs.push((value, 1)),
+        }
+    }
+
+    let mut counted: Vec<bool> = vec![false; runs.len()];
+    for i in 1..runs.len() {
+        let (before, after) = (runs[i - 1], runs[i]);
+        let step: u8 = before.0.abs_diff(after.0);
+        if step > MAX_BAND_STEP || before.1 < MIN_BAND_RUN || after.1 < MIN_BAND_RUN {
+            continue;
+        }
+        tally.steps += 1;
+        tally.step_total += step as usize;
+        for index in [i - 1, i] {
+            if !counted[index] {
+                counted[index] = true;
+                tally.banded += runs[index].1;
+                tally.runs += 1;
+                tally.run_pixels += runs[index].1;
+            }
+        }
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+    if denominator == 0 { 0.0 } else { numerator as f32 / denominator as f32 }
+}
+
+pub fn analyze_gray_banding(gray: &GrayImage) -> BandingReport {
+    let (width, height) = gray.dimensions();
+    let mut tally: Tally = Tally::default();
+
+    for row in gray.rows() {
+        let values: Vec<u8> = row.map(|pixel| pixel[0]).collect();
+        scan_line(&values, &mut tally);
+    }
+    for x in 0..width {
+        let values: Vec<u8> = (0..height).map(|y| gray.get_pixel(x, y)[0]).collect();
+        scan_line(&values, &mut tally);
+    }
+
+    BandingReport {
+        smooth_fraction: ratio(tally.smooth, tally.pixels),
+        banded_fraction: ratio(tally.banded, tally.pixels),
+        band_width: ratio(tally.run_pixels, tally.runs),
+        step_size: ratio(tally.step_total, tally.steps),
+    }
+}
+
+pub fn analyze_banding(image: &RgbImage) -> BandingReport {
+    analyze_gray_banding(&grayscale(image))
+}
+
+impl BandingReport {
+    pub fn is_visible(&self) -> bool {
+        self.banded_fraction >= VISIBLE_BANDING
+    }
+
+    pub fn suggestion(&self) -> String {
+        if !self.is_visible() {
+            return "No visible banding; dithering is optional".to_string();
+        }
+        // Error diffusion hides bands best when the noise it adds is about one step high.
+        let strength: &str = if self.step_size <= 2.0 { "light" } else if self.step_size <= 4.0 { "medium" } else { "strong" };
+        let kernel: &str = if self.band_width >= 16.0 {
+            "Floyd-Steinberg (wide bands need error diffusion to break up)"
+        } else {
+            "Floyd-Steinberg (narrow bands; a light diffusion is enough)"
+        };
+        format!(
+            "Dither with {}, {} strength (about +/-{:.0} levels); use -quant=N,dither or -floyd, or --auto-dither",
+            kernel, strength, self.step_size.max(1.0).ceil(),
+        )
+    }
+}
+
+// Counts pixels in flat output runs whose borders are steps the source did not have,
+// i.e. smooth source gradients that an operation such as color reduction turned into bands.
+fn artificial_band_pixels(before: &[u8], after: &[u8]) -> (usize, usize) {
+    let mut banded: usize = 0;
+    let mut run_start: usize = 0;
+    let mut previous_counted: Option<usize> = None;
+    for end in 1..=after.len() {
+        if end < after.len() && after[end] == after[end - 1] {
+            continue;
+        }
+        let run: (usize, usize) = (run_start, end);
+        if end < after.len() {
+            let source_step: u8 = before[end].abs_diff(before[end - 1]);
+            let output_step: u8 = after[end].abs_diff(after[end - 1]);
+            let next_end: usize = (end + 1..after.len()).find(|&i| after[i] != after[end]).unwrap_or(after.len());
+            let artificial: bool = source_step <= MAX_BAND_STEP && output_step > source_step;
+            if artificial && run.1 - run.0 >= MIN_BAND_RUN && next_end - end >= MIN_BAND_RUN {
+                if previous_counted != Some(run.0) {
+                    banded += run.1 - run.0;
+                }
+                banded += next_end - end;
+                previous_counted = Some(end);
+            }
+        }
+        run_start = end;
+    }
+    (banded, after.len())
+}
+
+// True when an operation turned smooth areas into visible bands, e.g. after reducing colors.
+pub fn introduces_banding(before: &RgbImage, after: &RgbImage) -> bool {
+    let (before, after) = (grayscale(before), grayscale(after));
+    let mut banded: usize = 0;
+    let mut total: usize = 0;
+    for (source_row, output_row) in before.rows().zip(after.rows()) {
+        let source: Vec<u8> = source_row.map(|pixel| pixel[0]).collect();
+        let output: Vec<u8> = output_row.map(|pixel| pixel[0]).collect();
+        let (row_banded, row_total) = artificial_band_pixels(&source, &output);
+        banded += row_banded;
+        total += row_total;
+    }
+    ratio(banded, total) >= VISIBLE_BANDING
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn detects_stepped_gradient() {
+        let stepped: GrayImage = GrayImage::from_fn(256, 16, |x, _| Luma([100 + (x / 32) as u8 * 2]));
+        let report: BandingReport = analyze_gray_banding(&stepped);
+        assert!(report.is_visible());
+        assert_eq!(report.step_size, 2.0);
+
+        let noise: GrayImage = GrayImage::from_fn(256, 16, |x, y| Luma([((x * 7919 + y * 104729) % 251) as u8]));
+        assert!(!analyze_gray_banding(&noise).is_visible());
+
+        let smooth: RgbImage = RgbImage::from_fn(256, 4, |x, _| image::Rgb([x as u8; 3]));
+        let posterized: RgbImage = RgbImage::from_fn(256, 4, |x, _| image::Rgb([(x / 64 * 85) as u8; 3]));
+        assert!(introduces_banding(&smooth, &posterized));
+        assert!(!introduces_banding(&smooth, &smooth));
+    }
+}
@@ -0,0 +1,40 @@
+use filter::analyze::{analyze_banding, BandingReport};
+use filter::error::Error;
+use filter::filter::open_image;
+
+fn print_usage() {
+    println!("Usage: cargo r analyze [check] input.png");
+    println!("Checks:");
+    println!("  banding: Measure banding in smooth regions and suggest a dither kernel and strength");
+}
+
+fn banding(args: &[String]) -> Result<(), Error> {
+    let input_path: &String = match args {
+        [path] => path,
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let image = open_image(input_path)?;
+
+    let report: BandingReport = analyze_banding(&image.to_rgb8());
+    println!("Smooth area: {:.1}%", report.smooth_fraction * 100.0);
+    println!("Banded area: {:.1}%", report.banded_fraction * 100.0);
+    if report.banded_fraction > 0.0 {
+        println!("Band width: {:.1} px, step: {:.1} levels", report.band_width, report.step_size);
+    }
+    println!("{}", report.suggestion());
+    Ok(())
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("banding") => banding(&args[1..]),
+        _ => {
+            print_usage();
+            Ok(())
+        },
+    }
+}
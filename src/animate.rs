@@ -0,0 +1,126 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::f64::consts::TAU;
+use crate::color::{hsl_to_rgb, rgb_to_hsl};
+use crate::filter::Color;
+use crate::geometry::resample;
+use crate::math::sin_f64;
+
+// Effects stepped through a loop of frames, so the last frame leads back into the first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    // A sine wave over the rows, moving down by one wavelength per loop.
+    Wave,
+    // Rows in random bands torn sideways, with the red channel split off; new bands every frame.
+    Glitch,
+    // Every hue turned once around the color wheel.
+    Cycle,
+}
+
+impl Effect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wave" => Some(Effect::Wave),
+            "glitch" => Some(Effect::Glitch),
+            "cycle" => Some(Effect::Cycle),
+            _ => None,
+        }
+    }
+}
+
+// Fixed xorshift sequence per frame, so the same input gives the same animation.
+struct Sequence(u64);
+
+impl Sequence {
+    fn below(&mut self, bound: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound.max(1) as u64) as u32
+    }
+}
+
+fn wave(image: &DynamicImage, phase: f64) -> RgbaImage {
+    let (width, height) = (image.width(), image.height());
+    let amplitude: f64 = (width as f64 / 40.0).max(1.0);
+    let wavelength: f64 = (height as f64 / 3.0).max(2.0);
+    resample(image, width, height, |x, y| {
+        let shift: f64 = amplitude * sin_f64(TAU * (y.floor() / wavelength - phase));
+        ((x - shift).rem_euclid(width as f64), y)
+    }).to_rgba8()
+}
+
+fn glitch(image: &RgbaImage, frame: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let mut sequence: Sequence = Sequence(0x9E37_79B9_7F4A_7C15 ^ (frame as u64 + 1).wrapping_mul(0x2545_F491_4F6C_DD1D));
+    let mut shifts: Vec<i64> = vec![0; height as usize];
+    for _ in 0..3 + sequence.below(4) {
+        let top: u32 = sequence.below(height);
+        let band: u32 = 1 + sequence.below((height / 8).max(1));
+        let shift: i64 = sequence.below(width / 5 + 1) as i64 - (width / 10) as i64;
+        for row in shifts.iter_mut().skip(top as usize).take(band as usize) {
+            *row = shift;
+        }
+    }
+    let split: i64 = 1 + sequence.below((width / 80).max(2)) as i64;
+
+    let source = |x: i64, y: u32| -> &Rgba<u8> { image.get_pixel(x.rem_euclid(width as i64) as u32, y) };
+    RgbaImage::from_fn(width, height, |x, y| {
+        let from: i64 = x as i64 - shifts[y as usize];
+        let pixel: &Rgba<u8> = source(from, y);
+        Rgba([source(from - split, y)[0], pixel[1], pixel[2], pixel[3]])
+    })
+}
+
+fn cycle(image: &RgbaImage, degrees: f32) -> RgbaImage {
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel: &Rgba<u8> = image.get_pixel(x, y);
+        let (hue, saturation, lightness) = rgb_to_hsl(Color::from_rgb_components(pixel[0], pixel[1], pixel[2]));
+        let turned: Color = hsl_to_rgb(hue + degrees, saturation, lightness);
+        Rgba([turned.r, turned.g, turned.b, pixel[3]])
+    })
+}
+
+// Frame `frame` of a loop of `frames`.
+pub fn effect_frame(image: &DynamicImage, effect: Effect, frame: u32, frames: u32) -> RgbaImage {
+    let phase: f64 = frame as f64 / frames.max(1) as f64;
+    match effect {
+        Effect::Wave => wave(image, phase),
+        Effect::Glitch => glitch(&image.to_rgba8(), frame),
+        Effect::Cycle => cycle(&image.to_rgba8(), (phase * 360.0) as f32),
+    }
+}
+
+pub fn effect_frames(image: &DynamicImage, effect: Effect, frames: u32) -> Vec<RgbaImage> {
+    (0..frames).map(|frame| effect_frame(image, effect, frame, frames)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::encode_animated_gif;
+    use image::codecs::gif::GifDecoder;
+    use image::{AnimationDecoder, Frame, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn effects_loop_and_export_every_frame() {
+        let image: DynamicImage = RgbImage::from_fn(40, 30, |x, y| Rgb([(x * 6) as u8, (y * 8) as u8, 90])).into();
+        for effect in [Effect::Wave, Effect::Glitch, Effect::Cycle] {
+            let frames: Vec<RgbaImage> = effect_frames(&image, effect, 6);
+            assert_eq!(frames.len(), 6);
+            assert!(frames.iter().all(|frame| frame.dimensions() == (40, 30)));
+            assert_ne!(frames[1], frames[2], "{:?}", effect);
+        }
+
+        // One full loop later the wave and the colors are back where they started
+        for effect in [Effect::Wave, Effect::Cycle] {
+            assert_eq!(effect_frame(&image, effect, 6, 6), effect_frame(&image, effect, 0, 6));
+        }
+        assert_eq!(effect_frame(&image, Effect::Glitch, 3, 6), effect_frame(&image, Effect::Glitch, 3, 6));
+
+        let gif: Vec<u8> = encode_animated_gif(&effect_frames(&image, Effect::Cycle, 4), 50).unwrap();
+        let decoded: Vec<Frame> = GifDecoder::new(Cursor::new(gif)).unwrap().into_frames().collect_frames().unwrap();
+        assert_eq!(decoded.len(), 4);
+        assert_eq!(decoded[0].delay().numer_denom_ms(), (50, 1));
+    }
+}
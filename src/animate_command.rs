@@ -0,0 +1,63 @@
+use filter::animate::{effect_frames, Effect};
+use filter::encode::encode_animated_gif;
+use filter::error::Error;
+use filter::filter::open_image;
+use image::{DynamicImage, RgbaImage};
+
+fn print_usage() {
+    println!("Usage: cargo r animate-effect input.png --effect=wave|glitch|cycle [--frames=N] [--delay=MS] output.gif");
+    println!("Effects:");
+    println!("  wave: Rows shifted along a sine wave that travels down the image once per loop");
+    println!("  glitch: Bands of rows torn sideways with the red channel split off, new bands every frame");
+    println!("  cycle: Hues turned once around the color wheel");
+    println!("Options:");
+    println!("  --frames=N: Frames in the loop (default 12)");
+    println!("  --delay=MS: Time each frame is shown in milliseconds (default 80)");
+}
+
+fn parse_count(value: &str, name: &str) -> Result<u32, Error> {
+    match value.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(Error::InvalidArgument(format!("Invalid {}: {}", name, value))),
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let mut effect: Option<Effect> = None;
+    let mut frames: u32 = 12;
+    let mut delay: u32 = 80;
+    let mut paths: Vec<&String> = Vec::new();
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--effect=") {
+            effect = Some(Effect::from_name(value).ok_or_else(|| Error::InvalidArgument(format!("Unknown effect: {}", value)))?);
+        } else if let Some(value) = arg.strip_prefix("--frames=") {
+            frames = parse_count(value, "frame count")?;
+        } else if let Some(value) = arg.strip_prefix("--delay=") {
+            delay = parse_count(value, "delay")?;
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    let effect: Effect = match effect {
+        Some(effect) if paths.len() == 2 => effect,
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let image: DynamicImage = open_image(paths[0])?;
+    if image.width() > u16::MAX as u32 || image.height() > u16::MAX as u32 {
+        return Err(Error::InvalidArgument(format!("GIF images are limited to {} pixels per side", u16::MAX)));
+    }
+
+    let frame_images: Vec<RgbaImage> = effect_frames(&image, effect, frames);
+    let bytes: Vec<u8> = encode_animated_gif(&frame_images, delay).map_err(|source| Error::Encode { path: paths[1].into(), source })?;
+    std::fs::write(paths[1], bytes).map_err(|source| Error::Io { path: paths[1].into(), source })?;
+    println!("The animation is saved: {} ({} frames)", paths[1], frames);
+    Ok(())
+}
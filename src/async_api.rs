@@ -0,0 +1,123 @@
+use image::DynamicImage;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// Cooperative cancellation: jobs check the token between steps, so a cancelled request stops
+// after the operation that is running, and one still waiting for a permit never starts.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// One image operation of a job, e.g. `Box::new(|image| gaussian_blur(&image, 2.0, BorderPolicy::Clamp))`.
+pub type Step = Box<dyn FnOnce(DynamicImage) -> DynamicImage + Send>;
+
+// Runs CPU-bound image work on tokio's blocking thread pool so async handlers stay responsive,
+// with at most `max_jobs` jobs running at once; the others wait for a permit.
+#[derive(Clone)]
+pub struct Executor {
+    permits: Arc<Semaphore>,
+}
+
+impl Executor {
+    pub fn new(max_jobs: usize) -> Self {
+        Executor { permits: Arc::new(Semaphore::new(max_jobs.max(1))) }
+    }
+
+    pub async fn run<T, F>(&self, token: &CancellationToken, job: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&CancellationToken) -> Result<T, String> + Send + 'static,
+    {
+        let permit: OwnedSemaphorePermit = self.permits.clone().acquire_owned().await.map_err(|e| format!("executor closed: {}", e))?;
+        if token.is_cancelled() {
+            return Err("cancelled".to_string());
+        }
+        let token: CancellationToken = token.clone();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            job(&token)
+        })
+        .await
+        .map_err(|e| format!("image job failed: {}", e))?
+    }
+
+    // Applies the steps in order, checking for cancellation before each one.
+    pub async fn apply(&self, image: DynamicImage, steps: Vec<Step>, token: &CancellationToken) -> Result<DynamicImage, String> {
+        self.run(token, move |token| {
+            let mut image: DynamicImage = image;
+            for step in steps {
+                if token.is_cancelled() {
+                    return Err("cancelled".to_string());
+                }
+                image = step(image);
+            }
+            Ok(image)
+        })
+        .await
+    }
+
+    // Like apply, but gives up after `limit`. The token is cancelled then, so the job stops
+    // after the operation it is in and frees its permit.
+    pub async fn apply_with_timeout(&self, image: DynamicImage, steps: Vec<Step>, token: &CancellationToken, limit: Duration) -> Result<DynamicImage, String> {
+        match tokio::time::timeout(limit, self.apply(image, steps, token)).await {
+            Ok(result) => result,
+            Err(_) => {
+                token.cancel();
+                Err(format!("timed out after {} s", limit.as_secs_f64()))
+            }
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.permits.available_permits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convolve::{gaussian_blur, BorderPolicy};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn runs_steps_and_honours_cancellation() {
+        let runtime: tokio::runtime::Runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let executor: Executor = Executor::new(2);
+        let image: DynamicImage = RgbImage::from_fn(16, 16, |x, _| Rgb([if x < 8 { 0 } else { 255 }; 3])).into();
+
+        let token: CancellationToken = CancellationToken::new();
+        let steps: Vec<Step> = vec![Box::new(|image| gaussian_blur(&image, 1.0, BorderPolicy::Clamp))];
+        let blurred: DynamicImage = runtime.block_on(executor.apply(image.clone(), steps, &token)).unwrap();
+        assert_eq!(blurred.to_rgb8(), gaussian_blur(&image, 1.0, BorderPolicy::Clamp).to_rgb8());
+        assert_eq!(executor.available_permits(), 2);
+
+        let slow: CancellationToken = CancellationToken::new();
+        let steps: Vec<Step> = vec![Box::new(|image| {
+            std::thread::sleep(Duration::from_millis(200));
+            image
+        })];
+        let result: Result<DynamicImage, String> = runtime.block_on(executor.apply_with_timeout(image.clone(), steps, &slow, Duration::from_millis(20)));
+        assert!(result.unwrap_err().starts_with("timed out") && slow.is_cancelled());
+
+        token.cancel();
+        let steps: Vec<Step> = vec![Box::new(|image| image)];
+        assert_eq!(runtime.block_on(executor.apply(image, steps, &token)).unwrap_err(), "cancelled");
+    }
+}
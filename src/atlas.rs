@@ -0,0 +1,189 @@
+use image::{GenericImage, RgbaImage};
+use serde_json::json;
+
+// One image going into an atlas. Trimmed sprites remember the size of the image they were cut
+// from and where in it they sat, so engines can draw them back in place.
+pub struct Sprite {
+    pub name: String,
+    pub image: RgbaImage,
+    pub source_width: u32,
+    pub source_height: u32,
+    pub offset_x: u32,
+    pub offset_y: u32,
+}
+
+// Where a sprite ended up in the atlas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub source_width: u32,
+    pub source_height: u32,
+    pub offset_x: u32,
+    pub offset_y: u32,
+}
+
+impl Frame {
+    pub fn trimmed(&self) -> bool {
+        self.width != self.source_width || self.height != self.source_height
+    }
+}
+
+// The smallest rectangle (x, y, width, height) holding every pixel that is not fully transparent.
+pub fn opaque_bounds(image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel[3] == 0 {
+            continue;
+        }
+        bounds = Some(match bounds {
+            Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+            None => (x, y, x, y),
+        });
+    }
+    bounds.map(|(left, top, right, bottom)| (left, top, right - left + 1, bottom - top + 1))
+}
+
+impl Sprite {
+    pub fn new(name: &str, image: RgbaImage) -> Self {
+        let (source_width, source_height) = image.dimensions();
+        Sprite { name: name.to_string(), image, source_width, source_height, offset_x: 0, offset_y: 0 }
+    }
+
+    // Cuts away transparent borders. A fully transparent image keeps one pixel.
+    pub fn trimmed(self) -> Self {
+        let (x, y, width, height) = opaque_bounds(&self.image).unwrap_or((0, 0, 1, 1));
+        let image: RgbaImage = image::imageops::crop_imm(&self.image, x, y, width, height).to_image();
+        Sprite { image, offset_x: self.offset_x + x, offset_y: self.offset_y + y, ..self }
+    }
+}
+
+// Shelf packing: sprites sorted from tallest to shortest fill rows left to right, a new row
+// starting below the tallest sprite of the last one when the next sprite does not fit. Without
+// `max_width` the rows are about as wide as a square holding every sprite. `padding` pixels are
+// left between sprites.
+pub fn pack(sprites: &[Sprite], padding: u32, max_width: Option<u32>) -> Result<(RgbaImage, Vec<Frame>), String> {
+    let widest: u32 = sprites.iter().map(|sprite| sprite.image.width()).max().unwrap_or(0);
+    let width: u32 = match max_width {
+        Some(max_width) if widest > max_width => {
+            let sprite: &Sprite = sprites.iter().find(|sprite| sprite.image.width() == widest).unwrap();
+            return Err(format!("{} is {} pixels wide, more than the atlas width of {}", sprite.name, widest, max_width));
+        },
+        Some(max_width) => max_width,
+        None => {
+            let area: u64 = sprites.iter().map(|sprite| (sprite.image.width() + padding) as u64 * (sprite.image.height() + padding) as u64).sum();
+            ((area as f64).sqrt().ceil() as u32).max(widest)
+        },
+    };
+
+    let mut order: Vec<usize> = (0..sprites.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (first, second) = (&sprites[a].image, &sprites[b].image);
+        second.height().cmp(&first.height()).then(second.width().cmp(&first.width())).then(sprites[a].name.cmp(&sprites[b].name))
+    });
+
+    let mut frames: Vec<Frame> = Vec::with_capacity(sprites.len());
+    let (mut x, mut y, mut shelf_height): (u32, u32, u32) = (0, 0, 0);
+    for &index in &order {
+        let sprite: &Sprite = &sprites[index];
+        let (sprite_width, sprite_height) = sprite.image.dimensions();
+        if x > 0 && x + sprite_width > width {
+            x = 0;
+            y += shelf_height + padding;
+            shelf_height = 0;
+        }
+        frames.push(Frame {
+            name: sprite.name.clone(),
+            x,
+            y,
+            width: sprite_width,
+            height: sprite_height,
+            source_width: sprite.source_width,
+            source_height: sprite.source_height,
+            offset_x: sprite.offset_x,
+            offset_y: sprite.offset_y,
+        });
+        x += sprite_width + padding;
+        shelf_height = shelf_height.max(sprite_height);
+    }
+
+    let used_width: u32 = frames.iter().map(|frame| frame.x + frame.width).max().unwrap_or(0).max(1);
+    let used_height: u32 = frames.iter().map(|frame| frame.y + frame.height).max().unwrap_or(0).max(1);
+    let mut atlas: RgbaImage = RgbaImage::new(used_width, used_height);
+    for (frame, &index) in frames.iter().zip(&order) {
+        atlas.copy_from(&sprites[index].image, frame.x, frame.y).map_err(|e| e.to_string())?;
+    }
+    frames.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((atlas, frames))
+}
+
+// The frame map in the JSON hash layout of TexturePacker, which Phaser, PixiJS, Cocos and most
+// engine importers read.
+pub fn frame_map(frames: &[Frame], image_name: &str, width: u32, height: u32) -> serde_json::Value {
+    let mut entries: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
+    for frame in frames {
+        entries.insert(frame.name.clone(), json!({
+            "frame": { "x": frame.x, "y": frame.y, "w": frame.width, "h": frame.height },
+            "rotated": false,
+            "trimmed": frame.trimmed(),
+            "spriteSourceSize": { "x": frame.offset_x, "y": frame.offset_y, "w": frame.width, "h": frame.height },
+            "sourceSize": { "w": frame.source_width, "h": frame.source_height },
+        }));
+    }
+    json!({
+        "frames": entries,
+        "meta": { "image": image_name, "format": "RGBA8888", "size": { "w": width, "h": height }, "scale": "1" },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn sprites_are_trimmed_and_packed_without_overlap() {
+        // A 10 x 8 image with a 3 x 2 opaque patch at (4, 5)
+        let mut padded: RgbaImage = RgbaImage::new(10, 8);
+        for (x, y) in [(4, 5), (6, 5), (5, 6)] {
+            padded.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        }
+        assert_eq!(opaque_bounds(&padded), Some((4, 5, 3, 2)));
+        assert_eq!(opaque_bounds(&RgbaImage::new(3, 3)), None);
+        let trimmed: Sprite = Sprite::new("patch", padded).trimmed();
+        assert_eq!(trimmed.image.dimensions(), (3, 2));
+        assert_eq!((trimmed.offset_x, trimmed.offset_y, trimmed.source_width, trimmed.source_height), (4, 5, 10, 8));
+        assert_eq!(Sprite::new("empty", RgbaImage::new(3, 3)).trimmed().image.dimensions(), (1, 1));
+
+        let mut sprites: Vec<Sprite> = vec![trimmed];
+        for (index, (width, height)) in [(16, 16), (8, 12), (20, 4), (5, 5), (16, 16)].into_iter().enumerate() {
+            sprites.push(Sprite::new(&format!("s{}", index), RgbaImage::from_pixel(width, height, Rgba([index as u8, 0, 0, 255]))));
+        }
+        let (atlas, frames) = pack(&sprites, 2, None).unwrap();
+        assert_eq!(frames.len(), 6);
+        assert_eq!(frames[0].name, "patch");
+        for (i, a) in frames.iter().enumerate() {
+            assert!(a.x + a.width <= atlas.width() && a.y + a.height <= atlas.height());
+            for b in &frames[i + 1..] {
+                let apart: bool = a.x + a.width + 2 <= b.x || b.x + b.width + 2 <= a.x || a.y + a.height + 2 <= b.y || b.y + b.height + 2 <= a.y;
+                assert!(apart, "{:?} and {:?} are closer than the padding", a, b);
+            }
+        }
+        let s3: &Frame = frames.iter().find(|frame| frame.name == "s3").unwrap();
+        assert_eq!(atlas.get_pixel(s3.x + 4, s3.y + 4), &Rgba([3, 0, 0, 255]));
+
+        assert!(pack(&sprites, 0, Some(18)).is_err());
+        let (narrow, _) = pack(&sprites, 0, Some(20)).unwrap();
+        assert_eq!(narrow.width(), 20);
+
+        let map: serde_json::Value = frame_map(&frames, "atlas.png", atlas.width(), atlas.height());
+        assert_eq!(map["frames"]["patch"]["trimmed"], true);
+        assert_eq!(map["frames"]["patch"]["spriteSourceSize"]["x"], 4);
+        assert_eq!(map["frames"]["patch"]["sourceSize"]["w"], 10);
+        assert_eq!(map["frames"]["s0"]["trimmed"], false);
+        assert_eq!(map["meta"]["size"]["w"], atlas.width());
+    }
+}
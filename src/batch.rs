@@ -0,0 +1,108 @@
+use image::ImageFormat;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// Files with an extension we can decode.
+fn is_image_file(file: &Path) -> bool {
+    #[cfg(feature = "jxl")]
+    if crate::jxl::has_jxl_extension(file) {
+        return true;
+    }
+    ImageFormat::from_path(file).is_ok()
+}
+
+// An input that stands for several files: a directory or a file name with * or ? wildcards.
+pub fn is_batch_input(input: &str) -> bool {
+    Path::new(input).is_dir() || input.contains(['*', '?'])
+}
+
+// `*` matches any run of characters and `?` any single character.
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => wildcard_match(&pattern[1..], name) || (!name.is_empty() && wildcard_match(pattern, &name[1..])),
+        (Some('?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(expected), Some(actual)) if expected == actual => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+// The image files an input names, sorted: every file with an image extension in a directory,
+// the files matching a pattern (wildcards only in the file name, as in images/*.png), or the
+// file itself.
+pub fn expand_input(input: &str) -> Result<Vec<PathBuf>, String> {
+    let path: &Path = Path::new(input);
+    let (directory, pattern): (&Path, Vec<char>) = if path.is_dir() {
+        (path, vec!['*'])
+    } else if is_batch_input(input) {
+        let name: String = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let parent: &Path = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if parent.to_string_lossy().contains(['*', '?']) {
+            return Err(format!("Wildcards are only supported in the file name: {}", input));
+        }
+        (parent, name.chars().collect())
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let entries: std::fs::ReadDir = std::fs::read_dir(directory).map_err(|e| format!("Failed to read directory {}: {}", directory.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file() && is_image_file(file))
+        .filter(|file| {
+            let name: Vec<char> = file.file_name().unwrap_or_default().to_string_lossy().chars().collect();
+            wildcard_match(&pattern, &name)
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// Pairs every input file with an output of the same name in `output_directory`.
+pub fn batch_jobs(inputs: &[String], output_directory: &Path) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    if output_directory.is_file() {
+        return Err(format!("Several inputs need an output directory, but {} is a file", output_directory.display()));
+    }
+    let mut jobs: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut outputs: HashSet<PathBuf> = HashSet::new();
+    for input in inputs {
+        let files: Vec<PathBuf> = expand_input(input)?;
+        if files.is_empty() {
+            return Err(format!("No images match {}", input));
+        }
+        for file in files {
+            let output: PathBuf = output_directory.join(file.file_name().unwrap_or_default());
+            if !outputs.insert(output.clone()) {
+                return Err(format!("More than one input would be saved as {}", output.display()));
+            }
+            jobs.push((file, output));
+        }
+    }
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_directories_and_patterns() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("image_rust_batch_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for name in ["b.png", "a.png", "c.jpg", "notes.txt"] {
+            std::fs::write(directory.join(name), b"").unwrap();
+        }
+
+        let all: Vec<PathBuf> = expand_input(&directory.to_string_lossy()).unwrap();
+        assert_eq!(all, vec![directory.join("a.png"), directory.join("b.png"), directory.join("c.jpg")]);
+        let pattern: String = directory.join("?.png").to_string_lossy().into_owned();
+        assert_eq!(expand_input(&pattern).unwrap(), vec![directory.join("a.png"), directory.join("b.png")]);
+
+        let output: PathBuf = directory.join("out");
+        let jobs: Vec<(PathBuf, PathBuf)> = batch_jobs(std::slice::from_ref(&pattern), &output).unwrap();
+        assert_eq!(jobs[1], (directory.join("b.png"), output.join("b.png")));
+        assert!(batch_jobs(&[pattern.clone(), pattern], &output).is_err());
+        assert!(batch_jobs(&[directory.join("*.gif").to_string_lossy().into_owned()], &output).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
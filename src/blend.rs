@@ -0,0 +1,147 @@
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::path::Path;
+use crate::filter::{decode_file, resize, AlphaMode};
+
+// How -blendwith combines the second image (the source) with the image being filtered (the
+// backdrop). The Photoshop modes mix colors and then composite source-over; the Porter-Duff
+// modes keep the source colors and only differ in which areas survive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+    Exclusion,
+    HardLight,
+    SoftLight,
+    Add,
+    In,
+    Out,
+    Atop,
+    Xor,
+}
+
+impl BlendMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "normal" | "over" => Some(BlendMode::Normal),
+            "multiply" => Some(BlendMode::Multiply),
+            "screen" => Some(BlendMode::Screen),
+            "overlay" => Some(BlendMode::Overlay),
+            "darken" => Some(BlendMode::Darken),
+            "lighten" => Some(BlendMode::Lighten),
+            "difference" => Some(BlendMode::Difference),
+            "exclusion" => Some(BlendMode::Exclusion),
+            "hard-light" => Some(BlendMode::HardLight),
+            "soft-light" => Some(BlendMode::SoftLight),
+            "add" => Some(BlendMode::Add),
+            "in" => Some(BlendMode::In),
+            "out" => Some(BlendMode::Out),
+            "atop" => Some(BlendMode::Atop),
+            "xor" => Some(BlendMode::Xor),
+            _ => None,
+        }
+    }
+
+    // Blended value of one channel, backdrop and source in 0-1.
+    fn mix(&self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Overlay => BlendMode::HardLight.mix(source, backdrop),
+            BlendMode::Darken => backdrop.min(source),
+            BlendMode::Lighten => backdrop.max(source),
+            BlendMode::Difference => (backdrop - source).abs(),
+            BlendMode::Exclusion => backdrop + source - 2.0 * backdrop * source,
+            BlendMode::HardLight if source <= 0.5 => BlendMode::Multiply.mix(backdrop, 2.0 * source),
+            BlendMode::HardLight => BlendMode::Screen.mix(backdrop, 2.0 * source - 1.0),
+            BlendMode::SoftLight if source <= 0.5 => backdrop - (1.0 - 2.0 * source) * backdrop * (1.0 - backdrop),
+            BlendMode::SoftLight => {
+                let curve: f32 = if backdrop <= 0.25 { ((16.0 * backdrop - 12.0) * backdrop + 4.0) * backdrop } else { backdrop.sqrt() };
+                backdrop + (2.0 * source - 1.0) * (curve - backdrop)
+            },
+            BlendMode::Add => (backdrop + source).min(1.0),
+            _ => source,
+        }
+    }
+
+    // Porter-Duff fractions of the source and the backdrop that make it into the output.
+    fn fractions(&self, source_alpha: f32, backdrop_alpha: f32) -> (f32, f32) {
+        match self {
+            BlendMode::In => (backdrop_alpha, 0.0),
+            BlendMode::Out => (1.0 - backdrop_alpha, 0.0),
+            BlendMode::Atop => (backdrop_alpha, 1.0 - source_alpha),
+            BlendMode::Xor => (1.0 - backdrop_alpha, 1.0 - source_alpha),
+            _ => (1.0, 1.0 - source_alpha),
+        }
+    }
+}
+
+// Composites `layer` onto `image` following the W3C compositing rules, with the layer's alpha
+// scaled by `opacity` (0-1). A layer of another size is stretched to the image first.
+pub fn blend(image: &DynamicImage, layer: &DynamicImage, mode: BlendMode, opacity: f32) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let layer: RgbaImage = if layer.dimensions() == (width, height) {
+        layer.to_rgba8()
+    } else {
+        resize(layer, width, height, AlphaMode::Premultiplied).to_rgba8()
+    };
+    let backdrop: RgbaImage = image.to_rgba8();
+    let opacity: f32 = opacity.clamp(0.0, 1.0);
+
+    let output: RgbaImage = RgbaImage::from_fn(width, height, |x, y| {
+        let (base, top) = (backdrop.get_pixel(x, y), layer.get_pixel(x, y));
+        let backdrop_alpha: f32 = base[3] as f32 / 255.0;
+        let source_alpha: f32 = top[3] as f32 / 255.0 * opacity;
+        let (source_fraction, backdrop_fraction) = mode.fractions(source_alpha, backdrop_alpha);
+        let alpha: f32 = source_alpha * source_fraction + backdrop_alpha * backdrop_fraction;
+        let mut pixel: Rgba<u8> = Rgba([0, 0, 0, (alpha * 255.0).round() as u8]);
+        if alpha > 0.0 {
+            for channel in 0..3 {
+                let (backdrop_value, source_value) = (base[channel] as f32 / 255.0, top[channel] as f32 / 255.0);
+                let mixed: f32 = (1.0 - backdrop_alpha) * source_value + backdrop_alpha * mode.mix(backdrop_value, source_value);
+                let premultiplied: f32 = source_alpha * source_fraction * mixed + backdrop_alpha * backdrop_fraction * backdrop_value;
+                pixel[channel] = (premultiplied / alpha * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        pixel
+    });
+    // Opaque results of opaque images stay RGB
+    if image.color().has_alpha() || output.pixels().any(|pixel| pixel[3] < 255) {
+        output.into()
+    } else {
+        DynamicImage::ImageRgba8(output).to_rgb8().into()
+    }
+}
+
+// Loads the second image and blends it onto `image`.
+pub fn blend_with(image: &DynamicImage, path: &Path, mode: BlendMode, opacity: f32) -> Result<DynamicImage, String> {
+    let layer: DynamicImage = decode_file(path).map_err(|e| format!("failed to load blend image {}: {}", path.display(), e))?;
+    Ok(blend(image, &layer, mode, opacity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn blend_modes_follow_the_compositing_rules() {
+        let gray: DynamicImage = RgbImage::from_pixel(2, 2, Rgb([128, 128, 128])).into();
+        let white: DynamicImage = RgbImage::from_pixel(2, 2, Rgb([255, 255, 255])).into();
+        assert_eq!(blend(&gray, &white, BlendMode::Multiply, 1.0).to_rgb8().get_pixel(0, 0)[0], 128);
+        assert_eq!(blend(&gray, &white, BlendMode::Screen, 1.0).to_rgb8().get_pixel(0, 0)[0], 255);
+        assert_eq!(blend(&gray, &white, BlendMode::Difference, 1.0).to_rgb8().get_pixel(0, 0)[0], 127);
+        assert_eq!(blend(&gray, &white, BlendMode::Normal, 0.5).to_rgb8().get_pixel(0, 0)[0], 192);
+        assert!(blend(&gray, &white, BlendMode::Overlay, 1.0).as_rgb8().is_some());
+
+        let half: DynamicImage = RgbaImage::from_fn(4, 4, |x, _| Rgba([200, 0, 0, if x < 2 { 255 } else { 0 }])).into();
+        let cut: RgbaImage = blend(&half, &gray, BlendMode::In, 1.0).to_rgba8();
+        assert_eq!((cut.get_pixel(0, 0).0, cut.get_pixel(3, 0)[3]), ([128, 128, 128, 255], 0));
+        let xor: RgbaImage = blend(&half, &gray, BlendMode::Xor, 1.0).to_rgba8();
+        assert_eq!((xor.get_pixel(0, 0)[3], xor.get_pixel(3, 0).0), (0, [128, 128, 128, 255]));
+    }
+}
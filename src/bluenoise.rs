@@ -0,0 +1,161 @@
+use image::{DynamicImage, GrayImage};
+use std::sync::OnceLock;
+use crate::filter::grayscale;
+use crate::math::exp_f64;
+
+// Side of the threshold mask tiled over the image. 64 x 64 is large enough that the tiling does
+// not show and small enough to generate in a moment on first use.
+const MASK_SIZE: usize = 64;
+
+// Width of the Gaussian the void-and-cluster energy is measured with, as in Ulichney's paper.
+const SIGMA: f64 = 1.5;
+
+// Fixed xorshift sequence, so the mask is the same on every run.
+struct Sequence(u64);
+
+impl Sequence {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound.max(1) as u64) as usize
+    }
+}
+
+// Toroidal Gaussian energy of a set of points on a size x size grid, updated as points come and go.
+#[derive(Clone)]
+struct Energy {
+    size: usize,
+    weights: Vec<f64>,
+    energy: Vec<f64>,
+    points: Vec<bool>,
+}
+
+impl Energy {
+    fn new(size: usize) -> Self {
+        let wrapped = |d: usize| d.min(size - d) as f64;
+        let weights: Vec<f64> = (0..size * size)
+            .map(|index| exp_f64(-(wrapped(index % size).powi(2) + wrapped(index / size).powi(2)) / (2.0 * SIGMA * SIGMA)))
+            .collect();
+        Energy { size, weights, energy: vec![0.0; size * size], points: vec![false; size * size] }
+    }
+
+    fn toggle(&mut self, index: usize) {
+        self.points[index] = !self.points[index];
+        let sign: f64 = if self.points[index] { 1.0 } else { -1.0 };
+        let (x, y) = (index % self.size, index / self.size);
+        for (target, energy) in self.energy.iter_mut().enumerate() {
+            let dx: usize = (target % self.size + self.size - x) % self.size;
+            let dy: usize = (target / self.size + self.size - y) % self.size;
+            *energy += sign * self.weights[dy * self.size + dx];
+        }
+    }
+
+    // The point with the most points around it.
+    fn tightest_cluster(&self) -> usize {
+        (0..self.points.len()).filter(|&index| self.points[index]).max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b])).unwrap_or(0)
+    }
+
+    // The empty position farthest from all points.
+    fn largest_void(&self) -> usize {
+        (0..self.points.len()).filter(|&index| !self.points[index]).min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b])).unwrap_or(0)
+    }
+}
+
+// Ulichney's void-and-cluster method: the rank 0..size^2 at which every position of a
+// size x size tile turns on. Any threshold on the ranks gives evenly spread points with no
+// low-frequency clumps, and the tile wraps around seamlessly.
+pub fn void_and_cluster(size: usize) -> Vec<u32> {
+    let count: usize = size * size;
+    let mut sequence: Sequence = Sequence(0x2545f4914f6cdd1d);
+    let mut pattern: Energy = Energy::new(size);
+    let initial: usize = (count / 10).max(1);
+    while pattern.points.iter().filter(|&&point| point).count() < initial {
+        let index: usize = sequence.below(count);
+        if !pattern.points[index] {
+            pattern.toggle(index);
+        }
+    }
+
+    // Spread the random points out: move the tightest cluster into the largest void until the
+    // point removed is the one that would be put back
+    for _ in 0..count {
+        let cluster: usize = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void: usize = pattern.largest_void();
+        pattern.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks: Vec<u32> = vec![0; count];
+    let mut removing: Energy = pattern.clone();
+    for rank in (0..initial).rev() {
+        let cluster: usize = removing.tightest_cluster();
+        removing.toggle(cluster);
+        ranks[cluster] = rank as u32;
+    }
+    // With a linear energy the densest empty spot among the zeros is the emptiest spot among the
+    // ones, so filling voids also covers the second half of Ulichney's method
+    for rank in initial..count {
+        let void: usize = pattern.largest_void();
+        pattern.toggle(void);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+// The mask as thresholds 0-254, one per rank level.
+fn mask() -> &'static [u8] {
+    static MASK: OnceLock<Vec<u8>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let count: u32 = (MASK_SIZE * MASK_SIZE) as u32;
+        void_and_cluster(MASK_SIZE).into_iter().map(|rank| (rank * 255 / count) as u8).collect()
+    })
+}
+
+// Dithers to `levels` gray levels by comparing each pixel with the tiled blue noise mask, like
+// -bayer but without its regular cross-hatch.
+pub fn blue_noise_dither(image: &GrayImage, levels: u8) -> GrayImage {
+    let mask: &[u8] = mask();
+    let steps: u32 = levels.max(2) as u32 - 1;
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let threshold: u32 = mask[(y as usize % MASK_SIZE) * MASK_SIZE + x as usize % MASK_SIZE] as u32;
+        let position: u32 = image.get_pixel(x, y)[0] as u32 * steps;
+        let level: u32 = position / 255 + if position % 255 > threshold { 1 } else { 0 };
+        image::Luma([(level.min(steps) * 255 / steps) as u8])
+    })
+}
+
+pub fn apply_blue_noise_dithering(image: &DynamicImage, levels: u8) -> GrayImage {
+    blue_noise_dither(&grayscale(&image.to_rgb8()), levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn ranks_are_a_permutation_and_keep_the_average() {
+        let mut ranks: Vec<u32> = void_and_cluster(16);
+        let first: Vec<usize> = (0..256).filter(|&index| ranks[index] < 32).collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..256).collect::<Vec<u32>>());
+
+        // The first eighth of the points are spread out: no two are next to each other
+        for &a in &first {
+            for &b in &first {
+                let (dx, dy) = ((a % 16).abs_diff(b % 16), (a / 16).abs_diff(b / 16));
+                assert!(a == b || dx.min(16 - dx).max(dy.min(16 - dy)) > 1, "{} and {} touch", a, b);
+            }
+        }
+
+        for value in [0u8, 40, 128, 200, 255] {
+            let dithered: GrayImage = blue_noise_dither(&GrayImage::from_pixel(64, 64, Luma([value])), 2);
+            let mean: f64 = dithered.pixels().map(|pixel| pixel[0] as f64).sum::<f64>() / 4096.0;
+            assert!((mean - value as f64).abs() < 3.0, "{} became {}", value, mean);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use image::{GrayImage, Rgb, RgbImage};
+
+const BRAILLE_BASE: u32 = 0x2800;
+// Bit for the dot at (x, y) inside a 2x4 braille cell.
+const DOT_BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+fn cell_color(colors: &RgbImage, cell_x: u32, cell_y: u32) -> Rgb<u8> {
+    let (width, height) = colors.dimensions();
+    let mut sum: [u32; 3] = [0; 3];
+    let mut count: u32 = 0;
+    for y in cell_y * 4..(cell_y * 4 + 4).min(height) {
+        for x in cell_x * 2..(cell_x * 2 + 2).min(width) {
+            let pixel: &Rgb<u8> = colors.get_pixel(x, y);
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as u32;
+            }
+            count += 1;
+        }
+    }
+    Rgb([(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8])
+}
+
+// Lit pixels (value >= 128) become raised dots, so the text reads right on a dark terminal.
+// With `colors` every character is tinted with the average color of its cell using 24-bit ANSI codes.
+pub fn braille(dots: &GrayImage, colors: Option<&RgbImage>) -> String {
+    let (width, height) = dots.dimensions();
+    let mut text: String = String::new();
+
+    for cell_y in 0..height.div_ceil(4) {
+        for cell_x in 0..width.div_ceil(2) {
+            let mut bits: u32 = 0;
+            for (dx, column) in DOT_BITS.iter().enumerate() {
+                for (dy, bit) in column.iter().enumerate() {
+                    let (x, y) = (cell_x * 2 + dx as u32, cell_y * 4 + dy as u32);
+                    if x < width && y < height && dots.get_pixel(x, y)[0] >= 128 {
+                        bits |= bit;
+                    }
+                }
+            }
+            if let Some(colors) = colors {
+                let color: Rgb<u8> = cell_color(colors, cell_x, cell_y);
+                text.push_str(&format!("\x1b[38;2;{};{};{}m", color[0], color[1], color[2]));
+            }
+            text.push(char::from_u32(BRAILLE_BASE + bits).unwrap_or(' '));
+        }
+        if colors.is_some() {
+            text.push_str("\x1b[0m");
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn maps_dots_to_braille_cells() {
+        let dots: GrayImage = GrayImage::from_fn(3, 4, |x, y| Luma([if x == 0 || (x == 2 && y == 3) { 255 } else { 0 }]));
+        assert_eq!(braille(&dots, None), "\u{2847}\u{2840}\n");
+    }
+}
@@ -0,0 +1,279 @@
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+use crate::color::rgb_to_hsl;
+use crate::filter::{decode_file, Color};
+
+// How -split breaks an image into grayscale planes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelSet {
+    Rgba,
+    Hsl,
+    YCbCr,
+}
+
+impl ChannelSet {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" | "rgba" => Some(ChannelSet::Rgba),
+            "hsl" => Some(ChannelSet::Hsl),
+            "ycbcr" => Some(ChannelSet::YCbCr),
+            _ => None,
+        }
+    }
+}
+
+// Full-range BT.601, as in JPEG.
+fn to_ycbcr(pixel: &Rgba<u8>) -> [f32; 3] {
+    let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+        128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b,
+    ]
+}
+
+fn from_ycbcr([y, cb, cr]: [f32; 3]) -> [u8; 3] {
+    [
+        to_u8(y + 1.402 * (cr - 128.0)),
+        to_u8(y - 0.344136 * (cb - 128.0) - 0.714136 * (cr - 128.0)),
+        to_u8(y + 1.772 * (cb - 128.0)),
+    ]
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+fn plane(image: &RgbaImage, channel: impl Fn(&Rgba<u8>) -> u8) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| Luma([channel(image.get_pixel(x, y))]))
+}
+
+// Named planes, e.g. ("r", ...), ("g", ...), ("b", ...) and ("a", ...) when the image has alpha.
+// Hue maps 0-360 degrees to 0-255; Y/Cb/Cr are full-range BT.601 as in JPEG.
+pub fn split_channels(image: &DynamicImage, set: ChannelSet) -> Vec<(&'static str, GrayImage)> {
+    let rgba: RgbaImage = image.to_rgba8();
+    let mut planes: Vec<(&'static str, GrayImage)> = match set {
+        ChannelSet::Rgba => vec![
+            ("r", plane(&rgba, |pixel| pixel[0])),
+            ("g", plane(&rgba, |pixel| pixel[1])),
+            ("b", plane(&rgba, |pixel| pixel[2])),
+        ],
+        ChannelSet::Hsl => {
+            let hsl = |pixel: &Rgba<u8>| rgb_to_hsl(Color::from_rgb_components(pixel[0], pixel[1], pixel[2]));
+            vec![
+                ("h", plane(&rgba, |pixel| (hsl(pixel).0 / 360.0 * 255.0).round() as u8)),
+                ("s", plane(&rgba, |pixel| (hsl(pixel).1 * 255.0).round() as u8)),
+                ("l", plane(&rgba, |pixel| (hsl(pixel).2 * 255.0).round() as u8)),
+            ]
+        },
+        ChannelSet::YCbCr => vec![
+            ("y", plane(&rgba, |pixel| to_u8(to_ycbcr(pixel)[0]))),
+            ("cb", plane(&rgba, |pixel| to_u8(to_ycbcr(pixel)[1]))),
+            ("cr", plane(&rgba, |pixel| to_u8(to_ycbcr(pixel)[2]))),
+        ],
+    };
+    if image.color().has_alpha() {
+        planes.push(("a", plane(&rgba, |pixel| pixel[3])));
+    }
+    planes
+}
+
+// Chroma resolution of a YCbCr encoding, as in video and JPEG.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subsampling {
+    Yuv420,
+    Yuv422,
+    Yuv411,
+}
+
+impl Subsampling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "4:2:0" | "420" => Some(Subsampling::Yuv420),
+            "4:2:2" | "422" => Some(Subsampling::Yuv422),
+            "4:1:1" | "411" => Some(Subsampling::Yuv411),
+            _ => None,
+        }
+    }
+
+    // Width and height of the pixel block that shares one chroma sample.
+    fn block(&self) -> (u32, u32) {
+        match self {
+            Subsampling::Yuv420 => (2, 2),
+            Subsampling::Yuv422 => (2, 1),
+            Subsampling::Yuv411 => (4, 1),
+        }
+    }
+}
+
+// Keeps full-resolution luma but averages Cb and Cr over each block, the color bleeding of
+// video and JPEG encoding. Alpha is kept as it is.
+pub fn chroma_subsample(image: &DynamicImage, subsampling: Subsampling) -> DynamicImage {
+    let rgba: RgbaImage = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let (block_width, block_height) = subsampling.block();
+    let columns: u32 = width.div_ceil(block_width);
+    let mut chroma: Vec<[f32; 3]> = vec![[0.0; 3]; (columns * height.div_ceil(block_height)) as usize];
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        let [_, cb, cr] = to_ycbcr(pixel);
+        let entry: &mut [f32; 3] = &mut chroma[((y / block_height) * columns + x / block_width) as usize];
+        *entry = [entry[0] + cb, entry[1] + cr, entry[2] + 1.0];
+    }
+
+    let output: RgbaImage = RgbaImage::from_fn(width, height, |x, y| {
+        let pixel: &Rgba<u8> = rgba.get_pixel(x, y);
+        let [cb, cr, count] = chroma[((y / block_height) * columns + x / block_width) as usize];
+        let [r, g, b] = from_ycbcr([to_ycbcr(pixel)[0], cb / count, cr / count]);
+        Rgba([r, g, b, pixel[3]])
+    });
+    if image.color().has_alpha() { output.into() } else { DynamicImage::ImageRgba8(output).to_rgb8().into() }
+}
+
+// Which of R, G, B and A an operation touches, from letters such as "rb" or "a".
+pub fn parse_channel_mask(letters: &str) -> Option<[bool; 4]> {
+    let mut mask: [bool; 4] = [false; 4];
+    for letter in letters.chars().filter(|&letter| letter != ',') {
+        let index: usize = "rgba".find(letter)?;
+        if mask[index] {
+            return None;
+        }
+        mask[index] = true;
+    }
+    mask.contains(&true).then_some(mask)
+}
+
+// Inverts only the channels in the mask. Inverting alpha keeps or adds an alpha channel; otherwise
+// the output has alpha only if the input did.
+pub fn reverse_channels(image: &DynamicImage, mask: [bool; 4]) -> DynamicImage {
+    let mut rgba: RgbaImage = image.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for (value, &inverted) in pixel.0.iter_mut().zip(&mask) {
+            if inverted {
+                *value = 255 - *value;
+            }
+        }
+    }
+    if image.color().has_alpha() || mask[3] { rgba.into() } else { DynamicImage::ImageRgba8(rgba).to_rgb8().into() }
+}
+
+// Writes every plane as DIRECTORY/<name>.png and returns the paths.
+pub fn save_channels(image: &DynamicImage, set: ChannelSet, directory: &Path) -> Result<Vec<PathBuf>, String> {
+    std::fs::create_dir_all(directory).map_err(|e| format!("failed to create {}: {}", directory.display(), e))?;
+    split_channels(image, set).into_iter()
+        .map(|(name, plane)| {
+            let path: PathBuf = directory.join(format!("{}.png", name));
+            plane.save(&path).map_err(|e| format!("failed to save channel {}: {}", path.display(), e))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+// Packs three or four grayscale images of the same size into the R, G, B (and A) channels.
+pub fn combine_channels(planes: &[GrayImage]) -> Result<DynamicImage, String> {
+    if planes.len() != 3 && planes.len() != 4 {
+        return Err(format!("-combine needs 3 or 4 channel images, got {}", planes.len()));
+    }
+    let (width, height) = planes[0].dimensions();
+    if planes.iter().any(|plane| plane.dimensions() != (width, height)) {
+        return Err("-combine channel images must all have the same size".to_string());
+    }
+    let value = |index: usize, x: u32, y: u32| planes[index].get_pixel(x, y)[0];
+    Ok(match planes.len() {
+        3 => RgbImage::from_fn(width, height, |x, y| Rgb([value(0, x, y), value(1, x, y), value(2, x, y)])).into(),
+        _ => RgbaImage::from_fn(width, height, |x, y| Rgba([value(0, x, y), value(1, x, y), value(2, x, y), value(3, x, y)])).into(),
+    })
+}
+
+// Red/cyan anaglyph: red from the left eye's view, green and blue from the right eye's, packed
+// with combine_channels like -combine. The views must have the same size.
+pub fn anaglyph(left: &DynamicImage, right: &DynamicImage) -> Result<DynamicImage, String> {
+    if left.dimensions() != right.dimensions() {
+        return Err(format!("-anaglyph views must have the same size, got {}x{} and {}x{}", left.width(), left.height(), right.width(), right.height()));
+    }
+    let mut planes: Vec<GrayImage> = split_channels(right, ChannelSet::Rgba).into_iter().map(|(_, plane)| plane).take(3).collect();
+    planes[0] = split_channels(left, ChannelSet::Rgba).swap_remove(0).1;
+    combine_channels(&planes)
+}
+
+fn load(path: &Path) -> Result<DynamicImage, String> {
+    decode_file(path).map_err(|e| format!("failed to load {}: {}", path.display(), e))
+}
+
+// With one path the image is the left view and the path the right one; with two, both views are
+// loaded and the image is replaced.
+pub fn load_anaglyph(image: &DynamicImage, paths: &[PathBuf]) -> Result<DynamicImage, String> {
+    match paths {
+        [right] => anaglyph(image, &load(right)?),
+        [left, right] => anaglyph(&load(left)?, &load(right)?),
+        _ => Err(format!("-anaglyph needs one or two views, got {}", paths.len())),
+    }
+}
+
+// Fakes the two views from one image and a depth map (white is near): each eye's view moves
+// pixels sideways by up to half of `shift` pixels in proportion to their nearness, in opposite
+// directions, and the views are combined into an anaglyph.
+pub fn depth_anaglyph(image: &DynamicImage, depth: &GrayImage, shift: f32) -> Result<DynamicImage, String> {
+    let (width, height) = image.dimensions();
+    let depth: GrayImage = image::imageops::resize(depth, width, height, image::imageops::FilterType::Triangle);
+    let source: RgbaImage = image.to_rgba8();
+    let view = |direction: f32| -> DynamicImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let offset: f32 = direction * shift / 2.0 * depth.get_pixel(x, y)[0] as f32 / 255.0;
+            let source_x: u32 = (x as f32 - offset).round().clamp(0.0, width as f32 - 1.0) as u32;
+            *source.get_pixel(source_x, y)
+        }).into()
+    };
+    anaglyph(&view(1.0), &view(-1.0))
+}
+
+pub fn load_depth_anaglyph(image: &DynamicImage, depth_path: &Path, shift: f32) -> Result<DynamicImage, String> {
+    depth_anaglyph(image, &load(depth_path)?.to_luma8(), shift)
+}
+
+// Loads the channel images (converted to grayscale) and combines them.
+pub fn load_and_combine(paths: &[PathBuf]) -> Result<DynamicImage, String> {
+    let planes: Vec<GrayImage> = paths.iter()
+        .map(|path| decode_file(path).map(|image| image.to_luma8()).map_err(|e| format!("failed to load channel {}: {}", path.display(), e)))
+        .collect::<Result<Vec<GrayImage>, String>>()?;
+    combine_channels(&planes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_combine_and_subsample() {
+        let image: DynamicImage = RgbaImage::from_fn(4, 3, |x, y| Rgba([x as u8 * 60, y as u8 * 90, 17, 200])).into();
+        let planes: Vec<(&str, GrayImage)> = split_channels(&image, ChannelSet::Rgba);
+        assert_eq!(planes.iter().map(|(name, _)| *name).collect::<Vec<&str>>(), ["r", "g", "b", "a"]);
+        let gray: Vec<GrayImage> = planes.into_iter().map(|(_, plane)| plane).collect();
+        assert_eq!(combine_channels(&gray).unwrap().to_rgba8(), image.to_rgba8());
+        assert!(combine_channels(&gray[..2]).is_err());
+
+        let white: DynamicImage = RgbImage::from_pixel(1, 1, Rgb([255, 255, 255])).into();
+        let ycbcr: Vec<u8> = split_channels(&white, ChannelSet::YCbCr).iter().map(|(_, plane)| plane.get_pixel(0, 0)[0]).collect();
+        assert_eq!(ycbcr, [255, 128, 128]);
+
+        let stripes: DynamicImage = RgbImage::from_fn(4, 2, |x, _| if x % 2 == 0 { Rgb([255, 0, 0]) } else { Rgb([0, 0, 255]) }).into();
+        let subsampled: RgbImage = chroma_subsample(&stripes, Subsampling::Yuv422).to_rgb8();
+        assert!(subsampled.get_pixel(0, 0)[2] > 0 && subsampled.get_pixel(1, 0)[0] > 0);
+        assert_eq!(chroma_subsample(&white, Subsampling::Yuv420).to_rgb8(), white.to_rgb8());
+
+        assert_eq!(parse_channel_mask("ra"), Some([true, false, false, true]));
+        assert_eq!(parse_channel_mask("rr"), None);
+        assert_eq!(parse_channel_mask("x"), None);
+        let inverted: DynamicImage = reverse_channels(&image, [false, true, false, true]);
+        assert_eq!(inverted.to_rgba8().get_pixel(1, 1), &Rgba([60, 165, 17, 55]));
+        let masked: DynamicImage = reverse_channels(&white, [false, false, false, true]);
+        assert_eq!(masked.to_rgba8().get_pixel(0, 0), &Rgba([255, 255, 255, 0]));
+
+        let red: DynamicImage = RgbImage::from_pixel(4, 3, Rgb([200, 10, 20])).into();
+        let blue: DynamicImage = RgbImage::from_pixel(4, 3, Rgb([30, 40, 250])).into();
+        assert_eq!(anaglyph(&red, &blue).unwrap().to_rgb8().get_pixel(2, 1), &Rgb([200, 40, 250]));
+        assert!(anaglyph(&red, &white).is_err());
+        // A flat far depth map shifts nothing
+        let flat: DynamicImage = depth_anaglyph(&stripes, &GrayImage::new(4, 2), 6.0).unwrap();
+        assert_eq!(flat.to_rgb8(), stripes.to_rgb8());
+    }
+}
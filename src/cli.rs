@@ -0,0 +1,355 @@
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::error::ErrorKind;
+use clap_complete::Shell;
+use clap::{value_parser, Args, Command as ClapCommand, CommandFactory, Parser, Subcommand, ValueHint};
+use filter::color::ColorSpace;
+use filter::config::parse_format;
+use filter::convolve::BorderPolicy;
+use filter::filter::AlphaMode;
+use filter::limits::OversizePolicy;
+use filter::palette::{DistanceMetric, PaletteMode};
+use image::ImageFormat;
+use std::path::Path;
+use std::time::Duration;
+use crate::help_command;
+use crate::variations::{self, Jitter};
+use crate::OutputSize;
+
+#[derive(Parser)]
+#[command(
+    name = "filter",
+    version,
+    about = "Palette mapping, dithering, pixel art and tone filters for images",
+    disable_help_subcommand = true,
+    after_help = "Without a subcommand, the arguments go to 'filter': filter -pal -floyd input.png output.png\n\
+                  Operation details: filter help OPERATION, or filter -OPERATION --help",
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(
+        about = "Apply operations to INPUT and save OUTPUT (run without arguments for the list)",
+        override_usage = "filter [OPERATIONS]... [OPTIONS] INPUT OUTPUT",
+        after_long_help = "Colors: #rgb, #rrggbb, rgb(r, g, b) or CSS names such as navy\n\
+                           Example: filter -pal -pix=4 -floyd input.png output.png\n\
+                           Operations can also be written with two dashes or by their full name: --floyd, --pixelate=4, --invert\n\
+                           Pipes: - as the input reads stdin, - as the output writes stdout (needs --format), and messages\n\
+                           then go to stderr: curl -s URL | filter -pal --format=png - - > out.png\n\
+                           Batch: several inputs, a directory or a pattern such as 'images/*.png' save into an output\n\
+                           directory, reading the palette once: filter -pal -floyd 'images/*.png' out/\n\
+                           Defaults come from ~/.config/image_rust/config.toml (palette_dir, threads, format, quality for\n\
+                           JPEG and AVIF, distance) and the IMAGE_RUST_PALETTE_DIR, IMAGE_RUST_THREADS, IMAGE_RUST_FORMAT,\n\
+                           IMAGE_RUST_QUALITY and IMAGE_RUST_DISTANCE variables\n\
+                           -pal=NAME searches IMAGE_RUST_PALETTE_PATH, then ~/.local/share/image_rust/palettes, then the\n\
+                           palette directory\n\
+                           Exit status: 2 for invalid arguments, 3 for unreadable files, 4 for undecodable images,\n\
+                           5 for failed encodes, 6 for invalid palettes and 1 for other failures",
+    )]
+    Filter(Box<FilterArgs>),
+    #[command(about = "Generate, render, install and list palettes", disable_help_flag = true)]
+    Palette(Passthrough),
+    #[command(about = "Show the format, size, color type and color count of an image")]
+    Info(InfoArgs),
+    #[command(about = "Check an image for problems such as banding", disable_help_flag = true)]
+    Analyze(Passthrough),
+    #[command(about = "Show details of an operation", disable_help_flag = true)]
+    Help(Passthrough),
+    #[command(about = "Print a shell completion script", disable_help_flag = true)]
+    Completions(CompletionsArgs),
+    #[command(about = "Write test patterns", disable_help_flag = true)]
+    Generate(Passthrough),
+    #[command(about = "Rerun the commands recorded in reports or manifests", disable_help_flag = true)]
+    Replay(Passthrough),
+    #[command(about = "Make a looping GIF from one image by stepping an effect through frames", disable_help_flag = true)]
+    AnimateEffect(Passthrough),
+    #[command(about = "Pack a directory of sprites into an atlas with a JSON frame map", disable_help_flag = true)]
+    Pack(Passthrough),
+    #[command(about = "Render a labeled grid of results over a range of one parameter", disable_help_flag = true)]
+    Sweep(Passthrough),
+}
+
+// Operations are order-dependent and use -name=value flags, so they stay a list parsed in order
+// by apply, together with the paths; the --options around them are typed here.
+#[derive(Args)]
+#[command(disable_help_flag = true)]
+pub struct FilterArgs {
+    #[arg(allow_hyphen_values = true, value_name = "OPERATIONS... INPUT OUTPUT", help = "The operations in order, then the input path(s) and the output path")]
+    pub args: Vec<String>,
+    #[arg(long, help = "Show the resolved configuration and operations without processing")]
+    pub explain: bool,
+    #[arg(long, help = "Skip processing when the output is newer than the input and palette")]
+    pub only_changed: bool,
+    #[arg(long, require_equals = true, value_name = "PATH", value_hint = ValueHint::FilePath, help = "Write per-pixel palette indices and the palette", long_help = "Write per-pixel palette indices and the palette to PATH")]
+    pub emit_index_map: Option<String>,
+    #[arg(long, require_equals = true, value_name = "PATH", value_hint = ValueHint::FilePath, help = "Write the xterm 256 color index of every pixel as text", long_help = "Write the xterm 256 color index of every pixel as text, one line per row")]
+    pub emit_xterm: Option<String>,
+    #[arg(long, require_equals = true, value_name = "PATH", value_hint = ValueHint::FilePath, help = "Write the 1-bit result as Unicode braille text", long_help = "Write the 1-bit result as Unicode braille text, 2x4 dots per character")]
+    pub emit_braille: Option<String>,
+    #[arg(long, help = "Tint braille characters with ANSI 24-bit colors")]
+    pub braille_color: bool,
+    #[arg(long, require_equals = true, value_name = "KB", value_parser = value_parser!(u64).range(1..), help = "Reduce colors until a PNG/GIF output fits", long_help = "Reduce colors until a PNG/GIF output fits in KB kilobytes")]
+    pub target_size: Option<u64>,
+    #[arg(long, require_equals = true, value_name = "MODE", default_value = "premultiplied", value_parser = PossibleValuesParser::new(["premultiplied", "straight"]).map(|name| alpha_mode(&name)), help = "Alpha handling while resampling")]
+    pub alpha: AlphaMode,
+    #[arg(long, require_equals = true, value_name = "SPACE", default_value = "oklab", value_parser = PossibleValuesParser::new(["srgb", "linear", "lab", "oklab"]).map(|name| ColorSpace::from_name(&name).expect("listed above")), help = "Interpolation space for gradients")]
+    pub blend_space: ColorSpace,
+    #[arg(long, require_equals = true, value_name = "METRIC", value_parser = PossibleValuesParser::new(["rgb", "lab", "oklab", "ciede2000"]).map(|name| DistanceMetric::from_name(&name).expect("listed above")), help = "Color distance used to match palette colors", long_help = "Color distance used to match palette colors (default rgb, or the configured distance);\n\
+                     oklab suits photos on small palettes and is much faster than ciede2000")]
+    pub distance: Option<DistanceMetric>,
+    #[arg(long, help = "Match -pal colors through a lookup table of the RGB cube", long_help = "Match -pal colors through a 32x32x32 table of the RGB cube, built once per image;\n\
+                     much faster on large images and with ciede2000, at the cost of 8-level color steps")]
+    pub palette_lut: bool,
+    #[arg(long, require_equals = true, value_name = "MODE", default_value = "full", value_parser = PossibleValuesParser::new(["full", "luminance", "luminance-dither"]).map(|name| PaletteMode::from_name(&name).expect("listed above")), help = "Replace whole colors or only brightness", long_help = "-pal replaces whole colors (full), or only snaps brightness to the palette's lightness levels\n\
+                     and keeps hue and saturation")]
+    pub palette_mode: PaletteMode,
+    #[arg(long, require_equals = true, value_name = "DIR", value_hint = ValueHint::DirPath, help = "Also save the image after every operation", long_help = "Also save the image after every operation as DIR/NN-operation.png")]
+    pub save_steps: Option<String>,
+    #[arg(long, help = "Name the output after the SHA-256 of its content", long_help = "Name the output after the SHA-256 of its content (same directory and extension) and record\n\
+                     input -> output in manifest.json there")]
+    pub name_by_hash: bool,
+    #[arg(long, require_equals = true, value_name = "SECS", value_parser = seconds, help = "Fail an input whose loading, processing and saving take longer", long_help = "Fail an input whose loading, processing and saving take longer, checked between operations;\n\
+                     a batch goes on with the next input and exits with code 1 at the end")]
+    pub timeout: Option<Duration>,
+    #[arg(long, require_equals = true, value_name = "N[,reject|downscale]", value_parser = pixel_limit, help = "Refuse or shrink inputs over N pixels", long_help = "Refuse inputs over N pixels, or shrink them to N pixels, checked from the file header before\n\
+                     decoding (for untrusted files)")]
+    pub max_pixels: Option<(u64, OversizePolicy)>,
+    #[arg(long, require_equals = true, value_name = "FORMAT", value_parser = output_format, help = "Output format instead of the output extension, or auto", long_help = "Output format such as png, jpg, gif, avif or dds, instead of the output extension.\n\
+                     .dds and .ktx2 outputs are GPU textures, BC4 compressed for gray results and BC1 otherwise;\n\
+                     .jxl outputs are lossless JPEG XL when built with the jxl feature, which also reads JPEG XL\n\
+                     inputs (AVIF inputs need the avif feature).\n\
+                     auto writes an indexed PNG when an 8-bit result has at most 256 colors and a truecolor PNG\n\
+                     otherwise, to a .png output (batch outputs are renamed to .png)")]
+    pub format: Option<OutputFormat>,
+    #[arg(long, help = "Save PNG with Adam7 interlacing, GIF interlaced and JPEG progressive")]
+    pub interlace: bool,
+    #[arg(long, help = "Treat the input as data such as normal, roughness and height maps", long_help = "Treat the input as data (normal, roughness, height maps): resize keeps the bit depth and\n\
+                     straight alpha, and only resize, crop, canvas, pad, split and combine are allowed")]
+    pub data_texture: bool,
+    #[arg(long, help = "Write OUTPUT_STEM.report.json next to the output", long_help = "Write OUTPUT_STEM.report.json with sizes, color count, palette, operations, per-operation\n\
+                     timings and SHA-256 hashes of the input and output")]
+    pub report: bool,
+    #[arg(long, help = "Run the operations twice and fail if the outputs differ", long_help = "Run the operations twice in this process and fail if the outputs differ, which catches\n\
+                     thread-order effects only; alone, fail unless this build reproduces the reference digest,\n\
+                     which compares platforms and build profiles (FFT filters are not covered)")]
+    pub verify_determinism: bool,
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "text", value_parser = ["text", "json"], help = "Print pixels processed, timings, throughput and peak memory")]
+    pub stats: Option<String>,
+    #[arg(long, value_name = "REF", num_args = 0..=1, require_equals = true, default_missing_value = "1000", value_parser = value_parser!(u32).range(1..), help = "Scale pixel parameters tuned for a REF pixel shorter side", long_help = "Treat pixel parameters as tuned for a REF pixel shorter side (default 1000) and scale them\n\
+                     to each image; percentages such as -pix=1.5% are always relative")]
+    pub auto_scale_params: Option<u32>,
+    #[arg(long, require_equals = true, value_name = "POLICY", default_value = "clamp", value_parser = PossibleValuesParser::new(["clamp", "wrap", "mirror"]).map(|name| BorderPolicy::from_name(&name).expect("listed above")), help = "How convolution filters read past the image edge")]
+    pub border: BorderPolicy,
+    #[arg(long, help = "Dither -quant results when reducing colors causes banding", long_help = "Dither -quant results when reducing colors turns smooth gradients into bands")]
+    pub auto_dither: bool,
+    #[arg(long, help = "Dither on all cores with the same result", long_help = "Dither on all cores, each row following the one above (same result, faster)")]
+    pub parallel_dither: bool,
+    #[arg(long, help = "Scan error diffusion rows alternately left and right", long_help = "Scan error diffusion rows alternately left and right (fewer worm patterns)")]
+    pub dither_serpentine: bool,
+    #[arg(long, require_equals = true, value_name = "first|FILE", help = "Reuse one -quant palette across runs", long_help = "Reuse one -quant palette across runs (frame sequences); 'first' keeps it in\n\
+                     palette.lock.json next to the output, created on the first run")]
+    pub lock_palette_from: Option<String>,
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "4", value_parser = value_parser!(u32).range(1..), help = "First write a quick 1/N resolution render", long_help = "First write a quick 1/N resolution render (default 4) next to the output")]
+    pub preview: Option<u32>,
+    #[arg(long, require_equals = true, value_name = "N", value_parser = value_parser!(u32).range(1..), help = "Save N variants with --jitter")]
+    pub variations: Option<u32>,
+    #[arg(long, require_equals = true, value_name = "OPERATION[.PARAMETER]:RANGE", value_delimiter = ',', value_parser = variations::parse_jitter, help = "Parameters the variants move randomly", long_help = "With --variations, save N variants of each input, decoded once, each with the named\n\
+                     parameters moved randomly by up to RANGE (or RANGE% of the value) either way, as\n\
+                     output-1.png ...; PARAMETER is a name from the operation's help or a position from 1\n\
+                     (default: the first value), e.g. --variations=6 --jitter=blur:1,wave.wavelength:25%")]
+    pub jitter: Vec<Jitter>,
+    #[arg(long, require_equals = true, value_name = "SIZES", value_delimiter = ',', value_parser = crate::parse_output_size, help = "Also save the result at scales such as 0.5x or widths such as 512", long_help = "Also save the result at each scale or width next to the output, as output@0.5x.png or\n\
+                     output-512w.png, for mipmaps and responsive images")]
+    pub sizes: Vec<OutputSize>,
+}
+
+// --format=auto picks PNG settings from the result; any other name is a fixed format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Auto,
+    Fixed(ImageFormat),
+}
+
+fn output_format(value: &str) -> Result<OutputFormat, String> {
+    match value {
+        "auto" => Ok(OutputFormat::Auto),
+        _ => parse_format(value).map(OutputFormat::Fixed),
+    }
+}
+
+fn alpha_mode(name: &str) -> AlphaMode {
+    match name {
+        "straight" => AlphaMode::Straight,
+        _ => AlphaMode::Premultiplied,
+    }
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err("expected seconds greater than 0".to_string()),
+    }
+}
+
+fn pixel_limit(value: &str) -> Result<(u64, OversizePolicy), String> {
+    let (limit, policy) = value.split_once(',').unwrap_or((value, "reject"));
+    match (limit.parse::<u64>(), OversizePolicy::from_name(policy)) {
+        (Ok(limit), Some(policy)) if limit > 0 => Ok((limit, policy)),
+        _ => Err("expected N[,reject|downscale]".to_string()),
+    }
+}
+
+// The --long spellings of the filter options, e.g. for suggestions on a typo.
+pub fn filter_options() -> Vec<String> {
+    let mut command: ClapCommand = Cli::command();
+    command.build();
+    command.find_subcommand("filter")
+        .map(|filter| filter.get_arguments().filter_map(|arg| arg.get_long()).map(|long| format!("--{}", long)).collect())
+        .unwrap_or_default()
+}
+
+// What `filter` prints without arguments: the filter options from clap, the operations from their
+// help pages and the other subcommands.
+pub fn print_filter_usage() {
+    let mut command: ClapCommand = Cli::command();
+    command.build();
+    if let Some(filter) = command.find_subcommand_mut("filter") {
+        println!("{}", filter.render_long_help());
+    }
+    println!("Operations:");
+    help_command::print_summary();
+    println!("Commands:");
+    for subcommand in command.get_subcommands().filter(|subcommand| subcommand.get_name() != "filter") {
+        println!("  {}: {}", subcommand.get_name(), subcommand.get_about().map(|about| about.to_string()).unwrap_or_default());
+    }
+    println!("Operation details: filter help OPERATION, or filter -OPERATION --help");
+}
+
+// Subcommands that parse and explain their own arguments.
+#[derive(Args)]
+pub struct Passthrough {
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    #[arg(value_name = "SHELL", value_parser = PossibleValuesParser::new(["bash", "zsh", "fish", "powershell"]).map(|name| name.parse::<Shell>().expect("listed above")))]
+    pub shell: Option<Shell>,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    #[arg(value_name = "IMAGE", value_parser = existing_file)]
+    pub input: String,
+}
+
+fn existing_file(path: &str) -> Result<String, String> {
+    if Path::new(path).is_file() {
+        Ok(path.to_string())
+    } else {
+        Err(format!("{} does not exist", path))
+    }
+}
+
+// Arguments without a subcommand keep working as `filter [operations] input output`.
+pub fn with_subcommand(args: &[String]) -> Vec<String> {
+    let normalized: Vec<String> = match args.get(1).map(String::as_str) {
+        Some(first) if Cli::command().find_subcommand(first).is_some() || ["--help", "-h", "--version", "-V"].contains(&first) => args.to_vec(),
+        _ => {
+            let mut normalized: Vec<String> = args.iter().take(1).cloned().collect();
+            normalized.push("filter".to_string());
+            normalized.extend(args.iter().skip(1).cloned());
+            normalized
+        },
+    };
+    if normalized.get(1).map(String::as_str) != Some("filter") {
+        return normalized;
+    }
+
+    // Filter options may come anywhere among the operations and paths. They go first, and "--"
+    // keeps clap from reading the -name=value operations that follow as options.
+    let options: Vec<String> = filter_options();
+    let (mut ordered, rest): (Vec<String>, Vec<String>) = normalized[2..].iter().cloned()
+        .partition(|arg| arg.starts_with("--") && options.iter().any(|option| *option == arg.split('=').next().unwrap_or_default()));
+    ordered.splice(0..0, normalized[..2].iter().cloned());
+    ordered.push("--".to_string());
+    ordered.extend(rest);
+    ordered
+}
+
+// `filter -pix=4 -floyd --help` explains the operations instead of running them.
+pub fn operation_help(args: &[String]) -> bool {
+    if !args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return false;
+    }
+    for arg in args.iter().filter(|arg| help_command::find(arg).is_some()) {
+        help_command::run(std::slice::from_ref(arg)).expect("operation found above");
+        println!();
+    }
+    true
+}
+
+// The input and output must be the last two arguments; catches operations after the paths and
+// paths given the wrong way round.
+pub fn check_paths(input_path: &str, output_path: &str) -> Result<(), String> {
+    if let Some(flag) = [input_path, output_path].into_iter().find(|path| path.starts_with('-') && *path != "-") {
+        return Err(format!("expected INPUT and OUTPUT paths last, found {}", flag));
+    }
+    if !Path::new(input_path).exists() && Path::new(output_path).is_file() {
+        return Err(format!("input {} does not exist but output {} does; are the paths swapped?", input_path, output_path));
+    }
+    Ok(())
+}
+
+// Reports a bad argument like clap's own errors, with the usage line, and exits with status 2.
+pub fn usage_error(subcommand: &str, message: &str) -> ! {
+    let mut command: ClapCommand = Cli::command();
+    command.build();
+    match command.find_subcommand_mut(subcommand) {
+        Some(filter) => filter.error(ErrorKind::InvalidValue, message).exit(),
+        None => command.error(ErrorKind::InvalidValue, message).exit(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_subcommands_and_the_filter_shorthand() {
+        let shorthand: Vec<String> = with_subcommand(&arguments(&["filter", "-pix=4", "-floyd", "in.png", "out.png"]));
+        match Cli::try_parse_from(&shorthand).unwrap().command {
+            Command::Filter(filter) => assert_eq!(filter.args, arguments(&["-pix=4", "-floyd", "in.png", "out.png"])),
+            _ => panic!("expected the filter subcommand"),
+        }
+        let palette: Vec<String> = with_subcommand(&arguments(&["filter", "palette", "list", "--names"]));
+        assert!(matches!(Cli::try_parse_from(&palette).unwrap().command, Command::Palette(args) if args.args.len() == 2));
+        assert!(Cli::try_parse_from(arguments(&["filter", "info", "no-such-image.png"])).is_err());
+
+        // Options are typed wherever they appear; the operations keep their order
+        let mixed: Vec<String> = with_subcommand(&arguments(&["filter", "-pal", "--stats", "-floyd", "--distance=oklab", "--preview", "in.png", "out.png", "--sizes=0.5x,64"]));
+        match Cli::try_parse_from(&mixed).unwrap().command {
+            Command::Filter(filter) => {
+                assert_eq!(filter.args, arguments(&["-pal", "-floyd", "in.png", "out.png"]));
+                assert_eq!((filter.stats.as_deref(), filter.distance, filter.preview), (Some("text"), Some(DistanceMetric::OkLab), Some(4)));
+                assert!(matches!(filter.sizes[..], [OutputSize::Scale(_), OutputSize::Width(64)]));
+                assert_eq!(filter.border, BorderPolicy::Clamp);
+            },
+            _ => panic!("expected the filter subcommand"),
+        }
+        for invalid in ["--timeout=0", "--max-pixels=10,shrink", "--format=xyz", "--border=none", "--preview=0", "--timeout"] {
+            assert!(Cli::try_parse_from(with_subcommand(&arguments(&["filter", "-pix", invalid, "in.png", "out.png"]))).is_err(), "{}", invalid);
+        }
+
+        assert!(check_paths("in.png", "-floyd").is_err());
+        assert!(check_paths("in.png", "out.png").is_ok());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::filter::Color;
+use crate::math;
+use std::sync::LazyLock;
+
+fn hue_to_channel(p: f32, q: f32, t: f32) -> f32 {
+    let t: f32 = t.rem_euclid(1.0);
+    if t < 1.0 / 6.0 {
+        p + (q - p) * 6.0 * t
+    } else if t < 0.5 {
+        q
+    } else if t < 2.0 / 3.0 {
+        p + (q - p) * (2.0 / 3.0 - t) * 6.0
+    } else {
+        p
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// Hue in degrees, saturation and lightness in 0..=1.
+pub fn hsl_to_rgb(h: f32, s: f32, l: f32) -> Color {
+    if s <= 0.0 {
+        let gray: u8 = to_u8(l);
+        return Color::from_rgb_components(gray, gray, gray);
+    }
+
+    let hue: f32 = h.rem_euclid(360.0) / 360.0;
+    let q: f32 = if l < 0.5 { l * (1.0 + s) } else { l + s - l * s };
+    let p: f32 = 2.0 * l - q;
+
+    Color::from_rgb_components(
+        to_u8(hue_to_channel(p, q, hue + 1.0 / 3.0)),
+        to_u8(hue_to_channel(p, q, hue)),
+        to_u8(hue_to_channel(p, q, hue - 1.0 / 3.0)),
+    )
+}
+
+pub fn rgb_to_hsl(color: Color) -> (f32, f32, f32) {
+    let r: f32 = color.r as f32 / 255.0;
+    let g: f32 = color.g as f32 / 255.0;
+    let b: f32 = color.b as f32 / 255.0;
+
+    let max: f32 = r.max(g).max(b);
+    let min: f32 = r.min(g).min(b);
+    let l: f32 = (max + min) / 2.0;
+    let delta: f32 = max - min;
+
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+
+    let s: f32 = if l > 0.5 { delta / (2.0 - max - min) } else { delta / (max + min) };
+    let h: f32 = if max == r {
+        (g - b) / delta + if g < b { 6.0 } else { 0.0 }
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+
+    (h * 60.0, s, l)
+}
+
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    let mut table: [f32; 256] = [0.0; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+        let c: f32 = value as f32 / 255.0;
+        *entry = if c <= 0.04045 { c / 12.92 } else { math::powf((c + 0.055) / 1.055, 2.4) };
+    }
+    table
+});
+
+pub fn srgb_to_linear(value: u8) -> f32 {
+    SRGB_TO_LINEAR[value as usize]
+}
+
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let c: f32 = value.clamp(0.0, 1.0);
+    let encoded: f32 = if c <= 0.0031308 { c * 12.92 } else { 1.055 * math::powf(c, 1.0 / 2.4) - 0.055 };
+    to_u8(encoded)
+}
+
+// CIELAB with a D65 white point.
+const WHITE_X: f32 = 0.95047;
+const WHITE_Y: f32 = 1.0;
+const WHITE_Z: f32 = 1.08883;
+
+fn lab_f(t: f32) -> f32 {
+    if t > 216.0 / 24389.0 { math::cbrt(t) } else { (24389.0 / 27.0 * t + 16.0) / 116.0 }
+}
+
+fn lab_f_inverse(t: f32) -> f32 {
+    if t * t * t > 216.0 / 24389.0 { t * t * t } else { (116.0 * t - 16.0) * 27.0 / 24389.0 }
+}
+
+pub fn rgb_to_lab(color: Color) -> [f32; 3] {
+    let r: f32 = srgb_to_linear(color.r);
+    let g: f32 = srgb_to_linear(color.g);
+    let b: f32 = srgb_to_linear(color.b);
+
+    let x: f32 = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+    let y: f32 = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z: f32 = 0.0193339 * r + 0.119192 * g + 0.9503041 * b;
+
+    let fx: f32 = lab_f(x / WHITE_X);
+    let fy: f32 = lab_f(y / WHITE_Y);
+    let fz: f32 = lab_f(z / WHITE_Z);
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+// CIEDE2000 difference between two CIELAB colors (Sharma, Wu and Dalal's formulation). Unlike
+// plain Lab distance it weighs hue more for saturated colors and lightness less near black and white.
+pub fn ciede2000(first: [f32; 3], second: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = first.map(|value| value as f64);
+    let [l2, a2, b2] = second.map(|value| value as f64);
+    let mean_chroma: f64 = (math::hypot_f64(a1, b1) + math::hypot_f64(a2, b2)) / 2.0;
+    let g: f64 = 0.5 * (1.0 - (mean_chroma.powi(7) / (mean_chroma.powi(7) + 25f64.powi(7))).sqrt());
+    let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+    let (c1, c2) = (math::hypot_f64(a1, b1), math::hypot_f64(a2, b2));
+    let hue = |a: f64, b: f64| if a == 0.0 && b == 0.0 { 0.0 } else { math::atan2_f64(b, a).to_degrees().rem_euclid(360.0) };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+    let delta_l: f64 = l2 - l1;
+    let delta_c: f64 = c2 - c1;
+    let delta_h: f64 = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 <= h1 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1 - 360.0
+    };
+    let delta_h: f64 = 2.0 * (c1 * c2).sqrt() * math::sin_f64((delta_h / 2.0).to_radians());
+
+    let mean_l: f64 = (l1 + l2) / 2.0;
+    let mean_c: f64 = (c1 + c2) / 2.0;
+    let mean_h: f64 = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+    let t: f64 = 1.0 - 0.17 * math::cos_f64((mean_h - 30.0).to_radians()) + 0.24 * math::cos_f64((2.0 * mean_h).to_radians())
+        + 0.32 * math::cos_f64((3.0 * mean_h + 6.0).to_radians()) - 0.20 * math::cos_f64((4.0 * mean_h - 63.0).to_radians());
+    let rotation: f64 = 60.0 * math::exp_f64(-((mean_h - 275.0) / 25.0).powi(2));
+    let r_c: f64 = 2.0 * (mean_c.powi(7) / (mean_c.powi(7) + 25f64.powi(7))).sqrt();
+    let s_l: f64 = 1.0 + 0.015 * (mean_l - 50.0).powi(2) / (20.0 + (mean_l - 50.0).powi(2)).sqrt();
+    let s_c: f64 = 1.0 + 0.045 * mean_c;
+    let s_h: f64 = 1.0 + 0.015 * mean_c * t;
+    let r_t: f64 = -math::sin_f64(rotation.to_radians()) * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt() as f32
+}
+
+pub fn lab_to_rgb(lab: [f32; 3]) -> Color {
+    let fy: f32 = (lab[0] + 16.0) / 116.0;
+    let fx: f32 = fy + lab[1] / 500.0;
+    let fz: f32 = fy - lab[2] / 200.0;
+
+    let x: f32 = lab_f_inverse(fx) * WHITE_X;
+    let y: f32 = lab_f_inverse(fy) * WHITE_Y;
+    let z: f32 = lab_f_inverse(fz) * WHITE_Z;
+
+    let r: f32 = 3.2404542 * x - 1.5371385 * y - 0.4985314 * z;
+    let g: f32 = -0.969266 * x + 1.8760108 * y + 0.0415560 * z;
+    let b: f32 = 0.0556434 * x - 0.2040259 * y + 1.0572252 * z;
+
+    Color::from_rgb_components(linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b))
+}
+
+pub fn rgb_to_oklab(color: Color) -> [f32; 3] {
+    let r: f32 = srgb_to_linear(color.r);
+    let g: f32 = srgb_to_linear(color.g);
+    let b: f32 = srgb_to_linear(color.b);
+
+    let l: f32 = math::cbrt(0.41222147 * r + 0.53633254 * g + 0.05144599 * b);
+    let m: f32 = math::cbrt(0.2119035 * r + 0.6806995 * g + 0.10739696 * b);
+    let s: f32 = math::cbrt(0.08830246 * r + 0.28171884 * g + 0.6299787 * b);
+
+    [
+        0.21045426 * l + 0.7936178 * m - 0.00407205 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.02590404 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+pub fn oklab_to_rgb(lab: [f32; 3]) -> Color {
+    let l: f32 = lab[0] + 0.39633778 * lab[1] + 0.21580376 * lab[2];
+    let m: f32 = lab[0] - 0.10556135 * lab[1] - 0.06385417 * lab[2];
+    let s: f32 = lab[0] - 0.08948418 * lab[1] - 1.2914856 * lab[2];
+
+    let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+    Color::from_rgb_components(
+        linear_to_srgb(4.0767417 * l - 3.3077116 * m + 0.23096994 * s),
+        linear_to_srgb(-1.268438 * l + 2.6097574 * m - 0.34131938 * s),
+        linear_to_srgb(-0.0041960863 * l - 0.7034186 * m + 1.7076147 * s),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Rgb,
+    Linear,
+    Lab,
+    OkLab,
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" | "srgb" => Some(ColorSpace::Rgb),
+            "linear" => Some(ColorSpace::Linear),
+            "lab" => Some(ColorSpace::Lab),
+            "oklab" => Some(ColorSpace::OkLab),
+            _ => None,
+        }
+    }
+}
+
+fn lerp3(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+pub fn mix(from: Color, to: Color, t: f32, space: ColorSpace) -> Color {
+    match space {
+        ColorSpace::Rgb => {
+            let mixed: [f32; 3] = lerp3(
+                [from.r as f32, from.g as f32, from.b as f32],
+                [to.r as f32, to.g as f32, to.b as f32],
+                t,
+            );
+            Color::from_rgb_components(mixed[0].round() as u8, mixed[1].round() as u8, mixed[2].round() as u8)
+        },
+        ColorSpace::Linear => {
+            let mixed: [f32; 3] = lerp3(
+                [srgb_to_linear(from.r), srgb_to_linear(from.g), srgb_to_linear(from.b)],
+                [srgb_to_linear(to.r), srgb_to_linear(to.g), srgb_to_linear(to.b)],
+                t,
+            );
+            Color::from_rgb_components(linear_to_srgb(mixed[0]), linear_to_srgb(mixed[1]), linear_to_srgb(mixed[2]))
+        },
+        ColorSpace::Lab => lab_to_rgb(lerp3(rgb_to_lab(from), rgb_to_lab(to), t)),
+        ColorSpace::OkLab => oklab_to_rgb(lerp3(rgb_to_oklab(from), rgb_to_oklab(to), t)),
+    }
+}
+
+pub fn ramp(from: Color, to: Color, steps: u32, space: ColorSpace) -> Vec<Color> {
+    if steps < 2 {
+        return vec![from];
+    }
+    (0..steps)
+        .map(|step| mix(from, to, step as f32 / (steps - 1) as f32, space))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lab_round_trip() {
+        for color in [
+            Color::from_rgb_components(0, 0, 0),
+            Color::from_rgb_components(255, 255, 255),
+            Color::from_rgb_components(185, 76, 174),
+            Color::from_rgb_components(15, 56, 15),
+        ] {
+            let back: Color = lab_to_rgb(rgb_to_lab(color));
+            assert_eq!((back.r, back.g, back.b), (color.r, color.g, color.b));
+            let back: Color = oklab_to_rgb(rgb_to_oklab(color));
+            assert_eq!((back.r, back.g, back.b), (color.r, color.g, color.b));
+        }
+
+        // Pairs from Sharma, Wu and Dalal's CIEDE2000 test data
+        for (first, second, expected) in [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([2.0776, 0.0795, -1.135], [0.9033, -0.0636, -0.5514], 0.9082),
+        ] {
+            assert!((ciede2000(first, second) - expected).abs() < 1e-3, "{:?} {:?}", first, second);
+            assert!((ciede2000(second, first) - expected).abs() < 1e-3);
+        }
+    }
+}
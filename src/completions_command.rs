@@ -0,0 +1,132 @@
+const BINARY: &str = "filter";
+
+use clap::{Arg, Command as ClapCommand, CommandFactory};
+use clap_complete::Shell;
+use filter::error::Error;
+use crate::cli::Cli;
+use crate::help_command::OPERATIONS;
+
+fn print_usage() {
+    println!("Usage: cargo r completions bash|zsh|fish|powershell");
+    println!("Prints a completion script; palette names are looked up with 'palette list --names' as you type -pal=");
+    println!("Example: filter completions bash > ~/.local/share/bash-completion/completions/filter");
+}
+
+fn operation_flags() -> String {
+    OPERATIONS.iter().flat_map(|doc| doc.flags.iter()).copied().collect::<Vec<&str>>().join(" ")
+}
+
+// The script clap_complete writes for the subcommands and options. The arguments without a
+// subcommand go to filter, so its options complete there as well.
+fn generated(shell: Shell) -> String {
+    let mut command: ClapCommand = Cli::command();
+    let options: Vec<Arg> = command.find_subcommand("filter")
+        .map(|filter| filter.get_arguments().filter(|arg| arg.get_long().is_some()).cloned().collect())
+        .unwrap_or_default();
+    command = command.args(options);
+    let mut script: Vec<u8> = Vec::new();
+    clap_complete::generate(shell, &mut command, BINARY, &mut script);
+    String::from_utf8(script).expect("completion scripts are UTF-8")
+}
+
+// What clap cannot know, ahead of its completions: installed palette names after -pal=, looked up
+// as you type, and the -operation flags, which are values to clap.
+fn bash() -> String {
+    format!(r#"{generated}
+_{bin}_dynamic() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    if [[ "$cur" == -pal=* ]]; then
+        COMPREPLY=($(compgen -P "-pal=" -W "$({bin} palette list --names 2>/dev/null)" -- "${{cur#-pal=}}"))
+    elif [[ "$cur" == -[!-]* ]]; then
+        COMPREPLY=($(compgen -W "{operations}" -- "$cur"))
+        [[ ${{#COMPREPLY[@]}} -eq 1 && "${{COMPREPLY[0]}}" == *= ]] && compopt -o nospace
+    else
+        _{bin} "$@"
+    fi
+}}
+complete -F _{bin}_dynamic -o bashdefault -o default {bin}
+"#,
+        generated = generated(Shell::Bash), bin = BINARY, operations = operation_flags(),
+    )
+}
+
+fn zsh() -> String {
+    format!(r#"{generated}
+_{bin}_dynamic() {{
+    local cur="${{words[CURRENT]}}"
+    if [[ "$cur" == -pal=* ]]; then
+        compadd -P '-pal=' -- ${{(f)"$({bin} palette list --names 2>/dev/null)"}}
+    elif [[ "$cur" == -[^-]* ]]; then
+        compadd -S '' -- {operations}
+    else
+        _{bin} "$@"
+    fi
+}}
+compdef _{bin}_dynamic {bin}
+"#,
+        generated = generated(Shell::Zsh), bin = BINARY, operations = operation_flags(),
+    )
+}
+
+fn fish() -> String {
+    format!(r#"{generated}
+complete -c {bin} -f -n 'string match -q -- "-pal=*" (commandline -ct)' -a '(printf -- "-pal=%s
+" ({bin} palette list --names 2>/dev/null))'
+complete -c {bin} -n 'string match -qr -- "^-[^-]" (commandline -ct)' -a '{operations}'
+"#,
+        generated = generated(Shell::Fish), bin = BINARY, operations = operation_flags(),
+    )
+}
+
+// PowerShell keeps one completer per command, so clap's becomes a script block the hook falls
+// back to.
+fn powershell() -> String {
+    let generated: String = generated(Shell::PowerShell).replacen(&format!("Register-ArgumentCompleter -Native -CommandName '{}' -ScriptBlock {{", BINARY), "$clapCompleter = {", 1);
+    format!(r#"{generated}
+Register-ArgumentCompleter -Native -CommandName {bin} -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    if ($wordToComplete -like '-pal=*') {{
+        $candidates = & {bin} palette list --names 2>$null | ForEach-Object {{ "-pal=$_" }}
+    }} elseif ($wordToComplete -match '^-[^-]') {{
+        $candidates = '{operations}' -split ' '
+    }} else {{
+        return & $clapCompleter $wordToComplete $commandAst $cursorPosition
+    }}
+    $candidates | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+"#,
+        generated = generated, bin = BINARY, operations = operation_flags(),
+    )
+}
+
+pub fn run(shell: Option<Shell>) -> Result<(), Error> {
+    let script: String = match shell {
+        Some(Shell::Bash) => bash(),
+        Some(Shell::Zsh) => zsh(),
+        Some(Shell::Fish) => fish(),
+        Some(Shell::PowerShell) => powershell(),
+        Some(shell) => return Err(Error::InvalidArgument(format!("Unknown shell: {}", shell))),
+        None => {
+            print_usage();
+            return Ok(());
+        }
+    };
+    print!("{}", script);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_complete_options_operations_and_palettes() {
+        for script in [bash(), zsh(), fish(), powershell()] {
+            assert!(script.contains("stats") && script.contains("palette-mode"), "{}", script);
+            assert!(script.contains("-floyd") && script.contains("-pal="));
+        }
+        assert_eq!(powershell().matches("Register-ArgumentCompleter").count(), 1);
+    }
+}
@@ -0,0 +1,236 @@
+use serde::Deserialize;
+use std::fmt;
+use std::path::PathBuf;
+use image::ImageFormat;
+use crate::palette::DistanceMetric;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingSource {
+    Default,
+    ConfigFile,
+    Environment,
+    CommandLine,
+}
+
+impl fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingSource::Default => write!(f, "default"),
+            SettingSource::ConfigFile => write!(f, "config file"),
+            SettingSource::Environment => write!(f, "environment"),
+            SettingSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: SettingSource,
+}
+
+impl<T> Setting<T> {
+    fn new(value: T) -> Self {
+        Setting { value, source: SettingSource::Default }
+    }
+
+    fn set(&mut self, value: T, source: SettingSource) {
+        self.value = value;
+        self.source = source;
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    palette_dir: Option<String>,
+    threads: Option<usize>,
+    format: Option<String>,
+    quality: Option<u8>,
+    distance: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub config_path: Option<PathBuf>,
+    pub palette_dir: Setting<PathBuf>,
+    pub threads: Setting<usize>,
+    pub format: Setting<Option<ImageFormat>>,
+    pub quality: Setting<u8>,
+    pub distance: Setting<DistanceMetric>,
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("IMAGE_RUST_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home: PathBuf = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config_home.join("image_rust").join("config.toml"))
+}
+
+pub fn parse_format(value: &str) -> Result<ImageFormat, String> {
+    ImageFormat::from_extension(value).ok_or_else(|| format!("unknown output format '{}'", value))
+}
+
+fn parse_threads(value: usize) -> Result<usize, String> {
+    if value == 0 { Err("thread count must be at least 1".to_string()) } else { Ok(value) }
+}
+
+fn parse_distance(value: &str) -> Result<DistanceMetric, String> {
+    DistanceMetric::from_name(value).ok_or_else(|| format!("unknown distance metric '{}'", value))
+}
+
+fn parse_quality(value: u8) -> Result<u8, String> {
+    if (1..=100).contains(&value) { Ok(value) } else { Err(format!("quality must be from 1 to 100, got {}", value)) }
+}
+
+impl Settings {
+    pub fn defaults() -> Self {
+        Settings {
+            config_path: None,
+            palette_dir: Setting::new(PathBuf::from(".")),
+            threads: Setting::new(std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+            format: Setting::new(None),
+            quality: Setting::new(75),
+            distance: Setting::new(DistanceMetric::Rgb),
+        }
+    }
+
+    // Defaults, then the config file, then IMAGE_RUST_* environment variables.
+    pub fn load() -> Result<Self, String> {
+        let mut settings: Settings = Settings::defaults();
+
+        if let Some(path) = config_path() {
+            if path.exists() {
+                let text: String = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+                let file: ConfigFile = toml::from_str(&text)
+                    .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+                settings.apply_file(file).map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+                settings.config_path = Some(path);
+            }
+        }
+
+        settings.apply_environment()?;
+        Ok(settings)
+    }
+
+    fn apply_file(&mut self, file: ConfigFile) -> Result<(), String> {
+        let source: SettingSource = SettingSource::ConfigFile;
+        if let Some(dir) = file.palette_dir {
+            self.palette_dir.set(PathBuf::from(dir), source);
+        }
+        if let Some(threads) = file.threads {
+            self.threads.set(parse_threads(threads)?, source);
+        }
+        if let Some(format) = file.format {
+            self.format.set(Some(parse_format(&format)?), source);
+        }
+        if let Some(quality) = file.quality {
+            self.quality.set(parse_quality(quality)?, source);
+        }
+        if let Some(distance) = file.distance {
+            self.distance.set(parse_distance(&distance)?, source);
+        }
+        Ok(())
+    }
+
+    fn apply_environment(&mut self) -> Result<(), String> {
+        self.apply_variables(|name| std::env::var(name).ok())
+    }
+
+    // The IMAGE_RUST_* variables as `variable` returns them.
+    fn apply_variables(&mut self, variable: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        let source: SettingSource = SettingSource::Environment;
+        if let Some(dir) = variable("IMAGE_RUST_PALETTE_DIR") {
+            self.palette_dir.set(PathBuf::from(dir), source);
+        }
+        if let Some(threads) = variable("IMAGE_RUST_THREADS") {
+            let parsed: usize = threads.parse().map_err(|_| format!("IMAGE_RUST_THREADS: invalid number '{}'", threads))?;
+            self.threads.set(parse_threads(parsed).map_err(|e| format!("IMAGE_RUST_THREADS: {}", e))?, source);
+        }
+        if let Some(format) = variable("IMAGE_RUST_FORMAT") {
+            self.format.set(Some(parse_format(&format).map_err(|e| format!("IMAGE_RUST_FORMAT: {}", e))?), source);
+        }
+        if let Some(quality) = variable("IMAGE_RUST_QUALITY") {
+            let parsed: u8 = quality.parse().map_err(|_| format!("IMAGE_RUST_QUALITY: invalid number '{}'", quality))?;
+            self.quality.set(parse_quality(parsed).map_err(|e| format!("IMAGE_RUST_QUALITY: {}", e))?, source);
+        }
+        if let Some(distance) = variable("IMAGE_RUST_DISTANCE") {
+            self.distance.set(parse_distance(&distance).map_err(|e| format!("IMAGE_RUST_DISTANCE: {}", e))?, source);
+        }
+        Ok(())
+    }
+
+    // --format=FORMAT, which beats the config file and environment.
+    pub fn set_format(&mut self, format: ImageFormat) {
+        self.format.set(Some(format), SettingSource::CommandLine);
+    }
+
+    // --distance=METRIC, which beats the config file and environment.
+    pub fn set_distance(&mut self, metric: DistanceMetric) {
+        self.distance.set(metric, SettingSource::CommandLine);
+    }
+
+    pub fn palette_path(&self) -> PathBuf {
+        self.palette_dir.value.join("palette.json")
+    }
+
+    pub fn explain(&self) -> String {
+        let config: String = match &self.config_path {
+            Some(path) => path.display().to_string(),
+            None => "none".to_string(),
+        };
+        let format: String = match self.format.value {
+            Some(format) => format!("{:?}", format),
+            None => "from output extension".to_string(),
+        };
+        format!(
+            "Config file: {}\n  palette_dir = {} ({})\n  threads = {} ({})\n  format = {} ({})\n  quality = {} ({})\n  distance = {:?} ({})",
+            config,
+            self.palette_dir.value.display(), self.palette_dir.source,
+            self.threads.value, self.threads.source,
+            format, self.format.source,
+            self.quality.value, self.quality.source,
+            self.distance.value, self.distance.source,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_sources_override_earlier_ones() {
+        let mut settings: Settings = Settings::defaults();
+        let file: ConfigFile = toml::from_str("threads = 3\nformat = \"jpg\"\nquality = 50\ndistance = \"oklab\"").unwrap();
+        settings.apply_file(file).unwrap();
+        settings.apply_variables(|name| match name {
+            "IMAGE_RUST_QUALITY" => Some("90".to_string()),
+            "IMAGE_RUST_DISTANCE" => Some("ciede2000".to_string()),
+            _ => None,
+        }).unwrap();
+        settings.set_distance(DistanceMetric::Lab);
+
+        assert_eq!((settings.palette_dir.value.clone(), settings.palette_dir.source), (PathBuf::from("."), SettingSource::Default));
+        assert_eq!((settings.threads.value, settings.threads.source), (3, SettingSource::ConfigFile));
+        assert_eq!((settings.format.value, settings.format.source), (Some(ImageFormat::Jpeg), SettingSource::ConfigFile));
+        assert_eq!((settings.quality.value, settings.quality.source), (90, SettingSource::Environment));
+        assert_eq!((settings.distance.value, settings.distance.source), (DistanceMetric::Lab, SettingSource::CommandLine));
+        settings.set_format(ImageFormat::Png);
+        assert_eq!(settings.format.source, SettingSource::CommandLine);
+
+        let explained: String = settings.explain();
+        for line in ["palette_dir = . (default)", "threads = 3 (config file)", "format = Png (command line)", "quality = 90 (environment)", "distance = Lab (command line)"] {
+            assert!(explained.contains(line), "{} not in {}", line, explained);
+        }
+
+        assert!(Settings::defaults().apply_file(toml::from_str("quality = 0").unwrap()).is_err());
+        assert!(toml::from_str::<ConfigFile>("colour = 1").is_err());
+        assert!(Settings::defaults().apply_variables(|name| (name == "IMAGE_RUST_THREADS").then(|| "many".to_string())).is_err());
+    }
+}
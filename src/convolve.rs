@@ -0,0 +1,260 @@
+use image::{DynamicImage, Rgba, Rgba32FImage};
+use serde::Deserialize;
+use std::path::Path;
+use crate::fft::gaussian_blur_fft;
+use crate::math;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderPolicy {
+    // Repeats the edge pixel.
+    Clamp,
+    // Continues from the opposite edge, for tiling textures.
+    Wrap,
+    // Reflects the image at the edge (d c b a | a b c d).
+    Mirror,
+}
+
+impl BorderPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "clamp" => Some(BorderPolicy::Clamp),
+            "wrap" => Some(BorderPolicy::Wrap),
+            "mirror" => Some(BorderPolicy::Mirror),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn index(&self, position: i64, length: u32) -> usize {
+        let length: i64 = length as i64;
+        let index: i64 = match self {
+            BorderPolicy::Clamp => position.clamp(0, length - 1),
+            BorderPolicy::Wrap => position.rem_euclid(length),
+            BorderPolicy::Mirror => {
+                let period: i64 = position.rem_euclid(2 * length);
+                if period < length { period } else { 2 * length - 1 - period }
+            }
+        };
+        index as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    width: usize,
+    height: usize,
+    weights: Vec<f32>,
+    bias: f32,
+    // Row and column factors when the kernel is their outer product; convolving with them
+    // costs width + height multiplications per pixel instead of width * height.
+    separable: Option<(Vec<f32>, Vec<f32>)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KernelFile {
+    weights: Vec<Vec<f32>>,
+    divisor: Option<f32>,
+    #[serde(default)]
+    bias: f32,
+}
+
+impl Kernel {
+    // Weights are divided by `divisor`, or by their sum when it is not given and not zero.
+    pub fn new(width: usize, height: usize, weights: &[f32], divisor: Option<f32>, bias: f32) -> Result<Self, String> {
+        if width == 0 || height == 0 || weights.len() != width * height {
+            return Err(format!("kernel needs {} x {} weights, got {}", width, height, weights.len()));
+        }
+        let sum: f32 = weights.iter().sum();
+        let divisor: f32 = divisor.unwrap_or(if sum.abs() > f32::EPSILON { sum } else { 1.0 });
+        if divisor == 0.0 || !divisor.is_finite() {
+            return Err("kernel divisor must be a non-zero number".to_string());
+        }
+        Ok(Kernel {
+            width,
+            height,
+            weights: weights.iter().map(|weight| weight / divisor).collect(),
+            bias,
+            separable: None,
+        })
+    }
+
+    pub fn separable(horizontal: &[f32], vertical: &[f32]) -> Self {
+        let weights: Vec<f32> = vertical.iter().flat_map(|&v| horizontal.iter().map(move |&h| h * v)).collect();
+        Kernel {
+            width: horizontal.len(),
+            height: vertical.len(),
+            weights,
+            bias: 0.0,
+            separable: Some((horizontal.to_vec(), vertical.to_vec())),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path: &Path = path.as_ref();
+        let text: String = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let file: KernelFile = serde_json::from_str(&text).map_err(|e| format!("invalid kernel {}: {}", path.display(), e))?;
+        let width: usize = file.weights.first().map_or(0, |row| row.len());
+        if file.weights.iter().any(|row| row.len() != width) {
+            return Err(format!("invalid kernel {}: rows have different lengths", path.display()));
+        }
+        let weights: Vec<f32> = file.weights.concat();
+        Kernel::new(width, file.weights.len(), &weights, file.divisor, file.bias)
+            .map_err(|e| format!("invalid kernel {}: {}", path.display(), e))
+    }
+
+    pub fn gaussian(sigma: f32) -> Self {
+        let sigma: f32 = sigma.max(0.01);
+        let radius: i32 = (sigma * 3.0).ceil() as i32;
+        let mut weights: Vec<f32> = (-radius..=radius).map(|x| math::exp(-(x * x) as f32 / (2.0 * sigma * sigma))).collect();
+        let sum: f32 = weights.iter().sum();
+        weights.iter_mut().for_each(|weight| *weight /= sum);
+        Kernel::separable(&weights, &weights)
+    }
+
+    pub fn sharpen() -> Self {
+        Kernel::from_weights(&[0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0], 0.0)
+    }
+
+    // Laplacian edge detector; flat areas become black.
+    pub fn edge() -> Self {
+        Kernel::from_weights(&[-1.0, -1.0, -1.0, -1.0, 8.0, -1.0, -1.0, -1.0, -1.0], 0.0)
+    }
+
+    // Lit from the top left, with flat areas at mid gray.
+    pub fn emboss() -> Self {
+        Kernel::from_weights(&[-2.0, -1.0, 0.0, -1.0, 0.0, 1.0, 0.0, 1.0, 2.0], 128.0)
+    }
+
+    fn from_weights(weights: &[f32; 9], bias: f32) -> Self {
+        Kernel { width: 3, height: 3, weights: weights.to_vec(), bias, separable: None }
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+fn convolve_pass(source: &Rgba32FImage, taps: &[f32], horizontal: bool, border: BorderPolicy) -> Rgba32FImage {
+    let (width, height) = source.dimensions();
+    let anchor: i64 = (taps.len() / 2) as i64;
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let mut sum: [f32; 3] = [0.0; 3];
+        for (offset, &weight) in taps.iter().enumerate() {
+            let shift: i64 = offset as i64 - anchor;
+            let pixel: &Rgba<f32> = if horizontal {
+                source.get_pixel(border.index(x as i64 + shift, width) as u32, y)
+            } else {
+                source.get_pixel(x, border.index(y as i64 + shift, height) as u32)
+            };
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] * weight;
+            }
+        }
+        Rgba([sum[0], sum[1], sum[2], source.get_pixel(x, y)[3]])
+    })
+}
+
+// Convolves the color channels in 0-255 float space; alpha is left unchanged.
+pub fn convolve(image: &DynamicImage, kernel: &Kernel, border: BorderPolicy) -> DynamicImage {
+    let has_alpha: bool = image.color().has_alpha();
+    let mut source: Rgba32FImage = image.to_rgba32f();
+    for pixel in source.pixels_mut() {
+        *pixel = Rgba([pixel[0] * 255.0, pixel[1] * 255.0, pixel[2] * 255.0, pixel[3]]);
+    }
+    let (width, height) = source.dimensions();
+
+    let mut result: Rgba32FImage = match &kernel.separable {
+        Some((horizontal, vertical)) => {
+            let rows: Rgba32FImage = convolve_pass(&source, horizontal, true, border);
+            convolve_pass(&rows, vertical, false, border)
+        },
+        None => {
+            let anchor_x: i64 = (kernel.width / 2) as i64;
+            let anchor_y: i64 = (kernel.height / 2) as i64;
+            Rgba32FImage::from_fn(width, height, |x, y| {
+                let mut sum: [f32; 3] = [0.0; 3];
+                for ky in 0..kernel.height {
+                    let sy: u32 = border.index(y as i64 + ky as i64 - anchor_y, height) as u32;
+                    for kx in 0..kernel.width {
+                        let weight: f32 = kernel.weights[ky * kernel.width + kx];
+                        let sx: u32 = border.index(x as i64 + kx as i64 - anchor_x, width) as u32;
+                        let pixel: &Rgba<f32> = source.get_pixel(sx, sy);
+                        for channel in 0..3 {
+                            sum[channel] += pixel[channel] * weight;
+                        }
+                    }
+                }
+                Rgba([sum[0], sum[1], sum[2], source.get_pixel(x, y)[3]])
+            })
+        }
+    };
+
+    for pixel in result.pixels_mut() {
+        *pixel = Rgba([
+            ((pixel[0] + kernel.bias) / 255.0).clamp(0.0, 1.0),
+            ((pixel[1] + kernel.bias) / 255.0).clamp(0.0, 1.0),
+            ((pixel[2] + kernel.bias) / 255.0).clamp(0.0, 1.0),
+            pixel[3],
+        ]);
+    }
+    let output: DynamicImage = DynamicImage::ImageRgba32F(result);
+    if has_alpha { output.to_rgba8().into() } else { output.to_rgb8().into() }
+}
+
+// Gradient magnitude |Gx| + |Gy| of a single-channel image (row-major, `width` values per row)
+// with the 3x3 Sobel operators and clamped borders. Seam carving uses it as the energy map.
+pub fn sobel_energy(values: &[f32], width: usize, height: usize) -> Vec<f32> {
+    const SOBEL_X: [f32; 9] = [-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0];
+    let at = |x: i64, y: i64| values[BorderPolicy::Clamp.index(y, height as u32) * width + BorderPolicy::Clamp.index(x, width as u32)];
+    let mut energy: Vec<f32> = Vec::with_capacity(width * height);
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let (mut gx, mut gy) = (0.0, 0.0);
+            for ky in 0..3 {
+                for kx in 0..3 {
+                    let value: f32 = at(x + kx - 1, y + ky - 1);
+                    gx += SOBEL_X[(ky * 3 + kx) as usize] * value;
+                    // Sobel Y is Sobel X transposed
+                    gy += SOBEL_X[(kx * 3 + ky) as usize] * value;
+                }
+            }
+            energy.push(f32::abs(gx) + f32::abs(gy));
+        }
+    }
+    energy
+}
+
+// Past this radius the FFT path is faster than the separable spatial passes.
+const FFT_BLUR_RADIUS: f32 = 25.0;
+
+pub fn gaussian_blur(image: &DynamicImage, sigma: f32, border: BorderPolicy) -> DynamicImage {
+    if sigma * 3.0 > FFT_BLUR_RADIUS {
+        gaussian_blur_fft(image, sigma, border)
+    } else {
+        convolve(image, &Kernel::gaussian(sigma), border)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn separable_matches_full_kernel_at_every_border() {
+        let image: DynamicImage = RgbImage::from_fn(9, 7, |x, y| Rgb([(x * 28) as u8, (y * 36) as u8, ((x * y) % 256) as u8])).into();
+        let gaussian: Kernel = Kernel::gaussian(1.2);
+        let (width, height) = gaussian.dimensions();
+        let full: Kernel = Kernel::new(width, height, &gaussian.weights, Some(1.0), 0.0).unwrap();
+
+        for border in [BorderPolicy::Clamp, BorderPolicy::Wrap, BorderPolicy::Mirror] {
+            let fast: Vec<u8> = convolve(&image, &gaussian, border).to_rgb8().into_raw();
+            let slow: Vec<u8> = convolve(&image, &full, border).to_rgb8().into_raw();
+            assert!(fast.iter().zip(&slow).all(|(a, b)| a.abs_diff(*b) <= 1), "{:?}", border);
+        }
+
+        assert_eq!(BorderPolicy::Mirror.index(-1, 4), 0);
+        assert_eq!(BorderPolicy::Mirror.index(5, 4), 2);
+        assert_eq!(BorderPolicy::Wrap.index(-1, 4), 3);
+    }
+}
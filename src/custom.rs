@@ -0,0 +1,136 @@
+use image::DynamicImage;
+use std::fmt;
+use std::sync::Arc;
+use crate::filter::{apply_floyd_steinberg_dithering, pixelate, reverse, Color};
+use crate::palette::{DistanceMetric, Palette, PaletteMatcher};
+
+// An image operation as a value, so library users can write their own and run them in a
+// pipeline next to the built-in ones.
+pub trait Filter {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage;
+
+    // Shown in progress output, observers and reports.
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+}
+
+// Nearest colors by RGB distance; use a PaletteMatcher for another metric. A palette without
+// colors leaves the image as it is.
+impl Filter for Palette {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let colors: Vec<Color> = self.get_colors().iter().map(Color::from_rgb).collect();
+        DynamicImage::ImageRgb8(PaletteMatcher::new(&colors, DistanceMetric::Rgb).map_image(image))
+    }
+
+    fn name(&self) -> &'static str {
+        "palette"
+    }
+}
+
+impl Filter for PaletteMatcher {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        DynamicImage::ImageRgb8(self.map_image(image))
+    }
+
+    fn name(&self) -> &'static str {
+        "palette"
+    }
+}
+
+// Pixel size in pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Pixelate(pub u32);
+
+impl Filter for Pixelate {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        DynamicImage::ImageRgb8(pixelate(image, self.0))
+    }
+
+    fn name(&self) -> &'static str {
+        "pixelate"
+    }
+}
+
+// 1-bit dithering; the result is a grayscale image of only black and white.
+#[derive(Debug, Clone, Copy)]
+pub struct FloydSteinberg;
+
+impl Filter for FloydSteinberg {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        DynamicImage::ImageLuma8(apply_floyd_steinberg_dithering(image))
+    }
+
+    fn name(&self) -> &'static str {
+        "floyd"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reverse;
+
+impl Filter for Reverse {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        DynamicImage::ImageRgb8(reverse(image))
+    }
+
+    fn name(&self) -> &'static str {
+        "reverse"
+    }
+}
+
+// A user filter inside FilterOperation::Custom, shared so operation lists stay cheap to clone.
+#[derive(Clone)]
+pub struct CustomFilter(pub Arc<dyn Filter + Send + Sync>);
+
+impl CustomFilter {
+    pub fn new<F: Filter + Send + Sync + 'static>(filter: F) -> Self {
+        CustomFilter(Arc::new(filter))
+    }
+}
+
+impl Filter for CustomFilter {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        self.0.apply(image)
+    }
+
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+impl fmt::Debug for CustomFilter {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "CustomFilter({})", self.0.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::FilterPipeline;
+    use image::{Rgb, RgbImage};
+
+    struct Darken;
+
+    impl Filter for Darken {
+        fn apply(&self, image: &DynamicImage) -> DynamicImage {
+            let mut rgb: RgbImage = image.to_rgb8();
+            rgb.pixels_mut().for_each(|pixel| pixel.0 = pixel.0.map(|value| value / 2));
+            DynamicImage::ImageRgb8(rgb)
+        }
+    }
+
+    #[test]
+    fn custom_filters_run_in_a_pipeline() {
+        let image: DynamicImage = RgbImage::from_fn(6, 4, |x, y| Rgb([x as u8 * 40, y as u8 * 60, 200])).into();
+        assert_eq!(Reverse.apply(&image).to_rgb8(), reverse(&image));
+        assert!(FloydSteinberg.apply(&image).as_luma8().is_some());
+
+        let pipeline: FilterPipeline = FilterPipeline::new().reverse().filter(Darken).filter(Pixelate(2));
+        assert_eq!(format!("{:?}", pipeline.operations()[1]), "Custom(CustomFilter(custom))");
+        let output: RgbImage = pipeline.run(image.clone()).unwrap().to_rgb8();
+        let expected: DynamicImage = Pixelate(2).apply(&Darken.apply(&Reverse.apply(&image)));
+        assert_eq!(output, expected.to_rgb8());
+    }
+}
@@ -0,0 +1,65 @@
+use image::{DynamicImage, Rgb, RgbImage};
+use sha2::{Digest, Sha256};
+use crate::color::ColorSpace;
+use crate::convolve::{gaussian_blur, BorderPolicy};
+use crate::filter::{apply_floyd_steinberg_dithering, duotone, resize, AlphaMode, Color};
+use crate::generate::{gradient_ramp, smpte_bars, zone_plate};
+use crate::palette::{DistanceMetric, Palette, PaletteMatcher};
+use crate::tone::{clahe, clarity, tonemap, ToneMapper};
+
+// Digest of reference_digest() on the platform this release was built on. Any other platform or
+// build profile must produce it too; when it does not, outputs are not reproducible there.
+pub const REFERENCE_DIGEST: &str = "76ed3c468b7977cd3974eb3bbb5f0959234021555fb628bac5a6c0e0a71c82b9";
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Covers the pixels, the dimensions and the pixel format, so equal digests mean equal images.
+pub fn image_digest(image: &DynamicImage) -> String {
+    let mut hasher: Sha256 = Sha256::new();
+    hasher.update(image.width().to_le_bytes());
+    hasher.update(image.height().to_le_bytes());
+    hasher.update(format!("{:?}", image.color()).as_bytes());
+    hasher.update(image.as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Runs the float-heavy filters (color conversion, resampling, blur, tone curves, palette
+// matching) on generated images and hashes the results. FFT based operations are left out:
+// rustfft picks different SIMD code per CPU, so they are only reproducible on one architecture.
+pub fn reference_digest() -> String {
+    let bars: DynamicImage = smpte_bars(96, 64).into();
+    let ramp: DynamicImage = gradient_ramp(96, 64).into();
+    let xterm: Vec<Color> = Palette::xterm256().get_colors().iter().map(Color::from_rgb).collect();
+    let matcher: PaletteMatcher = PaletteMatcher::new(&xterm, DistanceMetric::OkLab);
+    let matched: RgbImage = RgbImage::from_fn(96, 64, |x, y| {
+        let pixel: &Rgb<u8> = ramp.as_rgb8().map(|ramp| ramp.get_pixel(x, y)).unwrap_or(&Rgb([0, 0, 0]));
+        let color: Color = matcher.nearest_color(Color::from_rgb(pixel));
+        Rgb([color.r, color.g, color.b])
+    });
+
+    let results: Vec<DynamicImage> = vec![
+        apply_floyd_steinberg_dithering(&ramp).into(),
+        resize(&bars, 61, 37, AlphaMode::Premultiplied),
+        gaussian_blur(&ramp, 2.5, BorderPolicy::Mirror),
+        matched.into(),
+        clahe(&ramp, 4, 2.0),
+        tonemap(&bars, ToneMapper::Aces, 0.5),
+        clarity(&bars, 60.0),
+        duotone(&ramp, Color::from_rgb_components(20, 10, 60), Color::from_rgb_components(250, 220, 120), ColorSpace::OkLab).into(),
+        zone_plate(64, 64).into(),
+    ];
+    let digests: String = results.iter().map(image_digest).collect::<Vec<String>>().join("\n");
+    sha256_hex(digests.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_workload_matches_published_digest() {
+        assert_eq!(reference_digest(), REFERENCE_DIGEST);
+    }
+}
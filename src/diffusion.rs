@@ -0,0 +1,149 @@
+use image::{GrayImage, RgbImage};
+use crate::portable::diffuse;
+#[cfg(feature = "fixed-point")]
+use crate::portable::diffuse_rgb;
+#[cfg(not(feature = "fixed-point"))]
+use crate::portable::{neighbour, row_order};
+#[cfg(not(feature = "fixed-point"))]
+use image::Rgb;
+use crate::filter::Color;
+use crate::palette::PaletteMatcher;
+
+pub use crate::portable::{diffusion_kernel, DiffusionKernel, ATKINSON, BURKES, DIFFUSION_KERNELS, FLOYD_STEINBERG, JARVIS_JUDICE_NINKE, SIERRA, SIERRA_TWO_ROW, STUCKI};
+
+// Same diffusion the no_std portable module runs on raw buffers; see portable::diffuse.
+pub fn diffuse_gray(image: &GrayImage, kernel: &DiffusionKernel, serpentine: bool, quantize: impl Fn(u8) -> u8) -> GrayImage {
+    let mut output: GrayImage = image.clone();
+    diffuse(&mut output, image.width() as usize, kernel, serpentine, quantize);
+    output
+}
+
+// With the fixed-point feature palette diffusion runs the integer core code, exactly as on an MCU.
+#[cfg(feature = "fixed-point")]
+pub fn diffuse_palette(image: &RgbImage, kernel: &DiffusionKernel, serpentine: bool, matcher: &PaletteMatcher) -> RgbImage {
+    let mut output: RgbImage = image.clone();
+    diffuse_rgb(&mut output, image.width() as usize, kernel, serpentine, |[r, g, b]| {
+        let color: Color = matcher.nearest_color(Color::from_rgb_components(r, g, b));
+        [color.r, color.g, color.b]
+    });
+    output
+}
+
+#[cfg(not(feature = "fixed-point"))]
+pub fn diffuse_palette(image: &RgbImage, kernel: &DiffusionKernel, serpentine: bool, matcher: &PaletteMatcher) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let mut errors: Vec<[f32; 3]> = vec![[0.0; 3]; width as usize * height as usize];
+    let mut output: RgbImage = RgbImage::new(width, height);
+    let divisor: f32 = kernel.divisor.max(1) as f32;
+
+    for y in 0..height {
+        let (columns, direction) = row_order(y as usize, width as usize, serpentine);
+        for x in columns.map(|column| column as u32) {
+            let index: usize = y as usize * width as usize + x as usize;
+            let pixel: &Rgb<u8> = image.get_pixel(x, y);
+            let wanted: [f32; 3] = [
+                (pixel[0] as f32 + errors[index][0]).clamp(0.0, 255.0),
+                (pixel[1] as f32 + errors[index][1]).clamp(0.0, 255.0),
+                (pixel[2] as f32 + errors[index][2]).clamp(0.0, 255.0),
+            ];
+            let new_color: Color = matcher.nearest_color(Color::from_rgb_components(
+                wanted[0].round() as u8,
+                wanted[1].round() as u8,
+                wanted[2].round() as u8,
+            ));
+            output.put_pixel(x, y, Rgb([new_color.r, new_color.g, new_color.b]));
+
+            let error: [f32; 3] = [
+                wanted[0] - new_color.r as f32,
+                wanted[1] - new_color.g as f32,
+                wanted[2] - new_color.b as f32,
+            ];
+            for &(dx, dy, weight) in kernel.taps {
+                if let Some(target) = neighbour(x as usize, y as usize, dx * direction, dy, width as usize, height as usize) {
+                    for channel in 0..3 {
+                        errors[target][channel] += error[channel] * weight as f32 / divisor;
+                    }
+                }
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::DistanceMetric;
+    use crate::portable::threshold;
+    use image::{GenericImageView, Luma, Rgb};
+
+    fn mean(image: &GrayImage) -> f64 {
+        image.pixels().map(|pixel| pixel[0] as f64).sum::<f64>() / (image.width() * image.height()) as f64
+    }
+
+    #[test]
+    fn gray_diffusion_survives_adversarial_input() {
+        for value in [0u8, 1, 127, 128, 254, 255] {
+            let flat: GrayImage = GrayImage::from_pixel(64, 64, Luma([value]));
+            let dithered: GrayImage = diffuse_gray(&flat, &FLOYD_STEINBERG, false, threshold);
+            assert!((mean(&dithered) - value as f64).abs() < 4.0, "mean drifted for {}", value);
+        }
+
+        let checker: GrayImage = GrayImage::from_fn(257, 3, |x, y| Luma([if (x + y) % 2 == 0 { 255 } else { 0 }]));
+        assert_eq!(diffuse_gray(&checker, &FLOYD_STEINBERG, false, threshold), checker);
+
+        let column: GrayImage = GrayImage::from_pixel(1, 1000, Luma([200]));
+        assert_eq!(diffuse_gray(&column, &FLOYD_STEINBERG, false, threshold).height(), 1000);
+
+        // Every pixel rounds the same way, so errors stack instead of cancelling
+        let heavy: DiffusionKernel = DiffusionKernel { name: "heavy", divisor: 1, taps: &[(1, 0, 1000), (0, 1, 1000)] };
+        let stacked: GrayImage = diffuse_gray(&GrayImage::from_pixel(128, 128, Luma([100])), &heavy, false, threshold);
+        assert!(stacked.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+    }
+
+    #[test]
+    fn atkinson_keeps_midtones_and_clips_extremes() {
+        let gray: GrayImage = GrayImage::from_pixel(64, 64, Luma([128]));
+        assert!((mean(&diffuse_gray(&gray, &ATKINSON, false, threshold)) - 128.0).abs() < 8.0);
+
+        // Near black, Floyd-Steinberg still scatters white dots; Atkinson drops that error
+        let shadow: GrayImage = GrayImage::from_pixel(64, 64, Luma([12]));
+        assert!(mean(&diffuse_gray(&shadow, &FLOYD_STEINBERG, false, threshold)) > 0.0);
+        assert!(mean(&diffuse_gray(&shadow, &ATKINSON, false, threshold)) < mean(&diffuse_gray(&shadow, &FLOYD_STEINBERG, false, threshold)));
+    }
+
+    #[test]
+    fn wide_kernels_pass_on_all_of_the_error() {
+        let gray: GrayImage = GrayImage::from_fn(64, 64, |x, _| Luma([(x * 4) as u8]));
+        for kernel in [JARVIS_JUDICE_NINKE, STUCKI, SIERRA, SIERRA_TWO_ROW, BURKES] {
+            assert_eq!(kernel.taps.iter().map(|&(_, _, weight)| weight).sum::<i32>(), kernel.divisor, "{}", kernel.name);
+            assert!(kernel.taps.iter().all(|&(dx, dy, _)| dy > 0 || dx > 0), "{} sends error backwards", kernel.name);
+            for serpentine in [false, true] {
+                assert!((mean(&diffuse_gray(&gray, &kernel, serpentine, threshold)) - mean(&gray)).abs() < 4.0, "{}", kernel.name);
+            }
+        }
+        // Serpentine rows mirror the kernel, so the pattern changes from the second row on
+        let raster: GrayImage = diffuse_gray(&gray, &FLOYD_STEINBERG, false, threshold);
+        let serpentine: GrayImage = diffuse_gray(&gray, &FLOYD_STEINBERG, true, threshold);
+        assert_eq!(raster.view(0, 0, 64, 1).to_image(), serpentine.view(0, 0, 64, 1).to_image());
+        assert_ne!(raster, serpentine);
+        assert_eq!(diffusion_kernel("stucki"), Some(&STUCKI));
+        assert_eq!(diffusion_kernel("floyd"), None);
+    }
+
+    #[test]
+    fn palette_diffusion_keeps_errors_bounded() {
+        let black: [Color; 1] = [Color::from_rgb_components(0, 0, 0)];
+        let matcher: PaletteMatcher = PaletteMatcher::new(&black, DistanceMetric::Rgb);
+        let white: RgbImage = RgbImage::from_pixel(300, 300, Rgb([255, 255, 255]));
+        let output: RgbImage = diffuse_palette(&white, &FLOYD_STEINBERG, false, &matcher);
+        assert!(output.pixels().all(|pixel| pixel.0 == [0, 0, 0]));
+
+        let two: [Color; 2] = [Color::from_rgb_components(0, 0, 0), Color::from_rgb_components(255, 255, 255)];
+        let matcher: PaletteMatcher = PaletteMatcher::new(&two, DistanceMetric::Rgb);
+        let gray: RgbImage = RgbImage::from_pixel(64, 64, Rgb([64, 64, 64]));
+        let output: RgbImage = diffuse_palette(&gray, &FLOYD_STEINBERG, false, &matcher);
+        let lit: usize = output.pixels().filter(|pixel| pixel[0] == 255).count();
+        assert!((lit as f64 / (64.0 * 64.0) - 0.25).abs() < 0.02);
+    }
+}
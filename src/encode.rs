@@ -0,0 +1,206 @@
+use image::codecs::avif::AvifEncoder;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::{ColorType, Delay, DynamicImage, Frame, ImageError, ImageFormat, ImageResult, RgbaImage};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Write};
+use crate::filter::quantize_colors;
+use crate::interlace::png_chunk;
+use crate::palette::DistanceMetric;
+use crate::texture::{encode_texture, Container};
+
+pub fn encode(image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+    let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, format)?;
+    Ok(bytes.into_inner())
+}
+
+// Like encode, with `quality` for JPEG and AVIF.
+pub fn encode_with_quality(image: &DynamicImage, format: ImageFormat, quality: u8) -> ImageResult<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => encode_jpeg(image, quality),
+        ImageFormat::Avif => encode_avif(image, quality),
+        _ => encode(image, format),
+    }
+}
+
+pub fn encode_jpeg(image: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>> {
+    let mut bytes: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let encoder: JpegEncoder<&mut Cursor<Vec<u8>>> = JpegEncoder::new_with_quality(&mut bytes, quality);
+    match image.color() {
+        ColorType::L8 | ColorType::Rgb8 => image.write_with_encoder(encoder)?,
+        _ => DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?,
+    }
+    Ok(bytes.into_inner())
+}
+
+// The encoder's speed 6 of 1-10 keeps large photos to seconds at little cost in size.
+pub fn encode_avif(image: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let encoder: AvifEncoder<&mut Vec<u8>> = AvifEncoder::new_with_speed_quality(&mut bytes, 6, quality.clamp(1, 100));
+    match image.color() {
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => image.write_with_encoder(encoder)?,
+        _ => DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder)?,
+    }
+    Ok(bytes)
+}
+
+// A looping GIF showing each frame for `delay_ms` milliseconds. Colors are reduced to 256 per
+// frame by the encoder.
+pub fn encode_animated_gif(frames: &[RgbaImage], delay_ms: u32) -> ImageResult<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    {
+        let mut encoder: GifEncoder<&mut Vec<u8>> = GifEncoder::new_with_speed(&mut bytes, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        let delay: Delay = Delay::from_numer_denom_ms(delay_ms, 1);
+        encoder.encode_frames(frames.iter().map(|frame| Frame::from_parts(frame.clone(), 0, 0, delay)))?;
+    }
+    Ok(bytes)
+}
+
+// Decodes the image from JPEG at `quality` `passes` times over, so compression artifacts
+// pile up. JPEG has no alpha; the original alpha channel is put back afterwards.
+pub fn jpegify(image: &DynamicImage, quality: u8, passes: u32) -> ImageResult<DynamicImage> {
+    let mut result: DynamicImage = image.clone();
+    for _ in 0..passes {
+        result = image::load_from_memory_with_format(&encode_jpeg(&result, quality)?, ImageFormat::Jpeg)?;
+    }
+    if !image.color().has_alpha() {
+        return Ok(result);
+    }
+    let mut rgba: RgbaImage = result.to_rgba8();
+    for (pixel, original) in rgba.pixels_mut().zip(image.to_rgba8().pixels()) {
+        pixel[3] = original[3];
+    }
+    Ok(rgba.into())
+}
+
+// PNG with a palette of the image's colors, translucent ones first so the tRNS chunk stays short,
+// at the smallest bit depth that holds them. None for images with more than 256 colors.
+pub fn encode_indexed_png(image: &DynamicImage) -> Option<Vec<u8>> {
+    // Palette entries are 8-bit
+    if !matches!(image.color(), ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8) {
+        return None;
+    }
+    let rgba: RgbaImage = image.to_rgba8();
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut seen: HashSet<[u8; 4]> = HashSet::new();
+    for pixel in rgba.pixels() {
+        if seen.insert(pixel.0) {
+            if palette.len() == 256 {
+                return None;
+            }
+            palette.push(pixel.0);
+        }
+    }
+    palette.sort_by_key(|color| color[3] == 255);
+    let indices: HashMap<[u8; 4], u8> = palette.iter().enumerate().map(|(index, &color)| (color, index as u8)).collect();
+    let depth: u8 = match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+
+    let per_byte: usize = 8 / depth as usize;
+    let mut rows: Vec<u8> = Vec::new();
+    for row in rgba.rows() {
+        let row: Vec<u8> = row.map(|pixel| indices[&pixel.0]).collect();
+        rows.push(0);
+        rows.extend(row.chunks(per_byte).map(|chunk| {
+            chunk.iter().enumerate().fold(0u8, |byte, (i, &index)| byte | index << (8 - depth as usize * (i + 1)))
+        }));
+    }
+    let mut compressor: ZlibEncoder<Vec<u8>> = ZlibEncoder::new(Vec::new(), Compression::best());
+    // Writing into a Vec cannot fail
+    compressor.write_all(&rows).expect("in-memory write");
+    let compressed: Vec<u8> = compressor.finish().expect("in-memory write");
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&rgba.width().to_be_bytes());
+    header.extend_from_slice(&rgba.height().to_be_bytes());
+    header.extend_from_slice(&[depth, 3, 0, 0, 0]);
+    let colors: Vec<u8> = palette.iter().flat_map(|color| color[..3].to_vec()).collect();
+    let alphas: Vec<u8> = palette.iter().take_while(|color| color[3] < 255).map(|color| color[3]).collect();
+
+    let mut output: Vec<u8> = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut output, b"IHDR", &header);
+    png_chunk(&mut output, b"PLTE", &colors);
+    if !alphas.is_empty() {
+        png_chunk(&mut output, b"tRNS", &alphas);
+    }
+    png_chunk(&mut output, b"IDAT", &compressed);
+    png_chunk(&mut output, b"IEND", &[]);
+    Some(output)
+}
+
+// --format=auto: an indexed PNG for 8-bit images of up to 256 colors, such as palette and
+// dithered results, and a truecolor PNG otherwise, which keeps 16-bit results at 16 bits.
+// Returns the bytes and a name for the choice.
+pub fn encode_auto(image: &DynamicImage) -> ImageResult<(Vec<u8>, &'static str)> {
+    match encode_indexed_png(image) {
+        Some(bytes) => Ok((bytes, "indexed PNG")),
+        None => Ok((encode(image, ImageFormat::Png)?, "truecolor PNG")),
+    }
+}
+
+pub fn save_image(image: &DynamicImage, path: &str, format: Option<ImageFormat>, quality: u8) -> ImageResult<()> {
+    // The image crate only reads DDS; .dds and .ktx2 outputs are block compressed here
+    let container: Option<Container> = match format {
+        Some(ImageFormat::Dds) => Some(Container::Dds),
+        Some(_) => None,
+        None => Container::from_path(path),
+    };
+    if let Some(container) = container {
+        return std::fs::write(path, encode_texture(image, container)).map_err(ImageError::IoError);
+    }
+    // Nor does it know JPEG XL, which the jxl feature writes losslessly for .jxl outputs
+    #[cfg(feature = "jxl")]
+    if format.is_none() && crate::jxl::has_jxl_extension(path) {
+        return std::fs::write(path, crate::jxl::encode(image)?).map_err(ImageError::IoError);
+    }
+
+    let format: ImageFormat = match format {
+        Some(format) => format,
+        None => ImageFormat::from_path(path)?,
+    };
+
+    if matches!(format, ImageFormat::Jpeg | ImageFormat::Avif) {
+        return std::fs::write(path, encode_with_quality(image, format, quality)?).map_err(ImageError::IoError);
+    }
+    image.save_with_format(path, format)
+}
+
+pub struct FittedImage {
+    pub image: DynamicImage,
+    pub bytes: Vec<u8>,
+    pub colors: Option<u32>,
+    pub dithered: bool,
+}
+
+// Tries progressively fewer colors (dithered first, then flat, which compresses better)
+// until the encoded size fits. Returns the smallest attempt when nothing fits.
+pub fn fit_to_size(image: &DynamicImage, format: ImageFormat, max_bytes: usize, metric: DistanceMetric) -> ImageResult<(FittedImage, bool)> {
+    let original: Vec<u8> = encode(image, format)?;
+    if original.len() <= max_bytes {
+        return Ok((FittedImage { image: image.clone(), bytes: original, colors: None, dithered: false }, true));
+    }
+
+    let mut smallest: FittedImage = FittedImage { image: image.clone(), bytes: original, colors: None, dithered: false };
+    for colors in [256, 128, 64, 32, 16, 8, 4, 2] {
+        for dithered in [true, false] {
+            let candidate: DynamicImage = DynamicImage::ImageRgb8(quantize_colors(image, colors, dithered, metric));
+            let bytes: Vec<u8> = encode(&candidate, format)?;
+            let fits: bool = bytes.len() <= max_bytes;
+            if fits || bytes.len() < smallest.bytes.len() {
+                smallest = FittedImage { image: candidate, bytes, colors: Some(colors), dithered };
+            }
+            if fits {
+                return Ok((smallest, true));
+            }
+        }
+    }
+    Ok((smallest, false))
+}
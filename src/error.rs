@@ -0,0 +1,57 @@
+use std::fmt;
+use std::path::PathBuf;
+
+// Why a library call or a CLI run failed, so scripts and embedding code can tell failures apart
+// instead of reading stdout.
+#[derive(Debug)]
+pub enum Error {
+    Io { path: PathBuf, source: std::io::Error },
+    Decode { path: PathBuf, source: image::ImageError },
+    Encode { path: PathBuf, source: image::ImageError },
+    PaletteParse { path: PathBuf, source: serde_json::Error },
+    // A palette file in another format, such as GIMP .gpl, that could not be read.
+    InvalidPalette { path: PathBuf, message: String },
+    InvalidArgument(String),
+    // Failures the rest of the library reports as messages, e.g. from sinks and reports.
+    Operation(String),
+}
+
+impl Error {
+    // Process exit status of the CLI for this failure: 2 for bad arguments, 3 for files that
+    // cannot be read or written, 4 for undecodable images, 5 for failed encodes, 6 for broken
+    // palettes and 1 for everything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument(_) => 2,
+            Error::Io { .. } => 3,
+            Error::Decode { .. } => 4,
+            Error::Encode { .. } => 5,
+            Error::PaletteParse { .. } | Error::InvalidPalette { .. } => 6,
+            Error::Operation(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Decode { path, source } => write!(f, "Failed to load image {}: {}", path.display(), source),
+            Error::Encode { path, source } => write!(f, "Failed to save image {}: {}", path.display(), source),
+            Error::PaletteParse { path, source } => write!(f, "Invalid palette {}: {}", path.display(), source),
+            Error::InvalidPalette { path, message } => write!(f, "Invalid palette {}: {}", path.display(), message),
+            Error::InvalidArgument(message) | Error::Operation(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Decode { source, .. } | Error::Encode { source, .. } => Some(source),
+            Error::PaletteParse { source, .. } => Some(source),
+            Error::InvalidPalette { .. } | Error::InvalidArgument(_) | Error::Operation(_) => None,
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use image::{DynamicImage, GrayImage, Luma, Rgba, Rgba32FImage};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use crate::convolve::BorderPolicy;
+use crate::math;
+
+// Frequencies inside this fraction of Nyquist are image content, never halftone screens.
+const DESCREEN_PROTECTED_RADIUS: f32 = 0.15;
+// A frequency this many times stronger than its neighbourhood is treated as a screen peak.
+const DESCREEN_PEAK_RATIO: f32 = 4.0;
+
+struct Plane {
+    width: usize,
+    height: usize,
+    data: Vec<Complex<f32>>,
+}
+
+impl Plane {
+    fn transform(&mut self, inverse: bool) {
+        let mut planner: FftPlanner<f32> = FftPlanner::new();
+        let (row_fft, column_fft): (Arc<dyn Fft<f32>>, Arc<dyn Fft<f32>>) = if inverse {
+            (planner.plan_fft_inverse(self.width), planner.plan_fft_inverse(self.height))
+        } else {
+            (planner.plan_fft_forward(self.width), planner.plan_fft_forward(self.height))
+        };
+
+        row_fft.process(&mut self.data);
+        let mut column: Vec<Complex<f32>> = vec![Complex::new(0.0, 0.0); self.height];
+        for x in 0..self.width {
+            for (y, value) in column.iter_mut().enumerate() {
+                *value = self.data[y * self.width + x];
+            }
+            column_fft.process(&mut column);
+            for (y, value) in column.iter().enumerate() {
+                self.data[y * self.width + x] = *value;
+            }
+        }
+
+        if inverse {
+            let scale: f32 = 1.0 / (self.width * self.height) as f32;
+            self.data.iter_mut().for_each(|value| *value *= scale);
+        }
+    }
+
+    // Signed frequency of a row or column index in cycles per pixel.
+    fn frequency(index: usize, length: usize) -> f32 {
+        let signed: i64 = if index <= length / 2 { index as i64 } else { index as i64 - length as i64 };
+        signed as f32 / length as f32
+    }
+}
+
+// One plane per color channel, padded by `padding` pixels filled according to the border policy.
+fn channel_planes(image: &Rgba32FImage, padding: usize, border: BorderPolicy) -> Vec<Plane> {
+    let (width, height) = image.dimensions();
+    let padded_width: usize = width as usize + 2 * padding;
+    let padded_height: usize = height as usize + 2 * padding;
+    (0..3).map(|channel| {
+        let mut data: Vec<Complex<f32>> = Vec::with_capacity(padded_width * padded_height);
+        for y in 0..padded_height {
+            let sy: u32 = border.index(y as i64 - padding as i64, height) as u32;
+            for x in 0..padded_width {
+                let sx: u32 = border.index(x as i64 - padding as i64, width) as u32;
+                data.push(Complex::new(image.get_pixel(sx, sy)[channel], 0.0));
+            }
+        }
+        Plane { width: padded_width, height: padded_height, data }
+    }).collect()
+}
+
+fn assemble(image: &DynamicImage, source: &Rgba32FImage, planes: &[Plane], padding: usize) -> DynamicImage {
+    let (width, height) = source.dimensions();
+    let output: Rgba32FImage = Rgba32FImage::from_fn(width, height, |x, y| {
+        let index: usize = (y as usize + padding) * planes[0].width + x as usize + padding;
+        Rgba([
+            planes[0].data[index].re.clamp(0.0, 1.0),
+            planes[1].data[index].re.clamp(0.0, 1.0),
+            planes[2].data[index].re.clamp(0.0, 1.0),
+            source.get_pixel(x, y)[3],
+        ])
+    });
+    let output: DynamicImage = DynamicImage::ImageRgba32F(output);
+    if image.color().has_alpha() { output.to_rgba8().into() } else { output.to_rgb8().into() }
+}
+
+// Multiplies by the Gaussian's transfer function, so the cost does not grow with sigma.
+pub fn gaussian_blur_fft(image: &DynamicImage, sigma: f32, border: BorderPolicy) -> DynamicImage {
+    let source: Rgba32FImage = image.to_rgba32f();
+    // Clamped or mirrored padding wider than the image repeats what is already there, so it
+    // stops at the image size however large sigma gets.
+    let limit: usize = source.width().max(source.height()) as usize;
+    let padding: usize = if border == BorderPolicy::Wrap { 0 } else { ((sigma * 3.0).ceil() as usize).min(limit) };
+    let mut planes: Vec<Plane> = channel_planes(&source, padding, border);
+
+    for plane in planes.iter_mut() {
+        plane.transform(false);
+        for y in 0..plane.height {
+            let v: f32 = Plane::frequency(y, plane.height);
+            for x in 0..plane.width {
+                let u: f32 = Plane::frequency(x, plane.width);
+                plane.data[y * plane.width + x] *= math::exp(-2.0 * PI * PI * sigma * sigma * (u * u + v * v));
+            }
+        }
+        plane.transform(true);
+    }
+    assemble(image, &source, &planes, padding)
+}
+
+// Finds isolated peaks in the luminance spectrum (the regular dot pattern of a printed
+// halftone screen) and removes them from every channel.
+pub fn descreen(image: &DynamicImage) -> DynamicImage {
+    let source: Rgba32FImage = image.to_rgba32f();
+    let mut planes: Vec<Plane> = channel_planes(&source, 0, BorderPolicy::Clamp);
+    planes.iter_mut().for_each(|plane| plane.transform(false));
+    let (width, height) = (planes[0].width, planes[0].height);
+
+    let magnitude: Vec<f32> = (0..width * height)
+        .map(|index| (0.299 * planes[0].data[index] + 0.587 * planes[1].data[index] + 0.114 * planes[2].data[index]).norm())
+        .collect();
+
+    let mut notch: Vec<bool> = vec![false; width * height];
+    for y in 0..height {
+        let v: f32 = Plane::frequency(y, height) * 2.0;
+        for x in 0..width {
+            let u: f32 = Plane::frequency(x, width) * 2.0;
+            if (u * u + v * v).sqrt() < DESCREEN_PROTECTED_RADIUS {
+                continue;
+            }
+            let mut sum: f32 = 0.0;
+            let mut count: u32 = 0;
+            for dy in -3i64..=3 {
+                for dx in -3i64..=3 {
+                    if dx.abs() <= 1 && dy.abs() <= 1 {
+                        continue;
+                    }
+                    let nx: usize = (x as i64 + dx).rem_euclid(width as i64) as usize;
+                    let ny: usize = (y as i64 + dy).rem_euclid(height as i64) as usize;
+                    sum += magnitude[ny * width + nx];
+                    count += 1;
+                }
+            }
+            if magnitude[y * width + x] > DESCREEN_PEAK_RATIO * sum / count as f32 {
+                notch[y * width + x] = true;
+            }
+        }
+    }
+
+    // Peaks leak into their direct neighbours, so the notches are widened by one bin.
+    let mut widened: Vec<bool> = notch.clone();
+    for y in 0..height {
+        for x in 0..width {
+            if !notch[y * width + x] {
+                continue;
+            }
+            for dy in -1i64..=1 {
+                for dx in -1i64..=1 {
+                    let nx: usize = (x as i64 + dx).rem_euclid(width as i64) as usize;
+                    let ny: usize = (y as i64 + dy).rem_euclid(height as i64) as usize;
+                    widened[ny * width + nx] = true;
+                }
+            }
+        }
+    }
+
+    for plane in planes.iter_mut() {
+        for (value, &removed) in plane.data.iter_mut().zip(&widened) {
+            if removed {
+                *value = Complex::new(0.0, 0.0);
+            }
+        }
+        plane.transform(true);
+    }
+    assemble(image, &source, &planes, 0)
+}
+
+// Log magnitude of the luminance spectrum with the zero frequency in the center.
+pub fn spectrum(image: &DynamicImage) -> GrayImage {
+    let luma: GrayImage = image.to_luma8();
+    let (width, height) = luma.dimensions();
+    let mut plane: Plane = Plane {
+        width: width as usize,
+        height: height as usize,
+        data: luma.pixels().map(|pixel| Complex::new(pixel[0] as f32 / 255.0, 0.0)).collect(),
+    };
+    plane.transform(false);
+
+    let logs: Vec<f32> = plane.data.iter().map(|value| math::ln_1p(value.norm())).collect();
+    let max: f32 = logs.iter().cloned().fold(0.0, f32::max).max(f32::EPSILON);
+    GrayImage::from_fn(width, height, |x, y| {
+        let sx: usize = (x + width - width / 2) as usize % width as usize;
+        let sy: usize = (y + height - height / 2) as usize % height as usize;
+        Luma([(logs[sy * width as usize + sx] / max * 255.0).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convolve::{convolve, Kernel};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn fft_blur_matches_spatial_blur() {
+        let image: DynamicImage = RgbImage::from_fn(24, 20, |x, y| Rgb([if (x / 4 + y / 4) % 2 == 0 { 255 } else { 0 }, (x * 10) as u8, (y * 12) as u8])).into();
+        for border in [BorderPolicy::Clamp, BorderPolicy::Wrap, BorderPolicy::Mirror] {
+            let fast: Vec<u8> = gaussian_blur_fft(&image, 2.0, border).to_rgb8().into_raw();
+            let spatial: Vec<u8> = convolve(&image, &Kernel::gaussian(2.0), border).to_rgb8().into_raw();
+            assert!(fast.iter().zip(&spatial).all(|(a, b)| a.abs_diff(*b) <= 2), "{:?}", border);
+        }
+    }
+
+    #[test]
+    fn huge_sigma_flattens_the_image() {
+        let image: DynamicImage = RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, 128])).into();
+        for border in [BorderPolicy::Clamp, BorderPolicy::Wrap, BorderPolicy::Mirror] {
+            let blurred: RgbImage = gaussian_blur_fft(&image, 1e9, border).to_rgb8();
+            assert_eq!(blurred.dimensions(), (64, 48));
+            let first: Rgb<u8> = *blurred.get_pixel(0, 0);
+            assert!(blurred.pixels().all(|pixel| pixel.0.iter().zip(first.0).all(|(a, b)| a.abs_diff(b) <= 1)), "{:?}", border);
+        }
+    }
+}
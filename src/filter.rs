@@ -1,14 +1,247 @@
-use image::{imageops, DynamicImage, Pixel, GenericImageView, GrayImage, ImageBuffer, Luma, Rgb, RgbImage };
+use image::{imageops, DynamicImage, Pixel, GenericImageView, GrayImage, ImageBuffer, Luma, Rgb, Rgb32FImage, RgbImage, Rgba, Rgba32FImage, RgbaImage };
 use std::f32;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use crate::blend::BlendMode;
+use crate::channels::{ChannelSet, Subsampling};
+use crate::custom::CustomFilter;
+use crate::error::Error;
+use crate::library::PaletteSource;
 use crate::palette::*;
+use crate::quantize::octree_palette;
+use crate::remap::Remap;
+use crate::color::{mix, ColorSpace};
+use crate::convolve::Kernel;
+use crate::tone::ToneMapper;
+use crate::portable::{bayer_dither, luma, neighbour, threshold};
+use crate::diffusion::{diffuse_gray, diffuse_palette, DiffusionKernel, ATKINSON, FLOYD_STEINBERG};
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum FilterOperation {
     Palette,
-    Pixelate(u32),
+    Pixelate(Length),
     FloydSteinberg,
+    Atkinson,
+    ErrorDiffusion(&'static DiffusionKernel),
+    Bayer(u8),
+    BlueNoise(u8),
     Reverse,
+    ReverseChannels([bool; 4]),
+    Quantize(u32, bool),
+    Resize(Length, Length),
+    Duotone(Color, Color),
+    Crop(Length, Length, Length, Length),
+    Trim,
+    Canvas(CanvasSize),
+    Pad(Length),
+    Blur(f32),
+    Sharpen,
+    Edge,
+    Emboss,
+    Convolve(Kernel),
+    Descreen,
+    Spectrum,
+    BoxBlur(Length),
+    AdaptiveThreshold(Length, i32),
+    LocalContrast(Length, f32),
+    Clahe(u32, f32),
+    Dehaze(f32),
+    Tonemap(ToneMapper, f32),
+    Exposure(f32),
+    Highlights(f32),
+    Shadows(f32),
+    Clarity(f32),
+    Split(PathBuf, ChannelSet),
+    Combine(Vec<PathBuf>),
+    Anaglyph(Vec<PathBuf>),
+    Depth3d(PathBuf, f32),
+    ChromaSubsample(Subsampling),
+    Jpegify(u8, u32),
+    PaletteDownscale(Length, Length),
+    FloydTwoColor(Color, Color),
+    SwapPalette(PaletteSource, PaletteSource),
+    Custom(CustomFilter),
+    BlendWith(PathBuf, BlendMode, f32),
+    Synthesize(u32, u32, Option<u32>),
+    SeamCarve(Length, Length),
+    Warp([(f32, f32); 4]),
+    Affine([f32; 6]),
+    Lens(f32, f32, bool),
+    Rotate(f32, Option<Color>, bool),
+    Remap(Remap),
+}
+
+// A size in pixels, or a percentage of the shorter image side resolved per image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl Length {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.strip_suffix('%') {
+            Some(percent) => match percent.parse::<f32>() {
+                Ok(percent) if percent > 0.0 && percent.is_finite() => Some(Length::Percent(percent)),
+                _ => None,
+            },
+            None => value.parse::<u32>().ok().map(Length::Pixels),
+        }
+    }
+
+    pub fn pixels(&self, width: u32, height: u32) -> u32 {
+        self.of(width.min(height))
+    }
+
+    // Resolves a percentage against one axis, e.g. the image width for a crop x offset.
+    pub fn of(&self, reference: u32) -> u32 {
+        match *self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => ((reference as f32 * percent / 100.0).round() as u32).max(1),
+        }
+    }
+
+    fn scaled(&self, factor: f32) -> Length {
+        match *self {
+            Length::Pixels(0) => Length::Pixels(0),
+            Length::Pixels(pixels) => Length::Pixels(((pixels as f32 * factor).round() as u32).max(1)),
+            percent => percent,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CanvasSize {
+    Exact(Length, Length),
+    // The smallest canvas of this aspect ratio that holds the whole image.
+    Aspect(u32, u32),
+}
+
+impl CanvasSize {
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            CanvasSize::Exact(canvas_width, canvas_height) => (canvas_width.of(width), canvas_height.of(height)),
+            CanvasSize::Aspect(ratio_width, ratio_height) => {
+                let wide: u64 = width as u64 * ratio_height as u64;
+                let tall: u64 = height as u64 * ratio_width as u64;
+                if wide >= tall {
+                    (width, (wide as f64 / ratio_width as f64).round() as u32)
+                } else {
+                    ((tall as f64 / ratio_height as f64).round() as u32, height)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Premultiplied,
+    Straight,
+}
+
+impl FilterOperation {
+    // Adapts size parameters when the pipeline runs on a resized copy of the image.
+    pub fn scaled(&self, factor: f32) -> FilterOperation {
+        match *self {
+            FilterOperation::Resize(width, height) => FilterOperation::Resize(width.scaled(factor), height.scaled(factor)),
+            FilterOperation::PaletteDownscale(width, height) => FilterOperation::PaletteDownscale(width.scaled(factor), height.scaled(factor)),
+            FilterOperation::SeamCarve(width, height) => FilterOperation::SeamCarve(width.scaled(factor), height.scaled(factor)),
+            FilterOperation::Crop(x, y, width, height) => FilterOperation::Crop(
+                x.scaled(factor), y.scaled(factor), width.scaled(factor), height.scaled(factor),
+            ),
+            FilterOperation::Canvas(CanvasSize::Exact(width, height)) => {
+                FilterOperation::Canvas(CanvasSize::Exact(width.scaled(factor), height.scaled(factor)))
+            },
+            FilterOperation::Pad(size) => FilterOperation::Pad(size.scaled(factor)),
+            FilterOperation::Warp(corners) => FilterOperation::Warp(corners.map(|(x, y)| (x * factor, y * factor))),
+            FilterOperation::Affine([a, b, c, d, e, f]) => FilterOperation::Affine([a, b, c * factor, d, e, f * factor]),
+            FilterOperation::Remap(Remap::Roll(dx, dy)) => {
+                FilterOperation::Remap(Remap::Roll((dx as f32 * factor).round() as i32, (dy as f32 * factor).round() as i32))
+            },
+            _ => self.scaled_parameters(factor),
+        }
+    }
+
+    // Scales effect parameters (block sizes and the like) but not output geometry, so a recipe
+    // tuned on one resolution looks the same on another.
+    pub fn scaled_parameters(&self, factor: f32) -> FilterOperation {
+        match *self {
+            FilterOperation::Pixelate(size) => FilterOperation::Pixelate(size.scaled(factor)),
+            FilterOperation::Blur(sigma) => FilterOperation::Blur(sigma * factor),
+            FilterOperation::BoxBlur(radius) => FilterOperation::BoxBlur(radius.scaled(factor)),
+            FilterOperation::AdaptiveThreshold(radius, offset) => FilterOperation::AdaptiveThreshold(radius.scaled(factor), offset),
+            FilterOperation::LocalContrast(radius, amount) => FilterOperation::LocalContrast(radius.scaled(factor), amount),
+            FilterOperation::Remap(Remap::Wave(amplitude, wavelength, vertical)) => {
+                FilterOperation::Remap(Remap::Wave(amplitude * factor, wavelength * factor, vertical))
+            },
+            _ => self.clone(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterOperation::Palette => "palette",
+            FilterOperation::Pixelate(_) => "pixelate",
+            FilterOperation::FloydSteinberg => "floyd",
+            FilterOperation::Atkinson => "atkinson",
+            FilterOperation::ErrorDiffusion(_) => "dither",
+            FilterOperation::Bayer(_) => "bayer",
+            FilterOperation::BlueNoise(_) => "bluenoise",
+            FilterOperation::Reverse => "reverse",
+            FilterOperation::ReverseChannels(_) => "reverse",
+            FilterOperation::Quantize(..) => "quantize",
+            FilterOperation::Resize(..) => "resize",
+            FilterOperation::Duotone(..) => "duotone",
+            FilterOperation::Crop(..) => "crop",
+            FilterOperation::Trim => "trim",
+            FilterOperation::Canvas(_) => "canvas",
+            FilterOperation::Pad(_) => "pad",
+            FilterOperation::Blur(_) => "blur",
+            FilterOperation::Sharpen => "sharpen",
+            FilterOperation::Edge => "edge",
+            FilterOperation::Emboss => "emboss",
+            FilterOperation::Convolve(_) => "kernel",
+            FilterOperation::Descreen => "descreen",
+            FilterOperation::Spectrum => "spectrum",
+            FilterOperation::BoxBlur(_) => "boxblur",
+            FilterOperation::AdaptiveThreshold(..) => "athreshold",
+            FilterOperation::LocalContrast(..) => "localcontrast",
+            FilterOperation::Clahe(..) => "clahe",
+            FilterOperation::Dehaze(_) => "dehaze",
+            FilterOperation::Tonemap(..) => "tonemap",
+            FilterOperation::Exposure(_) => "exposure",
+            FilterOperation::Highlights(_) => "highlights",
+            FilterOperation::Shadows(_) => "shadows",
+            FilterOperation::Clarity(_) => "clarity",
+            FilterOperation::Split(..) => "split",
+            FilterOperation::Combine(_) => "combine",
+            FilterOperation::Anaglyph(_) => "anaglyph",
+            FilterOperation::Depth3d(..) => "depth3d",
+            FilterOperation::ChromaSubsample(_) => "chromasub",
+            FilterOperation::Jpegify(..) => "jpegify",
+            FilterOperation::PaletteDownscale(..) => "palresize",
+            FilterOperation::FloydTwoColor(..) => "floyd2",
+            FilterOperation::SwapPalette(..) => "swap-palette",
+            FilterOperation::Custom(ref filter) => filter.0.name(),
+            FilterOperation::BlendWith(..) => "blendwith",
+            FilterOperation::Synthesize(..) => "synth",
+            FilterOperation::SeamCarve(..) => "seamcarve",
+            FilterOperation::Warp(_) => "warp",
+            FilterOperation::Affine(_) => "affine",
+            FilterOperation::Lens(..) => "lens",
+            FilterOperation::Rotate(..) => "rotate",
+            FilterOperation::Remap(mapping) => mapping.name(),
+        }
+    }
+
+    // Operations that only move or resample values, never reinterpret them as colors, so they
+    // are safe on normal maps, roughness maps and other data textures.
+    pub fn is_data_safe(&self) -> bool {
+        matches!(self, FilterOperation::Resize(..) | FilterOperation::Crop(..) | FilterOperation::Canvas(_) | FilterOperation::Pad(_)
+            | FilterOperation::Split(..) | FilterOperation::Combine(_))
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -37,52 +270,69 @@ impl Color {
 //     (r + g + b).sqrt()
 // }
 
-pub fn save<P, Container>(output_path: &str, img: ImageBuffer<P, Container>) -> () 
+pub fn save<P, Container>(output_path: &str, img: ImageBuffer<P, Container>) -> Result<(), Error>
 where 
     P: Pixel<Subpixel = u8> + 'static + image::PixelWithColorType,
     Container: std::ops::Deref<Target = [u8]>,
 {
-    img.save(output_path).expect("Failed to save image!");
+    img.save(output_path).map_err(|source| Error::Encode { path: PathBuf::from(output_path), source })?;
     println!("The image is saved: {}", output_path);
+    Ok(())
 }
 
 
-pub fn apply_palette(input_image: &DynamicImage, palette_path: &str) -> RgbImage {
-    let (width, height) = input_image.dimensions();
+// image::open, which with the jxl feature also reads JPEG XL.
+pub fn decode_file<P: AsRef<std::path::Path>>(path: P) -> image::ImageResult<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    return crate::jxl::open(path);
+    #[cfg(not(feature = "jxl"))]
+    image::open(path)
+}
 
-    let palette = match Palette::from_file(palette_path) {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("Error loading palette from {}: {}", palette_path, e);
-            return fallback_palette(input_image);
-        }
-    };
+// image::load_from_memory, which with the jxl feature also reads JPEG XL.
+pub fn decode_bytes(bytes: &[u8]) -> image::ImageResult<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    return crate::jxl::load_from_memory(bytes);
+    #[cfg(not(feature = "jxl"))]
+    image::load_from_memory(bytes)
+}
 
-    println!("Palette: {}\n{}\n{:?}", palette.name, palette.description, palette.colors);
-    
+// Opens an image file; files that cannot be read fail with Error::Io and files that do not
+// decode with Error::Decode.
+pub fn open_image<P: AsRef<std::path::Path>>(path: P) -> Result<DynamicImage, Error> {
+    let path: PathBuf = path.as_ref().to_path_buf();
+    match decode_file(&path) {
+        Ok(image) => Ok(image),
+        Err(image::ImageError::IoError(source)) => Err(Error::Io { path, source }),
+        Err(source) => Err(Error::Decode { path, source }),
+    }
+}
+
+pub fn apply_palette(input_image: &DynamicImage, palette_path: &str, metric: DistanceMetric) -> Result<RgbImage, Error> {
+    let palette: Palette = Palette::from_file(palette_path)?;
+    apply_loaded_palette(input_image, &palette, metric)
+}
+
+pub fn apply_loaded_palette(input_image: &DynamicImage, palette: &Palette, metric: DistanceMetric) -> Result<RgbImage, Error> {
     let palette_colors: Vec<Rgb<u8>> = palette.get_colors();
 
     if palette_colors.is_empty() {
-        eprintln!("Warning: Palette has no colors, using fallback");
-        return fallback_palette(input_image);
+        return Err(Error::Operation(format!("palette {} has no colors", palette.name)));
     }
 
     let colors: Vec<Color> = palette_colors.iter()
-        .map(|rgb| Color::from_rgb(rgb))
+        .map(Color::from_rgb)
         .collect();
 
-    set_active_palette(&colors);
-
-    ImageBuffer::from_fn(width, height, |x, y| {
-        let pixel: image::Rgba<u8> = input_image.get_pixel(x, y);
-        let input_color: Color = Color { r: pixel[0], g: pixel[1], b: pixel[2] };
-        let new_color: Color = get_nearest_color(input_color);
-        Rgb([new_color.r, new_color.g, new_color.b])
-    })
+    Ok(PaletteMatcher::new(&colors, metric).map_image(input_image))
 }
 
-fn quantize(value: u8) -> u8 {
-    if value < 128 { 0 } else { 255 }
+// Paints a 1-bit image with two colors: black pixels become `dark`, white ones `light`.
+pub fn two_color(image: &GrayImage, dark: Color, light: Color) -> RgbImage {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let color: Color = if image.get_pixel(x, y)[0] < 128 { dark } else { light };
+        Rgb([color.r, color.g, color.b])
+    })
 }
 
 pub fn grayscale(image: &RgbImage) -> GrayImage {
@@ -91,8 +341,7 @@ pub fn grayscale(image: &RgbImage) -> GrayImage {
 
     for (x, y, pixel) in image.enumerate_pixels() {
         let Rgb([r, g, b]) = *pixel;
-        let gray_value: u8 = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8;
-        gray_image.put_pixel(x, y, Luma([gray_value]));
+        gray_image.put_pixel(x, y, Luma([luma(r, g, b)]));
     }
     gray_image
 }
@@ -108,45 +357,106 @@ pub fn reverse(image: &DynamicImage) -> RgbImage {
     })
 }
 
+// Black and white error diffusion with any kernel; -floyd, -atkinson and -dither all run this.
+pub fn error_diffusion_dithering(image: &GrayImage, kernel: &DiffusionKernel, serpentine: bool) -> GrayImage {
+    diffuse_gray(image, kernel, serpentine, threshold)
+}
+
+pub fn apply_error_diffusion_dithering(image: &DynamicImage, kernel: &DiffusionKernel, serpentine: bool) -> GrayImage {
+    error_diffusion_dithering(&grayscale(&image.to_rgb8()), kernel, serpentine)
+}
+
 pub fn floyd_steinberg_dithering(image: &GrayImage) -> GrayImage {
-    let (width, height) = image.dimensions();
-    let mut img: ImageBuffer<Luma<u8>, Vec<u8>> = image.clone();
-    for y in 0..height {
-        for x in 0..width {
-            let old_pixel: u8 = img.get_pixel(x, y)[0];
-            let new_pixel: u8 = quantize(old_pixel);
-            let error: i16 = old_pixel as i16 - new_pixel as i16;
-
-            img.put_pixel(x, y, Luma([new_pixel]));
-
-            if x + 1 < width {
-                let right_pixel: i16 = img.get_pixel(x + 1, y)[0] as i16;
-                img.put_pixel(x + 1, y, Luma([(right_pixel + (error * 7 / 16) as i16).clamp(0, 255) as u8]));
-            }
+    error_diffusion_dithering(image, &FLOYD_STEINBERG, false)
+}
 
-            if y + 1 < height {
-                if x > 0 {
-                    let bottom_left_pixel: i16 = img.get_pixel(x - 1, y + 1)[0] as i16;
-                    img.put_pixel(x - 1, y + 1, Luma([(bottom_left_pixel + (error * 3 / 16) as i16).clamp(0, 255) as u8]));
-                }
+pub fn apply_floyd_steinberg_dithering(image: &DynamicImage) -> GrayImage {
+    let rgb_img: ImageBuffer<Rgb<u8>, Vec<u8>> = image.clone().into_rgb8();
+    let grayscaled_img: ImageBuffer<Luma<u8>, Vec<u8>> = grayscale(&rgb_img);
+    floyd_steinberg_dithering(&grayscaled_img)
+}
 
-                let bottom_pixel: i16 = img.get_pixel(x, y + 1)[0] as i16;
-                img.put_pixel(x, y + 1, Luma([(bottom_pixel + (error * 5 / 16) as i16).clamp(0, 255) as u8]));
+pub fn atkinson_dithering(image: &GrayImage) -> GrayImage {
+    error_diffusion_dithering(image, &ATKINSON, false)
+}
 
-                if x + 1 < width {
-                    let bottom_right_pixel = img.get_pixel(x + 1, y + 1)[0] as i16;
-                    img.put_pixel(x + 1, y + 1, Luma([(bottom_right_pixel + (error * 1 / 16) as i16).clamp(0, 255) as u8]));
-                }
-            }
-        }
+pub fn apply_atkinson_dithering(image: &DynamicImage) -> GrayImage {
+    atkinson_dithering(&grayscale(&image.to_rgb8()))
+}
+
+pub fn apply_bayer_dithering(image: &DynamicImage, levels: u8) -> GrayImage {
+    let mut gray: GrayImage = grayscale(&image.to_rgb8());
+    let width: usize = gray.width() as usize;
+    bayer_dither(&mut gray, width, levels);
+    gray
+}
+
+// Error diffusion is serial along a row, but Floyd-Steinberg pushes error at most one pixel
+// back on the row below, so row y can start on pixel x as soon as row y - 1 is two pixels
+// past it. Rows are dealt to the threads in turn and each follows the row above at that lag,
+// so error crosses every row and the result is the same as `floyd_steinberg_dithering`.
+pub fn parallel_floyd_steinberg_dithering(image: &GrayImage, threads: usize) -> GrayImage {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let threads: usize = threads.clamp(1, height.max(1));
+    if threads == 1 || width == 0 {
+        return floyd_steinberg_dithering(image);
     }
-    img
-} 
+    let divisor: i32 = FLOYD_STEINBERG.divisor;
+    let errors: Vec<AtomicI32> = (0..width * height).map(|_| AtomicI32::new(0)).collect();
+    // Pixels of each row done so far, published after that pixel's error has been pushed
+    let progress: Vec<AtomicUsize> = (0..height).map(|_| AtomicUsize::new(0)).collect();
 
-pub fn apply_floyd_steinberg_dithering(image: &DynamicImage) -> GrayImage {
+    let rows: Vec<Vec<(usize, Vec<u8>)>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|first| {
+                let (errors, progress) = (&errors, &progress);
+                scope.spawn(move || {
+                    (first..height).step_by(threads).map(|y| {
+                        let mut row: Vec<u8> = Vec::with_capacity(width);
+                        let mut ready: usize = if y == 0 { width } else { 0 };
+                        for x in 0..width {
+                            let needed: usize = (x + 2).min(width);
+                            while ready < needed {
+                                ready = progress[y - 1].load(Ordering::Acquire);
+                                if ready < needed {
+                                    std::thread::yield_now();
+                                }
+                            }
+                            let index: usize = y * width + x;
+                            let scaled: i32 = image.as_raw()[index] as i32 * divisor + errors[index].load(Ordering::Relaxed);
+                            let wanted: u8 = (scaled + divisor / 2).div_euclid(divisor).clamp(0, 255) as u8;
+                            let new_value: u8 = threshold(wanted);
+                            row.push(new_value);
+
+                            let error: i32 = wanted as i32 - new_value as i32;
+                            for &(dx, dy, weight) in FLOYD_STEINBERG.taps {
+                                if let Some(target) = neighbour(x, y, dx, dy, width, height) {
+                                    errors[target].fetch_add(error * weight, Ordering::Relaxed);
+                                }
+                            }
+                            progress[y].store(x + 1, Ordering::Release);
+                        }
+                        (y, row)
+                    }).collect()
+                })
+            })
+            .collect();
+        handles.into_iter()
+            .map(|handle| handle.join().expect("Dithering thread panicked"))
+            .collect()
+    });
+
+    let mut pixels: Vec<u8> = vec![0; width * height];
+    for (y, row) in rows.into_iter().flatten() {
+        pixels[y * width..(y + 1) * width].copy_from_slice(&row);
+    }
+    GrayImage::from_raw(width as u32, height as u32, pixels).expect("Buffer matches the image size")
+}
+
+pub fn apply_parallel_floyd_steinberg_dithering(image: &DynamicImage, threads: usize) -> GrayImage {
     let rgb_img: ImageBuffer<Rgb<u8>, Vec<u8>> = image.clone().into_rgb8();
     let grayscaled_img: ImageBuffer<Luma<u8>, Vec<u8>> = grayscale(&rgb_img);
-    floyd_steinberg_dithering(&grayscaled_img)
+    parallel_floyd_steinberg_dithering(&grayscaled_img, threads)
 }
 
 pub fn pixelate(image: &DynamicImage, pixel_size: u32) -> RgbImage {
@@ -157,4 +467,183 @@ pub fn pixelate(image: &DynamicImage, pixel_size: u32) -> RgbImage {
     let small_height: u32 = height / pixel_size;
     let small_img: ImageBuffer<Rgb<u8>, Vec<u8>> = imageops::resize(&rgb_img, small_width, small_height, imageops::FilterType::Nearest);
     imageops::resize(&small_img, width, height, imageops::FilterType::Nearest)
-}
\ No newline at end of file
+}
+
+pub fn palette_dithering(image: &RgbImage, palette: &[Color], metric: DistanceMetric) -> RgbImage {
+    palette_diffusion_dithering(image, palette, metric, &FLOYD_STEINBERG, false)
+}
+
+// Color error diffusion: every pixel becomes the nearest palette color and the error of each
+// channel is passed on with the kernel.
+pub fn palette_diffusion_dithering(image: &RgbImage, palette: &[Color], metric: DistanceMetric, kernel: &DiffusionKernel, serpentine: bool) -> RgbImage {
+    let matcher: PaletteMatcher = PaletteMatcher::new(palette, metric);
+    diffuse_palette(image, kernel, serpentine, &matcher)
+}
+
+pub fn map_to_palette(image: &DynamicImage, colors: &[Color], dither: bool, metric: DistanceMetric) -> RgbImage {
+    if dither {
+        return palette_dithering(&image.to_rgb8(), colors, metric);
+    }
+    PaletteMatcher::new(colors, metric).map_image(image)
+}
+
+pub fn quantize_colors(image: &DynamicImage, max_colors: u32, dither: bool, metric: DistanceMetric) -> RgbImage {
+    let colors: Vec<Color> = octree_palette(&image.to_rgb8(), max_colors as usize);
+    map_to_palette(image, &colors, dither, metric)
+}
+
+pub fn duotone(image: &DynamicImage, dark: Color, light: Color, space: ColorSpace) -> RgbImage {
+    let gradient: Vec<Color> = (0..=255u32)
+        .map(|level| mix(dark, light, level as f32 / 255.0, space))
+        .collect();
+    let gray_img: ImageBuffer<Luma<u8>, Vec<u8>> = grayscale(&image.to_rgb8());
+
+    let (width, height) = gray_img.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let color: Color = gradient[gray_img.get_pixel(x, y)[0] as usize];
+        Rgb([color.r, color.g, color.b])
+    })
+}
+
+// Filtering straight alpha lets the color of fully transparent pixels bleed into visible
+// edges (dark fringes), so colors are weighted by alpha while resampling.
+pub fn resize(image: &DynamicImage, width: u32, height: u32, alpha_mode: AlphaMode) -> DynamicImage {
+    let filter: imageops::FilterType = imageops::FilterType::Triangle;
+    if !image.color().has_alpha() {
+        let rgb_img: Rgb32FImage = image.to_rgb32f();
+        return DynamicImage::ImageRgb32F(imageops::resize(&rgb_img, width, height, filter)).to_rgb8().into();
+    }
+
+    let mut rgba_img: Rgba32FImage = image.to_rgba32f();
+    if alpha_mode == AlphaMode::Straight {
+        return DynamicImage::ImageRgba32F(imageops::resize(&rgba_img, width, height, filter)).to_rgba8().into();
+    }
+
+    for pixel in rgba_img.pixels_mut() {
+        let alpha: f32 = pixel[3];
+        *pixel = Rgba([pixel[0] * alpha, pixel[1] * alpha, pixel[2] * alpha, alpha]);
+    }
+    let mut resized: Rgba32FImage = imageops::resize(&rgba_img, width, height, filter);
+    for pixel in resized.pixels_mut() {
+        let alpha: f32 = pixel[3].clamp(0.0, 1.0);
+        *pixel = if alpha > 0.0 {
+            Rgba([pixel[0] / alpha, pixel[1] / alpha, pixel[2] / alpha, alpha])
+        } else {
+            Rgba([0.0, 0.0, 0.0, 0.0])
+        };
+    }
+    DynamicImage::ImageRgba32F(resized).to_rgba8().into()
+}
+
+// Resamples the stored values as they are: same pixel format and bit depth, and alpha is
+// treated as an independent channel rather than coverage.
+pub fn resize_data(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    image.resize_exact(width, height, imageops::FilterType::Triangle)
+}
+
+// Shrinks by giving every output pixel the palette color that covers most of its source block,
+// instead of averaging the block and quantizing the blend. Pixel art keeps its hard edges and
+// never picks up in-between colors. Ties go to the earlier palette color.
+pub fn coverage_downscale(image: &DynamicImage, width: u32, height: u32, matcher: &PaletteMatcher) -> RgbImage {
+    let (source_width, source_height) = image.dimensions();
+    let (width, height) = (width.max(1), height.max(1));
+    let indices: Vec<usize> = image.to_rgb8().pixels()
+        .map(|pixel| matcher.nearest_index(Color::from_rgb(pixel)).unwrap_or(0))
+        .collect();
+    let colors: &[Color] = matcher.colors();
+    if colors.is_empty() {
+        return resize(image, width, height, AlphaMode::Premultiplied).to_rgb8();
+    }
+
+    // Block edges of output pixel i along an axis, at least one source pixel wide
+    let span = |i: u32, size: u32, source_size: u32| {
+        let start: u32 = (i as u64 * source_size as u64 / size as u64) as u32;
+        let end: u32 = ((i as u64 + 1) * source_size as u64 / size as u64) as u32;
+        (start.min(source_size - 1), end.max(start + 1).min(source_size))
+    };
+    let mut counts: Vec<u32> = vec![0; colors.len()];
+    RgbImage::from_fn(width, height, |x, y| {
+        counts.iter_mut().for_each(|count| *count = 0);
+        let (x_start, x_end) = span(x, width, source_width);
+        let (y_start, y_end) = span(y, height, source_height);
+        for source_y in y_start..y_end {
+            for source_x in x_start..x_end {
+                counts[indices[(source_y * source_width + source_x) as usize]] += 1;
+            }
+        }
+        let best: usize = counts.iter().enumerate()
+            .fold(0, |best, (index, &count)| if count > counts[best] { index } else { best });
+        Rgb([colors[best].r, colors[best].g, colors[best].b])
+    })
+}
+
+// The crop rectangle is clamped to the image and keeps at least one pixel.
+pub fn crop(image: &DynamicImage, x: u32, y: u32, width: u32, height: u32) -> DynamicImage {
+    let (image_width, image_height) = image.dimensions();
+    let x: u32 = x.min(image_width - 1);
+    let y: u32 = y.min(image_height - 1);
+    image.crop_imm(x, y, width.clamp(1, image_width - x), height.clamp(1, image_height - y))
+}
+
+// Centers the image on a transparent canvas; a smaller canvas crops the edges evenly.
+pub fn canvas(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (image_width, image_height) = image.dimensions();
+    let mut output: RgbaImage = RgbaImage::new(width.max(1), height.max(1));
+    let offset_x: i64 = (width as i64 - image_width as i64) / 2;
+    let offset_y: i64 = (height as i64 - image_height as i64) / 2;
+    imageops::overlay(&mut output, &image.to_rgba8(), offset_x, offset_y);
+    DynamicImage::ImageRgba8(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiplied_resize_has_no_dark_fringe() {
+        let source: RgbaImage = ImageBuffer::from_fn(8, 8, |x, _| {
+            if x < 4 { Rgba([0, 0, 0, 0]) } else { Rgba([255, 255, 255, 255]) }
+        });
+        let image: DynamicImage = DynamicImage::ImageRgba8(source);
+
+        let premultiplied: RgbaImage = resize(&image, 3, 3, AlphaMode::Premultiplied).to_rgba8();
+        let straight: RgbaImage = resize(&image, 3, 3, AlphaMode::Straight).to_rgba8();
+
+        let edge: &Rgba<u8> = premultiplied.get_pixel(1, 1);
+        assert!(edge[3] > 0 && edge[3] < 255);
+        assert_eq!(edge[0], 255);
+        assert!(straight.get_pixel(1, 1)[0] < 255);
+    }
+
+    #[test]
+    fn parallel_dither_matches_the_serial_one() {
+        let gradient: GrayImage = ImageBuffer::from_fn(97, 61, |x, y| Luma([((x * 3 + y * 2) % 256) as u8]));
+        let serial: GrayImage = floyd_steinberg_dithering(&gradient);
+        for threads in [2, 3, 8] {
+            let parallel: GrayImage = parallel_floyd_steinberg_dithering(&gradient, threads);
+            // Rows are interleaved across the threads, yet every pixel is identical to the serial output
+            assert_eq!(parallel, serial, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn coverage_downscale_keeps_majority_colors() {
+        let palette: Vec<Color> = vec![Color::from_rgb_components(0, 0, 0), Color::from_rgb_components(255, 0, 0), Color::from_rgb_components(255, 255, 255)];
+        let matcher: PaletteMatcher = PaletteMatcher::new(&palette, DistanceMetric::Rgb);
+        // 2x2 blocks with three red pixels and one black, or all white
+        let source: RgbImage = ImageBuffer::from_fn(4, 2, |x, y| match (x, y) {
+            (0, 0) => Rgb([0, 0, 0]),
+            (0..=1, _) => Rgb([250, 5, 5]),
+            _ => Rgb([255, 255, 255]),
+        });
+        let small: RgbImage = coverage_downscale(&DynamicImage::ImageRgb8(source), 2, 1, &matcher);
+        assert_eq!(small.into_raw(), vec![255, 0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn canvas_aspect_holds_whole_image() {
+        assert_eq!(CanvasSize::Aspect(16, 9).dimensions(1000, 1000), (1778, 1000));
+        assert_eq!(CanvasSize::Aspect(16, 9).dimensions(1920, 500), (1920, 1080));
+        assert_eq!(CanvasSize::Exact(Length::Percent(50.0), Length::Pixels(7)).dimensions(300, 100), (150, 7));
+    }
+}
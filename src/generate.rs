@@ -0,0 +1,99 @@
+use image::{GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
+use std::f32::consts::PI;
+use crate::math;
+
+// Studio-range levels (16-235) as used by broadcast test cards.
+const BLACK: u8 = 16;
+const BAR: u8 = 180;
+const WHITE: u8 = 235;
+
+fn segment(position: u32, total: u32, fractions: &[f32]) -> usize {
+    let t: f32 = (position as f32 + 0.5) / total as f32;
+    let mut edge: f32 = 0.0;
+    for (index, fraction) in fractions.iter().enumerate() {
+        edge += fraction;
+        if t < edge {
+            return index;
+        }
+    }
+    fractions.len() - 1
+}
+
+pub fn smpte_bars(width: u32, height: u32) -> RgbImage {
+    let bar: f32 = 1.0 / 7.0;
+    let top: [Rgb<u8>; 7] = [
+        Rgb([BAR, BAR, BAR]),
+        Rgb([BAR, BAR, BLACK]),
+        Rgb([BLACK, BAR, BAR]),
+        Rgb([BLACK, BAR, BLACK]),
+        Rgb([BAR, BLACK, BAR]),
+        Rgb([BAR, BLACK, BLACK]),
+        Rgb([BLACK, BLACK, BAR]),
+    ];
+    let middle: [Rgb<u8>; 7] = [
+        Rgb([BLACK, BLACK, BAR]),
+        Rgb([BLACK, BLACK, BLACK]),
+        Rgb([BAR, BLACK, BAR]),
+        Rgb([BLACK, BLACK, BLACK]),
+        Rgb([BLACK, BAR, BAR]),
+        Rgb([BLACK, BLACK, BLACK]),
+        Rgb([BAR, BAR, BAR]),
+    ];
+    // -I, white, +Q, black, then the PLUGE steps just below, at and above black
+    let bottom: [Rgb<u8>; 8] = [
+        Rgb([0, 33, 76]),
+        Rgb([WHITE, WHITE, WHITE]),
+        Rgb([50, 0, 106]),
+        Rgb([BLACK, BLACK, BLACK]),
+        Rgb([7, 7, 7]),
+        Rgb([BLACK, BLACK, BLACK]),
+        Rgb([25, 25, 25]),
+        Rgb([BLACK, BLACK, BLACK]),
+    ];
+    let bottom_widths: [f32; 8] = [
+        bar * 1.25, bar * 1.25, bar * 1.25, bar * 1.25,
+        bar / 3.0, bar / 3.0, bar / 3.0, bar,
+    ];
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        match segment(y, height, &[0.67, 0.08, 0.25]) {
+            0 => top[segment(x, width, &[bar; 7])],
+            1 => middle[segment(x, width, &[bar; 7])],
+            _ => bottom[segment(x, width, &bottom_widths)],
+        }
+    })
+}
+
+// Gray, red, green and blue ramps stacked as horizontal bands.
+pub fn gradient_ramp(width: u32, height: u32) -> RgbImage {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let level: u8 = if width > 1 { (x * 255 / (width - 1)) as u8 } else { 0 };
+        match segment(y, height, &[0.25; 4]) {
+            0 => Rgb([level, level, level]),
+            1 => Rgb([level, 0, 0]),
+            2 => Rgb([0, level, 0]),
+            _ => Rgb([0, 0, level]),
+        }
+    })
+}
+
+// Circular zone plate whose frequency rises from the center to the Nyquist limit at the edge.
+pub fn zone_plate(width: u32, height: u32) -> GrayImage {
+    let center_x: f32 = width as f32 / 2.0;
+    let center_y: f32 = height as f32 / 2.0;
+    let k: f32 = PI / width.max(height).max(1) as f32;
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let dx: f32 = x as f32 + 0.5 - center_x;
+        let dy: f32 = y as f32 + 0.5 - center_y;
+        let value: f32 = 0.5 + 0.5 * math::cos(k * (dx * dx + dy * dy));
+        Luma([(value * 255.0).round() as u8])
+    })
+}
+
+pub fn checkerboard(width: u32, height: u32, cell: u32) -> GrayImage {
+    let cell: u32 = cell.max(1);
+    ImageBuffer::from_fn(width, height, |x, y| {
+        if (x / cell + y / cell).is_multiple_of(2) { Luma([255]) } else { Luma([0]) }
+    })
+}
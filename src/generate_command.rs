@@ -0,0 +1,65 @@
+use filter::error::Error;
+use filter::generate::{checkerboard, gradient_ramp, smpte_bars, zone_plate};
+use image::DynamicImage;
+use crate::parse_size;
+
+fn print_usage() {
+    println!("Usage: cargo r generate [pattern] [options] output.png");
+    println!("Patterns:");
+    println!("  bars: SMPTE-style color bars");
+    println!("  ramp: Gray, red, green and blue gradient ramps");
+    println!("  zoneplate: Circular zone plate up to the Nyquist frequency");
+    println!("  checker [--cell=N]: Checkerboard with N pixel cells (default 8)");
+    println!("Options:");
+    println!("  --size=WxH: Output size (default 640x480)");
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let pattern: &str = match args.first() {
+        Some(pattern) => pattern,
+        None => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let mut size: (u32, u32) = (640, 480);
+    let mut cell: u32 = 8;
+    let mut output_path: Option<&String> = None;
+
+    for arg in &args[1..] {
+        if let Some(value) = arg.strip_prefix("--size=") {
+            size = parse_size(value).ok_or_else(|| Error::InvalidArgument(format!("Invalid size: {}", value)))?;
+        } else if let Some(value) = arg.strip_prefix("--cell=") {
+            match value.parse::<u32>() {
+                Ok(parsed) if parsed > 0 => cell = parsed,
+                _ => return Err(Error::InvalidArgument(format!("Invalid cell size: {}", value))),
+            }
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            output_path = Some(arg);
+        }
+    }
+
+    let output_path: &String = match output_path {
+        Some(path) => path,
+        None => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let (width, height) = size;
+    let image: DynamicImage = match pattern {
+        "bars" => DynamicImage::ImageRgb8(smpte_bars(width, height)),
+        "ramp" => DynamicImage::ImageRgb8(gradient_ramp(width, height)),
+        "zoneplate" => DynamicImage::ImageLuma8(zone_plate(width, height)),
+        "checker" => DynamicImage::ImageLuma8(checkerboard(width, height, cell)),
+        _ => return Err(Error::InvalidArgument(format!("Unknown pattern: {}", pattern))),
+    };
+
+    image.save(output_path).map_err(|source| Error::Encode { path: output_path.into(), source })?;
+    println!("The image is saved: {}", output_path);
+    Ok(())
+}
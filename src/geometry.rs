@@ -0,0 +1,225 @@
+use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
+use crate::filter::Color;
+use crate::math::{hypot_f64, sin_cos_f64};
+
+// Bilinear sample at continuous coordinates, with pixel centers at i + 0.5. Points outside the
+// image are transparent; colors are weighted by alpha so transparent pixels do not bleed in.
+fn sample_bilinear(image: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    if !(0.0..width).contains(&x) || !(0.0..height).contains(&y) {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let (fx, fy) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (fx.floor(), fy.floor());
+    let (tx, ty) = (fx - x0, fy - y0);
+    let clamp = |value: f64, size: u32| -> u32 { value.clamp(0.0, (size - 1) as f64) as u32 };
+
+    let mut sum: [f64; 4] = [0.0; 4];
+    for (dx, dy, weight) in [(0.0, 0.0, (1.0 - tx) * (1.0 - ty)), (1.0, 0.0, tx * (1.0 - ty)), (0.0, 1.0, (1.0 - tx) * ty), (1.0, 1.0, tx * ty)] {
+        let pixel: &Rgba<u8> = image.get_pixel(clamp(x0 + dx, image.width()), clamp(y0 + dy, image.height()));
+        let alpha: f64 = pixel[3] as f64 * weight;
+        for channel in 0..3 {
+            sum[channel] += pixel[channel] as f64 * alpha;
+        }
+        sum[3] += alpha;
+    }
+    if sum[3] <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([(sum[0] / sum[3]).round() as u8, (sum[1] / sum[3]).round() as u8, (sum[2] / sum[3]).round() as u8, sum[3].round().min(255.0) as u8])
+}
+
+// Fills a width x height image by sampling the source where `source_of` maps each output pixel
+// center. Without alpha in the input, uncovered areas become black.
+pub(crate) fn resample<F: Fn(f64, f64) -> (f64, f64)>(image: &DynamicImage, width: u32, height: u32, source_of: F) -> DynamicImage {
+    let source: RgbaImage = image.to_rgba8();
+    let output: RgbaImage = RgbaImage::from_fn(width, height, |x, y| {
+        let (sx, sy) = source_of(x as f64 + 0.5, y as f64 + 0.5);
+        sample_bilinear(&source, sx, sy)
+    });
+    if image.color().has_alpha() { output.into() } else { DynamicImage::ImageRgba8(output).to_rgb8().into() }
+}
+
+// Solves the 8 x 8 system in the augmented matrix by Gaussian elimination with partial pivoting.
+fn solve(mut rows: [[f64; 9]; 8]) -> Option<[f64; 8]> {
+    for column in 0..8 {
+        let pivot: usize = (column..8).max_by(|&a, &b| rows[a][column].abs().total_cmp(&rows[b][column].abs()))?;
+        if rows[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        rows.swap(column, pivot);
+        for row in 0..8 {
+            if row != column {
+                let factor: f64 = rows[row][column] / rows[column][column];
+                let pivot_row: [f64; 9] = rows[column];
+                for (value, pivot_value) in rows[row].iter_mut().zip(pivot_row) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    Some(std::array::from_fn(|index| rows[index][8] / rows[index][index]))
+}
+
+// Projective map (x, y) -> ((h0 x + h1 y + h2) / w, (h3 x + h4 y + h5) / w) with
+// w = h6 x + h7 y + 1.
+pub struct Homography([f64; 8]);
+
+impl Homography {
+    // The map taking the corners of a width x height rectangle, clockwise from the top left, to
+    // `corners`. None when three corners are on one line.
+    pub fn from_rectangle(width: f64, height: f64, corners: &[(f64, f64); 4]) -> Option<Homography> {
+        let rectangle: [(f64, f64); 4] = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+        let mut rows: [[f64; 9]; 8] = [[0.0; 9]; 8];
+        for (index, (&(u, v), &(x, y))) in rectangle.iter().zip(corners).enumerate() {
+            rows[2 * index] = [u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x];
+            rows[2 * index + 1] = [0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y];
+        }
+        solve(rows).map(Homography)
+    }
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let h: &[f64; 8] = &self.0;
+        let w: f64 = h[6] * x + h[7] * y + 1.0;
+        ((h[0] * x + h[1] * y + h[2]) / w, (h[3] * x + h[4] * y + h[5]) / w)
+    }
+}
+
+fn distance((ax, ay): (f64, f64), (bx, by): (f64, f64)) -> f64 {
+    ((ax - bx).powi(2) + (ay - by).powi(2)).sqrt()
+}
+
+// Perspective rectification: maps the quadrilateral with corners top left, top right, bottom
+// right, bottom left onto an upright rectangle as wide as its longer horizontal edge and as tall
+// as its longer vertical edge, as for a photographed page or painting.
+pub fn warp_quad(image: &DynamicImage, corners: &[(f32, f32); 4]) -> Result<DynamicImage, String> {
+    let corners: [(f64, f64); 4] = corners.map(|(x, y)| (x as f64, y as f64));
+    let width: f64 = distance(corners[0], corners[1]).max(distance(corners[3], corners[2])).round().max(1.0);
+    let height: f64 = distance(corners[0], corners[3]).max(distance(corners[1], corners[2])).round().max(1.0);
+    let homography: Homography = Homography::from_rectangle(width, height, &corners)
+        .ok_or_else(|| "the warp corners must form a quadrilateral, not a line".to_string())?;
+    Ok(resample(image, width as u32, height as u32, |x, y| homography.apply(x, y)))
+}
+
+// Affine transform [a, b, c, d, e, f] moving each source point (x, y) to
+// (a x + b y + c, d x + e y + f), on a canvas of the input size.
+pub fn affine(image: &DynamicImage, matrix: &[f32; 6]) -> Result<DynamicImage, String> {
+    let [a, b, c, d, e, f] = matrix.map(|value| value as f64);
+    let determinant: f64 = a * e - b * d;
+    if determinant.abs() < 1e-9 {
+        return Err(format!("the affine matrix {:?} cannot be inverted", matrix));
+    }
+    Ok(resample(image, image.width(), image.height(), |x, y| {
+        let (x, y) = (x - c, y - f);
+        ((e * x - b * y) / determinant, (a * y - d * x) / determinant)
+    }))
+}
+
+// Brown's radial model: a lens with coefficients k1, k2 images the point at radius r (in half
+// diagonals from the center) at r (1 + k1 r^2 + k2 r^4); negative k1 is barrel distortion.
+fn distorted_radius(radius: f64, k1: f64, k2: f64) -> f64 {
+    radius * (1.0 + k1 * radius.powi(2) + k2 * radius.powi(4))
+}
+
+// The undistorted radius the lens images at `radius`, by Newton's method. None where the model
+// folds back on itself, far outside a real lens's field.
+fn undistorted_radius(radius: f64, k1: f64, k2: f64) -> Option<f64> {
+    let mut estimate: f64 = radius;
+    for _ in 0..20 {
+        let slope: f64 = 1.0 + 3.0 * k1 * estimate.powi(2) + 5.0 * k2 * estimate.powi(4);
+        if slope <= 0.0 {
+            return None;
+        }
+        estimate -= (distorted_radius(estimate, k1, k2) - radius) / slope;
+    }
+    ((distorted_radius(estimate, k1, k2) - radius).abs() < 1e-6).then_some(estimate)
+}
+
+// Removes the radial distortion of a lens with coefficients k1, k2, or with `add` applies it, so
+// negative k1 bulges the image like a fisheye and positive k1 pinches it.
+pub fn lens(image: &DynamicImage, k1: f32, k2: f32, add: bool) -> DynamicImage {
+    let (k1, k2) = (k1 as f64, k2 as f64);
+    let (center_x, center_y) = (image.width() as f64 / 2.0, image.height() as f64 / 2.0);
+    let half_diagonal: f64 = hypot_f64(center_x, center_y);
+    resample(image, image.width(), image.height(), |x, y| {
+        let (dx, dy) = ((x - center_x) / half_diagonal, (y - center_y) / half_diagonal);
+        let radius: f64 = hypot_f64(dx, dy);
+        let source_radius: Option<f64> = if add { undistorted_radius(radius, k1, k2) } else { Some(distorted_radius(radius, k1, k2)) };
+        match source_radius {
+            Some(_) if radius == 0.0 => (x, y),
+            Some(source_radius) => {
+                let factor: f64 = source_radius / radius;
+                (center_x + dx * factor * half_diagonal, center_y + dy * factor * half_diagonal)
+            },
+            None => (-1.0, -1.0),
+        }
+    })
+}
+
+// Rotates clockwise by `degrees` about the image center. With `expand` the canvas grows to hold
+// the whole rotated image, otherwise it keeps the input size and the corners are cut off. The
+// uncovered area is `background`, or transparent (black without alpha in the input) if none.
+pub fn rotate(image: &DynamicImage, degrees: f32, background: Option<Color>, expand: bool) -> DynamicImage {
+    let (sin, cos) = sin_cos_f64((degrees as f64).to_radians());
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let (output_width, output_height) = if expand {
+        // Less a little, so that 90 degrees does not gain a column to rounding error
+        ((width * cos.abs() + height * sin.abs() - 1e-6).ceil().max(1.0), (width * sin.abs() + height * cos.abs() - 1e-6).ceil().max(1.0))
+    } else {
+        (width, height)
+    };
+    let source_of = |x: f64, y: f64| {
+        let (dx, dy) = (x - output_width / 2.0, y - output_height / 2.0);
+        (width / 2.0 + cos * dx + sin * dy, height / 2.0 - sin * dx + cos * dy)
+    };
+    let background: Color = match background {
+        Some(background) => background,
+        None => return resample(image, output_width as u32, output_height as u32, source_of),
+    };
+    let rotated: RgbaImage = resample(&DynamicImage::ImageRgba8(image.to_rgba8()), output_width as u32, output_height as u32, source_of).to_rgba8();
+    let over = |value: u8, under: u8, alpha: u8| ((value as u32 * alpha as u32 + under as u32 * (255 - alpha as u32) + 127) / 255) as u8;
+    RgbImage::from_fn(rotated.width(), rotated.height(), |x, y| {
+        let pixel: &Rgba<u8> = rotated.get_pixel(x, y);
+        Rgb([over(pixel[0], background.r, pixel[3]), over(pixel[1], background.g, pixel[3]), over(pixel[2], background.b, pixel[3])])
+    }).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warps_map_the_expected_pixels() {
+        let image: DynamicImage = RgbImage::from_fn(16, 10, |x, y| Rgb([(x * 16) as u8, (y * 25) as u8, 90])).into();
+        let identity: DynamicImage = warp_quad(&image, &[(0.0, 0.0), (16.0, 0.0), (16.0, 10.0), (0.0, 10.0)]).unwrap();
+        assert_eq!(identity.to_rgb8(), image.to_rgb8());
+
+        // A quad around the right half is stretched to fill the output
+        let half: RgbImage = warp_quad(&image, &[(8.0, 0.0), (16.0, 0.0), (16.0, 10.0), (8.0, 10.0)]).unwrap().to_rgb8();
+        assert_eq!(half.dimensions(), (8, 10));
+        assert_eq!(half.get_pixel(0, 3), image.to_rgb8().get_pixel(8, 3));
+
+        let shifted: RgbImage = affine(&image, &[1.0, 0.0, 3.0, 0.0, 1.0, 2.0]).unwrap().to_rgb8();
+        assert_eq!(shifted.get_pixel(5, 4), image.to_rgb8().get_pixel(2, 2));
+        assert_eq!(shifted.get_pixel(1, 1), &Rgb([0, 0, 0]));
+        assert!(affine(&image, &[1.0, 2.0, 0.0, 2.0, 4.0, 0.0]).is_err());
+        assert!(warp_quad(&image, &[(0.0, 0.0), (5.0, 0.0), (10.0, 0.0), (15.0, 0.0)]).is_err());
+
+        // Adding a distortion and correcting it returns the original away from the edges
+        assert_eq!(lens(&image, 0.0, 0.0, false).to_rgb8(), image.to_rgb8());
+        let round_trip: RgbImage = lens(&lens(&image, 0.2, 0.05, true), 0.2, 0.05, false).to_rgb8();
+        let original: RgbImage = image.to_rgb8();
+        for (x, y) in [(8, 5), (5, 4), (11, 6)] {
+            let (a, b) = (round_trip.get_pixel(x, y), original.get_pixel(x, y));
+            assert!(a.0.iter().zip(b.0).all(|(&a, b)| a.abs_diff(b) <= 12), "{:?} {:?}", a, b);
+        }
+
+        // A quarter turn clockwise moves the top left corner to the top right
+        let turned: RgbImage = rotate(&image, 90.0, None, true).to_rgb8();
+        assert_eq!(turned.dimensions(), (10, 16));
+        assert_eq!(turned.get_pixel(9, 0), original.get_pixel(0, 0));
+        assert_eq!(rotate(&image, 30.0, None, false).to_rgb8().dimensions(), (16, 10));
+        let filled: RgbImage = rotate(&image, 45.0, Some(Color::from_rgb_components(0, 255, 0)), true).to_rgb8();
+        assert_eq!(filled.get_pixel(0, 0), &Rgb([0, 255, 0]));
+    }
+}
@@ -0,0 +1,718 @@
+use filter::error::Error;
+use crate::registry::{self, OperationSpec};
+
+pub struct OperationDoc {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub flags: &'static [&'static str],
+    pub summary: &'static str,
+    pub details: &'static str,
+    pub parameters: &'static [(&'static str, &'static str)],
+    pub examples: &'static [&'static str],
+}
+
+pub const OPERATIONS: &[OperationDoc] = &[
+    OperationDoc {
+        name: "palette",
+        usage: "-pal[=NAME]",
+        flags: &["-pal", "-pal="],
+        summary: "Map every pixel to the nearest color of a palette",
+        details: "Without a name the palette.json in the palette directory is used. A name is looked up on\n\
+                  IMAGE_RUST_PALETTE_PATH, then in the installed library, then in the palette directory;\n\
+                  a path to a palette file works too. The built-in palettes (websafe, xterm256, gameboy, nes,\n\
+                  pico8, cga, c64 and zxspectrum) are used when no file of that name exists; cargo r palette\n\
+                  list shows them with the installed ones. --distance selects how 'nearest' is measured.\n\
+                  --palette-mode=luminance only snaps brightness to the palette's lightness levels and keeps\n\
+                  hue and saturation; luminance-dither also dithers the brightness.",
+        parameters: &[("NAME", "Installed palette name or palette file path, JSON or GIMP .gpl (optional)")],
+        examples: &["cargo r -pal input.png output.png", "cargo r -pal=gameboy --distance=oklab input.png output.png", "cargo r -pal=gameboy --palette-mode=luminance-dither input.png output.png"],
+    },
+    OperationDoc {
+        name: "pixpal",
+        usage: "-pixpal",
+        flags: &["-pixpal"],
+        summary: "Pixelate with 8 pixel blocks, then apply the palette",
+        details: "Shorthand for -pix=8 -pal.",
+        parameters: &[],
+        examples: &["cargo r -pixpal input.png output.png"],
+    },
+    OperationDoc {
+        name: "pixelate",
+        usage: "-pix[=N|N%]",
+        flags: &["-pix", "-pix="],
+        summary: "Render the image as N x N pixel blocks",
+        details: "The image is shrunk by N with nearest-neighbour sampling and scaled back up, so each\n\
+                  block takes the color of one source pixel. -pix=0 is ignored. N may be a percentage\n\
+                  of the shorter image side, and --auto-scale-params scales pixel sizes with resolution.",
+        parameters: &[("N", "Block size in pixels or N% of the shorter side (default 8)")],
+        examples: &["cargo r -pix input.png output.png", "cargo r -pix=4 -pal input.png output.png", "cargo r -pix=1.5% input.png output.png"],
+    },
+    OperationDoc {
+        name: "floyd",
+        usage: "-floyd",
+        flags: &["-floyd"],
+        summary: "Floyd-Steinberg dither to black and white",
+        details: "The image is converted to grayscale and the quantization error is diffused to the\n\
+                  neighbouring pixels. --parallel-dither runs the rows on all cores with the same result, and\n\
+                  --dither-serpentine scans every other row right to left to avoid diagonal worm patterns.\n\
+                  Right after -pal the dither is in color instead: each pixel becomes the nearest palette\n\
+                  color and the red, green and blue errors are diffused. -atkinson and -dither do the same.",
+        parameters: &[],
+        examples: &["cargo r -floyd input.png output.png", "cargo r -pix=2 -floyd --parallel-dither input.png output.png", "cargo r -pal=gameboy -floyd input.png output.png"],
+    },
+    OperationDoc {
+        name: "atkinson",
+        usage: "-atkinson",
+        flags: &["-atkinson"],
+        summary: "Atkinson dither to black and white, as on the classic Mac",
+        details: "Like -floyd, but only 6/8 of the quantization error is passed on, to six neighbours\n\
+                  spread over two rows. Midtones dither normally while near-black and near-white areas\n\
+                  become solid, which gives the lighter, crisper look of early Macintosh graphics.",
+        parameters: &[],
+        examples: &["cargo r -atkinson input.png output.png", "cargo r -resize=512x342 -atkinson photo.png mac.png"],
+    },
+    OperationDoc {
+        name: "dither",
+        usage: "-dither=KERNEL",
+        flags: &["-dither="],
+        summary: "Error diffusion dither to black and white with a chosen kernel",
+        details: "Like -floyd, but the quantization error is spread with KERNEL. The wide kernels pass it\n\
+                  to more neighbours over two or three rows, which avoids the diagonal worm patterns of\n\
+                  Floyd-Steinberg in flat areas at the cost of slightly softer edges. Burkes and the\n\
+                  two-row Sierra are faster than the three-row kernels and look much the same.",
+        parameters: &[
+            ("KERNEL", "floyd-steinberg, atkinson, jjn (Jarvis-Judice-Ninke), stucki, sierra, sierra2 or burkes"),
+        ],
+        examples: &["cargo r -dither=stucki input.png output.png", "cargo r -pix=2 -dither=jjn input.png output.png"],
+    },
+    OperationDoc {
+        name: "swap-palette",
+        usage: "-swap-palette=FROM,TO",
+        flags: &["-swap-palette="],
+        summary: "Recolor indexed art by swapping one palette for another",
+        details: "FROM and TO are palettes with the same number of colors, looked up like -pal=NAME.\n\
+                  Pixels of the n-th FROM color become the n-th TO color; other colors go by their\n\
+                  nearest FROM entry (see --distance). Transparency is kept, so it suits sprite sheets.",
+        parameters: &[("FROM", "Palette the art uses now"), ("TO", "Palette to switch to, same length")],
+        examples: &["cargo r -swap-palette=red-team.json,blue-team.json sprite.png blue.png"],
+    },
+    OperationDoc {
+        name: "floyd2",
+        usage: "-floyd2=DARK,LIGHT",
+        flags: &["-floyd2="],
+        summary: "Floyd-Steinberg dither to two chosen colors",
+        details: "Dithers like -floyd, then paints the black dots DARK and the white ones LIGHT, e.g.\n\
+                  Game Boy greens or ink on paper. Colors are #rgb, #rrggbb, rgb(r, g, b) or CSS names.",
+        parameters: &[("DARK", "Color of the dark dots"), ("LIGHT", "Color of the light dots")],
+        examples: &["cargo r -floyd2=#0f380f,#9bbc0f input.png output.png", "cargo r -floyd2=navy,ivory input.png output.png"],
+    },
+    OperationDoc {
+        name: "bayer",
+        usage: "-bayer[=LEVELS]",
+        flags: &["-bayer", "-bayer="],
+        summary: "Ordered 4x4 Bayer dither to gray levels",
+        details: "The grayscale image is compared against a repeating 4x4 threshold pattern, giving the\n\
+                  regular cross-hatch look of old hardware. Every pixel is independent, so unlike -floyd\n\
+                  it does not smear when the image is cut into tiles or streamed.",
+        parameters: &[("LEVELS", "Gray levels in the output, 2 to 255 (default 2)")],
+        examples: &["cargo r -bayer input.png output.png", "cargo r -bayer=4 input.png output.png"],
+    },
+    OperationDoc {
+        name: "bluenoise",
+        usage: "-bluenoise[=LEVELS]",
+        flags: &["-bluenoise", "-bluenoise="],
+        summary: "Blue noise dither to gray levels",
+        details: "Like -bayer, each pixel is compared against a tiled threshold mask, but the 64x64 mask is\n\
+                  made with the void-and-cluster method, so the dots are evenly spread without any visible\n\
+                  pattern. It looks as fine-grained as -floyd without its worms, and as with -bayer every\n\
+                  pixel is independent.",
+        parameters: &[("LEVELS", "Gray levels in the output, 2 to 255 (default 2)")],
+        examples: &["cargo r -bluenoise input.png output.png", "cargo r -bluenoise=4 -pal input.png output.png"],
+    },
+    OperationDoc {
+        name: "reverse",
+        usage: "-rev[=CHANNELS]",
+        flags: &["-rev", "-rev="],
+        summary: "Invert every color channel, or only some of them",
+        details: "Each channel value v becomes 255 - v. Without CHANNELS the red, green and blue channels\n\
+                  are inverted and alpha is dropped. With CHANNELS only those are inverted and alpha is\n\
+                  kept, so -rev=a turns a mask inside out and -rev=rb swaps a green cast for a magenta one.",
+        parameters: &[("CHANNELS", "letters from r, g, b and a, e.g. a, rb or rgba")],
+        examples: &["cargo r -rev input.png output.png", "cargo r -rev=a mask.png inverted_mask.png"],
+    },
+    OperationDoc {
+        name: "quantize",
+        usage: "-quant=N[,dither]",
+        flags: &["-quant="],
+        summary: "Reduce to N colors derived from the image",
+        details: "An octree palette of at most N colors is built from the image and every pixel is mapped\n\
+                  to it, optionally with error diffusion. --lock-palette-from reuses one palette across runs,\n\
+                  and `palette extract --method=octree` saves the same palette as JSON.",
+        parameters: &[("N", "Maximum number of colors"), ("dither", "Diffuse the quantization error (optional)")],
+        examples: &["cargo r -quant=16 input.png output.png", "cargo r -quant=8,dither input.png output.png"],
+    },
+    OperationDoc {
+        name: "resize",
+        usage: "-resize=WxH|P%",
+        flags: &["-resize="],
+        summary: "Resample to W x H pixels, keeping transparency",
+        details: "W and H are pixels or percentages of the current width and height; a single P% scales\n\
+                  both sides. Uses a triangle filter. With --alpha=premultiplied (the default) transparent pixels do not\n\
+                  bleed dark fringes into their neighbours; --alpha=straight filters channels independently.",
+        parameters: &[("W", "Output width in pixels or percent"), ("H", "Output height in pixels or percent")],
+        examples: &["cargo r -resize=320x240 input.png output.png", "cargo r -resize=50% input.png output.png"],
+    },
+    OperationDoc {
+        name: "palresize",
+        usage: "-palresize=WxH|P%",
+        flags: &["-palresize="],
+        summary: "Shrink pixel art by picking the most common palette color per block",
+        details: "Every output pixel covers a block of source pixels. Each source pixel is matched to the\n\
+                  palette (-pal=NAME selects it, --distance how), and the color matched most often wins.\n\
+                  Unlike -resize followed by -pal, no blended colors appear and one-pixel outlines survive\n\
+                  as long as they hold the majority of their block.",
+        parameters: &[("W", "Output width in pixels or percent"), ("H", "Output height in pixels or percent")],
+        examples: &["cargo r -palresize=50% sprite.png small.png", "cargo r -pal=websafe -palresize=25% sprite.png small.png"],
+    },
+    OperationDoc {
+        name: "seamcarve",
+        usage: "-seamcarve=WxH|P%",
+        flags: &["-seamcarve="],
+        summary: "Content-aware resize by removing or duplicating low-energy seams",
+        details: "Repeatedly removes the connected column (then row) of pixels with the least Sobel edge\n\
+                  energy, so flat background shrinks while people, text and other detail keep their\n\
+                  shape. Growing duplicates the cheapest seams instead. Slow on large images: every seam\n\
+                  recomputes the energy map.",
+        parameters: &[("W", "Output width in pixels or percent"), ("H", "Output height in pixels or percent")],
+        examples: &["cargo r -seamcarve=640x480 beach.png beach_small.png", "cargo r -seamcarve=120%x100% skyline.png wide.png"],
+    },
+    OperationDoc {
+        name: "warp",
+        usage: "-warp=quad(X1,Y1,X2,Y2,X3,Y3,X4,Y4)",
+        flags: &["-warp="],
+        summary: "Rectify a quadrilateral into an upright rectangle (perspective correction)",
+        details: "The four corners are the top left, top right, bottom right and bottom left of the\n\
+                  area to keep, in pixels, e.g. the corners of a photographed drawing. The output is as\n\
+                  wide as the longer of the top and bottom edges and as tall as the longer side edge,\n\
+                  sampled bilinearly. The quad( ) wrapper is optional.",
+        parameters: &[("X1,Y1 ... X4,Y4", "Corners clockwise from the top left, in pixels")],
+        examples: &["cargo r -warp=quad(112,80,905,60,940,700,90,730) scan.jpg flat.png", "cargo r -warp=quad(112,80,905,60,940,700,90,730) -pal scan.jpg art.png"],
+    },
+    OperationDoc {
+        name: "affine",
+        usage: "-affine=A,B,C,D,E,F",
+        flags: &["-affine="],
+        summary: "Move, rotate, scale or shear the image with an affine matrix",
+        details: "Each point (x, y) moves to (A x + B y + C, D x + E y + F), in pixels from the top left.\n\
+                  The canvas keeps the input size; uncovered areas are transparent, or black without\n\
+                  alpha. Sampling is bilinear. A matrix that collapses the image (A E = B D) is skipped.",
+        parameters: &[("A,B,D,E", "Linear part: scale, rotation and shear"), ("C,F", "Horizontal and vertical offset in pixels")],
+        examples: &["cargo r -affine=1,0.2,0,0,1,0 input.png sheared.png", "cargo r -affine=0.5,0,100,0,0.5,80 input.png inset.png"],
+    },
+    OperationDoc {
+        name: "lens",
+        usage: "-lens=K1[,K2][,add]",
+        flags: &["-lens="],
+        summary: "Correct or add radial lens distortion",
+        details: "Uses the radial model of Brown: a point at distance r from the center (in half\n\
+                  diagonals) is imaged at r (1 + K1 r^2 + K2 r^4). By default the distortion of a lens\n\
+                  with these coefficients is removed, so a barrel-distorted photo (negative K1) gets\n\
+                  straight lines again. With add the distortion is applied instead: negative K1 bulges\n\
+                  the image like a fisheye, positive K1 pinches it.",
+        parameters: &[("K1", "Second-order coefficient, typically -0.3 to 0.3"), ("K2", "Fourth-order coefficient (default 0)"), ("add", "Apply the distortion instead of removing it")],
+        examples: &["cargo r -lens=-0.18,0.02 wide_angle.jpg straight.png", "cargo r -lens=-0.25,add portrait.png fisheye.png"],
+    },
+    OperationDoc {
+        name: "rotate",
+        usage: "-rotate=DEGREES[,COLOR][,crop]",
+        flags: &["-rotate="],
+        summary: "Rotate by any angle with bilinear resampling",
+        details: "Positive angles turn the image clockwise about its center, negative ones counterclockwise.\n\
+                  The canvas grows to hold the whole rotated image; with crop it keeps the input size and\n\
+                  the corners are cut off. The uncovered area is filled with COLOR, or left transparent\n\
+                  (black for images without alpha) when no color is given.",
+        parameters: &[("DEGREES", "Angle, e.g. 90, -1.5 or 30"), ("COLOR", "Background as #hex, rgb(r,g,b) or a CSS name"), ("crop", "Keep the input size")],
+        examples: &["cargo r -rotate=90 input.png output.png", "cargo r -rotate=-1.8,white,crop scan.png straight.png"],
+    },
+    OperationDoc {
+        name: "polar",
+        usage: "-polar",
+        flags: &["-polar"],
+        summary: "Unroll the image around its center into polar coordinates",
+        details: "The largest circle around the center is unrolled into a rectangle of the same size: the\n\
+                  x axis is the angle, clockwise from the top, and the y axis the distance from the center.\n\
+                  Rings become rows and spokes become columns. -unpolar rolls it back up.",
+        parameters: &[],
+        examples: &["cargo r -polar dial.png unrolled.png", "cargo r -polar -blur=2 -unpolar input.png radial_blur.png"],
+    },
+    OperationDoc {
+        name: "unpolar",
+        usage: "-unpolar",
+        flags: &["-unpolar"],
+        summary: "Roll the image up into a disc around the center",
+        details: "The inverse of -polar: each row becomes a ring, the top row at the center and the bottom\n\
+                  row at the edge of the largest centered circle, and the columns run clockwise from the top.\n\
+                  Outside the circle is transparent (black without alpha).",
+        parameters: &[],
+        examples: &["cargo r -unpolar panorama.png little_planet.png"],
+    },
+    OperationDoc {
+        name: "swirl",
+        usage: "-swirl=DEGREES",
+        flags: &["-swirl="],
+        summary: "Twist the center of the image like a whirlpool",
+        details: "Pixels inside the largest centered circle are turned about the center, by DEGREES at the\n\
+                  center and less toward the edge of the circle, where the turn reaches zero. Positive angles\n\
+                  turn clockwise. Outside the circle the image is unchanged.",
+        parameters: &[("DEGREES", "Turn at the center, e.g. 90 or -270")],
+        examples: &["cargo r -swirl=180 input.png output.png"],
+    },
+    OperationDoc {
+        name: "spherize",
+        usage: "-spherize=AMOUNT",
+        flags: &["-spherize="],
+        summary: "Bulge or pinch the center of the image",
+        details: "Inside the largest centered circle the distance from the center is remapped so that\n\
+                  positive amounts magnify the middle as if the image were wrapped on a ball and negative\n\
+                  amounts pull it inward. The edge of the circle and everything outside stay in place.",
+        parameters: &[("AMOUNT", "More than -1 and at most 1; 0.5 is a clear bulge, -0.5 a pinch")],
+        examples: &["cargo r -spherize=0.5 input.png output.png", "cargo r -spherize=-0.4 input.png output.png"],
+    },
+    OperationDoc {
+        name: "kaleido",
+        usage: "-kaleido=SEGMENTS",
+        flags: &["-kaleido="],
+        summary: "Kaleidoscope: repeat a wedge of the image around the center",
+        details: "The wedge of 360/SEGMENTS degrees clockwise from the top of the image is repeated around\n\
+                  the center, every other copy mirrored, like the mirrors of a kaleidoscope. The wedge is\n\
+                  scaled so the output corners still come from inside the image. An even number of\n\
+                  segments makes every seam a mirror line; with an odd number one seam is a hard edge.",
+        parameters: &[("SEGMENTS", "Number of copies, 2 to 64, e.g. 6 or 8")],
+        examples: &["cargo r -kaleido=8 flowers.png kaleidoscope.png", "cargo r -swirl=90 -kaleido=6 input.png output.png"],
+    },
+    OperationDoc {
+        name: "mirror-tile",
+        usage: "-mirror-tile=COLUMNSxROWS",
+        flags: &["-mirror-tile="],
+        summary: "Tile shrunken copies of the image, mirrored so they meet seamlessly",
+        details: "The image is shrunk into each cell of a COLUMNS x ROWS grid, keeping the output size.\n\
+                  Every other column is flipped horizontally and every other row vertically, so each copy\n\
+                  meets its neighbours edge to edge, which makes seamless patterns from any photo.",
+        parameters: &[("COLUMNS", "Cells across"), ("ROWS", "Cells down")],
+        examples: &["cargo r -mirror-tile=2x2 input.png output.png", "cargo r -mirror-tile=4x4 -kaleido=4 input.png output.png"],
+    },
+    OperationDoc {
+        name: "wave",
+        usage: "-wave=AMPLITUDE,WAVELENGTH[,x|y]",
+        flags: &["-wave="],
+        summary: "Shift each row (or column) along a sine wave",
+        details: "Row y moves sideways by AMPLITUDE * sin(2 pi y / WAVELENGTH) pixels; with y the columns\n\
+                  move up and down instead. Pixels pushed past one edge come back in at the other, so the\n\
+                  image size and content are kept. Stepping the wave through frames makes simple animations.",
+        parameters: &[
+            ("AMPLITUDE", "Largest shift in pixels"),
+            ("WAVELENGTH", "Rows (or columns) per full wave"),
+            ("x|y", "x shifts rows sideways (default), y shifts columns up and down"),
+        ],
+        examples: &["cargo r -wave=4,32 input.png output.png", "cargo r -wave=2,6,y -pix=2 input.png output.png"],
+    },
+    OperationDoc {
+        name: "roll",
+        usage: "-roll=DX,DY",
+        flags: &["-roll="],
+        summary: "Translate the image, wrapping around the edges",
+        details: "Everything moves DX pixels right and DY pixels down (negative values go left and up),\n\
+                  and what leaves one edge reappears at the opposite one. Rolling by half the size\n\
+                  brings the edges of a tile into the middle to check that it repeats seamlessly.",
+        parameters: &[("DX", "Pixels to the right"), ("DY", "Pixels down")],
+        examples: &["cargo r -roll=10,-5 input.png output.png", "cargo r -roll=32,32 tile.png check.png"],
+    },
+    OperationDoc {
+        name: "crop",
+        usage: "-crop=X,Y,W,H",
+        flags: &["-crop="],
+        summary: "Keep a rectangle of the image",
+        details: "Each value is pixels or a percentage of the image width (X, W) or height (Y, H).\n\
+                  The rectangle is clamped to the image.",
+        parameters: &[("X,Y", "Top left corner"), ("W,H", "Rectangle size")],
+        examples: &["cargo r -crop=10%,10%,80%,80% input.png output.png", "cargo r -crop=0,0,64,64 input.png output.png"],
+    },
+    OperationDoc {
+        name: "trim",
+        usage: "-trim",
+        flags: &["-trim"],
+        summary: "Cut fully transparent borders off the image",
+        details: "Keeps the smallest rectangle holding every pixel that is not fully transparent; images\n\
+                  without alpha are left as they are. With --report the report records the size before\n\
+                  trimming and the offset of the kept rectangle, so game engines can put the sprite back in\n\
+                  place, and cargo r pack carries them into the atlas frame map.",
+        parameters: &[],
+        examples: &["cargo r -trim --report 'sprites/*.png' trimmed/", "cargo r -trim -pix=2 sprite.png out.png"],
+    },
+    OperationDoc {
+        name: "canvas",
+        usage: "-canvas=WxH|A:B",
+        flags: &["-canvas="],
+        summary: "Center the image on a transparent canvas",
+        details: "WxH takes pixels or percentages of the current size; a smaller canvas crops evenly.\n\
+                  A:B picks the smallest canvas with that aspect ratio that holds the whole image.",
+        parameters: &[("WxH", "Canvas size"), ("A:B", "Canvas aspect ratio")],
+        examples: &["cargo r -canvas=16:9 input.png output.png", "cargo r -canvas=120%x100% input.png output.png"],
+    },
+    OperationDoc {
+        name: "pad",
+        usage: "-pad=N|N%",
+        flags: &["-pad="],
+        summary: "Add a transparent border on every side",
+        details: "N is pixels or a percentage of the shorter image side.",
+        parameters: &[("N", "Border width")],
+        examples: &["cargo r -pad=5% input.png output.png"],
+    },
+    OperationDoc {
+        name: "duotone",
+        usage: "-duotone=DARK,LIGHT",
+        flags: &["-duotone="],
+        summary: "Map brightness onto a gradient between two colors",
+        details: "Shadows take the DARK color and highlights the LIGHT color; the gradient in between is\n\
+                  interpolated in the --blend-space color space (default oklab).",
+        parameters: &[("DARK", "Color for black"), ("LIGHT", "Color for white")],
+        examples: &["cargo r -duotone=navy,#ffcc00 input.png output.png"],
+    },
+    OperationDoc {
+        name: "blur",
+        usage: "-blur=SIGMA",
+        flags: &["-blur="],
+        summary: "Gaussian blur",
+        details: "A separable Gaussian with a radius of three sigma; radii above 25 pixels are filtered\n\
+                  in the frequency domain instead. --border picks how pixels past the edge are read:\n\
+                  clamp (default), wrap for tiling textures, or mirror.",
+        parameters: &[("SIGMA", "Standard deviation in pixels, at most 1000")],
+        examples: &["cargo r -blur=2.5 input.png output.png", "cargo r -blur=4 --border=wrap tile.png output.png"],
+    },
+    OperationDoc {
+        name: "sharpen",
+        usage: "-sharpen",
+        flags: &["-sharpen"],
+        summary: "Sharpen with a 3x3 kernel",
+        details: "Adds the difference to the four direct neighbours back onto every pixel.",
+        parameters: &[],
+        examples: &["cargo r -sharpen input.png output.png"],
+    },
+    OperationDoc {
+        name: "edge",
+        usage: "-edge",
+        flags: &["-edge"],
+        summary: "Laplacian edge detection",
+        details: "Flat areas turn black and edges light up.",
+        parameters: &[],
+        examples: &["cargo r -edge input.png output.png"],
+    },
+    OperationDoc {
+        name: "emboss",
+        usage: "-emboss",
+        flags: &["-emboss"],
+        summary: "Emboss lit from the top left",
+        details: "Flat areas turn mid gray; edges facing the light turn lighter.",
+        parameters: &[],
+        examples: &["cargo r -emboss input.png output.png"],
+    },
+    OperationDoc {
+        name: "kernel",
+        usage: "-kernel=FILE",
+        flags: &["-kernel="],
+        summary: "Convolve with a custom kernel from a JSON file",
+        details: "The file holds {\"weights\": [[...], ...], \"divisor\": D, \"bias\": B}. Rows must have the\n\
+                  same length; the center is at (width / 2, height / 2). Weights are divided by D, or by\n\
+                  their sum when D is left out, and B is added to the result.",
+        parameters: &[("FILE", "Kernel JSON file")],
+        examples: &["cargo r -kernel=box5.json input.png output.png"],
+    },
+    OperationDoc {
+        name: "descreen",
+        usage: "-descreen",
+        flags: &["-descreen"],
+        summary: "Remove halftone screen patterns from scans",
+        details: "Isolated peaks in the frequency spectrum away from the low frequencies are the regular\n\
+                  dot grid of a printed screen; they are notched out of every channel.",
+        parameters: &[],
+        examples: &["cargo r -descreen scan.png output.png", "cargo r -descreen -blur=0.7 scan.png output.png"],
+    },
+    OperationDoc {
+        name: "spectrum",
+        usage: "-spectrum",
+        flags: &["-spectrum"],
+        summary: "Show the log magnitude frequency spectrum",
+        details: "The zero frequency is in the center; bright spots away from it are periodic patterns\n\
+                  such as halftone screens or moire.",
+        parameters: &[],
+        examples: &["cargo r -spectrum scan.png spectrum.png"],
+    },
+    OperationDoc {
+        name: "boxblur",
+        usage: "-boxblur=RADIUS",
+        flags: &["-boxblur="],
+        summary: "Average every pixel with its square neighbourhood",
+        details: "Uses a summed-area table, so the cost per pixel is the same for any radius. Windows are\n\
+                  clipped at the image edges.",
+        parameters: &[("RADIUS", "Pixels on each side of the center, or a percentage like 2%")],
+        examples: &["cargo r -boxblur=40 input.png output.png"],
+    },
+    OperationDoc {
+        name: "athreshold",
+        usage: "-athreshold=RADIUS[,OFFSET]",
+        flags: &["-athreshold="],
+        summary: "Convert to black and white against the local mean",
+        details: "A pixel turns black when it is more than OFFSET levels darker than the average of the\n\
+                  square around it. This follows uneven lighting and paper tone on scans where a single\n\
+                  threshold fails. The radius should be a little larger than the strokes.",
+        parameters: &[
+            ("RADIUS", "Pixels on each side of the center, or a percentage like 2%"),
+            ("OFFSET", "Levels below the local mean that count as ink (default 10)"),
+        ],
+        examples: &["cargo r -athreshold=15 scan.png output.png", "cargo r -athreshold=2%,6 scan.png output.png"],
+    },
+    OperationDoc {
+        name: "localcontrast",
+        usage: "-localcontrast=RADIUS[,AMOUNT]",
+        flags: &["-localcontrast="],
+        summary: "Boost detail relative to the local mean",
+        details: "Every channel moves away from its box-blurred neighbourhood by AMOUNT times the difference.\n\
+                  Large radii lift flat, hazy scans; small radii act like a sharpen.",
+        parameters: &[
+            ("RADIUS", "Pixels on each side of the center, or a percentage like 5%"),
+            ("AMOUNT", "Strength, where 0 leaves the image unchanged (default 0.5)"),
+        ],
+        examples: &["cargo r -localcontrast=50,0.8 scan.png output.png"],
+    },
+    OperationDoc {
+        name: "clahe",
+        usage: "-clahe[=TILES[,CLIP]]",
+        flags: &["-clahe", "-clahe="],
+        summary: "Equalize lightness locally with contrast limiting",
+        details: "Builds a histogram equalization curve per tile of a TILES x TILES grid and blends the\n\
+                  curves of neighbouring tiles per pixel. Histogram bins are capped at CLIP times the\n\
+                  average, so flat areas and noise are not blown up. Only OKLab lightness changes;\n\
+                  hue and chroma are kept. Run it before dithering on foggy photos and faded scans.",
+        parameters: &[
+            ("TILES", "Tiles per side (default 8)"),
+            ("CLIP", "Clip limit, from 1 for the mildest to about 4 for strong contrast (default 2)"),
+        ],
+        examples: &["cargo r -clahe input.png output.png", "cargo r -clahe=4,3 -floyd scan.png output.png"],
+    },
+    OperationDoc {
+        name: "dehaze",
+        usage: "-dehaze[=STRENGTH]",
+        flags: &["-dehaze", "-dehaze="],
+        summary: "Remove atmospheric haze with the dark channel prior",
+        details: "Outside of haze almost every region has a color channel close to black, so the darkest\n\
+                  channel around a pixel tells how much haze covers it. That haze is subtracted and the\n\
+                  remaining contrast restored. Hazy photos quantize into a few washed-out colors;\n\
+                  dehazing first gives the palette real contrast to work with.",
+        parameters: &[("STRENGTH", "Share of the haze removed, 0 to 1 (default 0.95)")],
+        examples: &["cargo r -dehaze input.png output.png", "cargo r -dehaze=0.8 -quant=16 input.png output.png"],
+    },
+    OperationDoc {
+        name: "tonemap",
+        usage: "-tonemap=OPERATOR[,EV]",
+        flags: &["-tonemap="],
+        summary: "Map HDR input into 8-bit display range",
+        details: "EXR and Radiance HDR images are linear and can hold values far above white; every other\n\
+                  operation clips them. Put -tonemap first to compress highlights smoothly instead.\n\
+                  reinhard never clips but looks flat, aces has more contrast and a soft shoulder,\n\
+                  filmic keeps the most shadow detail. 8-bit input is decoded to linear light first.",
+        parameters: &[
+            ("OPERATOR", "reinhard, aces or filmic"),
+            ("EV", "Exposure in stops applied before mapping (default 0)"),
+        ],
+        examples: &["cargo r -tonemap=aces render.exr output.png", "cargo r -tonemap=filmic,1.5 -pix=4 render.hdr output.png"],
+    },
+    OperationDoc {
+        name: "exposure",
+        usage: "-exposure=EV",
+        flags: &["-exposure="],
+        summary: "Brighten or darken by photographic stops",
+        details: "Scales linear light by 2^EV, so +1 doubles the light like opening the aperture one stop.\n\
+                  Values pushed past white clip.",
+        parameters: &[("EV", "Stops, negative to darken")],
+        examples: &["cargo r -exposure=0.7 input.png output.png"],
+    },
+    OperationDoc {
+        name: "highlights",
+        usage: "-highlights=AMOUNT",
+        flags: &["-highlights="],
+        summary: "Recover or boost bright areas",
+        details: "Changes exposure by up to two stops, fading out towards the midtones and shadows.\n\
+                  Negative amounts bring back detail in skies and other bright areas.",
+        parameters: &[("AMOUNT", "-100 to 100")],
+        examples: &["cargo r -highlights=-60 input.png output.png"],
+    },
+    OperationDoc {
+        name: "shadows",
+        usage: "-shadows=AMOUNT",
+        flags: &["-shadows="],
+        summary: "Open up or deepen dark areas",
+        details: "Changes exposure by up to two stops, fading out towards the midtones and highlights.\n\
+                  Positive amounts reveal detail that dithering would otherwise turn solid black.",
+        parameters: &[("AMOUNT", "-100 to 100")],
+        examples: &["cargo r -shadows=40 input.png output.png", "cargo r -highlights=-30 -shadows=30 -floyd input.png output.png"],
+    },
+    OperationDoc {
+        name: "clarity",
+        usage: "-clarity=AMOUNT",
+        flags: &["-clarity="],
+        summary: "Add midtone punch with multi-scale local contrast",
+        details: "Lightness detail at three scales (0.5%, 2% and 6% of the shorter side) is amplified,\n\
+                  strongest in the midtones and fading out towards black and white. Running it before\n\
+                  -pix or a dither keeps shapes readable at low resolution. Negative amounts soften.",
+        parameters: &[("AMOUNT", "-100 to 100")],
+        examples: &["cargo r -clarity=50 input.png output.png", "cargo r -clarity=70 -pix=6 -pal input.png output.png"],
+    },
+    OperationDoc {
+        name: "split",
+        usage: "-split=DIR[,rgba|hsl|ycbcr]",
+        flags: &["-split="],
+        summary: "Save each channel as a grayscale image in a directory",
+        details: "Writes DIR/r.png, g.png and b.png (h/s/l or y/cb/cr for the other channel sets), plus
+                  a.png when the image has alpha. Hue is stored as 0-360 degrees scaled to 0-255, Y/Cb/Cr
+                  are full-range BT.601. The image itself passes through unchanged.",
+        parameters: &[("DIR", "Output directory, created if missing"), ("SET", "rgba (default), hsl or ycbcr")],
+        examples: &["cargo r -split=channels/ input.png output.png", "cargo r -split=planes,ycbcr input.png output.png"],
+    },
+    OperationDoc {
+        name: "combine",
+        usage: "-combine=R,G,B[,A]",
+        flags: &["-combine="],
+        summary: "Pack grayscale images into the R, G, B (and A) channels",
+        details: "Replaces the current image with one built from three or four images of the same size,
+                  each converted to grayscale first. Used for channel-packed textures such as
+                  occlusion/roughness/metallic maps.",
+        parameters: &[("R,G,B,A", "Image paths for each channel; alpha is optional")],
+        examples: &["cargo r --data-texture -combine=ao.png,rough.png,metal.png ao.png orm.png"],
+    },
+    OperationDoc {
+        name: "anaglyph",
+        usage: "-anaglyph=[LEFT,]RIGHT",
+        flags: &["-anaglyph="],
+        summary: "Red/cyan 3D image from a left and a right view",
+        details: "The red channel comes from the left eye's view and green and blue from the right eye's,\n\
+                  to be seen with red/cyan glasses. With one path the current image is the left view; with\n\
+                  two both views are loaded and the current image is replaced. The views must be the same size.",
+        parameters: &[("LEFT", "Left eye image (optional)"), ("RIGHT", "Right eye image")],
+        examples: &["cargo r -anaglyph=right.png left.png 3d.png", "cargo r -anaglyph=left.png,right.png -resize=50% any.png 3d.png"],
+    },
+    OperationDoc {
+        name: "depth3d",
+        usage: "-depth3d=DEPTH[,SHIFT]",
+        flags: &["-depth3d="],
+        summary: "Red/cyan 3D image from the image and a depth map",
+        details: "Two views are faked from the image: nearer pixels (lighter in the grayscale DEPTH map) are\n\
+                  moved sideways, up to SHIFT/2 pixels for white, in opposite directions for each eye, and the\n\
+                  views are combined as with -anaglyph. The depth map is resized to the image. Large shifts\n\
+                  tear at depth edges; a few percent of the width looks natural.",
+        parameters: &[("DEPTH", "Grayscale depth map, white is near"), ("SHIFT", "Disparity between the eyes for white, in pixels (default 8)")],
+        examples: &["cargo r -depth3d=depth.png,12 photo.png 3d.png"],
+    },
+    OperationDoc {
+        name: "synth",
+        usage: "-synth=WxH[,PATCH]",
+        flags: &["-synth="],
+        summary: "Grow a larger texture from the image by patch quilting",
+        details: "Treats the image as an exemplar and tiles a W x H texture from square patches of it.\n\
+                  Each patch is chosen to match the patches already placed where they overlap, and the\n\
+                  seam is cut along the path of least difference, so block edges do not show. Patches\n\
+                  should be large enough to hold the texture's features. The result is deterministic and\n\
+                  can be palettized like any other image.",
+        parameters: &[("WxH", "Size of the texture in pixels"), ("PATCH", "Patch size in pixels, at least 3 (default: a third of the shorter side)")],
+        examples: &["cargo r -synth=512x512 bricks.png wall.png", "cargo r -synth=256x256,24 -pal=gameboy moss.png moss_tile.png"],
+    },
+    OperationDoc {
+        name: "blendwith",
+        usage: "-blendwith=PATH[,MODE[,OPACITY]]",
+        flags: &["-blendwith="],
+        summary: "Blend a second image onto the image",
+        details: "The second image is stretched to the size of the image and composited on top of it.\n\
+                  normal, multiply, screen, overlay, darken, lighten, difference, exclusion, hard-light,\n\
+                  soft-light and add mix the colors like their Photoshop namesakes; in, out, atop and xor\n\
+                  are the Porter-Duff operators that keep the image only where the two do or do not\n\
+                  overlap. Transparent areas of either image are respected.",
+        parameters: &[("PATH", "Image to blend on top"), ("MODE", "Blend mode (default normal)"), ("OPACITY", "0 to 1, scales the second image's alpha (default 1)")],
+        examples: &["cargo r -blendwith=paper.png,multiply input.png output.png", "cargo r -blendwith=glow.png,screen,0.6 input.png output.png"],
+    },
+    OperationDoc {
+        name: "chromasub",
+        usage: "-chromasub=4:2:0|4:2:2|4:1:1",
+        flags: &["-chromasub="],
+        summary: "Simulate the color loss of chroma subsampling",
+        details: "Converts to YCbCr, averages Cb and Cr over 2x2 (4:2:0), 2x1 (4:2:2) or 4x1 (4:1:1)
+                  blocks and converts back. Edges stay sharp in brightness while saturated colors bleed,
+                  as in video and most JPEGs; useful to preview how a dithered image survives encoding.",
+        parameters: &[("SCHEME", "4:2:0, 4:2:2 or 4:1:1")],
+        examples: &["cargo r -chromasub=4:2:0 input.png output.png", "cargo r -pal=gameboy -chromasub=4:2:2 input.png output.png"],
+    },
+    OperationDoc {
+        name: "jpegify",
+        usage: "-jpegify=QUALITY[,PASSES]",
+        flags: &["-jpegify="],
+        summary: "Add real JPEG compression artifacts",
+        details: "Encodes the image as JPEG in memory and decodes it again, PASSES times (default 1).
+                  Low qualities give blocking and ringing; many passes at a medium quality drift colors
+                  further each time. Alpha is kept. The output format is unaffected.",
+        parameters: &[("QUALITY", "JPEG quality, 1-100"), ("PASSES", "Number of round trips (default 1)")],
+        examples: &["cargo r -jpegify=10 input.png output.png", "cargo r -jpegify=40,20 input.png output.png"],
+    },
+];
+
+// Finds a page by operation name or by any flag or alias the operation is written with.
+pub fn find(name: &str) -> Option<&'static OperationDoc> {
+    let flag: &str = name.split('=').next().unwrap_or(name);
+    let spec: Option<&OperationSpec> = registry::find(flag).or_else(|| registry::find(&format!("-{}", flag)));
+    let name: &str = spec.map_or(name, |spec| spec.name);
+    OPERATIONS.iter().find(|doc| {
+        doc.name == name || doc.flags.iter().any(|candidate| candidate.trim_end_matches('=') == flag)
+    })
+}
+
+pub fn print_summary() {
+    for doc in OPERATIONS {
+        println!("  {}: {}", doc.usage, doc.summary);
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let name: &str = match args.first() {
+        Some(name) => name,
+        None => {
+            println!("Usage: cargo r help [operation]");
+            println!("Operations:");
+            for doc in OPERATIONS {
+                println!("  {:<10} {}", doc.name, doc.summary);
+            }
+            return Ok(());
+        }
+    };
+
+    let doc: &OperationDoc = find(name).ok_or_else(|| Error::InvalidArgument(format!("Unknown operation: {}", name)))?;
+
+    println!("{} ({})", doc.name, doc.usage);
+    println!("  {}", doc.summary);
+    if let Some(spec) = registry::find(&format!("-{}", doc.name)).filter(|spec| !spec.aliases.is_empty()) {
+        println!("  Also written: {}", spec.aliases.iter().map(|alias| format!("-{}", alias)).collect::<Vec<String>>().join(", "));
+    }
+    println!();
+    for line in doc.details.lines() {
+        println!("  {}", line);
+    }
+    if !doc.parameters.is_empty() {
+        println!();
+        println!("Parameters:");
+        for (parameter, description) in doc.parameters {
+            println!("  {:<8} {}", parameter, description);
+        }
+    }
+    println!();
+    println!("Examples:");
+    for example in doc.examples {
+        println!("  {}", example);
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use filter::error::Error;
+use filter::filter::open_image;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use std::collections::HashSet;
+
+pub fn run(input_path: &str) -> Result<(), Error> {
+    let format: Option<ImageFormat> = ImageReader::open(input_path).ok()
+        .and_then(|reader| reader.with_guessed_format().ok())
+        .and_then(|reader| reader.format());
+    let image: DynamicImage = open_image(input_path)?;
+
+    let colors: HashSet<[u8; 4]> = image.to_rgba8().pixels().map(|pixel| pixel.0).collect();
+    println!("File: {}", input_path);
+    println!("Format: {}", format.map(|format| format!("{:?}", format)).unwrap_or_else(|| "unknown".to_string()));
+    println!("Size: {}x{}", image.width(), image.height());
+    println!("Color type: {:?}", image.color());
+    println!("Alpha: {}", if image.color().has_alpha() { "yes" } else { "no" });
+    println!("Colors: {}", colors.len());
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+
+// Summed-area table: entry (x, y) holds the sum of all values above and to the left of it,
+// so the sum over any rectangle takes four lookups whatever its size.
+pub struct IntegralImage {
+    width: u32,
+    height: u32,
+    sums: Vec<u64>,
+}
+
+impl IntegralImage {
+    pub fn from_fn(width: u32, height: u32, value: impl Fn(u32, u32) -> u64) -> Self {
+        let stride: usize = width as usize + 1;
+        let mut sums: Vec<u64> = vec![0; stride * (height as usize + 1)];
+        for y in 0..height {
+            let mut row: u64 = 0;
+            for x in 0..width {
+                row += value(x, y);
+                let index: usize = (y as usize + 1) * stride + x as usize + 1;
+                sums[index] = sums[index - stride] + row;
+            }
+        }
+        IntegralImage { width, height, sums }
+    }
+
+    pub fn new(image: &GrayImage) -> Self {
+        IntegralImage::from_fn(image.width(), image.height(), |x, y| image.get_pixel(x, y)[0] as u64)
+    }
+
+    // Sum and pixel count of the square window of `radius` around (x, y), clipped to the image.
+    pub fn window(&self, x: u32, y: u32, radius: u32) -> (u64, u64) {
+        let (x0, y0) = (x.saturating_sub(radius) as usize, y.saturating_sub(radius) as usize);
+        let x1: usize = (x as usize + radius as usize + 1).min(self.width as usize);
+        let y1: usize = (y as usize + radius as usize + 1).min(self.height as usize);
+        let stride: usize = self.width as usize + 1;
+        let sum: u64 = self.sums[y1 * stride + x1] + self.sums[y0 * stride + x0]
+            - self.sums[y0 * stride + x1] - self.sums[y1 * stride + x0];
+        (sum, ((x1 - x0) * (y1 - y0)) as u64)
+    }
+
+    pub fn mean(&self, x: u32, y: u32, radius: u32) -> f32 {
+        let (sum, count) = self.window(x, y, radius);
+        sum as f32 / count as f32
+    }
+}
+
+fn channel_tables(image: &RgbaImage) -> Vec<IntegralImage> {
+    (0..3).map(|channel| {
+        IntegralImage::from_fn(image.width(), image.height(), |x, y| image.get_pixel(x, y)[channel] as u64)
+    }).collect()
+}
+
+fn with_alpha_of(image: &DynamicImage, output: RgbaImage) -> DynamicImage {
+    if image.color().has_alpha() { output.into() } else { DynamicImage::ImageRgba8(output).to_rgb8().into() }
+}
+
+// Mean of the (2 * radius + 1) square around every pixel; alpha is left unchanged.
+pub fn box_blur(image: &DynamicImage, radius: u32) -> DynamicImage {
+    let source: RgbaImage = image.to_rgba8();
+    let tables: Vec<IntegralImage> = channel_tables(&source);
+    let output: RgbaImage = RgbaImage::from_fn(source.width(), source.height(), |x, y| {
+        let mean = |channel: usize| tables[channel].mean(x, y, radius).round() as u8;
+        Rgba([mean(0), mean(1), mean(2), source.get_pixel(x, y)[3]])
+    });
+    with_alpha_of(image, output)
+}
+
+// Black where a pixel is more than `offset` levels darker than the mean around it, white elsewhere.
+// Unlike a global threshold this follows uneven lighting, which is what scanned pages need.
+pub fn adaptive_threshold(image: &DynamicImage, radius: u32, offset: i32) -> GrayImage {
+    let gray: GrayImage = image.to_luma8();
+    let table: IntegralImage = IntegralImage::new(&gray);
+    GrayImage::from_fn(gray.width(), gray.height(), |x, y| {
+        let value: f32 = gray.get_pixel(x, y)[0] as f32;
+        Luma([if value + offset as f32 <= table.mean(x, y, radius) { 0 } else { 255 }])
+    })
+}
+
+// Pushes every pixel away from its local mean by `amount`, boosting mid-scale detail.
+pub fn local_contrast(image: &DynamicImage, radius: u32, amount: f32) -> DynamicImage {
+    let source: RgbaImage = image.to_rgba8();
+    let tables: Vec<IntegralImage> = channel_tables(&source);
+    let output: RgbaImage = RgbaImage::from_fn(source.width(), source.height(), |x, y| {
+        let pixel: &Rgba<u8> = source.get_pixel(x, y);
+        let boost = |channel: usize| {
+            let value: f32 = pixel[channel] as f32;
+            (value + amount * (value - tables[channel].mean(x, y, radius))).round().clamp(0.0, 255.0) as u8
+        };
+        Rgba([boost(0), boost(1), boost(2), pixel[3]])
+    });
+    with_alpha_of(image, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_sums_match_direct_sums() {
+        let gray: GrayImage = GrayImage::from_fn(13, 9, |x, y| Luma([((x * 31 + y * 17) % 256) as u8]));
+        let table: IntegralImage = IntegralImage::new(&gray);
+        for (x, y, radius) in [(0u32, 0u32, 2u32), (6, 4, 3), (12, 8, 1), (5, 5, 20)] {
+            let mut sum: u64 = 0;
+            let mut count: u64 = 0;
+            for sy in y.saturating_sub(radius)..(y + radius + 1).min(9) {
+                for sx in x.saturating_sub(radius)..(x + radius + 1).min(13) {
+                    sum += gray.get_pixel(sx, sy)[0] as u64;
+                    count += 1;
+                }
+            }
+            assert_eq!(table.window(x, y, radius), (sum, count));
+        }
+
+        // Dark text on a page lit from one side stays separable from the paper
+        let page: DynamicImage = GrayImage::from_fn(64, 8, |x, y| Luma([if x % 16 == 8 && y > 1 { 40 + x as u8 } else { 120 + 2 * x as u8 }])).into();
+        let binary: GrayImage = adaptive_threshold(&page, 4, 10);
+        assert!(binary.enumerate_pixels().all(|(x, y, pixel)| (pixel[0] == 0) == (x % 16 == 8 && y > 1)));
+    }
+}
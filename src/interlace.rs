@@ -0,0 +1,151 @@
+use crc32fast::Hasher;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{DynamicImage, ImageFormat};
+use std::io::Write;
+
+// (x start, y start, x step, y step) of the seven Adam7 passes.
+const ADAM7: [(u32, u32, u32, u32); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+// Encodes PNG with Adam7 interlacing, GIF with interlaced rows and JPEG as progressive, so
+// browsers can show a coarse version of a large image while it loads. Returns None for other
+// formats.
+pub fn encode_interlaced(image: &DynamicImage, format: ImageFormat, quality: u8) -> Result<Option<Vec<u8>>, String> {
+    match format {
+        ImageFormat::Png => Ok(Some(adam7_png(image))),
+        ImageFormat::Gif => interlaced_gif(image).map(Some),
+        ImageFormat::Jpeg => progressive_jpeg(image, quality).map(Some),
+        _ => Ok(None),
+    }
+}
+
+pub(crate) fn png_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    let mut hasher: Hasher = Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    output.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+// The image crate's PNG encoder cannot interlace, so this writes the chunks itself.
+// 8-bit gray, gray+alpha, RGB or RGBA depending on the image; rows use the Sub filter.
+fn adam7_png(image: &DynamicImage) -> Vec<u8> {
+    let (color_type, channels, pixels): (u8, usize, Vec<u8>) = match (image.color().has_color(), image.color().has_alpha()) {
+        (false, false) => (0, 1, image.to_luma8().into_raw()),
+        (false, true) => (4, 2, image.to_luma_alpha8().into_raw()),
+        (true, false) => (2, 3, image.to_rgb8().into_raw()),
+        (true, true) => (6, 4, image.to_rgba8().into_raw()),
+    };
+    let (width, height) = (image.width(), image.height());
+
+    let mut filtered: Vec<u8> = Vec::new();
+    for (x_start, y_start, x_step, y_step) in ADAM7 {
+        if x_start >= width || y_start >= height {
+            continue;
+        }
+        for y in (y_start..height).step_by(y_step as usize) {
+            let row: Vec<u8> = (x_start..width).step_by(x_step as usize)
+                .flat_map(|x| {
+                    let index: usize = (y as usize * width as usize + x as usize) * channels;
+                    pixels[index..index + channels].iter().copied()
+                })
+                .collect();
+            filtered.push(1);
+            filtered.extend(row.iter().enumerate().map(|(i, &value)| {
+                if i >= channels { value.wrapping_sub(row[i - channels]) } else { value }
+            }));
+        }
+    }
+
+    let mut compressor: ZlibEncoder<Vec<u8>> = ZlibEncoder::new(Vec::new(), Compression::best());
+    // Writing into a Vec cannot fail
+    compressor.write_all(&filtered).expect("in-memory write");
+    let compressed: Vec<u8> = compressor.finish().expect("in-memory write");
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, color_type, 0, 0, 1]);
+
+    let mut output: Vec<u8> = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut output, b"IHDR", &header);
+    png_chunk(&mut output, b"IDAT", &compressed);
+    png_chunk(&mut output, b"IEND", &[]);
+    output
+}
+
+// Row order of an interlaced GIF: every 8th row from 0, every 8th from 4, every 4th from 2,
+// then every 2nd from 1.
+fn gif_row_order(height: usize) -> Vec<usize> {
+    [(0, 8), (4, 8), (2, 4), (1, 2)].iter()
+        .flat_map(|&(start, step)| (start..height).step_by(step))
+        .collect()
+}
+
+// Colors are reduced to 256 by the gif crate when the image has more.
+fn interlaced_gif(image: &DynamicImage) -> Result<Vec<u8>, String> {
+    let (width, height) = (image.width(), image.height());
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(format!("GIF images are limited to {} pixels per side", u16::MAX));
+    }
+    let mut rgba: Vec<u8> = image.to_rgba8().into_raw();
+    // GIF has one transparent palette entry, so all transparent pixels must share a color
+    for pixel in rgba.chunks_exact_mut(4).filter(|pixel| pixel[3] == 0) {
+        pixel.copy_from_slice(&[0, 0, 0, 0]);
+    }
+    let mut frame: gif::Frame = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut rgba, 10);
+    let row_length: usize = width as usize;
+    frame.buffer = gif_row_order(height as usize).iter()
+        .flat_map(|&row| frame.buffer[row * row_length..(row + 1) * row_length].to_vec())
+        .collect::<Vec<u8>>()
+        .into();
+    frame.interlaced = true;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut encoder: gif::Encoder<&mut Vec<u8>> = gif::Encoder::new(&mut bytes, width as u16, height as u16, &[]).map_err(|e| e.to_string())?;
+    encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    drop(encoder);
+    Ok(bytes)
+}
+
+fn progressive_jpeg(image: &DynamicImage, quality: u8) -> Result<Vec<u8>, String> {
+    let (width, height) = (image.width(), image.height());
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(format!("JPEG images are limited to {} pixels per side", u16::MAX));
+    }
+    let (color_type, pixels): (jpeg_encoder::ColorType, Vec<u8>) = if image.color().has_color() {
+        (jpeg_encoder::ColorType::Rgb, image.to_rgb8().into_raw())
+    } else {
+        (jpeg_encoder::ColorType::Luma, image.to_luma8().into_raw())
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut encoder: jpeg_encoder::Encoder<&mut Vec<u8>> = jpeg_encoder::Encoder::new(&mut bytes, quality.clamp(1, 100));
+    encoder.set_progressive(true);
+    encoder.encode(&pixels, width as u16, height as u16, color_type).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn interlaced_outputs_decode_to_the_image() {
+        let image: DynamicImage = RgbaImage::from_fn(13, 11, |x, y| Rgba([x as u8 * 19, y as u8 * 23, 90, if x == 0 { 0 } else { 255 }])).into();
+        let png: Vec<u8> = encode_interlaced(&image, ImageFormat::Png, 90).unwrap().unwrap();
+        assert_eq!(png[28], 1);
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), image.to_rgba8());
+
+        let gif: Vec<u8> = encode_interlaced(&image, ImageFormat::Gif, 90).unwrap().unwrap();
+        let decoded: RgbaImage = image::load_from_memory(&gif).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(0, 5)[3], 0);
+        assert!(decoded.get_pixel(12, 10)[0].abs_diff(12 * 19) < 16);
+
+        let jpeg: Vec<u8> = encode_interlaced(&image, ImageFormat::Jpeg, 90).unwrap().unwrap();
+        assert_eq!(image::load_from_memory(&jpeg).unwrap().width(), 13);
+        assert!(encode_interlaced(&image, ImageFormat::Bmp, 90).unwrap().is_none());
+    }
+}
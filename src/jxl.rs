@@ -0,0 +1,95 @@
+use image::{DynamicImage, ImageError, ImageReader, ImageResult};
+use image::error::{EncodingError, ImageFormatHint};
+use jxl_oxide::integration::JxlDecoder;
+use std::io::{BufRead, Cursor, Seek};
+use std::path::Path;
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::EncoderOptions;
+use zune_jpegxl::JxlSimpleEncoder;
+
+// JPEG XL files start with a bare codestream marker or with the signature box of the container.
+const CODESTREAM_SIGNATURE: [u8; 2] = [0xFF, 0x0A];
+const CONTAINER_SIGNATURE: [u8; 12] = [0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A];
+
+pub fn is_jxl(bytes: &[u8]) -> bool {
+    bytes.starts_with(&CODESTREAM_SIGNATURE) || bytes.starts_with(&CONTAINER_SIGNATURE)
+}
+
+pub fn has_jxl_extension<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().is_some_and(|extension| extension.eq_ignore_ascii_case("jxl"))
+}
+
+// The image crate's reader for the formats it recognises itself, or a jxl-oxide decoder for
+// JPEG XL, which it does not.
+pub enum Reader<R: BufRead + Seek> {
+    Image(ImageReader<R>),
+    Jxl(Box<JxlDecoder<R>>),
+}
+
+pub fn reader<R: BufRead + Seek>(reader: ImageReader<R>) -> ImageResult<Reader<R>> {
+    if reader.format().is_some() {
+        return Ok(Reader::Image(reader));
+    }
+    let mut inner: R = reader.into_inner();
+    if is_jxl(inner.fill_buf()?) {
+        return Ok(Reader::Jxl(Box::new(JxlDecoder::new(inner)?)));
+    }
+    Ok(Reader::Image(ImageReader::new(inner)))
+}
+
+// Like ImageReader::decode, with JPEG XL as well.
+pub fn decode<R: BufRead + Seek>(reader: ImageReader<R>) -> ImageResult<DynamicImage> {
+    match self::reader(reader)? {
+        Reader::Image(reader) => reader.decode(),
+        Reader::Jxl(decoder) => DynamicImage::from_decoder(*decoder),
+    }
+}
+
+pub fn open<P: AsRef<Path>>(path: P) -> ImageResult<DynamicImage> {
+    decode(ImageReader::open(path)?.with_guessed_format()?)
+}
+
+pub fn load_from_memory(bytes: &[u8]) -> ImageResult<DynamicImage> {
+    decode(ImageReader::new(Cursor::new(bytes)).with_guessed_format()?)
+}
+
+// Lossless JPEG XL, 8 bits per channel; 16-bit and float images are reduced to 8 bits first.
+pub fn encode(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    let (pixels, colorspace): (Vec<u8>, ColorSpace) = match image {
+        DynamicImage::ImageLuma8(gray) => (gray.as_raw().clone(), ColorSpace::Luma),
+        DynamicImage::ImageLumaA8(gray) => (gray.as_raw().clone(), ColorSpace::LumaA),
+        image if image.color().has_alpha() => (image.to_rgba8().into_raw(), ColorSpace::RGBA),
+        image => (image.to_rgb8().into_raw(), ColorSpace::RGB),
+    };
+    let options: EncoderOptions = EncoderOptions::new(image.width() as usize, image.height() as usize, colorspace, BitDepth::Eight);
+    let mut bytes: Vec<u8> = Vec::new();
+    JxlSimpleEncoder::new(&pixels, options)
+        .encode(&mut bytes)
+        .map_err(|e| ImageError::Encoding(EncodingError::new(ImageFormatHint::Name("JPEG XL".to_string()), format!("{:?}", e))))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, ImageFormat, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+
+    #[test]
+    fn images_survive_a_lossless_round_trip() {
+        let rgb: DynamicImage = RgbImage::from_fn(37, 21, |x, y| Rgb([(x * 7) as u8, (y * 12) as u8, 200])).into();
+        let rgba: DynamicImage = RgbaImage::from_fn(16, 9, |x, y| Rgba([90, (x * 16) as u8, 30, (y * 28) as u8])).into();
+        for image in [rgb, rgba, GrayImage::from_fn(12, 12, |x, _| Luma([(x * 20) as u8])).into()] {
+            let bytes: Vec<u8> = encode(&image).unwrap();
+            assert!(is_jxl(&bytes));
+            let decoded: DynamicImage = load_from_memory(&bytes).unwrap();
+            assert_eq!(decoded.to_rgba8(), image.to_rgba8());
+        }
+
+        // Other formats still go to the image crate, and unknown data still fails
+        let png: Vec<u8> = crate::encode::encode(&RgbImage::new(3, 2).into(), ImageFormat::Png).unwrap();
+        assert_eq!(load_from_memory(&png).unwrap().width(), 3);
+        assert!(load_from_memory(b"not an image").is_err());
+        assert!(has_jxl_extension("photos/Shot.JXL") && !has_jxl_extension("shot.png"));
+    }
+}
@@ -0,0 +1,103 @@
+// A 3-d tree over palette coordinates. Nearest lookups visit a few dozen nodes instead of every
+// color, which matters from a few hundred colors on.
+
+struct Node {
+    point: [f32; 3],
+    index: usize,
+    axis: usize,
+    children: [Option<usize>; 2],
+}
+
+pub struct KdTree {
+    nodes: Vec<Node>,
+    root: Option<usize>,
+}
+
+fn squared_distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+impl KdTree {
+    pub fn new(points: &[[f32; 3]]) -> Self {
+        let mut tree: KdTree = KdTree { nodes: Vec::with_capacity(points.len()), root: None };
+        let mut indices: Vec<usize> = (0..points.len()).collect();
+        tree.root = tree.build(points, &mut indices);
+        tree
+    }
+
+    // Splits at the median of the axis with the widest spread.
+    fn build(&mut self, points: &[[f32; 3]], indices: &mut [usize]) -> Option<usize> {
+        if indices.is_empty() {
+            return None;
+        }
+        let spread = |axis: usize| {
+            let values = indices.iter().map(|&index| points[index][axis]);
+            values.clone().fold(f32::MIN, f32::max) - values.fold(f32::MAX, f32::min)
+        };
+        let axis: usize = (0..3).max_by(|&a, &b| spread(a).total_cmp(&spread(b))).unwrap_or(0);
+        indices.sort_by(|&a, &b| points[a][axis].total_cmp(&points[b][axis]));
+        let middle: usize = indices.len() / 2;
+        let index: usize = indices[middle];
+
+        let node: usize = self.nodes.len();
+        self.nodes.push(Node { point: points[index], index, axis, children: [None, None] });
+        let (below, rest) = indices.split_at_mut(middle);
+        let lower: Option<usize> = self.build(points, below);
+        let upper: Option<usize> = self.build(points, &mut rest[1..]);
+        self.nodes[node].children = [lower, upper];
+        Some(node)
+    }
+
+    // Index of the nearest point; of equally near points the lowest index, as a linear scan gives.
+    pub fn nearest(&self, target: &[f32; 3]) -> Option<usize> {
+        let mut best: (f32, usize) = (f32::MAX, usize::MAX);
+        self.search(self.root, target, &mut best);
+        if best.1 == usize::MAX { None } else { Some(best.1) }
+    }
+
+    fn search(&self, node: Option<usize>, target: &[f32; 3], best: &mut (f32, usize)) {
+        let node: &Node = match node {
+            Some(node) => &self.nodes[node],
+            None => return,
+        };
+        let distance: f32 = squared_distance(&node.point, target);
+        if distance < best.0 || (distance == best.0 && node.index < best.1) {
+            *best = (distance, node.index);
+        }
+
+        let offset: f32 = target[node.axis] - node.point[node.axis];
+        let (near, far) = if offset < 0.0 { (node.children[0], node.children[1]) } else { (node.children[1], node.children[0]) };
+        self.search(near, target, best);
+        // Points at the same distance can lie on either side of the split
+        if offset * offset <= best.0 {
+            self.search(far, target, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_matches_a_linear_scan() {
+        let mut state: u32 = 12345;
+        let mut next = move || {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) % 256) as f32
+        };
+        // Coarse values so that ties between several points are common
+        let points: Vec<[f32; 3]> = (0..300).map(|_| [(next() / 32.0).floor(), (next() / 32.0).floor(), (next() / 32.0).floor()]).collect();
+        let tree: KdTree = KdTree::new(&points);
+        for _ in 0..2000 {
+            let target: [f32; 3] = [next() / 32.0, next() / 32.0, next() / 32.0];
+            let linear: Option<usize> = points.iter()
+                .map(|point| squared_distance(point, &target))
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(index, _)| index);
+            assert_eq!(tree.nearest(&target), linear, "{:?}", target);
+        }
+        assert_eq!(KdTree::new(&[]).nearest(&[0.0, 0.0, 0.0]), None);
+    }
+}
@@ -1,2 +1,46 @@
+extern crate alloc;
+
+pub mod analyze;
+pub mod animate;
+pub mod atlas;
+#[cfg(feature = "async")]
+pub mod async_api;
+pub mod batch;
+pub mod blend;
+pub mod bluenoise;
+pub mod braille;
+pub mod channels;
+pub mod color;
+pub mod color_parse;
+pub mod config;
+pub mod convolve;
+pub mod custom;
+pub mod determinism;
+pub mod diffusion;
+pub mod encode;
+pub mod error;
+pub mod fft;
 pub mod filter;
-pub mod palette;
\ No newline at end of file
+pub mod font;
+pub mod geometry;
+pub mod generate;
+pub mod integral;
+pub mod interlace;
+#[cfg(feature = "jxl")]
+pub mod jxl;
+pub mod kdtree;
+pub mod library;
+pub mod limits;
+pub mod manifest;
+pub mod math;
+pub mod observer;
+pub mod palette;
+pub mod pipeline;
+pub mod portable;
+pub mod quantize;
+pub mod quilt;
+pub mod remap;
+pub mod seamcarve;
+pub mod sink;
+pub mod texture;
+pub mod tone;
\ No newline at end of file
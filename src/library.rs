@@ -0,0 +1,229 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::error::Error;
+use crate::palette::{is_gpl, Palette, BUILTIN_PALETTES};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaletteSource {
+    File(PathBuf),
+    Builtin(String),
+    // A palette already read from its source, so batch runs load it once.
+    Loaded(Box<PaletteSource>, Palette),
+}
+
+impl PaletteSource {
+    pub fn load(&self) -> Result<Palette, Error> {
+        match self {
+            PaletteSource::File(path) => Palette::from_file(path),
+            PaletteSource::Builtin(name) => Palette::builtin(name).ok_or_else(|| Error::InvalidArgument(format!("unknown built-in palette '{}'", name))),
+            PaletteSource::Loaded(_, palette) => Ok(palette.clone()),
+        }
+    }
+
+    // Reads the palette now, so later loads do not touch the file again.
+    pub fn preload(self) -> Result<PaletteSource, Error> {
+        match self {
+            PaletteSource::Loaded(..) => Ok(self),
+            source => {
+                let palette: Palette = source.load()?;
+                Ok(PaletteSource::Loaded(Box::new(source), palette))
+            },
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            PaletteSource::File(path) => Some(path),
+            PaletteSource::Builtin(_) => None,
+            PaletteSource::Loaded(source, _) => source.path(),
+        }
+    }
+}
+
+impl fmt::Display for PaletteSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteSource::File(path) => write!(f, "{}", path.display()),
+            PaletteSource::Builtin(name) => write!(f, "{} (built-in)", name),
+            PaletteSource::Loaded(source, _) => write!(f, "{}", source),
+        }
+    }
+}
+
+// Installed palettes live in $XDG_DATA_HOME/image_rust/palettes (~/.local/share by default).
+pub fn library_dir() -> Option<PathBuf> {
+    let data_home: PathBuf = match std::env::var("XDG_DATA_HOME") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".local").join("share"),
+    };
+    Some(data_home.join("image_rust").join("palettes"))
+}
+
+// IMAGE_RUST_PALETTE_PATH entries first, then the installed library, then the palette directory.
+pub fn search_path(palette_dir: &Path) -> Vec<PathBuf> {
+    let mut directories: Vec<PathBuf> = match std::env::var_os("IMAGE_RUST_PALETTE_PATH") {
+        Some(value) => std::env::split_paths(&value).filter(|dir| !dir.as_os_str().is_empty()).collect(),
+        None => Vec::new(),
+    };
+    if let Some(dir) = library_dir() {
+        directories.push(dir);
+    }
+    directories.push(palette_dir.to_path_buf());
+    directories
+}
+
+// Files on the search path win over built-in palettes of the same name.
+pub fn resolve_palette(name: &str, palette_dir: &Path) -> Result<PaletteSource, String> {
+    let direct: &Path = Path::new(name);
+    if direct.is_file() {
+        return Ok(PaletteSource::File(direct.to_path_buf()));
+    }
+
+    // In each directory NAME.json comes before a GIMP NAME.gpl
+    let file_names: Vec<String> = if name.ends_with(".json") || name.ends_with(".gpl") {
+        vec![name.to_string()]
+    } else {
+        vec![format!("{}.json", name), format!("{}.gpl", name)]
+    };
+    find_palette(name, &file_names, &search_path(palette_dir))
+}
+
+fn find_palette(name: &str, file_names: &[String], directories: &[PathBuf]) -> Result<PaletteSource, String> {
+    let candidates = directories.iter().flat_map(|dir| file_names.iter().map(move |file_name| dir.join(file_name)));
+    if let Some(path) = candidates.into_iter().find(|candidate| candidate.is_file()) {
+        return Ok(PaletteSource::File(path));
+    }
+    if BUILTIN_PALETTES.contains(&name) {
+        return Ok(PaletteSource::Builtin(name.to_string()));
+    }
+    let searched: Vec<String> = directories.iter().map(|dir| dir.display().to_string()).collect();
+    Err(format!("palette '{}' not found in {} or the built-in palettes", name, searched.join(", ")))
+}
+
+pub fn installed_palettes(palette_dir: &Path) -> Vec<(String, PaletteSource)> {
+    palettes_in(&search_path(palette_dir))
+}
+
+// The palettes of the first directory holding each name, then the built-in ones.
+fn palettes_in(directories: &[PathBuf]) -> Vec<(String, PaletteSource)> {
+    let mut palettes: Vec<(String, PaletteSource)> = Vec::new();
+    for dir in directories {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut found: Vec<(String, PaletteSource)> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json") || is_gpl(path))
+            .filter_map(|path| Some((path.file_stem()?.to_string_lossy().into_owned(), PaletteSource::File(path))))
+            .filter(|(name, _)| !palettes.iter().any(|(existing, _)| existing == name))
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        palettes.extend(found);
+    }
+    for name in BUILTIN_PALETTES {
+        if !palettes.iter().any(|(existing, _)| existing == name) {
+            palettes.push((name.to_string(), PaletteSource::Builtin(name.to_string())));
+        }
+    }
+    palettes
+}
+
+#[cfg(feature = "net")]
+fn download(url: &str) -> Result<String, String> {
+    ureq::get(url)
+        .call()
+        .map_err(|e| format!("failed to download {}: {}", url, e))?
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| format!("failed to read {}: {}", url, e))
+}
+
+#[cfg(not(feature = "net"))]
+fn download(url: &str) -> Result<String, String> {
+    Err(format!("cannot download {}: built without the `net` feature", url))
+}
+
+// Library names become file names in one directory, so they cannot hold a path: "../x" would
+// leave the library and "/tmp/x" would replace it in Path::join.
+fn check_palette_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(Error::InvalidArgument(format!("invalid palette name '{}': names cannot contain path separators or '..'", name)));
+    }
+    Ok(())
+}
+
+pub fn install_palette(source: &str, name: Option<&str>) -> Result<PathBuf, Error> {
+    let dir: PathBuf = library_dir().ok_or_else(|| Error::Operation("cannot locate the palette library: HOME is not set".to_string()))?;
+    install_palette_into(source, name, &dir)
+}
+
+fn install_palette_into(source: &str, name: Option<&str>, dir: &Path) -> Result<PathBuf, Error> {
+    let is_url: bool = source.starts_with("http://") || source.starts_with("https://");
+    let text: String = if is_url {
+        download(source).map_err(Error::Operation)?
+    } else {
+        std::fs::read_to_string(source).map_err(|e| Error::Io { path: source.into(), source: e })?
+    };
+
+    let name: String = match name {
+        Some(name) => name.to_string(),
+        None => {
+            let last_segment: &str = source.rsplit(['/', '\\']).next().unwrap_or(source);
+            Path::new(last_segment).file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .filter(|stem| !stem.is_empty())
+                .ok_or_else(|| Error::InvalidArgument(format!("cannot derive a palette name from {}, pass --name", source)))?
+        }
+    };
+    check_palette_name(&name)?;
+    // GIMP palettes are converted, so the library only holds JSON
+    let palette: Palette = if text.trim_start().starts_with("GIMP Palette") {
+        Palette::from_gpl(&text, &name).map_err(|message| Error::InvalidPalette { path: source.into(), message })?
+    } else {
+        serde_json::from_str(&text).map_err(|e| Error::PaletteParse { path: source.into(), source: e })?
+    };
+
+    std::fs::create_dir_all(dir).map_err(|e| Error::Io { path: dir.to_path_buf(), source: e })?;
+    let destination: PathBuf = dir.join(format!("{}.json", name));
+    palette.save(&destination)?;
+    Ok(destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installed_palettes_are_found_by_name() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("image_rust_library_{}", std::process::id()));
+        let source: PathBuf = dir.join("source.gpl");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&source, "GIMP Palette\nName: Sweetie\n26 28 44\n93 39 93\n").unwrap();
+        let source: &str = source.to_str().unwrap();
+        let library: PathBuf = dir.join("library");
+
+        let installed: PathBuf = install_palette_into(source, Some("sweet"), &library).unwrap();
+        assert_eq!(installed, library.join("sweet.json"));
+        assert_eq!(Palette::from_file(&installed).unwrap().colors, vec![[26, 28, 44], [93, 39, 93]]);
+        assert_eq!(install_palette_into(source, None, &library).unwrap(), library.join("source.json"));
+        for name in ["../escaped", "/tmp/absolute", "nested/name", "..", ""] {
+            assert!(install_palette_into(source, Some(name), &library).is_err(), "{}", name);
+        }
+        assert!(!dir.join("escaped.json").exists());
+
+        // JSON comes before GIMP files of the same name, and files before built-in palettes
+        std::fs::write(library.join("gameboy.gpl"), "GIMP Palette\n0 0 0\n").unwrap();
+        let directories: Vec<PathBuf> = vec![dir.join("missing"), library.clone()];
+        let names = |name: &str| vec![format!("{}.json", name), format!("{}.gpl", name)];
+        assert_eq!(find_palette("sweet", &names("sweet"), &directories), Ok(PaletteSource::File(library.join("sweet.json"))));
+        assert_eq!(find_palette("gameboy", &names("gameboy"), &directories), Ok(PaletteSource::File(library.join("gameboy.gpl"))));
+        assert_eq!(find_palette("pico8", &names("pico8"), &directories), Ok(PaletteSource::Builtin("pico8".to_string())));
+        assert!(find_palette("nosuch", &names("nosuch"), &directories).is_err());
+
+        let listed: Vec<(String, PaletteSource)> = palettes_in(&directories);
+        assert_eq!(listed.iter().filter(|(name, _)| name == "gameboy").count(), 1);
+        assert_eq!(listed[..3].iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>(), ["gameboy", "source", "sweet"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
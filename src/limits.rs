@@ -0,0 +1,135 @@
+use image::{DynamicImage, ImageReader, ImageResult, Limits};
+use std::io::{BufRead, Cursor, Seek};
+use crate::filter::{resize, AlphaMode};
+#[cfg(feature = "jxl")]
+use crate::jxl::{self, Reader};
+#[cfg(feature = "jxl")]
+use image::ImageDecoder;
+
+// What to do with an input over the pixel limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OversizePolicy {
+    Reject,
+    Downscale,
+}
+
+impl OversizePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reject" => Some(OversizePolicy::Reject),
+            "downscale" => Some(OversizePolicy::Downscale),
+            _ => None,
+        }
+    }
+}
+
+// Downscaling still has to decode the full image once, so inputs this many times over the limit
+// are rejected either way.
+const DOWNSCALE_CEILING: u64 = 16;
+
+// Bytes a decoder may allocate per pixel: 32-bit float RGBA, the widest format we decode.
+const BYTES_PER_PIXEL: u64 = 16;
+
+fn file_reader(path: &str) -> Result<ImageReader<std::io::BufReader<std::fs::File>>, String> {
+    ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| format!("Failed to load image {}: {}", path, e))
+}
+
+fn memory_reader<'a>(bytes: &'a [u8], path: &str) -> Result<ImageReader<Cursor<&'a [u8]>>, String> {
+    ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| format!("Failed to load image {}: {}", path, e))
+}
+
+// Opens an untrusted image without letting a decompression bomb allocate more than the limit
+// allows: the size is read from the header first, and the decoder is capped to that size.
+// Returns the image and, when it was downscaled, its original dimensions.
+pub fn open_limited(path: &str, max_pixels: u64, policy: OversizePolicy) -> Result<(DynamicImage, Option<(u32, u32)>), String> {
+    decode_limited(|| file_reader(path), path, max_pixels, policy)
+}
+
+// Like open_limited for an image already in memory, e.g. read from stdin; `path` names it in
+// messages.
+pub fn load_limited(bytes: &[u8], path: &str, max_pixels: u64, policy: OversizePolicy) -> Result<(DynamicImage, Option<(u32, u32)>), String> {
+    decode_limited(|| memory_reader(bytes, path), path, max_pixels, policy)
+}
+
+fn dimensions<R: BufRead + Seek>(reader: ImageReader<R>) -> ImageResult<(u32, u32)> {
+    #[cfg(feature = "jxl")]
+    let reader: ImageReader<R> = match jxl::reader(reader)? {
+        Reader::Jxl(decoder) => return Ok(decoder.dimensions()),
+        Reader::Image(reader) => reader,
+    };
+    reader.into_dimensions()
+}
+
+fn decode<R: BufRead + Seek>(reader: ImageReader<R>, limits: Limits) -> ImageResult<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    let reader: ImageReader<R> = match jxl::reader(reader)? {
+        Reader::Jxl(mut decoder) => {
+            decoder.set_limits(limits)?;
+            return DynamicImage::from_decoder(*decoder);
+        },
+        Reader::Image(reader) => reader,
+    };
+    let mut reader: ImageReader<R> = reader;
+    reader.limits(limits);
+    reader.decode()
+}
+
+fn decode_limited<R, F>(reader: F, path: &str, max_pixels: u64, policy: OversizePolicy) -> Result<(DynamicImage, Option<(u32, u32)>), String>
+where
+    R: BufRead + Seek,
+    F: Fn() -> Result<ImageReader<R>, String>,
+{
+    let (width, height) = dimensions(reader()?).map_err(|e| format!("Failed to load image {}: {}", path, e))?;
+    let pixels: u64 = width as u64 * height as u64;
+    let allowed: u64 = match policy {
+        _ if pixels <= max_pixels => max_pixels,
+        OversizePolicy::Reject => return Err(format!("{} has {}x{} pixels, more than the limit of {}", path, width, height, max_pixels)),
+        OversizePolicy::Downscale if pixels > max_pixels.saturating_mul(DOWNSCALE_CEILING) => {
+            return Err(format!("{} has {}x{} pixels, too many to downscale to {}", path, width, height, max_pixels));
+        },
+        OversizePolicy::Downscale => pixels,
+    };
+
+    let mut limits: Limits = Limits::default();
+    limits.max_image_width = Some(width);
+    limits.max_image_height = Some(height);
+    limits.max_alloc = Some(allowed.saturating_mul(BYTES_PER_PIXEL));
+    let image: DynamicImage = decode(reader()?, limits).map_err(|e| format!("Failed to load image {}: {}", path, e))?;
+    if pixels <= max_pixels {
+        return Ok((image, None));
+    }
+
+    let scale: f64 = (max_pixels as f64 / pixels as f64).sqrt();
+    let new_width: u32 = ((width as f64 * scale) as u32).max(1);
+    let new_height: u32 = ((height as f64 * scale) as u32).max(1);
+    Ok((resize(&image, new_width, new_height, AlphaMode::Premultiplied), Some((width, height))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn oversized_inputs_are_rejected_or_downscaled() {
+        let path: std::path::PathBuf = std::env::temp_dir().join(format!("image_rust_limits_{}.png", std::process::id()));
+        RgbImage::from_pixel(40, 30, Rgb([9, 9, 9])).save(&path).unwrap();
+        let path: &str = path.to_str().unwrap();
+
+        let (image, original) = open_limited(path, 1200, OversizePolicy::Reject).unwrap();
+        assert_eq!((image.width(), original), (40, None));
+        assert!(open_limited(path, 1199, OversizePolicy::Reject).is_err());
+        let (image, original) = open_limited(path, 300, OversizePolicy::Downscale).unwrap();
+        assert_eq!((image.width(), image.height(), original), (20, 15, Some((40, 30))));
+        assert!(open_limited(path, 50, OversizePolicy::Downscale).is_err());
+
+        let bytes: Vec<u8> = std::fs::read(path).unwrap();
+        assert_eq!(load_limited(&bytes, "-", 1200, OversizePolicy::Reject).unwrap().0.to_rgb8(), image::open(path).unwrap().to_rgb8());
+        assert!(load_limited(&bytes, "-", 1199, OversizePolicy::Reject).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,119 +1,814 @@
+mod analyze_command;
+mod animate_command;
+mod cli;
+mod completions_command;
+mod generate_command;
+mod help_command;
+mod info_command;
+mod pack_command;
+mod palette_command;
+mod registry;
+mod replay_command;
+mod report;
+mod stats;
+mod sweep_command;
+mod variations;
+
+use cli::{Cli, Command, FilterArgs, OutputFormat};
+use clap::Parser;
+use filter::batch::{batch_jobs, is_batch_input};
 use filter::filter::*;
-use image::{ DynamicImage, GrayImage, ImageBuffer, Luma, Rgb };
+use filter::braille::braille;
+use filter::interlace::encode_interlaced;
+use filter::config::Settings;
+use filter::encode::{encode_auto, fit_to_size, save_image};
+use filter::error::Error;
+use filter::library::PaletteSource;
+use filter::limits::{load_limited, open_limited};
+use filter::manifest::{rename_by_hash, Manifest};
+use filter::palette::{default_colors, write_index_map, write_xterm_map, PaletteMatcher};
+use filter::pipeline::{materialize, run_operations, RunOptions};
+use filter::determinism;
+use filter::observer::NoopObserver;
+use filter::sink::{FileSink, OutputSink, StepsSink, WriterSink};
+use image::{ DynamicImage, GenericImageView, GrayImage, ImageFormat, RgbImage };
+use registry::ParseContext;
+use report::{report_path, write_report};
+use stats::RunStats;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// Progress and result messages; they go to stderr while the image itself is written to stdout.
+macro_rules! status {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
+
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    let width: u32 = width.parse().ok()?;
+    let height: u32 = height.parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+// WxH where each side is pixels or a percentage of that side, or a single percentage for both.
+fn parse_geometry_size(value: &str) -> Option<(Length, Length)> {
+    let (width, height) = match value.split_once('x') {
+        Some((width, height)) => (Length::parse(width)?, Length::parse(height)?),
+        None if value.ends_with('%') => (Length::parse(value)?, Length::parse(value)?),
+        None => return None,
+    };
+    if width == Length::Pixels(0) || height == Length::Pixels(0) {
+        return None;
+    }
+    Some((width, height))
+}
+
+// Comma-separated numbers, optionally wrapped as name(...).
+fn parse_numbers(value: &str, name: &str) -> Option<Vec<f32>> {
+    let inner: &str = value.strip_prefix(name).and_then(|rest| rest.strip_prefix('(')?.strip_suffix(')')).unwrap_or(value);
+    inner.split(',').map(|number| number.trim().parse::<f32>().ok().filter(|number| number.is_finite())).collect()
+}
+
+fn modified_time<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn is_up_to_date(input_path: &str, output_path: &str, palette: &PaletteSource, operations: &[FilterOperation]) -> bool {
+    let output_time: SystemTime = match modified_time(output_path) {
+        Some(time) => time,
+        None => return false,
+    };
+
+    let mut sources: Vec<&Path> = vec![Path::new(input_path)];
+    if operations.iter().any(|op| matches!(op, FilterOperation::Palette | FilterOperation::PaletteDownscale(..))) {
+        if let Some(path) = palette.path() {
+            sources.push(path);
+        }
+    }
+
+    sources.iter().all(|source| match modified_time(source) {
+        Some(time) => time <= output_time,
+        None => false,
+    })
+}
 
-fn apply() {
-    let args: Vec<String> = std::env::args().collect();
-     
+fn preview_path(output_path: &str) -> String {
+    let path: &Path = Path::new(output_path);
+    let stem: String = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_name: String = match path.extension() {
+        Some(extension) => format!("{}.preview.{}", stem, extension.to_string_lossy()),
+        None => format!("{}.preview", stem),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+// One entry of --sizes: a scale of the output (0.5x) or a width in pixels keeping the aspect ratio.
+#[derive(Debug, Clone, Copy)]
+enum OutputSize {
+    Scale(f32),
+    Width(u32),
+}
+
+impl OutputSize {
+    fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            OutputSize::Scale(scale) => (((width as f32 * scale).round() as u32).max(1), ((height as f32 * scale).round() as u32).max(1)),
+            OutputSize::Width(target) => (target, ((height as f64 * target as f64 / width as f64).round() as u32).max(1)),
+        }
+    }
+
+    // output.png becomes output@0.5x.png or output-512w.png.
+    fn path(&self, output_path: &str) -> String {
+        let path: &Path = Path::new(output_path);
+        let stem: String = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let suffix: String = match *self {
+            OutputSize::Scale(scale) => format!("@{}x", scale),
+            OutputSize::Width(width) => format!("-{}w", width),
+        };
+        let file_name: String = match path.extension() {
+            Some(extension) => format!("{}{}.{}", stem, suffix, extension.to_string_lossy()),
+            None => format!("{}{}", stem, suffix),
+        };
+        path.with_file_name(file_name).to_string_lossy().into_owned()
+    }
+}
+
+fn parse_output_size(entry: &str) -> Result<OutputSize, String> {
+    let size: Option<OutputSize> = match entry.strip_suffix('x') {
+        Some(scale) => scale.parse::<f32>().ok().filter(|scale| scale.is_finite() && *scale > 0.0).map(OutputSize::Scale),
+        None => entry.parse::<u32>().ok().filter(|&width| width > 0).map(OutputSize::Width),
+    };
+    size.ok_or_else(|| "expected a scale such as 0.5x or a width such as 512".to_string())
+}
+
+// Renames the saved output after its content hash and records it in the directory's manifest.
+fn name_output_by_hash(input_path: &str, output_path: &str) -> Result<PathBuf, String> {
+    let hashed: PathBuf = rename_by_hash(Path::new(output_path))?;
+    let directory: &Path = hashed.parent().unwrap_or(Path::new(""));
+    let mut manifest: Manifest = Manifest::load(directory)?;
+    manifest.insert(input_path, &hashed);
+    manifest.save()?;
+    Ok(hashed)
+}
+
+// The palette of the last -pal or -quant step: the loaded palette, or the colors -quant left in
+// the image. Without either, the default palette.
+fn index_map_colors(image: &RgbImage, operations: &[FilterOperation], options: &RunOptions) -> Vec<Color> {
+    let last: Option<&FilterOperation> = operations.iter().rev()
+        .find(|op| matches!(op, FilterOperation::Palette | FilterOperation::Quantize(..) | FilterOperation::PaletteDownscale(..)));
+    match last {
+        Some(FilterOperation::Quantize(..)) => {
+            let mut colors: Vec<Color> = Vec::new();
+            for pixel in image.pixels() {
+                let color: Color = Color::from_rgb(pixel);
+                if !colors.iter().any(|known| (known.r, known.g, known.b) == (color.r, color.g, color.b)) {
+                    colors.push(color);
+                }
+                // More than an index map holds; write_index_map reports it
+                if colors.len() > 256 {
+                    break;
+                }
+            }
+            colors
+        },
+        Some(_) => match options.palette.load() {
+            Ok(palette) => palette.get_colors().iter().map(Color::from_rgb).collect(),
+            Err(_) => default_colors(),
+        },
+        None => default_colors(),
+    }
+}
+
+fn apply(args: &[String]) -> Result<(), Error> {
+    let settings: Settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            return Err(Error::InvalidArgument(format!("Configuration error: {}", e)));
+        }
+    };
+
+    let normalized: Vec<String> = cli::with_subcommand(args);
+    let command: Command = match Cli::try_parse_from(&normalized) {
+        Ok(cli) => cli.command,
+        Err(e) if !e.use_stderr() => {
+            let _ = e.print();
+            return Ok(());
+        },
+        Err(e) => e.exit(),
+    };
+    match command {
+        Command::Filter(filter) => run_filter(args, &normalized, *filter, settings),
+        Command::Palette(palette) => palette_command::run(&palette.args, &settings.palette_dir.value),
+        Command::Info(info) => info_command::run(&info.input),
+        Command::Analyze(analyze) => analyze_command::run(&analyze.args),
+        Command::Help(help) => help_command::run(&help.args),
+        Command::Completions(completions) => completions_command::run(completions.shell),
+        Command::Generate(generate) => generate_command::run(&generate.args),
+        Command::Replay(replay) => replay_command::run(&replay.args),
+        Command::AnimateEffect(animate) => animate_command::run(&animate.args),
+        Command::Pack(pack) => pack_command::run(&pack.args),
+        Command::Sweep(sweep) => sweep_command::run(&sweep.args, &settings),
+    }
+}
+
+// --verify-determinism without operations compares this build with the published reference digest.
+fn verify_reference_digest() -> Result<(), Error> {
+    let digest: String = determinism::reference_digest();
+    if digest != determinism::REFERENCE_DIGEST {
+        return Err(Error::Operation(format!("Not deterministic: the reference workload gives {} instead of {}", digest, determinism::REFERENCE_DIGEST)));
+    }
+    println!("Deterministic: the reference workload matches the published digest {}", digest);
+    Ok(())
+}
+
+// The operations in order, and the arguments they came from for --variations.
+fn parse_operations(args: &[String], settings: &Settings, named_palette: &mut Option<PaletteSource>) -> Result<(Vec<FilterOperation>, Vec<String>), Error> {
+    let mut operations: Vec<FilterOperation> = Vec::new();
+    let mut operation_args: Vec<String> = Vec::new();
+    for arg in args {
+        let mut context: ParseContext = ParseContext { palette_dir: &settings.palette_dir.value, named_palette };
+        match registry::parse(arg, &mut context) {
+            Some(parsed) => {
+                operations.extend(parsed.map_err(Error::InvalidArgument)?);
+                operation_args.push(arg.clone());
+            },
+            None => {
+                let options: Vec<String> = cli::filter_options();
+                let options: Vec<&str> = options.iter().map(String::as_str).collect();
+                return Err(Error::InvalidArgument(match registry::suggestion(arg, &options) {
+                    Some(suggestion) => format!("Unknown operation: {} (did you mean {}?)", arg, suggestion),
+                    None => format!("Unknown operation: {}", arg),
+                }));
+            },
+        }
+    }
+    Ok((operations, operation_args))
+}
+
+// The changed parameters and operations of every --variations variant.
+type Variants = Vec<(String, Vec<FilterOperation>)>;
+
+// Every variant is the operations with their own jittered values, run on the same decoded input.
+fn parse_variants(filter: &FilterArgs, operation_args: &[String], settings: &Settings, named_palette: &mut Option<PaletteSource>) -> Result<Option<Variants>, Error> {
+    match (filter.variations, filter.jitter.is_empty()) {
+        (Some(count), false) => {
+            let mut parsed: Variants = Vec::new();
+            for variant in variations::variants(operation_args, count, &filter.jitter).map_err(Error::InvalidArgument)? {
+                let (operations, _) = parse_operations(&variant.arguments, settings, named_palette)?;
+                parsed.push((variant.changed.join(" "), operations));
+            }
+            Ok(Some(parsed))
+        },
+        (Some(_), true) => Err(Error::InvalidArgument("--variations needs --jitter=OPERATION[.PARAMETER]:RANGE,...".to_string())),
+        (None, false) => Err(Error::InvalidArgument("--jitter needs --variations=N".to_string())),
+        (None, true) => Ok(None),
+    }
+}
+
+// Input and output path pairs; a batch saves every input into the output directory.
+fn batch_outputs(inputs: &[String], output_path: &str, batch: bool, auto_format: bool) -> Result<Vec<(String, String)>, Error> {
+    if !batch {
+        return Ok(vec![(inputs[0].clone(), output_path.to_string())]);
+    }
+    let mut jobs: Vec<(PathBuf, PathBuf)> = batch_jobs(inputs, Path::new(output_path)).map_err(Error::InvalidArgument)?;
+    // Outputs are named after the inputs, so --format=auto gives them the .png they hold
+    if auto_format {
+        let mut outputs: HashSet<PathBuf> = HashSet::new();
+        for (_, output) in jobs.iter_mut() {
+            output.set_extension("png");
+            if !outputs.insert(output.clone()) {
+                return Err(Error::InvalidArgument(format!("More than one input would be saved as {}", output.display())));
+            }
+        }
+    }
+    Ok(jobs.iter().map(|(input, output)| (input.to_string_lossy().into_owned(), output.to_string_lossy().into_owned())).collect())
+}
+
+fn explain(settings: &Settings, palette: &PaletteSource, operations: &[FilterOperation], jobs: &[(String, String)]) {
+    println!("{}", settings.explain());
+    println!("Palette: {}", palette);
+    println!("Operations:");
+    for op in operations {
+        println!("  {:?}", op);
+    }
+    for (input_path, output_path) in jobs {
+        println!("Input: {}\nOutput: {}", input_path, output_path);
+    }
+}
+
+// The filter subcommand: `filter [operations] [options] input... output`.
+fn run_filter(args: &[String], normalized: &[String], filter: FilterArgs, mut settings: Settings) -> Result<(), Error> {
+    // The operations and paths, after the program name as in the command line
+    let filter_args: Vec<String> = args.iter().take(1).cloned().chain(filter.args.iter().cloned()).collect();
+    let args: &[String] = &filter_args;
+    // with_subcommand put the options first, up to "--"
+    let option_args: &[String] = &normalized[2..normalized.iter().position(|arg| arg == "--").unwrap_or(2)];
+
+    if args.len() >= 3 && cli::operation_help(&args[1..]) {
+        return Ok(());
+    }
+    if args.len() == 1 && filter.verify_determinism {
+        return verify_reference_digest();
+    }
     if args.len() < 3 {
-        println!("Usage: cargo r [filter operations] input_path output_path");
-        println!("Filter operations:");
-        println!("  -pal: Apply palette described in ./palette.json");
-        println!("  -pixpal: Apply pixelation and palette");
-        println!("  -pix=N: Apply pixelation with size N (default 8)");
-        println!("  -floyd: Apply Floyd-Steinberg dithering");
-        println!("  -rev: Reverse colors");
-        println!("Example: cargo r -pal -pix=4 -floyd input.png output.png");
-        return;
-    }
-     
-    let input_path: &String = &args[args.len() - 2];
+        cli::print_filter_usage();
+        return Ok(());
+    }
+
     let output_path: &String = &args[args.len() - 1];
-    
-    let mut operations: Vec<FilterOperation> = Vec::new();
-    for i in 1..(args.len() - 2) {
-         let arg: &String = &args[i];
-         
-         if arg == "-pal" {
-             operations.push(FilterOperation::Palette);
-         } else if arg == "-pixpal" {
-             operations.push(FilterOperation::Pixelate(8));
-             operations.push(FilterOperation::Palette);
-         } else if arg == "-floyd" {
-             operations.push(FilterOperation::FloydSteinberg);
-         } else if arg.starts_with("-pix=") {
-             if let Some(size_str) = arg.strip_prefix("-pix=") {
-                 if let Ok(size) = size_str.parse::<u32>(){
-                     if size != 0 {
-                         operations.push(FilterOperation::Pixelate(size));
-                     }
-                 } else {
-                     println!("Invalid pixel size: {}", size_str);
-                     return;
-                 }
-             }
-         } else if arg == "-pix" {
-            operations.push(FilterOperation::Pixelate(8));
-         } else if arg == "-rev" {
-            operations.push(FilterOperation::Reverse);
-         }else {
-             println!("Unknown operation: {}", arg);
-             return;
-         }
-     }
-     
+    // The inputs are the trailing arguments that are not operations or options, so several files
+    // or a shell-expanded glob can go to one output directory
+    let first_input: usize = (1..args.len() - 1).rev().take_while(|&i| !args[i].starts_with('-') || args[i] == "-").last().unwrap_or(args.len() - 2);
+    let inputs: &[String] = &args[first_input..args.len() - 1];
+    let batch: bool = inputs.len() > 1 || is_batch_input(&inputs[0]);
+    cli::check_paths(&inputs[0], output_path).map_err(Error::InvalidArgument)?;
+
+    let mut named_palette: Option<PaletteSource> = None;
+    let (operations, operation_args) = parse_operations(&args[1..first_input], &settings, &mut named_palette)?;
+    if let Some(metric) = filter.distance {
+        settings.set_distance(metric);
+    }
+    let auto_format: bool = filter.format == Some(OutputFormat::Auto);
+    if let Some(OutputFormat::Fixed(format)) = filter.format {
+        settings.set_format(format);
+    }
+
     if operations.is_empty() {
-        println!("No filter operations specified!");
-        return;
+        return Err(Error::InvalidArgument("No filter operations specified!".to_string()));
     }
-     
-    let mut image: DynamicImage = match image::open(input_path) {
-         Ok(img) => img,
-         Err(e) => {
-             println!("Failed to load image {}: {}", input_path, e);
-             return;
+    let variants: Option<Variants> = parse_variants(&filter, &operation_args, &settings, &mut named_palette)?;
+
+    if filter.data_texture {
+        let unsafe_operations: Vec<&str> = operations.iter().filter(|op| !op.is_data_safe()).map(|op| op.name()).collect();
+        if !unsafe_operations.is_empty() {
+            return Err(Error::InvalidArgument(format!("--data-texture only allows resize, crop, canvas, pad, split and combine, not: {}", unsafe_operations.join(", "))));
         }
+        if filter.target_size.is_some() {
+            return Err(Error::InvalidArgument("--data-texture cannot be combined with --target-size, which reduces colors".to_string()));
+        }
+    }
+
+    if batch && (filter.emit_index_map.is_some() || filter.emit_xterm.is_some() || filter.emit_braille.is_some()) {
+        return Err(Error::InvalidArgument("--emit-index-map, --emit-xterm and --emit-braille write one file and cannot be used with several inputs".to_string()));
+    }
+    // "-" reads the input from stdin or writes the output to stdout
+    let from_stdin: bool = inputs.iter().any(|input| input == "-");
+    let to_stdout: bool = output_path == "-";
+    if batch && (from_stdin || to_stdout) {
+        return Err(Error::InvalidArgument("stdin and stdout ('-') only work with a single input and output".to_string()));
+    }
+    if to_stdout && settings.format.value.is_none() && !auto_format {
+        return Err(Error::InvalidArgument("Writing to stdout needs --format=FORMAT, e.g. --format=png".to_string()));
+    }
+    if to_stdout && (filter.preview.is_some() || filter.name_by_hash || filter.report || filter.only_changed || !filter.sizes.is_empty() || variants.is_some()) {
+        return Err(Error::InvalidArgument("--preview, --name-by-hash, --report, --only-changed, --sizes and --variations need an output file, not stdout".to_string()));
+    }
+    if auto_format && !batch && !to_stdout && ImageFormat::from_path(output_path).ok() != Some(ImageFormat::Png) {
+        return Err(Error::InvalidArgument(format!("--format=auto writes PNG, so the output needs a .png extension: {}", output_path)));
+    }
+    let jobs: Vec<(String, String)> = batch_outputs(inputs, output_path, batch, auto_format)?;
+
+    let palette: PaletteSource = named_palette.unwrap_or_else(|| PaletteSource::File(settings.palette_path()));
+    if filter.explain {
+        explain(&settings, &palette, &operations, &jobs);
+        return Ok(());
+    }
+
+    let mut stdin_bytes: Option<Vec<u8>> = None;
+    if from_stdin {
+        let mut bytes: Vec<u8> = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes).map_err(|source| Error::Io { path: PathBuf::from("-"), source })?;
+        stdin_bytes = Some(bytes);
+    }
+
+    if batch {
+        std::fs::create_dir_all(output_path).map_err(|source| Error::Io { path: PathBuf::from(output_path), source })?;
+    }
+
+    let palette_lock: Option<String> = match filter.lock_palette_from.as_deref() {
+        Some("first") => {
+            let directory: &Path = Path::new(&jobs[0].1).parent().unwrap_or(Path::new(""));
+            Some(directory.join("palette.lock.json").to_string_lossy().into_owned())
+        },
+        _ => filter.lock_palette_from.clone(),
     };
-     
-    let mut gray_image_option: Option<GrayImage> = None;
-     
-    for op in operations {
-        println!("Applying {:?}...", op);
-         
-        match op {
-            FilterOperation::Palette => {
-               if gray_image_option.is_some() {
-                   let gray: ImageBuffer<Luma<u8>, Vec<u8>> = gray_image_option.take().unwrap();
-                   image = DynamicImage::ImageLuma8(gray).into();
-               }
-               let rgb_image: ImageBuffer<Rgb<u8>, Vec<u8>> = apply_palette(&image, "palette.json");
-               image = DynamicImage::ImageRgb8(rgb_image);
-               gray_image_option = None;
+
+    // A palette that cannot be read is an error rather than a fallback to the default colors.
+    // It is read once here, not again for every input and operation.
+    let palette: PaletteSource = if operations.iter().any(|op| matches!(op, FilterOperation::Palette | FilterOperation::PaletteDownscale(..))) {
+        palette.preload()?
+    } else {
+        palette
+    };
+    let options: RunOptions = RunOptions {
+        parallel_dither: filter.parallel_dither,
+        serpentine: filter.dither_serpentine,
+        threads: settings.threads.value,
+        palette,
+        alpha_mode: filter.alpha,
+        blend_space: filter.blend_space,
+        palette_lock,
+        auto_dither: filter.auto_dither,
+        border: filter.border,
+        data_texture: filter.data_texture,
+        palette_mode: filter.palette_mode,
+        distance: settings.distance.value,
+        palette_lut: filter.palette_lut,
+        messages_to_stderr: to_stdout,
+        deadline: None,
+    };
+
+    let run: FilterRun = FilterRun {
+        arguments: option_args.iter().chain(&args[1..first_input]).cloned().collect(),
+        filter,
+        settings,
+        options,
+        operations,
+        variants,
+        stdin_bytes,
+        batch,
+        auto_format,
+        to_stdout,
+    };
+    // One set of --stats numbers for the whole batch, printed after the last input
+    let mut stats: RunStats = RunStats::new();
+    let mut failures: usize = 0;
+    for (input_path, output_path) in &jobs {
+        match run.process(input_path, output_path, &mut stats) {
+            Ok(()) => {},
+            Err(e) if !batch => return Err(e),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                failures += 1;
+            },
+        }
+    }
+    match run.filter.stats.as_deref() {
+        Some("json") => status!(to_stdout, "{}", stats.to_json()),
+        Some(_) => status!(to_stdout, "{}", stats.report()),
+        None => {}
+    }
+    if failures > 0 {
+        return Err(Error::Operation(format!("{} of {} images failed", failures, jobs.len())));
+    }
+    Ok(())
+}
+
+// The --timeout of one input: loading, processing and saving share it, checked between steps.
+// Running out fails only this input, so a batch goes on with the next one.
+struct Deadline<'a> {
+    at: Option<Instant>,
+    limit: Duration,
+    input_path: &'a str,
+}
+
+impl Deadline<'_> {
+    fn passed(&self) -> bool {
+        self.at.is_some_and(|at| Instant::now() >= at)
+    }
+
+    fn error(&self) -> Error {
+        Error::Operation(format!("Timed out: {} took longer than {} s", self.input_path, self.limit.as_secs_f64()))
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.passed() { Err(self.error()) } else { Ok(()) }
+    }
+
+    // A step that was cut short by the deadline reports the timeout instead of its own error.
+    fn blame(&self, error: Error) -> Error {
+        if self.passed() { self.error() } else { error }
+    }
+}
+
+// What every input of one `filter` run shares.
+struct FilterRun {
+    filter: FilterArgs,
+    settings: Settings,
+    options: RunOptions,
+    operations: Vec<FilterOperation>,
+    variants: Option<Variants>,
+    // The options and operations as given, recorded in reports
+    arguments: Vec<String>,
+    stdin_bytes: Option<Vec<u8>>,
+    batch: bool,
+    auto_format: bool,
+    to_stdout: bool,
+}
+
+impl FilterRun {
+    fn process(&self, input_path: &str, output_path: &str, stats: &mut RunStats) -> Result<(), Error> {
+        let to_stdout: bool = self.to_stdout;
+        // With --variations the input is decoded once and every variant saved next to the output
+        let outputs: Vec<(String, &[FilterOperation])> = match &self.variants {
+            Some(variants) => variants.iter().enumerate()
+                .map(|(index, (_, operations))| (variations::variation_path(output_path, index as u32 + 1, variants.len() as u32), operations.as_slice()))
+                .collect(),
+            None => vec![(output_path.to_string(), self.operations.as_slice())],
+        };
+        if self.filter.only_changed && outputs.iter().all(|(output_path, operations)| is_up_to_date(input_path, output_path, &self.options.palette, operations)) {
+            status!(to_stdout, "Skipping {}: {} is up to date", input_path, output_path);
+            return Ok(());
+        }
+
+        let deadline: Deadline = Deadline {
+            at: self.filter.timeout.map(|limit| Instant::now() + limit),
+            limit: self.filter.timeout.unwrap_or_default(),
+            input_path,
+        };
+        let options: RunOptions = RunOptions { deadline: deadline.at, ..self.options.clone() };
+        let decoded: DynamicImage = self.decode(input_path)?;
+        deadline.check()?;
+
+        for (number, (output_path, operations)) in outputs.iter().enumerate() {
+            let output_path: &str = output_path;
+            if let Some(variants) = &self.variants {
+                status!(to_stdout, "Variation {} of {}: {}", number + 1, variants.len(), variants[number].0);
+            }
+            let operations: Vec<FilterOperation> = match self.filter.auto_scale_params {
+                Some(reference) => {
+                    let (width, height) = decoded.dimensions();
+                    let factor: f32 = width.min(height) as f32 / reference as f32;
+                    operations.iter().map(|op| op.scaled_parameters(factor)).collect()
+                },
+                None => operations.to_vec(),
+            };
+
+            if let Some(divisor) = self.filter.preview {
+                self.save_preview(&decoded, &operations, divisor, &options, &deadline, output_path)?;
+            }
+
+            // Several inputs each get their own subdirectory of steps
+            let steps_path: Option<PathBuf> = self.filter.save_steps.as_ref().map(|directory| match self.batch {
+                true => Path::new(directory).join(Path::new(input_path).file_stem().unwrap_or_default()),
+                false => PathBuf::from(directory),
+            });
+            let mut steps_sink: Option<StepsSink> = steps_path.map(StepsSink::new).transpose().map_err(Error::Operation)?;
+            let steps: Option<&mut dyn OutputSink> = steps_sink.as_mut().map(|sink| sink as &mut dyn OutputSink);
+            let (mut image, mut gray_image_option) = run_operations(decoded.clone(), &operations, &options, stats, steps)
+                .map_err(|e| deadline.blame(e))?;
+
+            if self.filter.verify_determinism {
+                let mut first: DynamicImage = image.clone();
+                materialize(&mut first, &mut gray_image_option.clone());
+                self.verify_repeatable(&decoded, &first, &operations, &options, &deadline)?;
+            }
+
+            deadline.check()?;
+            let encoded_output: Option<Vec<u8>> = self.encode(&mut image, &mut gray_image_option, output_path)?;
+            self.write_maps(&image, &gray_image_option, &operations);
+            materialize(&mut image, &mut gray_image_option);
+            let path: PathBuf = self.save(&image, encoded_output, input_path, output_path)?;
+            self.save_sizes(&image, output_path)?;
+
+            if self.filter.report {
+                let palette: Option<String> = if operations.iter().any(|op| matches!(op, FilterOperation::Palette | FilterOperation::PaletteDownscale(..))) { Some(self.options.palette.to_string()) } else { None };
+                match write_report(&self.arguments, Path::new(input_path), &path, &image, palette, &operations, stats) {
+                    Ok(_) => status!(to_stdout, "The report is saved: {}", report_path(&path).display()),
+                    Err(e) => status!(to_stdout, "{}", e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the input from its file or from stdin, within --max-pixels.
+    fn decode(&self, input_path: &str) -> Result<DynamicImage, Error> {
+        match self.filter.max_pixels {
+            Some((limit, policy)) => {
+                let limited: Result<(DynamicImage, Option<(u32, u32)>), String> = match &self.stdin_bytes {
+                    Some(bytes) => load_limited(bytes, input_path, limit, policy),
+                    None => open_limited(input_path, limit, policy),
+                };
+                match limited {
+                    Ok((image, None)) => Ok(image),
+                    Ok((image, Some((width, height)))) => {
+                        eprintln!("Warning: {} has {}x{} pixels, downscaled to {}x{}", input_path, width, height, image.width(), image.height());
+                        Ok(image)
+                    },
+                    Err(e) => Err(Error::Operation(e)),
+                }
             },
-            FilterOperation::Pixelate(size) => {
-               if gray_image_option.is_some() {
-                   let gray: ImageBuffer<Luma<u8>, Vec<u8>> = gray_image_option.take().unwrap();
-                   image = DynamicImage::ImageLuma8(gray).into();
-               }
-               let rgb_image: ImageBuffer<Rgb<u8>, Vec<u8>> = pixelate(&image, size);
-               image = DynamicImage::ImageRgb8(rgb_image);
-               gray_image_option = None;
+            None => {
+                let opened: image::ImageResult<DynamicImage> = match &self.stdin_bytes {
+                    Some(bytes) => decode_bytes(bytes),
+                    None => decode_file(input_path),
+                };
+                match opened {
+                    Ok(image) => Ok(image),
+                    Err(image::ImageError::IoError(source)) => Err(Error::Io { path: PathBuf::from(input_path), source }),
+                    Err(source) => Err(Error::Decode { path: PathBuf::from(input_path), source }),
+                }
             },
-            FilterOperation::FloydSteinberg => {
-               let gray_image: ImageBuffer<Luma<u8>, Vec<u8>> = apply_floyd_steinberg_dithering(&image);
-               gray_image_option = Some(gray_image);
+        }
+    }
+
+    // --preview: the operations scaled down with the image, saved next to the output first.
+    fn save_preview(&self, image: &DynamicImage, operations: &[FilterOperation], divisor: u32, options: &RunOptions, deadline: &Deadline, output_path: &str) -> Result<(), Error> {
+        let (width, height) = image.dimensions();
+        let small_image: DynamicImage = resize(image, (width / divisor).max(1), (height / divisor).max(1), self.filter.alpha);
+        let preview_operations: Vec<FilterOperation> = operations.iter()
+            .map(|op| op.scaled(1.0 / divisor as f32))
+            .collect();
+        // The preview may reuse a locked palette but never creates one from its small image
+        let preview_options: RunOptions = RunOptions { palette_lock: options.palette_lock.clone().filter(|path| Path::new(path).exists()), ..options.clone() };
+        let (mut preview_image, mut preview_gray) = run_operations(small_image, &preview_operations, &preview_options, &mut NoopObserver, None)
+            .map_err(|e| deadline.blame(e))?;
+        materialize(&mut preview_image, &mut preview_gray);
+        let path: String = preview_path(output_path);
+        match save_image(&preview_image, &path, self.settings.format.value, self.settings.quality.value) {
+            Ok(_) => status!(self.to_stdout, "The preview is saved: {}", path),
+            Err(e) => status!(self.to_stdout, "Failed to save preview {}: {}", path, e),
+        }
+        Ok(())
+    }
+
+    // --verify-determinism: runs the operations on the input again and compares the results.
+    fn verify_repeatable(&self, input: &DynamicImage, first: &DynamicImage, operations: &[FilterOperation], options: &RunOptions, deadline: &Deadline) -> Result<(), Error> {
+        let (mut second, mut second_gray) = run_operations(input.clone(), operations, options, &mut NoopObserver, None)
+            .map_err(|e| deadline.blame(e))?;
+        materialize(&mut second, &mut second_gray);
+        let (first, second): (String, String) = (determinism::image_digest(first), determinism::image_digest(&second));
+        // Two runs in one process only show that thread scheduling does not change the result;
+        // other platforms and build profiles are compared through the reference digest
+        if first != second {
+            return Err(Error::Operation(format!("Not repeatable: two runs of {} give {} and {}", deadline.input_path, first, second)));
+        }
+        status!(self.to_stdout, "Repeatable: both runs give {}", first);
+        Ok(())
+    }
+
+    // The output bytes when --target-size, --interlace or --format=auto pick the encoding
+    // themselves; None leaves the encoding to the output sink.
+    fn encode(&self, image: &mut DynamicImage, gray_image_option: &mut Option<GrayImage>, output_path: &str) -> Result<Option<Vec<u8>>, Error> {
+        let mut encoded_output: Option<Vec<u8>> = None;
+        if let Some(kilobytes) = self.filter.target_size {
+            encoded_output = self.fit_target_size(image, gray_image_option, kilobytes, output_path)?;
+        }
+
+        if self.filter.interlace && encoded_output.is_some() {
+            eprintln!("Warning: --interlace is ignored with --target-size, which picks the encoding itself");
+        } else if self.filter.interlace {
+            match self.settings.format.value.or_else(|| ImageFormat::from_path(output_path).ok()) {
+                Some(format) => {
+                    materialize(image, gray_image_option);
+                    match encode_interlaced(image, format, self.settings.quality.value) {
+                        Ok(Some(bytes)) => encoded_output = Some(bytes),
+                        Ok(None) => eprintln!("Warning: --interlace only applies to PNG, GIF and JPEG outputs"),
+                        Err(e) => {
+                            return Err(Error::Operation(format!("Failed to encode image {}: {}", output_path, e)));
+                        }
+                    }
+                },
+                None => eprintln!("Warning: --interlace only applies to PNG, GIF and JPEG outputs"),
+            }
+        }
+
+        if self.auto_format && encoded_output.is_none() {
+            materialize(image, gray_image_option);
+            match encode_auto(image) {
+                Ok((bytes, chosen)) => {
+                    status!(self.to_stdout, "Format: {} ({} KB)", chosen, bytes.len().div_ceil(1024));
+                    encoded_output = Some(bytes);
+                },
+                Err(source) => {
+                    return Err(Error::Encode { path: PathBuf::from(output_path), source });
+                }
+            }
+        }
+        Ok(encoded_output)
+    }
+
+    // --target-size: fewer colors until a PNG or GIF output fits, which replaces the image.
+    fn fit_target_size(&self, image: &mut DynamicImage, gray_image_option: &mut Option<GrayImage>, kilobytes: u64, output_path: &str) -> Result<Option<Vec<u8>>, Error> {
+        let format: Option<ImageFormat> = self.settings.format.value.or_else(|| ImageFormat::from_path(output_path).ok());
+        let format: ImageFormat = match format {
+            Some(format @ (ImageFormat::Png | ImageFormat::Gif)) => format,
+            _ => {
+                eprintln!("Warning: --target-size only applies to PNG and GIF outputs");
+                return Ok(None);
+            }
+        };
+        materialize(image, gray_image_option);
+        match fit_to_size(image, format, kilobytes as usize * 1024, self.options.distance) {
+            Ok((fitted, fits)) => {
+                if !fits {
+                    eprintln!("Warning: could not fit {} into {} KB, keeping the smallest result", output_path, kilobytes);
+                }
+                if let Some(colors) = fitted.colors {
+                    let dithering: &str = if fitted.dithered { " with dithering" } else { "" };
+                    status!(self.to_stdout, "Reduced to {} colors{} ({} KB)", colors, dithering, fitted.bytes.len().div_ceil(1024));
+                }
+                *image = fitted.image;
+                Ok(Some(fitted.bytes))
             },
-            FilterOperation::Reverse => {
-               if gray_image_option.is_some() {
-                   let gray: ImageBuffer<Luma<u8>, Vec<u8>> = gray_image_option.take().unwrap();
-                   image = DynamicImage::ImageLuma8(gray).into();
-                  }
-               let rgb_image: ImageBuffer<Rgb<u8>, Vec<u8>> = reverse(&image);
-               image = DynamicImage::ImageRgb8(rgb_image);
-               gray_image_option = None;
+            Err(source) => Err(Error::Encode { path: PathBuf::from(output_path), source }),
+        }
+    }
+
+    // --emit-index-map, --emit-xterm and --emit-braille; a map that cannot be written is reported
+    // and the run goes on.
+    fn write_maps(&self, image: &DynamicImage, gray_image_option: &Option<GrayImage>, operations: &[FilterOperation]) {
+        let to_stdout: bool = self.to_stdout;
+        let rgb_image = || match gray_image_option {
+            Some(gray) => DynamicImage::ImageLuma8(gray.clone()).into_rgb8(),
+            None => image.to_rgb8(),
+        };
+
+        if let Some(path) = &self.filter.emit_index_map {
+            if !operations.iter().any(|op| matches!(op, FilterOperation::Palette | FilterOperation::Quantize(..) | FilterOperation::PaletteDownscale(..))) {
+                eprintln!("Warning: --emit-index-map without -pal or -quant maps against the default palette");
+            }
+            let rgb_image: RgbImage = rgb_image();
+            let palette: PaletteMatcher = PaletteMatcher::new(&index_map_colors(&rgb_image, operations, &self.options), self.options.distance);
+            match write_index_map(&rgb_image, &palette, path) {
+                Ok(_) => status!(to_stdout, "The index map is saved: {}", path),
+                Err(e) => status!(to_stdout, "Failed to write index map {}: {}", path, e),
+            }
+        }
+
+        if let Some(path) = &self.filter.emit_xterm {
+            match write_xterm_map(&rgb_image(), self.options.distance, path) {
+                Ok(_) => status!(to_stdout, "The xterm color map is saved: {}", path),
+                Err(e) => status!(to_stdout, "Failed to write xterm color map {}: {}", path, e),
+            }
+        }
+
+        if let Some(path) = &self.filter.emit_braille {
+            // After -floyd the color image is still the undithered input, which gives the cell tints
+            let dots: GrayImage = match gray_image_option {
+                Some(gray) => gray.clone(),
+                None => image.to_luma8(),
+            };
+            let colors: Option<RgbImage> = if self.filter.braille_color { Some(image.to_rgb8()) } else { None };
+            match std::fs::write(path, braille(&dots, colors.as_ref())) {
+                Ok(_) => status!(to_stdout, "The braille text is saved: {}", path),
+                Err(e) => status!(to_stdout, "Failed to write braille text {}: {}", path, e),
             }
         }
     }
-     
-    if let Some(gray_image) = gray_image_option {
-        save(output_path, gray_image);
-    } else {
-        match image.save(output_path) {
-            Ok(_) => println!("The image is saved: {}", output_path),
-            Err(e) => println!("Failed to save image {}: {}", output_path, e),
+
+    // Writes the result to the output file or stdout, through the bytes `encode` chose or an
+    // output sink, and returns where it was saved.
+    fn save(&self, image: &DynamicImage, encoded_output: Option<Vec<u8>>, input_path: &str, output_path: &str) -> Result<PathBuf, Error> {
+        let saved: Result<(), String> = match encoded_output {
+            Some(bytes) if self.to_stdout => {
+                let mut stdout: std::io::StdoutLock = std::io::stdout().lock();
+                stdout.write_all(&bytes).and_then(|_| stdout.flush()).map_err(|e| format!("Failed to write image to stdout: {}", e))
+            },
+            Some(bytes) => std::fs::write(output_path, bytes).map_err(|e| format!("Failed to save image {}: {}", output_path, e)),
+            None => {
+                let mut sink: Box<dyn OutputSink> = match self.settings.format.value {
+                    Some(format) if self.to_stdout => Box::new(WriterSink::new(std::io::stdout().lock(), format).with_quality(self.settings.quality.value)),
+                    _ => Box::new(FileSink::new(output_path, self.settings.format.value, self.settings.quality.value)),
+                };
+                sink.finish(image)
+            },
+        };
+        let saved_path: Result<PathBuf, String> = match saved {
+            Ok(_) if self.filter.name_by_hash => name_output_by_hash(input_path, output_path),
+            Ok(_) => Ok(PathBuf::from(output_path)),
+            Err(e) => Err(e),
+        };
+        let path: PathBuf = saved_path.map_err(Error::Operation)?;
+        if self.to_stdout {
+            eprintln!("The image is written to stdout");
+        } else {
+            println!("The image is saved: {}", path.display());
         }
+        Ok(path)
+    }
+
+    // Smaller copies of the finished image, so the operations and the decode run only once.
+    fn save_sizes(&self, image: &DynamicImage, output_path: &str) -> Result<(), Error> {
+        for size in &self.filter.sizes {
+            let (width, height) = size.dimensions(image.width(), image.height());
+            let sized_path: String = size.path(output_path);
+            match save_image(&resize(image, width, height, self.filter.alpha), &sized_path, self.settings.format.value, self.settings.quality.value) {
+                Ok(_) => println!("The image is saved: {} ({}x{})", sized_path, width, height),
+                Err(source) => {
+                    return Err(Error::Encode { path: PathBuf::from(sized_path), source });
+                }
+            }
+        }
+        Ok(())
     }
 }
 
 fn main() {
-     apply();       
+     let args: Vec<String> = std::env::args().collect();
+     if let Err(e) = apply(&args) {
+         if let Error::InvalidArgument(message) = &e {
+             cli::usage_error(&cli::with_subcommand(&args)[1], message);
+         }
+         eprintln!("Error: {}", e);
+         std::process::exit(e.exit_code());
+     }
 }
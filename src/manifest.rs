@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::determinism::sha256_hex;
+
+// manifest.json next to content-addressed outputs: input path -> output path, kept sorted so
+// the file diffs cleanly between builds.
+pub struct Manifest {
+    path: PathBuf,
+    pub entries: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    // Reads the manifest in `directory`, or starts an empty one.
+    pub fn load(directory: &Path) -> Result<Self, String> {
+        let path: PathBuf = directory.join("manifest.json");
+        let entries: BTreeMap<String, String> = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{} is not a valid manifest: {}", path.display(), e))?,
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(Manifest { path, entries })
+    }
+
+    pub fn insert(&mut self, input: &str, output: &Path) {
+        self.entries.insert(input.to_string(), output.to_string_lossy().into_owned());
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let text: String = serde_json::to_string_pretty(&self.entries).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, text + "\n").map_err(|e| format!("failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+// Moves a written output to <sha256 of its bytes>.<extension> in the same directory and returns
+// the new path. Identical results end up in the same file.
+pub fn rename_by_hash(path: &Path) -> Result<PathBuf, String> {
+    let bytes: Vec<u8> = std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let mut file_name: String = sha256_hex(&bytes);
+    if let Some(extension) = path.extension() {
+        file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+    }
+    let hashed: PathBuf = path.with_file_name(file_name);
+    std::fs::rename(path, &hashed).map_err(|e| format!("failed to rename {} to {}: {}", path.display(), hashed.display(), e))?;
+    Ok(hashed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_are_named_by_content() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("image_rust_manifest_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("out.png"), b"pixels").unwrap();
+
+        let hashed: PathBuf = rename_by_hash(&directory.join("out.png")).unwrap();
+        assert_eq!(hashed, directory.join(format!("{}.png", sha256_hex(b"pixels"))));
+        assert!(!directory.join("out.png").exists());
+
+        let mut manifest: Manifest = Manifest::load(&directory).unwrap();
+        manifest.insert("in.png", &hashed);
+        manifest.save().unwrap();
+        assert_eq!(Manifest::load(&directory).unwrap().entries["in.png"], hashed.to_string_lossy());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
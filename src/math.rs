@@ -0,0 +1,215 @@
+// Transcendental functions built only from IEEE add, multiply, divide and bit operations, which
+// are correctly rounded everywhere. The std versions call the platform libm, whose last bits differ
+// between x86 and ARM and between C libraries, so outputs built on them are not reproducible.
+use std::f64::consts::{FRAC_PI_2, LN_2, PI, SQRT_2};
+
+pub fn exp_f64(x: f64) -> f64 {
+    if x.is_nan() {
+        return x;
+    }
+    if x > 709.0 {
+        return f64::INFINITY;
+    }
+    if x < -745.0 {
+        return 0.0;
+    }
+    // x = k ln 2 + r with |r| <= ln 2 / 2, then exp(x) = 2^k exp(r)
+    let k: f64 = (x / LN_2).round();
+    let r: f64 = x - k * LN_2;
+    let mut term: f64 = 1.0;
+    let mut sum: f64 = 1.0;
+    for n in 1..=14 {
+        term *= r / n as f64;
+        sum += term;
+    }
+    // 2^k in two halves, so both stay normal near the ends of the range
+    let half: i64 = k as i64 / 2;
+    let scale = |power: i64| f64::from_bits(((power + 1023) as u64) << 52);
+    sum * scale(half) * scale(k as i64 - half)
+}
+
+fn ln_f64(x: f64) -> f64 {
+    if x.is_nan() || x < 0.0 {
+        return f64::NAN;
+    }
+    if x == 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if x.is_infinite() {
+        return x;
+    }
+    // Subnormals are scaled up first so the exponent field is meaningful
+    let (x, offset) = if x < f64::MIN_POSITIVE { (x * 18014398509481984.0, -54) } else { (x, 0) };
+    let bits: u64 = x.to_bits();
+    let mut exponent: i64 = ((bits >> 52) & 0x7ff) as i64 - 1023 + offset;
+    let mut mantissa: f64 = f64::from_bits((bits & 0x000f_ffff_ffff_ffff) | 0x3ff0_0000_0000_0000);
+    if mantissa > SQRT_2 {
+        mantissa /= 2.0;
+        exponent += 1;
+    }
+    // ln(m) = 2 atanh(s) with s = (m - 1) / (m + 1), |s| <= 0.172
+    let s: f64 = (mantissa - 1.0) / (mantissa + 1.0);
+    let s2: f64 = s * s;
+    let mut power: f64 = s;
+    let mut series: f64 = 0.0;
+    for n in (1..=25).step_by(2) {
+        series += power / n as f64;
+        power *= s2;
+    }
+    exponent as f64 * LN_2 + 2.0 * series
+}
+
+pub fn exp(x: f32) -> f32 {
+    exp_f64(x as f64) as f32
+}
+
+pub fn exp2(x: f32) -> f32 {
+    exp_f64(x as f64 * LN_2) as f32
+}
+
+pub fn ln_1p(x: f32) -> f32 {
+    ln_f64(1.0 + x as f64) as f32
+}
+
+// For a positive base.
+pub fn powf(base: f32, exponent: f32) -> f32 {
+    if base == 0.0 {
+        return if exponent == 0.0 { 1.0 } else { 0.0 };
+    }
+    exp_f64(exponent as f64 * ln_f64(base as f64)) as f32
+}
+
+// For a positive base.
+pub fn powf_f64(base: f64, exponent: f64) -> f64 {
+    if base == 0.0 {
+        return if exponent == 0.0 { 1.0 } else { 0.0 };
+    }
+    exp_f64(exponent * ln_f64(base))
+}
+
+// Taylor series of cos (first = 1) or sin (first = x) after reducing x to [-pi, pi], which is
+// exact enough for arguments of practical size.
+fn trig_series(x: f64, sine: bool) -> f64 {
+    if !x.is_finite() {
+        return f64::NAN;
+    }
+    let r: f64 = x - (x / (2.0 * PI)).round() * 2.0 * PI;
+    let r2: f64 = r * r;
+    let mut term: f64 = if sine { r } else { 1.0 };
+    let mut sum: f64 = term;
+    let first: usize = if sine { 3 } else { 2 };
+    for n in (first..=31).step_by(2) {
+        term *= -r2 / ((n - 1) * n) as f64;
+        sum += term;
+    }
+    sum
+}
+
+pub fn sin_f64(x: f64) -> f64 {
+    trig_series(x, true)
+}
+
+pub fn cos_f64(x: f64) -> f64 {
+    trig_series(x, false)
+}
+
+pub fn sin_cos_f64(x: f64) -> (f64, f64) {
+    (sin_f64(x), cos_f64(x))
+}
+
+pub fn cos(x: f32) -> f32 {
+    cos_f64(x as f64) as f32
+}
+
+pub fn atan_f64(x: f64) -> f64 {
+    if x.is_nan() {
+        return x;
+    }
+    if x.abs() > 1.0 {
+        return FRAC_PI_2.copysign(x) - atan_f64(1.0 / x);
+    }
+    // atan(t) = 2 atan(t / (1 + sqrt(1 + t^2))); three halvings leave |t| <= tan(pi / 32) for the
+    // series. sqrt is correctly rounded by IEEE 754, unlike the libm functions.
+    let mut t: f64 = x;
+    for _ in 0..3 {
+        t /= 1.0 + (1.0 + t * t).sqrt();
+    }
+    let t2: f64 = t * t;
+    let mut power: f64 = t;
+    let mut series: f64 = 0.0;
+    for n in (1..=41).step_by(2) {
+        series += if n % 4 == 1 { power / n as f64 } else { -power / n as f64 };
+        power *= t2;
+    }
+    8.0 * series
+}
+
+pub fn atan2_f64(y: f64, x: f64) -> f64 {
+    if x > 0.0 {
+        atan_f64(y / x)
+    } else if x < 0.0 {
+        atan_f64(y / x) + if y.is_sign_negative() { -PI } else { PI }
+    } else if y > 0.0 {
+        FRAC_PI_2
+    } else if y < 0.0 {
+        -FRAC_PI_2
+    } else {
+        0.0
+    }
+}
+
+// Without the overflow guard of libm hypot, which pixel coordinates do not need.
+pub fn hypot_f64(x: f64, y: f64) -> f64 {
+    (x * x + y * y).sqrt()
+}
+
+pub fn cbrt(x: f32) -> f32 {
+    if x == 0.0 || !x.is_finite() {
+        return x;
+    }
+    let value: f64 = (x as f64).abs();
+    // Dividing the exponent bits by three gives a guess within a few percent; Newton's method
+    // doubles the correct digits per step.
+    let mut root: f64 = f64::from_bits(value.to_bits() / 3 + 0x2a9f_7893_0000_0000);
+    for _ in 0..6 {
+        root -= (root * root * root - value) / (3.0 * root * root);
+    }
+    (root as f32).copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_std_within_an_ulp() {
+        let close = |a: f32, b: f32| (a - b).abs() <= b.abs() * 2.0 * f32::EPSILON;
+        for i in -400..=400 {
+            let x: f32 = i as f32 * 0.173;
+            assert!(close(exp(x), x.exp()), "exp {}", x);
+            assert!(close(cbrt(x), x.cbrt()), "cbrt {}", x);
+            assert!((cos(x) - x.cos()).abs() < 1e-6, "cos {}", x);
+            if x > -1.0 {
+                assert!(close(ln_1p(x), x.ln_1p()) || (ln_1p(x) - x.ln_1p()).abs() < 1e-7, "ln_1p {}", x);
+            }
+        }
+        for value in [1e-30f32, 0.0031308, 0.5, 1.0, 3.7, 1e20] {
+            assert!(close(powf(value, 1.0 / 2.4), value.powf(1.0 / 2.4)), "powf {}", value);
+        }
+        assert_eq!(exp2(3.0), 8.0);
+
+        for i in -300..=300 {
+            let x: f64 = i as f64 * 0.0731;
+            assert!((sin_f64(x) - x.sin()).abs() < 1e-14, "sin {}", x);
+            assert!((atan_f64(x * 7.0) - (x * 7.0).atan()).abs() < 1e-14, "atan {}", x);
+            for y in [-2.5, -0.0, 0.0, 1.0, 40.0] {
+                assert!((atan2_f64(y, x) - y.atan2(x)).abs() < 1e-14 || x == 0.0, "atan2 {} {}", y, x);
+                assert!((hypot_f64(x, y) - x.hypot(y)).abs() < 1e-12, "hypot {} {}", x, y);
+            }
+            if x > 0.0 {
+                assert!((powf_f64(x, 1.7) / x.powf(1.7) - 1.0).abs() < 1e-13, "powf {}", x);
+            }
+        }
+        assert_eq!(atan2_f64(0.0, -1.0), std::f64::consts::PI);
+    }
+}
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+// Callbacks from a pipeline run, for progress bars, metrics and logs without parsing stdout.
+// Every method has an empty default, so an observer only implements what it needs.
+pub trait PipelineObserver {
+    // `step` counts operations from 1; `operation` is the short name from FilterOperation::name.
+    fn operation_started(&mut self, _step: usize, _operation: &'static str, _width: u32, _height: u32) {}
+
+    fn operation_finished(&mut self, _step: usize, _operation: &'static str, _elapsed: Duration) {}
+
+    // Size of the image buffer an operation produced.
+    fn bytes_allocated(&mut self, _operation: &'static str, _bytes: usize) {}
+
+    // Rows of the current operation done so far out of `total`.
+    fn rows_processed(&mut self, _operation: &'static str, _rows: u32, _total: u32) {}
+
+    // A trim of a `source` width x height image kept the rectangle `bounds` (x, y, width, height).
+    fn trimmed(&mut self, _source: (u32, u32), _bounds: (u32, u32, u32, u32)) {}
+
+    // Something went wrong but the run continues, e.g. a locked palette could not be saved.
+    fn warning(&mut self, _message: &str) {}
+}
+
+// Observes nothing.
+pub struct NoopObserver;
+
+impl PipelineObserver for NoopObserver {}
@@ -0,0 +1,96 @@
+use filter::atlas::{frame_map, pack, Sprite};
+use filter::batch::expand_input;
+use filter::error::Error;
+use filter::filter::open_image;
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+use crate::report::report_path;
+
+fn print_usage() {
+    println!("Usage: cargo r pack DIR|'sprites/*.png' atlas.png [--meta=atlas.json] [--trim] [--padding=N] [--max-width=N]");
+    println!("Packs the images into one atlas and writes a JSON frame map (TexturePacker hash layout)");
+    println!("Options:");
+    println!("  --meta=PATH: Where to write the frame map (default: the atlas path with a .json extension)");
+    println!("  --trim: Cut fully transparent borders off each sprite; the map records the original size and offset");
+    println!("Sprites trimmed earlier with -trim --report keep the original size and offset from their reports");
+    println!("  --padding=N: Transparent pixels between sprites (default 0)");
+    println!("  --max-width=N: Atlas width in pixels (default: about square)");
+}
+
+// Size and offset recorded by -trim in the sprite's report, if it has one.
+fn recorded_trim(path: &Path) -> Option<(u32, u32, u32, u32)> {
+    let text: String = std::fs::read_to_string(report_path(path)).ok()?;
+    let report: serde_json::Value = serde_json::from_str(&text).ok()?;
+    let trim: &serde_json::Value = report.get("trim")?;
+    let field = |name: &str| trim.get(name).and_then(serde_json::Value::as_u64).map(|value| value as u32);
+    Some((field("source_width")?, field("source_height")?, field("offset_x")?, field("offset_y")?))
+}
+
+fn parse_pixels(value: &str, name: &str) -> Result<u32, Error> {
+    value.parse::<u32>().map_err(|_| Error::InvalidArgument(format!("Invalid {}: {}", name, value)))
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let mut meta: Option<PathBuf> = None;
+    let mut trim: bool = false;
+    let mut padding: u32 = 0;
+    let mut max_width: Option<u32> = None;
+    let mut paths: Vec<&String> = Vec::new();
+
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if let Some(value) = arg.strip_prefix("--meta=") {
+            meta = Some(PathBuf::from(value));
+        } else if arg == "--meta" {
+            match remaining.next() {
+                Some(value) => meta = Some(PathBuf::from(value)),
+                None => return Err(Error::InvalidArgument("--meta needs a path".to_string())),
+            }
+        } else if arg == "--trim" {
+            trim = true;
+        } else if let Some(value) = arg.strip_prefix("--padding=") {
+            padding = parse_pixels(value, "padding")?;
+        } else if let Some(value) = arg.strip_prefix("--max-width=") {
+            match parse_pixels(value, "width")? {
+                0 => return Err(Error::InvalidArgument(format!("Invalid width: {}", value))),
+                pixels => max_width = Some(pixels),
+            }
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    if paths.len() != 2 {
+        print_usage();
+        return Ok(());
+    }
+    let output_path: &Path = Path::new(paths[1]);
+    let meta_path: PathBuf = meta.unwrap_or_else(|| output_path.with_extension("json"));
+
+    let inputs: Vec<PathBuf> = expand_input(paths[0]).map_err(Error::InvalidArgument)?;
+    if inputs.is_empty() {
+        return Err(Error::InvalidArgument(format!("No images found in {}", paths[0])));
+    }
+
+    let mut sprites: Vec<Sprite> = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        let image: RgbaImage = open_image(input)?.to_rgba8();
+        let name: String = input.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut sprite: Sprite = Sprite::new(&name, image);
+        if let Some((source_width, source_height, offset_x, offset_y)) = recorded_trim(input) {
+            sprite = Sprite { source_width, source_height, offset_x, offset_y, ..sprite };
+        }
+        sprites.push(if trim { sprite.trimmed() } else { sprite });
+    }
+
+    let (atlas, frames) = pack(&sprites, padding, max_width).map_err(Error::Operation)?;
+    atlas.save(output_path).map_err(|source| Error::Encode { path: output_path.to_path_buf(), source })?;
+    let image_name: String = output_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let map: serde_json::Value = frame_map(&frames, &image_name, atlas.width(), atlas.height());
+    let text: String = serde_json::to_string_pretty(&map).unwrap_or_default();
+    std::fs::write(&meta_path, text + "\n").map_err(|source| Error::Io { path: meta_path.clone(), source })?;
+    println!("The atlas is saved: {} ({} x {}, {} sprites), frames in {}", output_path.display(), atlas.width(), atlas.height(), frames.len(), meta_path.display());
+    Ok(())
+}
@@ -1,13 +1,109 @@
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb, RgbImage};
+use crate::error::Error;
 use crate::filter::*;
-use std::sync::RwLock;
-use once_cell::sync::Lazy;
+use crate::color::{ciede2000, hsl_to_rgb, oklab_to_rgb, rgb_to_hsl, rgb_to_lab, rgb_to_oklab};
+#[cfg(feature = "fixed-point")]
+use crate::portable::{lab_fixed, nearest_lab_fixed};
+use crate::portable::{nearest_rgb, neighbour, FLOYD_STEINBERG};
+use crate::quantize::median_cut_palette;
+use crate::kdtree::KdTree;
+use crate::font::{draw_text, text_width, GLYPH_HEIGHT};
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub const BUILTIN_PALETTES: &[&str] = &["websafe", "xterm256", "gameboy", "nes", "pico8", "cga", "c64", "zxspectrum"];
+
+// Palettes of classic hardware and fantasy consoles, compiled in: name, title, description and
+// colors as 0xRRGGBB.
+const EMBEDDED_PALETTES: &[(&str, &str, &str, &[u32])] = &[
+    ("gameboy", "Game Boy", "The 4 greens of the original Game Boy screen, darkest first", &[
+        0x0F380F, 0x306230, 0x8BAC0F, 0x9BBC0F,
+    ]),
+    ("nes", "NES", "The NES (2C02) master palette in hardware order, without repeated blacks", &[
+        0x7C7C7C, 0x0000FC, 0x0000BC, 0x4428BC, 0x940084, 0xA80020, 0xA81000, 0x881400,
+        0x503000, 0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0xBCBCBC, 0x0078F8,
+        0x0058F8, 0x6844FC, 0xD800CC, 0xE40058, 0xF83800, 0xE45C10, 0xAC7C00, 0x00B800,
+        0x00A800, 0x00A844, 0x008888, 0xF8F8F8, 0x3CBCFC, 0x6888FC, 0x9878F8, 0xF878F8,
+        0xF85898, 0xF87858, 0xFCA044, 0xF8B800, 0xB8F818, 0x58D854, 0x58F898, 0x00E8D8,
+        0x787878, 0xFCFCFC, 0xA4E4FC, 0xB8B8F8, 0xD8B8F8, 0xF8B8F8, 0xF8A4C0, 0xF0D0B0,
+        0xFCE0A8, 0xF8D878, 0xD8F878, 0xB8F8B8, 0xB8F8D8, 0x00FCFC, 0xF8D8F8,
+    ]),
+    ("pico8", "PICO-8", "The 16 colors of the PICO-8 fantasy console", &[
+        0x000000, 0x1D2B53, 0x7E2553, 0x008751, 0xAB5236, 0x5F574F, 0xC2C3C7, 0xFFF1E8,
+        0xFF004D, 0xFFA300, 0xFFEC27, 0x00E436, 0x29ADFF, 0x83769C, 0xFF77A8, 0xFFCCAA,
+    ]),
+    ("cga", "CGA", "The 16 colors of IBM CGA text mode (also the EGA default palette)", &[
+        0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+        0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+    ]),
+    ("c64", "Commodore 64", "The 16 colors of the Commodore 64 as VICE shows them", &[
+        0x000000, 0xFFFFFF, 0x880000, 0xAAFFEE, 0xCC44CC, 0x00CC55, 0x0000AA, 0xEEEE77,
+        0xDD8855, 0x664400, 0xFF7777, 0x333333, 0x777777, 0xAAFF66, 0x0088FF, 0xBBBBBB,
+    ]),
+    ("zxspectrum", "ZX Spectrum", "The 15 colors of the ZX Spectrum: 8 normal and 7 bright", &[
+        0x000000, 0x0000D7, 0xD70000, 0xD700D7, 0x00D700, 0x00D7D7, 0xD7D700, 0xD7D7D7,
+        0x0000FF, 0xFF0000, 0xFF00FF, 0x00FF00, 0x00FFFF, 0xFFFF00, 0xFFFFFF,
+    ]),
+];
+
+pub fn is_gpl(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gpl"))
+}
+
+fn color_cube(levels: &[u8]) -> Vec<[u8; 3]> {
+    let mut colors: Vec<[u8; 3]> = Vec::new();
+    for &r in levels {
+        for &g in levels {
+            for &b in levels {
+                colors.push([r, g, b]);
+            }
+        }
+    }
+    colors
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwatchOrder {
+    Original,
+    Hue,
+    Lightness,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HarmonyScheme {
+    Complementary,
+    Triadic,
+    Analogous,
+    SplitComplementary,
+    Tetradic,
+}
+
+impl HarmonyScheme {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "complementary" => Some(HarmonyScheme::Complementary),
+            "triadic" => Some(HarmonyScheme::Triadic),
+            "analogous" => Some(HarmonyScheme::Analogous),
+            "split" | "split-complementary" => Some(HarmonyScheme::SplitComplementary),
+            "tetradic" => Some(HarmonyScheme::Tetradic),
+            _ => None,
+        }
+    }
+
+    pub fn hue_offsets(&self) -> &'static [f32] {
+        match self {
+            HarmonyScheme::Complementary => &[0.0, 180.0],
+            HarmonyScheme::Triadic => &[0.0, 120.0, 240.0],
+            HarmonyScheme::Analogous => &[0.0, -30.0, 30.0],
+            HarmonyScheme::SplitComplementary => &[0.0, 150.0, 210.0],
+            HarmonyScheme::Tetradic => &[0.0, 90.0, 180.0, 270.0],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Palette {
     pub name: String,
     pub description: String,
@@ -15,11 +111,195 @@ pub struct Palette {
 }
 
 impl Palette {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = File::open(path)?;
+    // JSON palettes, or GIMP palettes for files ending in .gpl.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        if is_gpl(&path) {
+            let text: String = std::fs::read_to_string(&path).map_err(|source| Error::Io { path: path.clone(), source })?;
+            let fallback_name: String = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            return Palette::from_gpl(&text, &fallback_name).map_err(|message| Error::InvalidPalette { path, message });
+        }
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(source) => return Err(Error::Io { path, source }),
+        };
         let reader = BufReader::new(file);
-        let palette: Palette = serde_json::from_reader(reader)?;
-        Ok(palette)
+        serde_json::from_reader(reader).map_err(|source| Error::PaletteParse { path, source })
+    }
+
+    // The GIMP palette format most palette sites export: a "GIMP Palette" line, optional Name: and
+    // Columns: lines and # comments, then one "R G B [name]" line per color.
+    pub fn from_gpl(text: &str, fallback_name: &str) -> Result<Self, String> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some("GIMP Palette") {
+            return Err("not a GIMP palette: the first line must be 'GIMP Palette'".to_string());
+        }
+        let mut name: String = fallback_name.to_string();
+        let mut colors: Vec<[u8; 3]> = Vec::new();
+        for line in lines {
+            if let Some(value) = line.strip_prefix("Name:") {
+                name = value.trim().to_string();
+            } else if line.starts_with('#') || line.starts_with("Columns:") {
+                continue;
+            } else {
+                let channels: Vec<u8> = line.split_whitespace().take(3).map_while(|value| value.parse::<u8>().ok()).collect();
+                match channels[..] {
+                    [r, g, b] => colors.push([r, g, b]),
+                    _ => return Err(format!("invalid color line '{}' (expected R G B from 0 to 255)", line)),
+                }
+            }
+        }
+        Ok(Palette { name, description: format!("{} colors from a GIMP palette", colors.len()), colors })
+    }
+
+    pub fn from_colors(name: &str, description: &str, colors: &[Color]) -> Self {
+        Palette {
+            name: name.to_string(),
+            description: description.to_string(),
+            colors: colors.iter().map(|color| [color.r, color.g, color.b]).collect(),
+        }
+    }
+
+    // The palette median cut finds for the image, most common colors first.
+    pub fn from_image(image: &RgbImage, count: usize) -> Self {
+        let colors: Vec<Color> = median_cut_palette(image, count);
+        Palette::from_colors("Extracted", &format!("{} colors extracted by median cut", colors.len()), &colors)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let file = match File::create(&path) {
+            Ok(file) => file,
+            Err(source) => return Err(Error::Io { path, source }),
+        };
+        let mut writer = BufWriter::new(file);
+        // Serializing a palette cannot fail, so any error here comes from writing the file
+        let written: std::io::Result<()> = serde_json::to_writer_pretty(&mut writer, self).map_err(std::io::Error::from).and_then(|_| writer.flush());
+        written.map_err(|source| Error::Io { path, source })
+    }
+
+    pub fn generate(hues: u32, shades: u32, saturation: f32) -> Self {
+        let mut colors: Vec<[u8; 3]> = Vec::new();
+        for shade in 0..shades {
+            let lightness: f32 = (shade + 1) as f32 / (shades + 1) as f32;
+            for hue in 0..hues {
+                let color: Color = hsl_to_rgb(hue as f32 * 360.0 / hues as f32, saturation, lightness);
+                colors.push([color.r, color.g, color.b]);
+            }
+        }
+
+        Palette {
+            name: format!("HSL {}x{}", hues, shades),
+            description: format!("{} evenly spaced hues at {} lightness steps", hues, shades),
+            colors,
+        }
+    }
+
+    pub fn harmonize(base: Color, scheme: HarmonyScheme, count: u32) -> Self {
+        let (hue, saturation, lightness) = rgb_to_hsl(base);
+        let offsets: &[f32] = scheme.hue_offsets();
+
+        // Once every scheme hue is used, further colors are lighter/darker tints of the same hues
+        let colors: Vec<[u8; 3]> = (0..count as usize)
+            .map(|index| {
+                let tier: usize = index / offsets.len();
+                let shift: f32 = tier.div_ceil(2) as f32 * 0.15 * if tier % 2 == 1 { 1.0 } else { -1.0 };
+                let color: Color = hsl_to_rgb(hue + offsets[index % offsets.len()], saturation, (lightness + shift).clamp(0.05, 0.95));
+                [color.r, color.g, color.b]
+            })
+            .collect();
+
+        Palette {
+            name: format!("{:?} #{:02X}{:02X}{:02X}", scheme, base.r, base.g, base.b),
+            description: format!("{} colors in a {:?} harmony", count, scheme),
+            colors,
+        }
+    }
+
+    // The 216 colors of the 6x6x6 cube browsers were guaranteed to show without dithering.
+    pub fn websafe() -> Self {
+        let colors: Vec<[u8; 3]> = color_cube(&[0x00, 0x33, 0x66, 0x99, 0xCC, 0xFF]);
+
+        Palette {
+            name: "Web safe".to_string(),
+            description: "216 color 6x6x6 web-safe cube".to_string(),
+            colors,
+        }
+    }
+
+    // In xterm index order: 16 system colors (xterm defaults), the 6x6x6 cube and 24 grays.
+    pub fn xterm256() -> Self {
+        let system: [[u8; 3]; 16] = [
+            [0, 0, 0], [205, 0, 0], [0, 205, 0], [205, 205, 0], [0, 0, 238], [205, 0, 205], [0, 205, 205], [229, 229, 229],
+            [127, 127, 127], [255, 0, 0], [0, 255, 0], [255, 255, 0], [92, 92, 255], [255, 0, 255], [0, 255, 255], [255, 255, 255],
+        ];
+        let mut colors: Vec<[u8; 3]> = system.to_vec();
+        colors.extend(color_cube(&[0, 95, 135, 175, 215, 255]));
+        colors.extend((0..24).map(|step| {
+            let level: u8 = 8 + step * 10;
+            [level, level, level]
+        }));
+
+        Palette {
+            name: "xterm 256".to_string(),
+            description: "xterm 256 color palette in index order".to_string(),
+            colors,
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "websafe" => Some(Palette::websafe()),
+            "xterm256" => Some(Palette::xterm256()),
+            _ => EMBEDDED_PALETTES.iter().find(|(embedded, ..)| *embedded == name).map(|&(_, title, description, colors)| Palette {
+                name: title.to_string(),
+                description: description.to_string(),
+                colors: colors.iter().map(|&rgb| [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]).collect(),
+            }),
+        }
+    }
+
+    pub fn render(&self, swatch_size: u32, order: SwatchOrder) -> RgbImage {
+        let mut colors: Vec<Color> = self.colors.iter()
+            .map(|&[r, g, b]| Color::from_rgb_components(r, g, b))
+            .collect();
+
+        match order {
+            SwatchOrder::Original => {}
+            SwatchOrder::Hue => colors.sort_by(|a, b| {
+                let (hue_a, saturation_a, _) = rgb_to_hsl(*a);
+                let (hue_b, saturation_b, _) = rgb_to_hsl(*b);
+                // Grays have no meaningful hue, keep them together at the end
+                (saturation_a == 0.0).cmp(&(saturation_b == 0.0))
+                    .then(hue_a.total_cmp(&hue_b))
+                    .then(rgb_to_lab(*a)[0].total_cmp(&rgb_to_lab(*b)[0]))
+            }),
+            SwatchOrder::Lightness => colors.sort_by(|a, b| rgb_to_lab(*a)[0].total_cmp(&rgb_to_lab(*b)[0])),
+        }
+
+        let count: u32 = colors.len().max(1) as u32;
+        let columns: u32 = (count as f32).sqrt().ceil() as u32;
+        let rows: u32 = count.div_ceil(columns);
+        let mut image: RgbImage = ImageBuffer::from_pixel(columns * swatch_size, rows * swatch_size, Rgb([0, 0, 0]));
+        let scale: u32 = (swatch_size / 32).max(1);
+
+        for (index, color) in colors.iter().enumerate() {
+            let origin_x: u32 = (index as u32 % columns) * swatch_size;
+            let origin_y: u32 = (index as u32 / columns) * swatch_size;
+            for y in 0..swatch_size {
+                for x in 0..swatch_size {
+                    image.put_pixel(origin_x + x, origin_y + y, Rgb([color.r, color.g, color.b]));
+                }
+            }
+
+            let label: String = format!("#{:02X}{:02X}{:02X}", color.r, color.g, color.b);
+            if text_width(&label, scale) + 2 * scale <= swatch_size {
+                let ink: Rgb<u8> = if rgb_to_lab(*color)[0] > 55.0 { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) };
+                let label_y: u32 = origin_y + swatch_size - (GLYPH_HEIGHT + 1) * scale;
+                draw_text(&mut image, origin_x + scale, label_y, &label, scale, ink);
+            }
+        }
+        image
     }
 
     pub fn get_colors(&self) -> Vec<Rgb<u8>> {
@@ -29,8 +309,185 @@ impl Palette {
     }
 }
 
-static ACTIVE_PALETTE: Lazy<RwLock<Vec<Color>>> = Lazy::new(|| {
-    RwLock::new(vec![
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    Rgb,
+    // CIE76 distance in CIELAB; matched in integer math only with the fixed-point feature.
+    Lab,
+    OkLab,
+    // CIEDE2000 in CIELAB: slower than the others, closest to how different colors look.
+    Ciede2000,
+}
+
+impl DistanceMetric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(DistanceMetric::Rgb),
+            "lab" => Some(DistanceMetric::Lab),
+            "oklab" => Some(DistanceMetric::OkLab),
+            "ciede2000" | "de2000" => Some(DistanceMetric::Ciede2000),
+            _ => None,
+        }
+    }
+}
+
+// Whether -pal replaces whole colors or only brightness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteMode {
+    Full,
+    Luminance,
+    LuminanceDither,
+}
+
+impl PaletteMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "full" => Some(PaletteMode::Full),
+            "luminance" => Some(PaletteMode::Luminance),
+            "luminance-dither" => Some(PaletteMode::LuminanceDither),
+            _ => None,
+        }
+    }
+}
+
+// From this many colors on, nearest lookups go through a k-d tree instead of a linear scan.
+const KD_TREE_MIN_COLORS: usize = 32;
+
+// Cells per channel of the lookup table of PaletteMatcher::with_lut: 32^3 cells of 8 levels each.
+pub const LUT_SIZE: usize = 32;
+
+// Palette colors with their coordinates precomputed in the space of the distance metric.
+pub struct PaletteMatcher {
+    colors: Vec<Color>,
+    metric: DistanceMetric,
+    coordinates: Vec<[f32; 3]>,
+    // CIEDE2000 is not a distance in Lab space, so it always scans
+    tree: Option<KdTree>,
+    // Palette index per cell of the RGB cube cut into LUT_SIZE steps per channel
+    lut: Option<Vec<u16>>,
+    // Integer CIELAB coordinates, matched instead of `coordinates` for Lab with fixed-point
+    #[cfg(feature = "fixed-point")]
+    fixed: Vec<[i32; 3]>,
+}
+
+impl PaletteMatcher {
+    pub fn new(colors: &[Color], metric: DistanceMetric) -> Self {
+        let coordinates: Vec<[f32; 3]> = match metric {
+            DistanceMetric::Rgb => Vec::new(),
+            DistanceMetric::Lab if cfg!(feature = "fixed-point") => Vec::new(),
+            DistanceMetric::Lab => colors.iter().map(|&color| rgb_to_lab(color)).collect(),
+            DistanceMetric::Ciede2000 => colors.iter().map(|&color| rgb_to_lab(color)).collect(),
+            DistanceMetric::OkLab => colors.iter().map(|&color| rgb_to_oklab(color)).collect(),
+        };
+        let tree: Option<KdTree> = match metric {
+            _ if colors.len() < KD_TREE_MIN_COLORS => None,
+            // The tree compares floats
+            DistanceMetric::Lab if cfg!(feature = "fixed-point") => None,
+            DistanceMetric::Rgb => Some(KdTree::new(&colors.iter().map(|color| [color.r as f32, color.g as f32, color.b as f32]).collect::<Vec<[f32; 3]>>())),
+            DistanceMetric::Lab | DistanceMetric::OkLab => Some(KdTree::new(&coordinates)),
+            DistanceMetric::Ciede2000 => None,
+        };
+        PaletteMatcher {
+            colors: colors.to_vec(),
+            metric,
+            coordinates,
+            tree,
+            lut: None,
+            #[cfg(feature = "fixed-point")]
+            fixed: match metric {
+                DistanceMetric::Lab => colors.iter().map(|color| lab_fixed([color.r, color.g, color.b])).collect(),
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    // Matches the center of every cell of a LUT_SIZE^3 grid over the RGB cube once, after which
+    // each pixel is a table lookup whatever the metric and palette size. Colors in the same cell
+    // share a match, so results can differ from exact matching where two palette colors are
+    // nearly equally close.
+    pub fn with_lut(mut self) -> Self {
+        if self.colors.is_empty() || self.colors.len() > u16::MAX as usize {
+            return self;
+        }
+        let step: u32 = 256 / LUT_SIZE as u32;
+        let center = |cell: usize| (cell as u32 * step + step / 2) as u8;
+        let lut: Vec<u16> = (0..LUT_SIZE * LUT_SIZE * LUT_SIZE)
+            .map(|cell| {
+                let color: Color = Color::from_rgb_components(center(cell / (LUT_SIZE * LUT_SIZE)), center(cell / LUT_SIZE % LUT_SIZE), center(cell % LUT_SIZE));
+                self.nearest_index(color).unwrap_or(0) as u16
+            })
+            .collect();
+        self.lut = Some(lut);
+        self
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
+    }
+
+    pub fn nearest_index(&self, color: Color) -> Option<usize> {
+        if let Some(lut) = &self.lut {
+            let shift: u32 = 8 - LUT_SIZE.trailing_zeros();
+            let cell: usize = ((color.r >> shift) as usize * LUT_SIZE + (color.g >> shift) as usize) * LUT_SIZE + (color.b >> shift) as usize;
+            return Some(lut[cell] as usize);
+        }
+        #[cfg(feature = "fixed-point")]
+        if self.metric == DistanceMetric::Lab {
+            return nearest_lab_fixed(self.fixed.iter().copied(), lab_fixed([color.r, color.g, color.b]));
+        }
+        if let Some(tree) = &self.tree {
+            let target: [f32; 3] = match self.metric {
+                DistanceMetric::Lab => rgb_to_lab(color),
+                DistanceMetric::OkLab => rgb_to_oklab(color),
+                _ => [color.r as f32, color.g as f32, color.b as f32],
+            };
+            return tree.nearest(&target);
+        }
+        match self.metric {
+            DistanceMetric::Rgb => nearest_index(&self.colors, color),
+            DistanceMetric::Lab | DistanceMetric::OkLab => {
+                let target: [f32; 3] = if self.metric == DistanceMetric::Lab { rgb_to_lab(color) } else { rgb_to_oklab(color) };
+                self.coordinates.iter()
+                    .map(|c| (c[0] - target[0]).powi(2) + (c[1] - target[1]).powi(2) + (c[2] - target[2]).powi(2))
+                    .enumerate()
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(index, _)| index)
+            },
+            DistanceMetric::Ciede2000 => {
+                let target: [f32; 3] = rgb_to_lab(color);
+                self.coordinates.iter()
+                    .map(|&c| ciede2000(c, target))
+                    .enumerate()
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(index, _)| index)
+            }
+        }
+    }
+
+    pub fn nearest_color(&self, color: Color) -> Color {
+        match self.nearest_index(color) {
+            Some(index) => self.colors[index],
+            None => color,
+        }
+    }
+
+    // Every pixel replaced by its nearest palette color, without dithering.
+    pub fn map_image(&self, image: &DynamicImage) -> RgbImage {
+        let rgb_image: RgbImage = image.to_rgb8();
+        ImageBuffer::from_fn(rgb_image.width(), rgb_image.height(), |x, y| {
+            let new_color: Color = self.nearest_color(Color::from_rgb(rgb_image.get_pixel(x, y)));
+            Rgb([new_color.r, new_color.g, new_color.b])
+        })
+    }
+}
+
+// Eight primary and secondary colors, e.g. for index maps of runs without a palette step.
+pub fn default_colors() -> Vec<Color> {
+    vec![
         Color { r: 0, g: 0, b: 0 },       // Black
         Color { r: 255, g: 255, b: 255 }, // White
         Color { r: 255, g: 0, b: 0 },     // Red
@@ -39,66 +496,112 @@ static ACTIVE_PALETTE: Lazy<RwLock<Vec<Color>>> = Lazy::new(|| {
         Color { r: 255, g: 255, b: 0 },   // Yellow
         Color { r: 255, g: 0, b: 255 },   // Magenta
         Color { r: 0, g: 255, b: 255 },   // Cyan
-    ])
-});
+    ]
+}
 
-pub fn set_active_palette(colors: &[Color]) {
-    if let Ok(mut palette) = ACTIVE_PALETTE.write() {
-        palette.clear();
-        palette.extend_from_slice(colors);
-    } else {
-        eprintln!("Warning: Failed to acquire write lock for palette.");
+pub fn nearest_index(palette: &[Color], color: Color) -> Option<usize> {
+    nearest_rgb(palette.iter().map(|c| [c.r, c.g, c.b]), [color.r, color.g, color.b])
+}
+
+// Layout: width (u32 LE), height (u32 LE), color count (u16 LE), count * RGB bytes,
+// then one palette index byte per pixel in row-major order.
+pub fn write_index_map<P: AsRef<Path>>(image: &RgbImage, palette: &PaletteMatcher, path: P) -> Result<(), Box<dyn std::error::Error>> {
+    let colors: &[Color] = palette.colors();
+    if colors.is_empty() || colors.len() > 256 {
+        return Err(format!("index map needs 1 to 256 palette colors, got {}", colors.len()).into());
     }
+
+    let (width, height) = image.dimensions();
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&width.to_le_bytes())?;
+    writer.write_all(&height.to_le_bytes())?;
+    writer.write_all(&(colors.len() as u16).to_le_bytes())?;
+    for color in colors {
+        writer.write_all(&[color.r, color.g, color.b])?;
+    }
+
+    let indices: Vec<u8> = image.pixels()
+        .map(|pixel| palette.nearest_index(Color::from_rgb(pixel)).unwrap_or(0) as u8)
+        .collect();
+    writer.write_all(&indices)?;
+    writer.flush()?;
+    Ok(())
 }
 
-pub fn get_nearest_color(color: Color) -> Color {
-    if let Ok(palette) = ACTIVE_PALETTE.read() {
-        if palette.is_empty() {
-            return color;
+// One row of space separated xterm color indices per image row. Only indices 16-255 are used,
+// since terminals disagree on the 16 system colors.
+pub fn write_xterm_map<P: AsRef<Path>>(image: &RgbImage, metric: DistanceMetric, path: P) -> Result<(), Box<dyn std::error::Error>> {
+    let colors: Vec<Color> = Palette::xterm256().colors[16..].iter()
+        .map(|&[r, g, b]| Color::from_rgb_components(r, g, b))
+        .collect();
+    let matcher: PaletteMatcher = PaletteMatcher::new(&colors, metric);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    for row in image.rows() {
+        let indices: Vec<String> = row
+            .map(|pixel| (matcher.nearest_index(Color::from_rgb(pixel)).unwrap_or(0) + 16).to_string())
+            .collect();
+        writeln!(writer, "{}", indices.join(" "))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Snaps only the OKLab lightness of every pixel to the lightness levels of the palette and keeps
+// its hue and chroma. With `dither` the lightness error is diffused Floyd-Steinberg style, giving
+// dithered brightness in the original colors.
+pub fn apply_palette_luminance(image: &DynamicImage, colors: &[Color], dither: bool) -> RgbImage {
+    let mut levels: Vec<f32> = colors.iter().map(|&color| rgb_to_oklab(color)[0]).collect();
+    levels.sort_by(f32::total_cmp);
+    let (width, height) = image.dimensions();
+    let rgb: RgbImage = image.to_rgb8();
+    if levels.is_empty() {
+        return rgb;
+    }
+
+    let mut errors: Vec<f32> = vec![0.0; (width * height) as usize];
+    let mut output: RgbImage = RgbImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let index: usize = (y * width + x) as usize;
+            let [lightness, a, b] = rgb_to_oklab(Color::from_rgb(rgb.get_pixel(x, y)));
+            let wanted: f32 = lightness + errors[index];
+            let level: f32 = levels.iter().copied()
+                .min_by(|p, q| (p - wanted).abs().total_cmp(&(q - wanted).abs()))
+                .unwrap_or(lightness);
+            if dither {
+                for &(dx, dy, weight) in FLOYD_STEINBERG.taps {
+                    if let Some(target) = neighbour(x as usize, y as usize, dx, dy, width as usize, height as usize) {
+                        errors[target] += (wanted - level) * weight as f32 / FLOYD_STEINBERG.divisor as f32;
+                    }
+                }
+            }
+            let color: Color = oklab_to_rgb([level, a, b]);
+            output.put_pixel(x, y, Rgb([color.r, color.g, color.b]));
         }
+    }
+    output
+}
 
-        palette.iter()
-            .min_by_key(|&&palette_color| {
-                let dr = palette_color.r as i32 - color.r as i32;
-                let dg = palette_color.g as i32 - color.g as i32;
-                let db = palette_color.b as i32 - color.b as i32;
-                dr * dr + dg * dg + db * db
-            })
-            .copied()
-            .unwrap_or(color)
-    } else {
-        eprintln!("Warning: Failed to acquire read lock for palette.");
-        color
-    }
-}
-
-pub fn fallback_palette(input_image: &DynamicImage) -> RgbImage {
-    if let Ok(palette) = ACTIVE_PALETTE.read() {
-        if palette.len() > 1 {
-        } else {
-            drop(palette);
-                let default_colors = vec![
-                Color { r: 0, g: 0, b: 0 },       // Black
-                Color { r: 255, g: 255, b: 255 }, // White
-                Color { r: 255, g: 0, b: 0 },     // Red
-                Color { r: 0, g: 255, b: 0 },     // Green
-                Color { r: 0, g: 0, b: 255 },     // Blue
-                Color { r: 255, g: 255, b: 0 },   // Yellow
-                Color { r: 255, g: 0, b: 255 },   // Magenta
-                Color { r: 0, g: 255, b: 255 },   // Cyan
-            ];
-            set_active_palette(&default_colors);
-        }
-    }
-    
-    let (width, height) = input_image.dimensions();
-    
-    ImageBuffer::from_fn(width, height, |x, y| {
-        let pixel = input_image.get_pixel(x, y);
-        let input_color = Color { r: pixel[0], g: pixel[1], b: pixel[2] };
-        let new_color = get_nearest_color(input_color);
-        Rgb([new_color.r, new_color.g, new_color.b])
-    })
+// Recolors art that already uses `from`: every pixel of color from[i] becomes to[i], so sprites
+// can switch team colors or skins. Pixels not in `from` take the entry of their nearest color.
+// Alpha is kept.
+pub fn swap_palette(image: &DynamicImage, from: &[Color], to: &[Color], metric: DistanceMetric) -> Result<DynamicImage, String> {
+    if from.len() != to.len() || from.is_empty() {
+        return Err(format!("palettes to swap need the same number of colors, not {} and {}", from.len(), to.len()));
+    }
+    let matcher: PaletteMatcher = PaletteMatcher::new(from, metric);
+    let mut rgba: image::RgbaImage = image.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let color: Color = Color::from_rgb_components(pixel[0], pixel[1], pixel[2]);
+        let index: usize = from.iter()
+            .position(|candidate| (candidate.r, candidate.g, candidate.b) == (color.r, color.g, color.b))
+            .or_else(|| matcher.nearest_index(color))
+            .unwrap_or(0);
+        let replacement: Color = to[index];
+        *pixel = image::Rgba([replacement.r, replacement.g, replacement.b, pixel[3]]);
+    }
+    Ok(if image.color().has_alpha() { rgba.into() } else { DynamicImage::ImageRgba8(rgba).to_rgb8().into() })
 }
 
 #[cfg(test)]
@@ -145,4 +648,129 @@ mod tests {
 
         remove_file(&test_file_path).expect("Failed to delete test file");
     }
+
+    #[test]
+    fn swap_palette_keeps_alpha_and_checks_lengths() {
+        let from: Vec<Color> = vec![Color::from_rgb_components(200, 0, 0), Color::from_rgb_components(0, 0, 0)];
+        let to: Vec<Color> = vec![Color::from_rgb_components(0, 0, 200), Color::from_rgb_components(0, 0, 0)];
+        let sprite: DynamicImage = image::RgbaImage::from_fn(2, 1, |x, _| image::Rgba([200 - x as u8 * 10, 0, 0, 255 - x as u8])).into();
+        let swapped: image::RgbaImage = swap_palette(&sprite, &from, &to, DistanceMetric::Rgb).unwrap().to_rgba8();
+        assert_eq!(swapped.into_raw(), vec![0, 0, 200, 255, 0, 0, 200, 254]);
+        assert!(swap_palette(&sprite, &from, &to[..1], DistanceMetric::Rgb).is_err());
+    }
+
+    #[test]
+    fn luminance_mode_keeps_hue() {
+        let grays: Vec<Color> = vec![Color::from_rgb_components(0, 0, 0), Color::from_rgb_components(255, 255, 255)];
+        let orange: DynamicImage = RgbImage::from_pixel(8, 8, Rgb([230, 140, 40])).into();
+        let snapped: RgbImage = apply_palette_luminance(&orange, &grays, false);
+        assert!(snapped.pixels().all(|pixel| pixel[0] > pixel[2]));
+        let dithered: RgbImage = apply_palette_luminance(&orange, &grays, true);
+        assert!(dithered.pixels().any(|pixel| pixel[0] < 60) && dithered.pixels().any(|pixel| pixel[0] > 200));
+    }
+
+    #[test]
+    fn broken_and_missing_palette_files_have_distinct_errors() {
+        let test_dir: &str = "./test_files";
+        create_dir_all(test_dir).expect("Failed to create test directory");
+        let test_file_path: String = format!("{}/broken_palette.json", test_dir);
+
+        let mut file: File = File::create(&test_file_path).expect("Failed to create test file");
+        file.write_all(b"{ \"name\": ").expect("Failed to write to test file");
+        assert!(matches!(Palette::from_file(&test_file_path), Err(Error::PaletteParse { .. })));
+        remove_file(&test_file_path).expect("Failed to delete test file");
+        assert!(matches!(Palette::from_file(&test_file_path), Err(Error::Io { .. })));
+
+        // Saving into a directory that does not exist is an I/O error on that path
+        let unwritable: String = format!("{}/missing/palette.json", test_dir);
+        assert!(matches!(Palette::from_colors("Empty", "", &[]).save(&unwritable), Err(Error::Io { path, .. }) if path == Path::new(&unwritable)));
+    }
+
+    #[test]
+    fn image_palette_keeps_the_dominant_colors() {
+        let mostly_dark: RgbImage = RgbImage::from_fn(4, 2, |x, _| if x < 3 { Rgb([10, 20, 30]) } else { Rgb([200, 100, 0]) });
+        let extracted: Palette = Palette::from_image(&mostly_dark, 8);
+        assert_eq!(extracted.colors, vec![[10, 20, 30], [200, 100, 0]]);
+    }
+
+    #[test]
+    fn every_metric_matches_the_nearest_red() {
+        let reds: Vec<Color> = vec![Color::from_rgb_components(0, 0, 0), Color::from_rgb_components(120, 20, 20), Color::from_rgb_components(255, 255, 255)];
+        for metric in [DistanceMetric::Rgb, DistanceMetric::Lab, DistanceMetric::OkLab, DistanceMetric::Ciede2000] {
+            let matched: Color = PaletteMatcher::new(&reds, metric).nearest_color(Color::from_rgb_components(110, 30, 25));
+            assert_eq!((matched.r, matched.g, matched.b), (120, 20, 20), "{:?}", metric);
+        }
+    }
+
+    #[test]
+    fn kd_tree_matches_linear_scan() {
+        // 256 colors go through the k-d tree and match what the linear scan finds
+        let xterm: Vec<Color> = Palette::xterm256().get_colors().iter().map(Color::from_rgb).collect();
+        let matcher: PaletteMatcher = PaletteMatcher::new(&xterm, DistanceMetric::Rgb);
+        for value in (0..=255u8).step_by(5) {
+            let color: Color = Color::from_rgb_components(value, 255 - value, value / 3);
+            assert_eq!(matcher.nearest_index(color), nearest_index(&xterm, color));
+        }
+    }
+
+    #[test]
+    fn lut_matches_cell_centers() {
+        // With the lookup table every color in a cell gets the match of the cell's center
+        let xterm: Vec<Color> = Palette::xterm256().get_colors().iter().map(Color::from_rgb).collect();
+        let table: PaletteMatcher = PaletteMatcher::new(&xterm, DistanceMetric::OkLab).with_lut();
+        let exact: PaletteMatcher = PaletteMatcher::new(&xterm, DistanceMetric::OkLab);
+        assert_eq!(table.nearest_index(Color::from_rgb_components(0, 0, 0)), exact.nearest_index(Color::from_rgb_components(4, 4, 4)));
+        assert_eq!(table.nearest_index(Color::from_rgb_components(255, 0, 130)), exact.nearest_index(Color::from_rgb_components(252, 4, 132)));
+        let photo: DynamicImage = RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, 128])).into();
+        let (fast, slow) = (table.map_image(&photo), exact.map_image(&photo));
+        let differing: usize = fast.pixels().zip(slow.pixels()).filter(|(a, b)| a != b).count();
+        assert!(differing < 64 * 64 / 4, "{} pixels differ", differing);
+        assert!(PaletteMatcher::new(&[], DistanceMetric::Rgb).with_lut().nearest_index(Color::from_rgb_components(1, 2, 3)).is_none());
+    }
+
+    #[test]
+    fn builtin_palettes_are_unique() {
+        assert!(BUILTIN_PALETTES.iter().all(|name| Palette::builtin(name).is_some()));
+        for (name, ..) in EMBEDDED_PALETTES {
+            let builtin: Palette = Palette::builtin(name).unwrap();
+            let unique: std::collections::HashSet<[u8; 3]> = builtin.colors.iter().copied().collect();
+            assert_eq!(unique.len(), builtin.colors.len(), "{}", name);
+        }
+        assert_eq!(Palette::builtin("gameboy").unwrap().colors, vec![[15, 56, 15], [48, 98, 48], [139, 172, 15], [155, 188, 15]]);
+        assert_eq!(Palette::builtin("pico8").unwrap().colors[8], [255, 0, 77]);
+        assert_eq!(Palette::builtin("nes").unwrap().colors.len(), 55);
+        assert!(Palette::builtin("gameboy2").is_none());
+    }
+
+    #[test]
+    fn gimp_palettes_are_read() {
+        let test_dir: &str = "./test_files";
+        create_dir_all(test_dir).expect("Failed to create test directory");
+        let gpl_path: String = format!("{}/sweetie.GPL", test_dir);
+        std::fs::write(&gpl_path, "GIMP Palette\nName: Sweetie\nColumns: 4\n#\n 26  28  44\tDark\n93 39 93 Purple\n\n177  62  83\n").unwrap();
+        let gimp: Palette = Palette::from_file(&gpl_path).unwrap();
+        assert_eq!(gimp.name, "Sweetie");
+        assert_eq!(gimp.colors, vec![[26, 28, 44], [93, 39, 93], [177, 62, 83]]);
+        assert_eq!(Palette::from_gpl("GIMP Palette\n0 0 0\n", "plain").unwrap().name, "plain");
+        std::fs::write(&gpl_path, "GIMP Palette\n300 0 0 Too bright\n").unwrap();
+        assert!(matches!(Palette::from_file(&gpl_path), Err(Error::InvalidPalette { .. })));
+        assert!(Palette::from_gpl("{ \"name\": \"json\" }", "json").is_err());
+        remove_file(&gpl_path).unwrap();
+    }
+
+    #[cfg(feature = "fixed-point")]
+    #[test]
+    fn lab_matching_uses_integer_distances() {
+        let xterm: Vec<Color> = Palette::xterm256().get_colors().iter().map(Color::from_rgb).collect();
+        let matcher: PaletteMatcher = PaletteMatcher::new(&xterm, DistanceMetric::Lab);
+        assert!(matcher.coordinates.is_empty() && matcher.tree.is_none());
+        for value in (0..=255u8).step_by(3) {
+            let color: [u8; 3] = [value, 255 - value, value / 3];
+            let expected: Option<usize> = xterm.iter()
+                .enumerate()
+                .min_by_key(|(_, candidate)| crate::portable::lab_distance_fixed([candidate.r, candidate.g, candidate.b], color))
+                .map(|(index, _)| index);
+            assert_eq!(matcher.nearest_index(Color::from_rgb_components(color[0], color[1], color[2])), expected);
+        }
+    }
 }
@@ -0,0 +1,382 @@
+use filter::color::{ramp as build_ramp, ColorSpace};
+use filter::color_parse::{parse_color, parse_color_pair};
+use filter::error::Error;
+use filter::filter::{decode_file, open_image, Color};
+use filter::library::{install_palette, installed_palettes, PaletteSource};
+use filter::palette::{HarmonyScheme, Palette, SwatchOrder, BUILTIN_PALETTES};
+use filter::quantize::{kmeans_palette, octree_palette, sample_pixels, KMEANS_ITERATIONS};
+use image::RgbImage;
+use std::path::{Path, PathBuf};
+
+fn print_usage() {
+    println!("Usage: cargo r palette [command] [options]");
+    println!("Palette commands:");
+    println!("  generate --hues=N --shades=M output.json: Build an HSL palette of N hues x M lightness steps");
+    println!("  ramp FROM,TO --steps=N [--space=srgb|linear|lab|oklab] palette.json: Append a gradient ramp to a palette");
+    println!("  render palette.json output.png [--swatch=32] [--sort=hue|lightness]: Render labeled swatches");
+    println!("  harmonize BASE --scheme=NAME --count=N output.json: Build a complementary, triadic, analogous,");
+    println!("      split or tetradic harmony palette around a base color");
+    println!("  extract INPUT --colors=N [--method=median-cut|octree|kmeans] output.json: Build an N color palette");
+    println!("      from an image (default 16). octree is the fastest and is what -quant=N uses; kmeans is slower");
+    println!("      but follows the colors of photos more closely: it clusters --samples=N pixels (default 20000)");
+    println!("      and takes --iterations=N (default {}) and --seed=N", KMEANS_ITERATIONS);
+    println!("  extract-dir DIR --colors=N [--samples=N] [--average=linear|srgb] output.json: Build one palette");
+    println!("      for every image in DIR with k-means over samples of each (N samples per image, default 20000);");
+    println!("      --iterations=N and --seed=N work as for extract");
+    println!("  install FILE|URL [--name=NAME]: Copy a palette (JSON or GIMP .gpl) into the library so -pal=NAME finds it");
+    println!("  list [--names]: Show palettes found on IMAGE_RUST_PALETTE_PATH, in the library and the palette directory,");
+    println!("      and the built-in palettes ({})", BUILTIN_PALETTES.join(", "));
+}
+
+fn parse_count(value: &str, name: &str) -> Result<u32, Error> {
+    match value.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(Error::InvalidArgument(format!("Invalid {}: {}", name, value))),
+    }
+}
+
+fn generate(args: &[String]) -> Result<(), Error> {
+    let mut hues: u32 = 12;
+    let mut shades: u32 = 3;
+    let mut output_path: Option<&String> = None;
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--hues=") {
+            hues = parse_count(value, "hue count")?;
+        } else if let Some(value) = arg.strip_prefix("--shades=") {
+            shades = parse_count(value, "shade count")?;
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            output_path = Some(arg);
+        }
+    }
+
+    let output_path: &String = match output_path {
+        Some(path) => path,
+        None => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let palette: Palette = Palette::generate(hues, shades, 1.0);
+    palette.save(output_path)?;
+    println!("The palette is saved: {} ({} colors)", output_path, palette.colors.len());
+    Ok(())
+}
+
+fn ramp(args: &[String]) -> Result<(), Error> {
+    let mut endpoints: Option<(Color, Color)> = None;
+    let mut steps: u32 = 8;
+    let mut space: ColorSpace = ColorSpace::OkLab;
+    let mut palette_path: Option<&String> = None;
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--steps=") {
+            steps = parse_count(value, "step count")?;
+        } else if let Some(value) = arg.strip_prefix("--space=") {
+            match ColorSpace::from_name(value) {
+                Some(parsed) => space = parsed,
+                None => return Err(Error::InvalidArgument(format!("Unknown color space: {}", value))),
+            }
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else if endpoints.is_none() {
+            match parse_color_pair(arg) {
+                Ok(pair) => endpoints = Some(pair),
+                Err(e) => return Err(Error::InvalidArgument(format!("Invalid ramp endpoints: {}", e))),
+            }
+        } else {
+            palette_path = Some(arg);
+        }
+    }
+
+    let ((from, to), palette_path) = match (endpoints, palette_path) {
+        (Some(endpoints), Some(path)) => (endpoints, path),
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let mut palette: Palette = if Path::new(palette_path).exists() {
+        Palette::from_file(palette_path)?
+    } else {
+        Palette {
+            name: "Ramp".to_string(),
+            description: "Gradient ramps".to_string(),
+            colors: Vec::new(),
+        }
+    };
+
+    let ramp_colors: Vec<Color> = build_ramp(from, to, steps, space);
+    palette.colors.extend(ramp_colors.iter().map(|color| [color.r, color.g, color.b]));
+
+    palette.save(palette_path)?;
+    println!("Appended {} colors to {}", ramp_colors.len(), palette_path);
+    Ok(())
+}
+
+fn render(args: &[String]) -> Result<(), Error> {
+    let mut swatch_size: u32 = 32;
+    let mut order: SwatchOrder = SwatchOrder::Original;
+    let mut paths: Vec<&String> = Vec::new();
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--swatch=") {
+            swatch_size = parse_count(value, "swatch size")?;
+        } else if let Some(value) = arg.strip_prefix("--sort=") {
+            order = match value {
+                "hue" => SwatchOrder::Hue,
+                "lightness" => SwatchOrder::Lightness,
+                "none" => SwatchOrder::Original,
+                _ => return Err(Error::InvalidArgument(format!("Unknown sort order: {}", value))),
+            };
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    if paths.len() != 2 {
+        print_usage();
+        return Ok(());
+    }
+
+    let palette: Palette = Palette::from_file(paths[0])?;
+    palette.render(swatch_size, order).save(paths[1]).map_err(|source| Error::Encode { path: paths[1].into(), source })?;
+    println!("The image is saved: {}", paths[1]);
+    Ok(())
+}
+
+fn harmonize(args: &[String]) -> Result<(), Error> {
+    let mut base: Option<Color> = None;
+    let mut scheme: HarmonyScheme = HarmonyScheme::Complementary;
+    let mut count: Option<u32> = None;
+    let mut output_path: Option<&String> = None;
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--scheme=") {
+            match HarmonyScheme::from_name(value) {
+                Some(parsed) => scheme = parsed,
+                None => return Err(Error::InvalidArgument(format!("Unknown harmony scheme: {}", value))),
+            }
+        } else if let Some(value) = arg.strip_prefix("--count=") {
+            count = Some(parse_count(value, "color count")?);
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else if base.is_none() {
+            match parse_color(arg) {
+                Ok(color) => base = Some(color),
+                Err(e) => return Err(Error::InvalidArgument(format!("Invalid base color: {}", e))),
+            }
+        } else {
+            output_path = Some(arg);
+        }
+    }
+
+    let (base, output_path) = match (base, output_path) {
+        (Some(base), Some(path)) => (base, path),
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let count: u32 = count.unwrap_or(scheme.hue_offsets().len() as u32);
+    let palette: Palette = Palette::harmonize(base, scheme, count);
+    palette.save(output_path)?;
+    println!("The palette is saved: {} ({} colors)", output_path, palette.colors.len());
+    Ok(())
+}
+
+fn parse_seed(value: &str) -> Result<u64, Error> {
+    value.parse::<u64>().map_err(|_| Error::InvalidArgument(format!("Invalid seed: {}", value)))
+}
+
+fn extract(args: &[String]) -> Result<(), Error> {
+    let mut colors: u32 = 16;
+    let mut method: &str = "median-cut";
+    let mut samples: u32 = 20000;
+    let mut iterations: u32 = KMEANS_ITERATIONS as u32;
+    let mut seed: u64 = 0;
+    let mut paths: Vec<&String> = Vec::new();
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--colors=") {
+            colors = parse_count(value, "color count")?;
+        } else if let Some(value) = arg.strip_prefix("--method=") {
+            method = match value {
+                "kmeans" | "k-means" => "kmeans",
+                "median-cut" | "mediancut" => "median-cut",
+                "octree" => "octree",
+                _ => return Err(Error::InvalidArgument(format!("Unknown extraction method: {}", value))),
+            };
+        } else if let Some(value) = arg.strip_prefix("--samples=") {
+            samples = parse_count(value, "sample count")?;
+        } else if let Some(value) = arg.strip_prefix("--iterations=") {
+            iterations = parse_count(value, "iteration count")?;
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            seed = parse_seed(value)?;
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    if paths.len() != 2 {
+        print_usage();
+        return Ok(());
+    }
+
+    let image: RgbImage = open_image(paths[0])?.to_rgb8();
+
+    let palette: Palette = match method {
+        "kmeans" => {
+            let palette_colors: Vec<Color> = kmeans_palette(&sample_pixels(&image, samples as usize), colors as usize, true, iterations as usize, seed);
+            Palette::from_colors("Extracted", &format!("{} colors extracted by k-means", palette_colors.len()), &palette_colors)
+        },
+        "octree" => {
+            let palette_colors: Vec<Color> = octree_palette(&image, colors as usize);
+            Palette::from_colors("Extracted", &format!("{} colors extracted by octree", palette_colors.len()), &palette_colors)
+        },
+        _ => Palette::from_image(&image, colors as usize),
+    };
+    palette.save(paths[1])?;
+    println!("The palette is saved: {} ({} colors)", paths[1], palette.colors.len());
+    Ok(())
+}
+
+fn extract_dir(args: &[String]) -> Result<(), Error> {
+    let mut colors: u32 = 16;
+    let mut samples_per_image: u32 = 20000;
+    let mut linear: bool = true;
+    let mut iterations: u32 = KMEANS_ITERATIONS as u32;
+    let mut seed: u64 = 0;
+    let mut paths: Vec<&String> = Vec::new();
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--colors=") {
+            colors = parse_count(value, "color count")?;
+        } else if let Some(value) = arg.strip_prefix("--samples=") {
+            samples_per_image = parse_count(value, "sample count")?;
+        } else if let Some(value) = arg.strip_prefix("--average=") {
+            linear = match value {
+                "linear" => true,
+                "srgb" => false,
+                _ => return Err(Error::InvalidArgument(format!("Unknown averaging space: {}", value))),
+            };
+        } else if let Some(value) = arg.strip_prefix("--iterations=") {
+            iterations = parse_count(value, "iteration count")?;
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            seed = parse_seed(value)?;
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    if paths.len() != 2 {
+        print_usage();
+        return Ok(());
+    }
+
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(paths[0])
+        .map_err(|source| Error::Io { path: paths[0].into(), source })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    entries.sort();
+
+    let mut samples: Vec<Color> = Vec::new();
+    let mut image_count: usize = 0;
+    for path in &entries {
+        match decode_file(path) {
+            Ok(image) => {
+                samples.extend(sample_pixels(&image.to_rgb8(), samples_per_image as usize));
+                image_count += 1;
+            },
+            Err(e) => eprintln!("Warning: skipping {}: {}", path.display(), e),
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(Error::InvalidArgument(format!("No images found in {}", paths[0])));
+    }
+
+    let palette_colors: Vec<Color> = kmeans_palette(&samples, colors as usize, linear, iterations as usize, seed);
+    let description: String = format!("{} colors from {} images in {}", palette_colors.len(), image_count, paths[0]);
+    let palette: Palette = Palette::from_colors("Extracted", &description, &palette_colors);
+    palette.save(paths[1])?;
+    println!("The palette is saved: {} ({} colors from {} images)", paths[1], palette_colors.len(), image_count);
+    Ok(())
+}
+
+fn install(args: &[String]) -> Result<(), Error> {
+    let mut name: Option<&str> = None;
+    let mut source: Option<&String> = None;
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--name=") {
+            name = Some(value);
+        } else if arg.starts_with("--") {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            source = Some(arg);
+        }
+    }
+
+    let source: &String = match source {
+        Some(source) => source,
+        None => {
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let path: PathBuf = install_palette(source, name)?;
+    println!("The palette is installed: {}", path.display());
+    Ok(())
+}
+
+fn list(args: &[String], palette_dir: &Path) -> Result<(), Error> {
+    let palettes: Vec<(String, PaletteSource)> = installed_palettes(palette_dir);
+    if args.iter().any(|arg| arg == "--names") {
+        for (name, _) in palettes {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+    if palettes.is_empty() {
+        println!("No palettes found");
+        return Ok(());
+    }
+    for (name, source) in palettes {
+        match source.load() {
+            Ok(palette) => println!("{:<16} {:>4} colors  {}", name, palette.colors.len(), source),
+            Err(_) => println!("{:<16} invalid  {}", name, source),
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: &[String], palette_dir: &Path) -> Result<(), Error> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("generate") => generate(&args[1..]),
+        Some("ramp") => ramp(&args[1..]),
+        Some("render") => render(&args[1..]),
+        Some("harmonize") => harmonize(&args[1..]),
+        Some("extract") => extract(&args[1..]),
+        Some("extract-dir") => extract_dir(&args[1..]),
+        Some("install") => install(&args[1..]),
+        Some("list") => list(&args[1..], palette_dir),
+        _ => {
+            print_usage();
+            Ok(())
+        },
+    }
+}
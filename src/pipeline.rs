@@ -0,0 +1,545 @@
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Luma, Rgb};
+use std::path::Path;
+use std::time::Instant;
+use crate::analyze::introduces_banding;
+use crate::atlas::opaque_bounds;
+use crate::blend::blend_with;
+use crate::bluenoise::apply_blue_noise_dithering;
+use crate::channels::{chroma_subsample, load_anaglyph, load_and_combine, load_depth_anaglyph, reverse_channels, save_channels};
+use crate::color::ColorSpace;
+use crate::convolve::{convolve, gaussian_blur, BorderPolicy, Kernel};
+use crate::custom::{CustomFilter, Filter, Pixelate, Reverse};
+use crate::error::Error;
+use crate::diffusion::{DiffusionKernel, ATKINSON, FLOYD_STEINBERG};
+use crate::encode::jpegify;
+use crate::fft::{descreen, spectrum};
+use crate::filter::*;
+use crate::geometry::{affine, lens, rotate, warp_quad};
+use crate::integral::{adaptive_threshold, box_blur, local_contrast};
+use crate::library::PaletteSource;
+use crate::observer::{NoopObserver, PipelineObserver};
+use crate::palette::{apply_palette_luminance, swap_palette, DistanceMetric, Palette, PaletteMatcher, PaletteMode};
+use crate::quantize::octree_palette;
+use crate::quilt::quilt;
+use crate::remap::remap;
+use crate::seamcarve::seam_carve;
+use crate::sink::OutputSink;
+use crate::tone::{clahe, clarity, dehaze, exposure, highlights, shadows, tonemap};
+
+// How a pipeline run treats the image beyond the operations themselves; the CLI fills it from
+// its options and the config file.
+#[derive(Clone)]
+pub struct RunOptions {
+    pub parallel_dither: bool,
+    // Error diffusion alternates the scan direction from row to row.
+    pub serpentine: bool,
+    pub threads: usize,
+    pub palette: PaletteSource,
+    pub alpha_mode: AlphaMode,
+    pub blend_space: ColorSpace,
+    pub palette_lock: Option<String>,
+    pub auto_dither: bool,
+    pub border: BorderPolicy,
+    pub data_texture: bool,
+    pub palette_mode: PaletteMode,
+    pub distance: DistanceMetric,
+    // -pal matches through a 32x32x32 lookup table built when the palette is applied.
+    pub palette_lut: bool,
+    // Progress messages go to stderr, e.g. while the image itself is written to stdout.
+    pub messages_to_stderr: bool,
+    // The run fails once this passes, checked before every operation; one already running finishes.
+    pub deadline: Option<Instant>,
+}
+
+impl RunOptions {
+    // The CLI defaults: serial dithering on all cores' worth of threads, premultiplied resizing,
+    // OKLab gradients, clamped convolution borders and plain RGB palette distances.
+    pub fn new(palette: PaletteSource) -> Self {
+        RunOptions {
+            parallel_dither: false,
+            serpentine: false,
+            threads: std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+            palette,
+            alpha_mode: AlphaMode::Premultiplied,
+            blend_space: ColorSpace::OkLab,
+            palette_lock: None,
+            auto_dither: false,
+            border: BorderPolicy::Clamp,
+            data_texture: false,
+            palette_mode: PaletteMode::Full,
+            distance: DistanceMetric::Rgb,
+            palette_lut: false,
+            messages_to_stderr: false,
+            deadline: None,
+        }
+    }
+}
+
+fn status(options: &RunOptions, message: &str) {
+    if options.messages_to_stderr {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+fn locked_palette(lock_path: &str, image: &DynamicImage, max_colors: u32, options: &RunOptions, observer: &mut dyn PipelineObserver) -> Vec<Color> {
+    if Path::new(lock_path).exists() {
+        match Palette::from_file(lock_path) {
+            Ok(palette) => return palette.get_colors().iter().map(Color::from_rgb).collect(),
+            Err(e) => observer.warning(&format!("failed to read locked palette {}: {}, extracting a new one", lock_path, e)),
+        }
+    }
+
+    let colors: Vec<Color> = octree_palette(&image.to_rgb8(), max_colors as usize);
+    let description: String = format!("{} colors locked for -quant", colors.len());
+    match Palette::from_colors("Locked", &description, &colors).save(lock_path) {
+        Ok(_) => status(options, &format!("The locked palette is saved: {}", lock_path)),
+        Err(e) => observer.warning(&format!("failed to save locked palette {}: {}", lock_path, e)),
+    }
+    colors
+}
+
+// -pal followed by -floyd, -atkinson or -dither dithers in color: the image as it was before the
+// palette step is diffused against the palette instead of being turned to black and white.
+fn palette_diffusion(original: &DynamicImage, kernel: &DiffusionKernel, options: &RunOptions) -> Result<DynamicImage, Error> {
+    let colors: Vec<Color> = palette_colors(options)?;
+    Ok(DynamicImage::ImageRgb8(palette_diffusion_dithering(&original.to_rgb8(), &colors, options.distance, kernel, options.serpentine)))
+}
+
+// The colors of the run's palette; a palette that cannot be loaded or has no colors fails the run.
+fn palette_colors(options: &RunOptions) -> Result<Vec<Color>, Error> {
+    let palette: Palette = options.palette.load()?;
+    if palette.colors.is_empty() {
+        return Err(Error::Operation(format!("palette {} has no colors", options.palette)));
+    }
+    Ok(palette.get_colors().iter().map(Color::from_rgb).collect())
+}
+
+// A built-in operation that needs the run's options, as a Filter.
+struct OperationFilter<F: Fn(&DynamicImage) -> DynamicImage> {
+    name: &'static str,
+    apply: F,
+}
+
+impl<F: Fn(&DynamicImage) -> DynamicImage> Filter for OperationFilter<F> {
+    fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        (self.apply)(image)
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+fn operation_filter<'a, F: Fn(&DynamicImage) -> DynamicImage + 'a>(operation: &FilterOperation, apply: F) -> Option<Box<dyn Filter + 'a>> {
+    Some(Box::new(OperationFilter { name: operation.name(), apply }))
+}
+
+// The operations that only map one image to another. The rest can fail, read or write other
+// files, report to the observer or leave a 1-bit result for the next step, so run_operations
+// handles them itself.
+fn image_filter<'a>(operation: &'a FilterOperation, options: &'a RunOptions) -> Option<Box<dyn Filter + 'a>> {
+    match *operation {
+        FilterOperation::Pixelate(size) => operation_filter(operation, move |image: &DynamicImage| Pixelate(size.pixels(image.width(), image.height())).apply(image)),
+        FilterOperation::Reverse => Some(Box::new(Reverse)),
+        FilterOperation::Custom(ref filter) => Some(Box::new(filter.clone())),
+        FilterOperation::ReverseChannels(mask) => operation_filter(operation, move |image: &DynamicImage| reverse_channels(image, mask)),
+        FilterOperation::SeamCarve(new_width, new_height) => operation_filter(operation, move |image: &DynamicImage| seam_carve(image, new_width.of(image.width()), new_height.of(image.height()))),
+        FilterOperation::Lens(k1, k2, add) => operation_filter(operation, move |image: &DynamicImage| lens(image, k1, k2, add)),
+        FilterOperation::Rotate(degrees, background, expand) => operation_filter(operation, move |image: &DynamicImage| rotate(image, degrees, background, expand)),
+        FilterOperation::Remap(mapping) => operation_filter(operation, move |image: &DynamicImage| remap(image, mapping)),
+        FilterOperation::Resize(new_width, new_height) => operation_filter(operation, move |image: &DynamicImage| {
+            let (width, height) = (new_width.of(image.width()), new_height.of(image.height()));
+            if options.data_texture { resize_data(image, width, height) } else { resize(image, width, height, options.alpha_mode) }
+        }),
+        FilterOperation::Crop(x, y, crop_width, crop_height) => operation_filter(operation, move |image: &DynamicImage| {
+            let (width, height) = image.dimensions();
+            crop(image, x.of(width), y.of(height), crop_width.of(width), crop_height.of(height))
+        }),
+        FilterOperation::Canvas(size) => operation_filter(operation, move |image: &DynamicImage| {
+            let (canvas_width, canvas_height) = size.dimensions(image.width(), image.height());
+            canvas(image, canvas_width, canvas_height)
+        }),
+        FilterOperation::Pad(size) => operation_filter(operation, move |image: &DynamicImage| {
+            let (width, height) = image.dimensions();
+            let padding: u32 = size.pixels(width, height);
+            canvas(image, width + 2 * padding, height + 2 * padding)
+        }),
+        FilterOperation::Blur(sigma) => operation_filter(operation, move |image: &DynamicImage| gaussian_blur(image, sigma, options.border)),
+        FilterOperation::Sharpen => operation_filter(operation, |image: &DynamicImage| convolve(image, &Kernel::sharpen(), options.border)),
+        FilterOperation::Edge => operation_filter(operation, |image: &DynamicImage| convolve(image, &Kernel::edge(), options.border)),
+        FilterOperation::Emboss => operation_filter(operation, |image: &DynamicImage| convolve(image, &Kernel::emboss(), options.border)),
+        FilterOperation::Convolve(ref kernel) => operation_filter(operation, move |image: &DynamicImage| convolve(image, kernel, options.border)),
+        FilterOperation::Descreen => operation_filter(operation, descreen),
+        FilterOperation::Spectrum => operation_filter(operation, |image: &DynamicImage| DynamicImage::ImageLuma8(spectrum(image))),
+        FilterOperation::BoxBlur(radius) => operation_filter(operation, move |image: &DynamicImage| box_blur(image, radius.pixels(image.width(), image.height()))),
+        FilterOperation::AdaptiveThreshold(radius, offset) => operation_filter(operation, move |image: &DynamicImage| {
+            DynamicImage::ImageLuma8(adaptive_threshold(image, radius.pixels(image.width(), image.height()), offset))
+        }),
+        FilterOperation::LocalContrast(radius, amount) => operation_filter(operation, move |image: &DynamicImage| local_contrast(image, radius.pixels(image.width(), image.height()), amount)),
+        FilterOperation::Clahe(tiles, clip) => operation_filter(operation, move |image: &DynamicImage| clahe(image, tiles, clip)),
+        FilterOperation::Dehaze(strength) => operation_filter(operation, move |image: &DynamicImage| dehaze(image, strength)),
+        FilterOperation::Tonemap(mapper, stops) => operation_filter(operation, move |image: &DynamicImage| tonemap(image, mapper, stops)),
+        FilterOperation::Exposure(stops) => operation_filter(operation, move |image: &DynamicImage| exposure(image, stops)),
+        FilterOperation::Highlights(amount) => operation_filter(operation, move |image: &DynamicImage| highlights(image, amount)),
+        FilterOperation::Shadows(amount) => operation_filter(operation, move |image: &DynamicImage| shadows(image, amount)),
+        FilterOperation::Clarity(amount) => operation_filter(operation, move |image: &DynamicImage| clarity(image, amount)),
+        FilterOperation::ChromaSubsample(subsampling) => operation_filter(operation, move |image: &DynamicImage| chroma_subsample(image, subsampling)),
+        FilterOperation::Duotone(dark, light) => operation_filter(operation, move |image: &DynamicImage| DynamicImage::ImageRgb8(duotone(image, dark, light, options.blend_space))),
+        _ => None,
+    }
+}
+
+// Replaces the image with the pending 1-bit result of a dithering step, if there is one, so the
+// next step works on what the dithering produced.
+pub fn materialize(image: &mut DynamicImage, gray_image_option: &mut Option<GrayImage>) {
+    if let Some(gray) = gray_image_option.take() {
+        *image = DynamicImage::ImageLuma8(gray);
+    }
+}
+
+pub fn run_operations(mut image: DynamicImage, operations: &[FilterOperation], options: &RunOptions, observer: &mut dyn PipelineObserver, mut steps: Option<&mut dyn OutputSink>) -> Result<(DynamicImage, Option<GrayImage>), Error> {
+    let mut gray_image_option: Option<GrayImage> = None;
+    let mut before_palette: Option<DynamicImage> = None;
+
+    for (step, op) in operations.iter().enumerate() {
+        if options.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Error::Operation(format!("timed out before step {} ({})", step + 1, op.name())));
+        }
+        status(options, &format!("Applying {:?}...", op));
+        let started: Instant = Instant::now();
+        let (width, height) = image.dimensions();
+        observer.operation_started(step + 1, op.name(), width, height);
+
+        if let Some(filter) = image_filter(op, options) {
+            materialize(&mut image, &mut gray_image_option);
+            image = filter.apply(&image);
+        } else {
+            match *op {
+                FilterOperation::Palette => {
+                    materialize(&mut image, &mut gray_image_option);
+                    let dithered_next: bool = matches!(operations.get(step + 1), Some(FilterOperation::FloydSteinberg | FilterOperation::Atkinson | FilterOperation::ErrorDiffusion(_)));
+                    before_palette = (dithered_next && options.palette_mode == PaletteMode::Full).then(|| image.clone());
+                    let colors: Vec<Color> = palette_colors(options)?;
+                    image = if options.palette_mode != PaletteMode::Full {
+                        DynamicImage::ImageRgb8(apply_palette_luminance(&image, &colors, options.palette_mode == PaletteMode::LuminanceDither))
+                    } else {
+                        let matcher: PaletteMatcher = PaletteMatcher::new(&colors, options.distance);
+                        if options.palette_lut { matcher.with_lut() } else { matcher }.apply(&image)
+                    };
+                },
+                FilterOperation::FloydSteinberg => match before_palette.take() {
+                    Some(original) => image = palette_diffusion(&original, &FLOYD_STEINBERG, options)?,
+                    None => {
+                        let gray_image: ImageBuffer<Luma<u8>, Vec<u8>> = if options.parallel_dither {
+                            apply_parallel_floyd_steinberg_dithering(&image, options.threads)
+                        } else {
+                            apply_error_diffusion_dithering(&image, &FLOYD_STEINBERG, options.serpentine)
+                        };
+                        gray_image_option = Some(gray_image);
+                    },
+                },
+                FilterOperation::Atkinson => match before_palette.take() {
+                    Some(original) => image = palette_diffusion(&original, &ATKINSON, options)?,
+                    None => gray_image_option = Some(apply_error_diffusion_dithering(&image, &ATKINSON, options.serpentine)),
+                },
+                FilterOperation::ErrorDiffusion(kernel) => match before_palette.take() {
+                    Some(original) => image = palette_diffusion(&original, kernel, options)?,
+                    None => gray_image_option = Some(apply_error_diffusion_dithering(&image, kernel, options.serpentine)),
+                },
+                FilterOperation::FloydTwoColor(dark, light) => {
+                    let gray_image: ImageBuffer<Luma<u8>, Vec<u8>> = if options.parallel_dither {
+                        apply_parallel_floyd_steinberg_dithering(&image, options.threads)
+                    } else {
+                        apply_error_diffusion_dithering(&image, &FLOYD_STEINBERG, options.serpentine)
+                    };
+                    image = DynamicImage::ImageRgb8(two_color(&gray_image, dark, light));
+                    gray_image_option = None;
+                },
+                FilterOperation::SwapPalette(ref from, ref to) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    let colors = |source: &PaletteSource| source.load().map(|palette| palette.get_colors().iter().map(Color::from_rgb).collect::<Vec<Color>>());
+                    image = colors(from).and_then(|from| colors(to).and_then(|to| swap_palette(&image, &from, &to, options.distance).map_err(Error::Operation)))?;
+                },
+                FilterOperation::Bayer(levels) => {
+                    gray_image_option = Some(apply_bayer_dithering(&image, levels));
+                },
+                FilterOperation::BlueNoise(levels) => {
+                    gray_image_option = Some(apply_blue_noise_dithering(&image, levels));
+                },
+                FilterOperation::Quantize(count, dither) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    let colors: Vec<Color> = match &options.palette_lock {
+                        Some(lock_path) => locked_palette(lock_path, &image, count, options, observer),
+                        None => octree_palette(&image.to_rgb8(), count as usize),
+                    };
+                    let mut rgb_image: ImageBuffer<Rgb<u8>, Vec<u8>> = map_to_palette(&image, &colors, dither, options.distance);
+                    if !dither && options.auto_dither && introduces_banding(&image.to_rgb8(), &rgb_image) {
+                        status(options, "Banding detected, dithering with Floyd-Steinberg");
+                        rgb_image = map_to_palette(&image, &colors, true, options.distance);
+                    }
+                    image = DynamicImage::ImageRgb8(rgb_image);
+                },
+                FilterOperation::PaletteDownscale(new_width, new_height) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    let colors: Vec<Color> = palette_colors(options)?;
+                    let matcher: PaletteMatcher = PaletteMatcher::new(&colors, options.distance);
+                    image = DynamicImage::ImageRgb8(coverage_downscale(&image, new_width.of(width), new_height.of(height), &matcher));
+                },
+                FilterOperation::Warp(ref corners) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    image = warp_quad(&image, corners).map_err(Error::Operation)?;
+                },
+                FilterOperation::Affine(ref matrix) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    image = affine(&image, matrix).map_err(Error::Operation)?;
+                },
+                FilterOperation::Trim => {
+                    materialize(&mut image, &mut gray_image_option);
+                    if image.color().has_alpha() {
+                        let (x, y, trim_width, trim_height) = opaque_bounds(&image.to_rgba8()).unwrap_or((0, 0, 1, 1));
+                        image = crop(&image, x, y, trim_width, trim_height);
+                        observer.trimmed((width, height), (x, y, trim_width, trim_height));
+                    }
+                },
+                FilterOperation::Split(ref directory, set) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    let paths: Vec<std::path::PathBuf> = save_channels(&image, set, directory).map_err(Error::Operation)?;
+                    status(options, &format!("The channels are saved: {}", paths.iter().map(|path| path.display().to_string()).collect::<Vec<String>>().join(", ")));
+                },
+                FilterOperation::Synthesize(new_width, new_height, patch) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    image = quilt(&image, new_width, new_height, patch).map_err(Error::Operation)?;
+                },
+                FilterOperation::BlendWith(ref path, mode, opacity) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    image = blend_with(&image, path, mode, opacity).map_err(Error::Operation)?;
+                },
+                FilterOperation::Combine(ref paths) => {
+                    image = load_and_combine(paths).map_err(Error::Operation)?;
+                    gray_image_option = None;
+                },
+                FilterOperation::Anaglyph(ref paths) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    image = load_anaglyph(&image, paths).map_err(Error::Operation)?;
+                },
+                FilterOperation::Depth3d(ref depth_path, shift) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    image = load_depth_anaglyph(&image, depth_path, shift).map_err(Error::Operation)?;
+                },
+                FilterOperation::Jpegify(quality, passes) => {
+                    materialize(&mut image, &mut gray_image_option);
+                    image = jpegify(&image, quality, passes).map_err(|e| Error::Operation(format!("JPEG round trip failed: {}", e)))?;
+                },
+                _ => unreachable!("{} runs as a Filter", op.name()),
+            }
+        }
+        let output_height: u32 = gray_image_option.as_ref().map_or(image.height(), |gray| gray.height());
+        let bytes: usize = gray_image_option.as_ref().map_or(image.as_bytes().len(), |gray| gray.len());
+        observer.rows_processed(op.name(), output_height, output_height);
+        observer.bytes_allocated(op.name(), bytes);
+        observer.operation_finished(step + 1, op.name(), started.elapsed());
+
+        if let Some(sink) = steps.as_deref_mut().filter(|sink| sink.wants_intermediates()) {
+            let current: DynamicImage = match &gray_image_option {
+                Some(gray) => DynamicImage::ImageLuma8(gray.clone()),
+                None => image.clone(),
+            };
+            if let Err(e) = sink.intermediate(step + 1, op.name(), &current) {
+                observer.warning(&e);
+            }
+        }
+    }
+    Ok((image, gray_image_option))
+}
+
+// Runs the operations and returns the final image, with 1-bit dithering results as grayscale.
+pub fn run(image: DynamicImage, operations: &[FilterOperation], options: &RunOptions) -> Result<DynamicImage, Error> {
+    match run_operations(image, operations, options, &mut NoopObserver, None)? {
+        (_, Some(gray)) => Ok(DynamicImage::ImageLuma8(gray)),
+        (image, None) => Ok(image),
+    }
+}
+
+// Builds a list of operations the way the CLI flags do, e.g.
+// `FilterPipeline::new().pixelate(4).palette("gameboy.json").floyd().run(image)`.
+// A pipeline has one palette; calling palette again replaces it for every palette step.
+pub struct FilterPipeline {
+    operations: Vec<FilterOperation>,
+    options: RunOptions,
+}
+
+pub type Pipeline = FilterPipeline;
+
+impl Default for FilterPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FilterPipeline {
+    // Without a palette call, palette steps use palette.json in the working directory.
+    pub fn new() -> Self {
+        FilterPipeline { operations: Vec::new(), options: RunOptions::new(PaletteSource::File("palette.json".into())) }
+    }
+
+    pub fn with_options(options: RunOptions) -> Self {
+        FilterPipeline { operations: Vec::new(), options }
+    }
+
+    pub fn operation(mut self, operation: FilterOperation) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn palette<P: Into<std::path::PathBuf>>(mut self, path: P) -> Self {
+        self.options.palette = PaletteSource::File(path.into());
+        self.operation(FilterOperation::Palette)
+    }
+
+    pub fn builtin_palette(mut self, name: &str) -> Self {
+        self.options.palette = PaletteSource::Builtin(name.to_string());
+        self.operation(FilterOperation::Palette)
+    }
+
+    // How palette steps measure the nearest color; RGB unless set.
+    pub fn distance(mut self, metric: DistanceMetric) -> Self {
+        self.options.distance = metric;
+        self
+    }
+
+    // Palette steps look colors up in a precomputed table instead of matching every pixel.
+    pub fn palette_lut(mut self) -> Self {
+        self.options.palette_lut = true;
+        self
+    }
+
+    pub fn trim(self) -> Self {
+        self.operation(FilterOperation::Trim)
+    }
+
+    pub fn pixelate(self, size: u32) -> Self {
+        self.operation(FilterOperation::Pixelate(Length::Pixels(size)))
+    }
+
+    pub fn floyd(self) -> Self {
+        self.operation(FilterOperation::FloydSteinberg)
+    }
+
+    pub fn atkinson(self) -> Self {
+        self.operation(FilterOperation::Atkinson)
+    }
+
+    pub fn dither(self, kernel: &'static DiffusionKernel) -> Self {
+        self.operation(FilterOperation::ErrorDiffusion(kernel))
+    }
+
+    pub fn bayer(self, levels: u8) -> Self {
+        self.operation(FilterOperation::Bayer(levels))
+    }
+
+    pub fn blue_noise(self, levels: u8) -> Self {
+        self.operation(FilterOperation::BlueNoise(levels))
+    }
+
+    pub fn reverse(self) -> Self {
+        self.operation(FilterOperation::Reverse)
+    }
+
+    pub fn quantize(self, colors: u32, dither: bool) -> Self {
+        self.operation(FilterOperation::Quantize(colors, dither))
+    }
+
+    pub fn resize(self, width: u32, height: u32) -> Self {
+        self.operation(FilterOperation::Resize(Length::Pixels(width), Length::Pixels(height)))
+    }
+
+    pub fn blur(self, sigma: f32) -> Self {
+        self.operation(FilterOperation::Blur(sigma))
+    }
+
+    // A filter of your own, run like the built-in operations.
+    pub fn filter<F: Filter + Send + Sync + 'static>(self, filter: F) -> Self {
+        self.operation(FilterOperation::Custom(CustomFilter::new(filter)))
+    }
+
+    pub fn operations(&self) -> &[FilterOperation] {
+        &self.operations
+    }
+
+    pub fn run(&self, image: DynamicImage) -> Result<DynamicImage, Error> {
+        run(image, &self.operations, &self.options)
+    }
+
+    pub fn run_observed(&self, image: DynamicImage, observer: &mut dyn PipelineObserver, steps: Option<&mut dyn OutputSink>) -> Result<(DynamicImage, Option<GrayImage>), Error> {
+        run_operations(image, &self.operations, &self.options, observer, steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn runs_operations_without_the_cli() {
+        let image: DynamicImage = RgbImage::from_fn(8, 6, |x, _| Rgb([x as u8 * 30, 40, 200])).into();
+        let options: RunOptions = RunOptions::new(PaletteSource::Builtin("websafe".to_string()));
+        let operations: Vec<FilterOperation> = vec![FilterOperation::Reverse, FilterOperation::Resize(Length::Pixels(4), Length::Pixels(3))];
+        let output: DynamicImage = run(image, &operations, &options).unwrap();
+        assert_eq!(output.dimensions(), (4, 3));
+        assert_eq!(output.to_rgb8().get_pixel(0, 0)[2], 55);
+
+        let dithered: DynamicImage = Pipeline::new().pixelate(2).floyd().run(output.clone()).unwrap();
+        assert!(dithered.as_luma8().is_some_and(|gray| gray.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255)));
+        let built: FilterPipeline = FilterPipeline::new().builtin_palette("websafe").reverse();
+        assert_eq!(built.operations().len(), 2);
+        assert_eq!(built.run(output.clone()).unwrap().to_rgb8(), run(output.clone(), built.operations(), &options).unwrap().to_rgb8());
+
+        // A palette that cannot be loaded fails the run instead of falling back to other colors
+        assert!(matches!(FilterPipeline::new().palette("/nonexistent/palette.json").run(output.clone()), Err(Error::Io { .. })));
+        assert!(matches!(FilterPipeline::new().builtin_palette("nosuch").floyd().run(output.clone()), Err(Error::InvalidArgument(_))));
+        let empty: RunOptions = RunOptions::new(PaletteSource::Loaded(Box::new(PaletteSource::Builtin("empty".to_string())), Palette::from_colors("Empty", "", &[])));
+        assert!(run(output, &[FilterOperation::Palette, FilterOperation::FloydSteinberg], &empty).is_err());
+    }
+
+    #[test]
+    fn filters_start_from_a_pending_dithering_result() {
+        let image: DynamicImage = RgbImage::from_fn(16, 8, |x, y| Rgb([x as u8 * 16, y as u8 * 32, 90])).into();
+        let options: RunOptions = RunOptions::new(PaletteSource::Builtin("websafe".to_string()));
+        let dithered: DynamicImage = run(image.clone(), &[FilterOperation::FloydSteinberg], &options).unwrap();
+        let reversed: DynamicImage = run(image, &[FilterOperation::FloydSteinberg, FilterOperation::Reverse], &options).unwrap();
+        assert_eq!(reversed.to_rgb8(), Reverse.apply(&dithered).to_rgb8());
+
+        assert_eq!(image_filter(&FilterOperation::Blur(1.0), &options).map(|filter| filter.name()), Some("blur"));
+        assert!(image_filter(&FilterOperation::Palette, &options).is_none());
+    }
+
+    #[test]
+    fn locked_palettes_are_created_once_and_reused() {
+        let lock_path: String = std::env::temp_dir().join(format!("image_rust_lock_{}.json", std::process::id())).to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&lock_path);
+        let mut options: RunOptions = RunOptions::new(PaletteSource::Builtin("websafe".to_string()));
+        options.palette_lock = Some(lock_path.clone());
+        let colors = |image: &DynamicImage| -> Vec<[u8; 3]> {
+            let mut colors: Vec<[u8; 3]> = image.to_rgb8().pixels().map(|pixel| pixel.0).collect();
+            colors.sort();
+            colors.dedup();
+            colors
+        };
+
+        let first: DynamicImage = RgbImage::from_fn(16, 4, |x, y| Rgb([x as u8 * 16, y as u8 * 60, 40])).into();
+        let quantized: DynamicImage = run(first, &[FilterOperation::Quantize(4, false)], &options).unwrap();
+        assert!(Path::new(&lock_path).exists());
+        let locked: Vec<[u8; 3]> = Palette::from_file(&lock_path).unwrap().get_colors().iter().map(|color| color.0).collect();
+        assert!(colors(&quantized).iter().all(|color| locked.contains(color)));
+
+        // A different image takes its colors from the lock, which stays as it was
+        let second: DynamicImage = RgbImage::from_fn(16, 4, |x, _| Rgb([20, 200, x as u8 * 12])).into();
+        let reused: DynamicImage = run(second, &[FilterOperation::Quantize(4, false)], &options).unwrap();
+        assert!(colors(&reused).iter().all(|color| locked.contains(color)));
+        assert_eq!(Palette::from_file(&lock_path).unwrap().get_colors().len(), locked.len());
+        std::fs::remove_file(&lock_path).unwrap();
+    }
+}
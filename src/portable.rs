@@ -0,0 +1,316 @@
+// Pure algorithms on raw 8-bit buffers, shared by the desktop filters and embedded firmware.
+// Only `core` and `alloc` may be used here, so the file builds unchanged under `#![no_std]`:
+// no std paths, no float functions such as round or sqrt, which need std. The no_std_check
+// crate in the workspace compiles this file as a no_std library, so a std use breaks the build.
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Error diffusion kernel: each tap sends weight / divisor of the error to the pixel at (dx, dy).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffusionKernel {
+    pub name: &'static str,
+    pub divisor: i32,
+    pub taps: &'static [(i32, i32, i32)],
+}
+
+pub const FLOYD_STEINBERG: DiffusionKernel = DiffusionKernel {
+    name: "floyd-steinberg",
+    divisor: 16,
+    taps: &[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)],
+};
+
+// Bill Atkinson's kernel from the classic Mac: six taps of 1/8 each, so a quarter of the error
+// is dropped. Flat shadows and highlights clip to solid black or white instead of speckling.
+pub const ATKINSON: DiffusionKernel = DiffusionKernel {
+    name: "atkinson",
+    divisor: 8,
+    taps: &[(1, 0, 1), (2, 0, 1), (-1, 1, 1), (0, 1, 1), (1, 1, 1), (0, 2, 1)],
+};
+
+// The wide kernels spread the error over two or three rows and up to two pixels to each side,
+// which breaks up Floyd-Steinberg's worm patterns at the cost of some sharpness.
+pub const JARVIS_JUDICE_NINKE: DiffusionKernel = DiffusionKernel {
+    name: "jjn",
+    divisor: 48,
+    taps: &[
+        (1, 0, 7), (2, 0, 5),
+        (-2, 1, 3), (-1, 1, 5), (0, 1, 7), (1, 1, 5), (2, 1, 3),
+        (-2, 2, 1), (-1, 2, 3), (0, 2, 5), (1, 2, 3), (2, 2, 1),
+    ],
+};
+
+pub const STUCKI: DiffusionKernel = DiffusionKernel {
+    name: "stucki",
+    divisor: 42,
+    taps: &[
+        (1, 0, 8), (2, 0, 4),
+        (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+        (-2, 2, 1), (-1, 2, 2), (0, 2, 4), (1, 2, 2), (2, 2, 1),
+    ],
+};
+
+pub const SIERRA: DiffusionKernel = DiffusionKernel {
+    name: "sierra",
+    divisor: 32,
+    taps: &[
+        (1, 0, 5), (2, 0, 3),
+        (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+        (-1, 2, 2), (0, 2, 3), (1, 2, 2),
+    ],
+};
+
+pub const SIERRA_TWO_ROW: DiffusionKernel = DiffusionKernel {
+    name: "sierra2",
+    divisor: 16,
+    taps: &[(1, 0, 4), (2, 0, 3), (-2, 1, 1), (-1, 1, 2), (0, 1, 3), (1, 1, 2), (2, 1, 1)],
+};
+
+pub const BURKES: DiffusionKernel = DiffusionKernel {
+    name: "burkes",
+    divisor: 32,
+    taps: &[(1, 0, 8), (2, 0, 4), (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2)],
+};
+
+pub const DIFFUSION_KERNELS: [&DiffusionKernel; 7] = [&FLOYD_STEINBERG, &ATKINSON, &JARVIS_JUDICE_NINKE, &STUCKI, &SIERRA, &SIERRA_TWO_ROW, &BURKES];
+
+pub fn diffusion_kernel(name: &str) -> Option<&'static DiffusionKernel> {
+    DIFFUSION_KERNELS.iter().copied().find(|kernel| kernel.name == name)
+}
+
+// 4x4 ordered dither thresholds, 0-15.
+pub const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// sRGB level to linear light in 1/65535 units.
+const SRGB_TO_LINEAR_Q16: [u16; 256] = [
+    0, 20, 40, 60, 80, 99, 119, 139, 159, 179, 199, 219, 241, 264, 288, 313,
+    340, 367, 396, 427, 458, 491, 526, 562, 599, 637, 677, 718, 761, 805, 851, 898,
+    947, 997, 1048, 1101, 1156, 1212, 1270, 1330, 1391, 1453, 1517, 1583, 1651, 1720, 1790, 1863,
+    1937, 2013, 2090, 2170, 2250, 2333, 2418, 2504, 2592, 2681, 2773, 2866, 2961, 3058, 3157, 3258,
+    3360, 3464, 3570, 3678, 3788, 3900, 4014, 4129, 4247, 4366, 4488, 4611, 4736, 4864, 4993, 5124,
+    5257, 5392, 5530, 5669, 5810, 5953, 6099, 6246, 6395, 6547, 6700, 6856, 7014, 7174, 7335, 7500,
+    7666, 7834, 8004, 8177, 8352, 8528, 8708, 8889, 9072, 9258, 9445, 9635, 9828, 10022, 10219, 10417,
+    10619, 10822, 11028, 11235, 11446, 11658, 11873, 12090, 12309, 12530, 12754, 12980, 13209, 13440, 13673, 13909,
+    14146, 14387, 14629, 14874, 15122, 15371, 15623, 15878, 16135, 16394, 16656, 16920, 17187, 17456, 17727, 18001,
+    18277, 18556, 18837, 19121, 19407, 19696, 19987, 20281, 20577, 20876, 21177, 21481, 21787, 22096, 22407, 22721,
+    23038, 23357, 23678, 24002, 24329, 24658, 24990, 25325, 25662, 26001, 26344, 26688, 27036, 27386, 27739, 28094,
+    28452, 28813, 29176, 29542, 29911, 30282, 30656, 31033, 31412, 31794, 32179, 32567, 32957, 33350, 33745, 34143,
+    34544, 34948, 35355, 35764, 36176, 36591, 37008, 37429, 37852, 38278, 38706, 39138, 39572, 40009, 40449, 40891,
+    41337, 41785, 42236, 42690, 43147, 43606, 44069, 44534, 45002, 45473, 45947, 46423, 46903, 47385, 47871, 48359,
+    48850, 49344, 49841, 50341, 50844, 51349, 51858, 52369, 52884, 53401, 53921, 54445, 54971, 55500, 56032, 56567,
+    57105, 57646, 58190, 58737, 59287, 59840, 60396, 60955, 61517, 62082, 62650, 63221, 63795, 64372, 64952, 65535,
+];
+
+// Rec. 601 luma with integer weights.
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000) as u8
+}
+
+pub fn threshold(value: u8) -> u8 {
+    if value < 128 { 0 } else { 255 }
+}
+
+// Nearest of `levels` evenly spaced values between 0 and 255.
+pub fn quantize_levels(value: u8, levels: u8) -> u8 {
+    let steps: u32 = levels.max(2) as u32 - 1;
+    let level: u32 = (value as u32 * steps + 127) / 255;
+    (level * 255 / steps) as u8
+}
+
+// Index of the palette color closest to `color` in squared RGB distance.
+pub fn nearest_rgb(palette: impl IntoIterator<Item = [u8; 3]>, color: [u8; 3]) -> Option<usize> {
+    palette.into_iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| {
+            let dr: i32 = candidate[0] as i32 - color[0] as i32;
+            let dg: i32 = candidate[1] as i32 - color[1] as i32;
+            let db: i32 = candidate[2] as i32 - color[2] as i32;
+            dr * dr + dg * dg + db * db
+        })
+        .map(|(index, _)| index)
+}
+
+// Largest r with r^3 <= value.
+fn integer_cbrt(value: u64) -> u64 {
+    let mut root: u64 = 0;
+    let mut bit: u64 = 1 << 21;
+    while bit > 0 {
+        let candidate: u64 = root | bit;
+        if candidate * candidate * candidate <= value {
+            root = candidate;
+        }
+        bit >>= 1;
+    }
+    root
+}
+
+// CIELAB f(t) with t and the result in 1/65536 units.
+fn lab_f_q16(t: u32) -> u32 {
+    if t as u64 * 24389 > 216 * 65536 {
+        integer_cbrt((t as u64) << 32) as u32
+    } else {
+        (t * 24389 / 27 + 16 * 65536) / 116
+    }
+}
+
+// CIELAB (D65) in 1/256 units, e.g. L = 25600 for white, using integer math only.
+// Matrix rows are already divided by the white point and scaled to sum to 4096.
+pub fn lab_fixed(color: [u8; 3]) -> [i32; 3] {
+    let [r, g, b] = color.map(|value| SRGB_TO_LINEAR_Q16[value as usize] as u32);
+    let x: u32 = (1777 * r + 1541 * g + 778 * b) >> 12;
+    let y: u32 = (871 * r + 2929 * g + 296 * b) >> 12;
+    let z: u32 = (73 * r + 448 * g + 3575 * b) >> 12;
+    let (fx, fy, fz) = (lab_f_q16(x) as i64, lab_f_q16(y) as i64, lab_f_q16(z) as i64);
+    [
+        ((116 * fy - 16 * 65536) >> 8) as i32,
+        ((500 * (fx - fy)) >> 8) as i32,
+        ((200 * (fy - fz)) >> 8) as i32,
+    ]
+}
+
+// Squared CIE76 distance between two lab_fixed results, in 1/65536 units.
+pub fn lab_coordinate_distance(a: [i32; 3], b: [i32; 3]) -> u64 {
+    (0..3).map(|channel| {
+        let difference: i64 = a[channel] as i64 - b[channel] as i64;
+        (difference * difference) as u64
+    }).sum()
+}
+
+// Squared CIE76 distance in 1/65536 units.
+pub fn lab_distance_fixed(a: [u8; 3], b: [u8; 3]) -> u64 {
+    lab_coordinate_distance(lab_fixed(a), lab_fixed(b))
+}
+
+// Index of the palette color closest to `target`, all as lab_fixed coordinates.
+pub fn nearest_lab_fixed(palette: impl IntoIterator<Item = [i32; 3]>, target: [i32; 3]) -> Option<usize> {
+    palette.into_iter()
+        .enumerate()
+        .min_by_key(|(_, candidate)| lab_coordinate_distance(*candidate, target))
+        .map(|(index, _)| index)
+}
+
+pub(crate) fn neighbour(x: usize, y: usize, dx: i32, dy: i32, width: usize, height: usize) -> Option<usize> {
+    let nx: i64 = x as i64 + dx as i64;
+    let ny: i64 = y as i64 + dy as i64;
+    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+        return None;
+    }
+    Some(ny as usize * width + nx as usize)
+}
+
+// The columns of row y in visiting order, and the direction error is pushed along the row. With
+// `serpentine` odd rows run right to left and the kernel is mirrored, which keeps diffusion
+// from drawing the diagonal worms a fixed left-to-right scan produces in flat areas.
+pub(crate) fn row_order(y: usize, width: usize, serpentine: bool) -> (impl Iterator<Item = usize>, i32) {
+    let reversed: bool = serpentine && y % 2 == 1;
+    ((0..width).map(move |column| if reversed { width - 1 - column } else { column }), if reversed { -1 } else { 1 })
+}
+
+// Error diffusion over a row-major gray buffer, in place.
+// Errors are kept in 1/divisor units as i32, so no weight is lost to integer division.
+// The value a pixel is quantized from is clamped to 0..=255 before its error is taken,
+// which bounds every error to +/-255 however many neighbours push it the same way.
+pub fn diffuse(pixels: &mut [u8], width: usize, kernel: &DiffusionKernel, serpentine: bool, quantize: impl Fn(u8) -> u8) {
+    if width == 0 {
+        return;
+    }
+    let height: usize = pixels.len() / width;
+    let mut errors: Vec<i32> = vec![0; width * height];
+    let divisor: i32 = kernel.divisor.max(1);
+
+    for y in 0..height {
+        let (columns, direction) = row_order(y, width, serpentine);
+        for x in columns {
+            let index: usize = y * width + x;
+            let scaled: i32 = pixels[index] as i32 * divisor + errors[index];
+            let wanted: u8 = (scaled + divisor / 2).div_euclid(divisor).clamp(0, 255) as u8;
+            let new_value: u8 = quantize(wanted);
+            pixels[index] = new_value;
+
+            let error: i32 = wanted as i32 - new_value as i32;
+            for &(dx, dy, weight) in kernel.taps {
+                if let Some(target) = neighbour(x, y, dx * direction, dy, width, height) {
+                    errors[target] += error * weight;
+                }
+            }
+        }
+    }
+}
+
+// Error diffusion over a row-major RGB buffer (three bytes per pixel), in place, with the same
+// integer error units and clamping as `diffuse`. `nearest` picks the output color.
+pub fn diffuse_rgb(pixels: &mut [u8], width: usize, kernel: &DiffusionKernel, serpentine: bool, nearest: impl Fn([u8; 3]) -> [u8; 3]) {
+    if width == 0 {
+        return;
+    }
+    let height: usize = pixels.len() / 3 / width;
+    let mut errors: Vec<[i32; 3]> = vec![[0; 3]; width * height];
+    let divisor: i32 = kernel.divisor.max(1);
+
+    for y in 0..height {
+        let (columns, direction) = row_order(y, width, serpentine);
+        for x in columns {
+            let index: usize = y * width + x;
+            let mut wanted: [u8; 3] = [0; 3];
+            for channel in 0..3 {
+                let scaled: i32 = pixels[index * 3 + channel] as i32 * divisor + errors[index][channel];
+                wanted[channel] = (scaled + divisor / 2).div_euclid(divisor).clamp(0, 255) as u8;
+            }
+            let new_color: [u8; 3] = nearest(wanted);
+            pixels[index * 3..index * 3 + 3].copy_from_slice(&new_color);
+
+            for &(dx, dy, weight) in kernel.taps {
+                if let Some(target) = neighbour(x, y, dx * direction, dy, width, height) {
+                    for channel in 0..3 {
+                        errors[target][channel] += (wanted[channel] as i32 - new_color[channel] as i32) * weight;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Ordered dithering to `levels` gray levels over a row-major buffer, in place. Needs no error
+// buffer, so it also works on a stream of rows.
+pub fn bayer_dither(pixels: &mut [u8], width: usize, levels: u8) {
+    if width == 0 {
+        return;
+    }
+    let steps: u32 = levels.max(2) as u32 - 1;
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = (index % width, index / width);
+        let threshold: u32 = (BAYER_4X4[y % 4][x % 4] as u32 * 2 + 1) * 255 / 32;
+        let position: u32 = *pixel as u32 * steps;
+        let level: u32 = position / 255 + if position % 255 > threshold { 1 } else { 0 };
+        *pixel = (level.min(steps) * 255 / steps) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_buffer_dithering_keeps_average_level() {
+        for value in [0u8, 64, 128, 200, 255] {
+            let mut diffused: Vec<u8> = vec![value; 32 * 32];
+            diffuse(&mut diffused, 32, &FLOYD_STEINBERG, false, threshold);
+            let mut ordered: Vec<u8> = vec![value; 32 * 32];
+            bayer_dither(&mut ordered, 32, 2);
+            for pixels in [diffused, ordered] {
+                let mean: u32 = pixels.iter().map(|&pixel| pixel as u32).sum::<u32>() / pixels.len() as u32;
+                assert!(mean.abs_diff(value as u32) <= 16, "{} became {}", value, mean);
+            }
+        }
+        assert_eq!(quantize_levels(100, 4), 85);
+        assert_eq!(nearest_rgb([[0, 0, 0], [250, 10, 10]], [200, 40, 0]), Some(1));
+        assert_eq!(luma(255, 255, 255), 255);
+
+        for color in [[0u8, 0, 0], [255, 255, 255], [255, 0, 0], [12, 200, 90], [128, 128, 128], [3, 5, 250]] {
+            let float: [f32; 3] = crate::color::rgb_to_lab(crate::filter::Color::from_rgb_components(color[0], color[1], color[2]));
+            let fixed: [i32; 3] = lab_fixed(color);
+            for channel in 0..3 {
+                assert!((fixed[channel] as f32 / 256.0 - float[channel]).abs() < 0.5, "{:?}: {:?} vs {:?}", color, fixed, float);
+            }
+        }
+    }
+}
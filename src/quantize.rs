@@ -0,0 +1,450 @@
+use image::RgbImage;
+use std::collections::BTreeMap;
+use crate::color::{linear_to_srgb, srgb_to_linear};
+use crate::filter::Color;
+
+const MAX_DEPTH: usize = 8;
+// Keeps the tree small on large photos; reduced further to the requested size at the end.
+const MAX_WORKING_LEAVES: usize = 4096;
+
+#[derive(Default)]
+struct OctreeNode {
+    red: u64,
+    green: u64,
+    blue: u64,
+    count: u64,
+    pixels: u64,
+    children: [Option<usize>; 8],
+    is_leaf: bool,
+}
+
+struct Octree {
+    nodes: Vec<OctreeNode>,
+    reducible: Vec<Vec<usize>>,
+    leaf_count: usize,
+}
+
+fn child_index(color: Color, depth: usize) -> usize {
+    let shift: usize = 7 - depth;
+    (((color.r >> shift) & 1) << 2 | ((color.g >> shift) & 1) << 1 | ((color.b >> shift) & 1)) as usize
+}
+
+impl Octree {
+    fn new() -> Self {
+        let mut reducible: Vec<Vec<usize>> = vec![Vec::new(); MAX_DEPTH];
+        reducible[0].push(0);
+        Octree {
+            nodes: vec![OctreeNode::default()],
+            reducible,
+            leaf_count: 0,
+        }
+    }
+
+    fn insert(&mut self, color: Color) {
+        let mut node: usize = 0;
+        let mut depth: usize = 0;
+
+        self.nodes[node].pixels += 1;
+        while !self.nodes[node].is_leaf {
+            let index: usize = child_index(color, depth);
+            let child: usize = match self.nodes[node].children[index] {
+                Some(child) => child,
+                None => {
+                    let child: usize = self.nodes.len();
+                    let is_leaf: bool = depth + 1 == MAX_DEPTH;
+                    self.nodes.push(OctreeNode { is_leaf, ..Default::default() });
+                    self.nodes[node].children[index] = Some(child);
+                    if is_leaf {
+                        self.leaf_count += 1;
+                    } else {
+                        self.reducible[depth + 1].push(child);
+                    }
+                    child
+                }
+            };
+            node = child;
+            depth += 1;
+            self.nodes[node].pixels += 1;
+        }
+
+        let leaf: &mut OctreeNode = &mut self.nodes[node];
+        leaf.red += color.r as u64;
+        leaf.green += color.g as u64;
+        leaf.blue += color.b as u64;
+        leaf.count += 1;
+    }
+
+    fn child_count(&self, node: usize) -> usize {
+        self.nodes[node].children.iter().flatten().count()
+    }
+
+    // Merges one node of the deepest level into a leaf, unless that would leave fewer than
+    // `min_leaves` leaves.
+    fn reduce(&mut self, min_leaves: usize) -> bool {
+        let depth: usize = match (0..MAX_DEPTH).rev().find(|&depth| !self.reducible[depth].is_empty()) {
+            Some(depth) => depth,
+            None => return false,
+        };
+
+        // Merge the least populated node first so that dominant colors keep their detail
+        let level: &Vec<usize> = &self.reducible[depth];
+        let position: usize = match (0..level.len())
+            .filter(|&position| self.leaf_count + 1 - self.child_count(level[position]) >= min_leaves)
+            .min_by_key(|&position| self.nodes[level[position]].pixels)
+        {
+            Some(position) => position,
+            None => return false,
+        };
+        let node: usize = self.reducible[depth].swap_remove(position);
+
+        // Deeper levels are already reduced, so every child here is a leaf
+        let mut merged: usize = 0;
+        for index in 0..8 {
+            if let Some(child) = self.nodes[node].children[index].take() {
+                let (red, green, blue, count) = {
+                    let leaf: &OctreeNode = &self.nodes[child];
+                    (leaf.red, leaf.green, leaf.blue, leaf.count)
+                };
+                let target: &mut OctreeNode = &mut self.nodes[node];
+                target.red += red;
+                target.green += green;
+                target.blue += blue;
+                target.count += count;
+                merged += 1;
+            }
+        }
+
+        self.nodes[node].is_leaf = true;
+        self.leaf_count = self.leaf_count + 1 - merged;
+        true
+    }
+
+    fn leaves(&self, node: usize, colors: &mut Vec<(Color, u64)>) {
+        let current: &OctreeNode = &self.nodes[node];
+        if current.is_leaf {
+            if let Some(red) = current.red.checked_div(current.count) {
+                colors.push((
+                    Color::from_rgb_components(
+                        red as u8,
+                        (current.green / current.count) as u8,
+                        (current.blue / current.count) as u8,
+                    ),
+                    current.count,
+                ));
+            }
+            return;
+        }
+        for child in current.children.iter().flatten() {
+            self.leaves(*child, colors);
+        }
+    }
+}
+
+fn distance(a: &([f64; 3], u64), b: &([f64; 3], u64)) -> f64 {
+    (a.0[0] - b.0[0]).powi(2) + (a.0[1] - b.0[1]).powi(2) + (a.0[2] - b.0[2]).powi(2)
+}
+
+// Octree reduction merges up to eight leaves at once, so the last few colors over the
+// budget are merged pairwise instead, closest pair first.
+fn merge_closest(colors: &mut Vec<([f64; 3], u64)>, max_colors: usize) {
+    while colors.len() > max_colors {
+        let mut best: (usize, usize, f64) = (0, 1, f64::MAX);
+        for i in 0..colors.len() {
+            for j in (i + 1)..colors.len() {
+                let d: f64 = distance(&colors[i], &colors[j]);
+                if d < best.2 {
+                    best = (i, j, d);
+                }
+            }
+        }
+
+        let (i, j, _) = best;
+        let removed: ([f64; 3], u64) = colors.swap_remove(j);
+        let kept: &mut ([f64; 3], u64) = &mut colors[i];
+        let total: f64 = (kept.1 + removed.1) as f64;
+        for channel in 0..3 {
+            kept.0[channel] = (kept.0[channel] * kept.1 as f64 + removed.0[channel] * removed.1 as f64) / total;
+        }
+        kept.1 += removed.1;
+    }
+}
+
+pub fn octree_palette(image: &RgbImage, max_colors: usize) -> Vec<Color> {
+    let max_colors: usize = max_colors.max(1);
+    let working_leaves: usize = MAX_WORKING_LEAVES.max(max_colors);
+    let mut tree: Octree = Octree::new();
+
+    for pixel in image.pixels() {
+        tree.insert(Color::from_rgb(pixel));
+        while tree.leaf_count > working_leaves && tree.reduce(working_leaves) {}
+    }
+    while tree.leaf_count > max_colors && tree.reduce(max_colors) {}
+
+    let mut leaves: Vec<(Color, u64)> = Vec::new();
+    tree.leaves(0, &mut leaves);
+    let mut colors: Vec<([f64; 3], u64)> = leaves.iter()
+        .map(|&(color, count)| ([color.r as f64, color.g as f64, color.b as f64], count))
+        .collect();
+    merge_closest(&mut colors, max_colors);
+
+    colors.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    colors.into_iter()
+        .map(|(rgb, _)| Color::from_rgb_components(rgb[0].round() as u8, rgb[1].round() as u8, rgb[2].round() as u8))
+        .collect()
+}
+
+// A box of distinct colors with their pixel counts.
+struct ColorBox {
+    colors: Vec<([u8; 3], u64)>,
+}
+
+impl ColorBox {
+    fn range(&self, channel: usize) -> u8 {
+        let values = self.colors.iter().map(|(rgb, _)| rgb[channel]);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    }
+
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..3).map(|channel| (channel, self.range(channel))).max_by_key(|&(_, range)| range).unwrap_or((0, 0))
+    }
+
+    // Sorts along the widest channel and splits where half of the pixels are on either side.
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel();
+        self.colors.sort_by_key(|(rgb, _)| rgb[channel]);
+        let total: u64 = self.colors.iter().map(|&(_, count)| count).sum();
+        let mut seen: u64 = 0;
+        let mut at: usize = 1;
+        for (index, &(_, count)) in self.colors.iter().enumerate() {
+            seen += count;
+            if seen * 2 >= total {
+                at = index + 1;
+                break;
+            }
+        }
+        let rest: Vec<([u8; 3], u64)> = self.colors.split_off(at.clamp(1, self.colors.len() - 1));
+        (self, ColorBox { colors: rest })
+    }
+
+    fn average(&self) -> ([f64; 3], u64) {
+        let total: u64 = self.colors.iter().map(|&(_, count)| count).sum();
+        let mut sum: [f64; 3] = [0.0; 3];
+        for &(rgb, count) in &self.colors {
+            for channel in 0..3 {
+                sum[channel] += rgb[channel] as f64 * count as f64;
+            }
+        }
+        ([sum[0] / total as f64, sum[1] / total as f64, sum[2] / total as f64], total)
+    }
+}
+
+// Heckbert's median cut: starting from one box around all colors, repeatedly halves the box with
+// the widest channel range at its median pixel until there are max_colors boxes, then averages each.
+pub fn median_cut_palette(image: &RgbImage, max_colors: usize) -> Vec<Color> {
+    let mut histogram: BTreeMap<[u8; 3], u64> = BTreeMap::new();
+    for pixel in image.pixels() {
+        *histogram.entry(pixel.0).or_insert(0) += 1;
+    }
+    if histogram.is_empty() {
+        return Vec::new();
+    }
+
+    let mut boxes: Vec<ColorBox> = vec![ColorBox { colors: histogram.into_iter().collect() }];
+    while boxes.len() < max_colors.max(1) {
+        let widest: Option<usize> = (0..boxes.len())
+            .filter(|&index| boxes[index].colors.len() > 1)
+            .max_by_key(|&index| boxes[index].widest_channel().1);
+        let Some(widest) = widest else { break };
+        let (low, high) = boxes.swap_remove(widest).split();
+        boxes.push(low);
+        boxes.push(high);
+    }
+
+    let mut colors: Vec<([f64; 3], u64)> = boxes.iter().map(ColorBox::average).collect();
+    colors.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    colors.into_iter()
+        .map(|(rgb, _)| Color::from_rgb_components(rgb[0].round() as u8, rgb[1].round() as u8, rgb[2].round() as u8))
+        .collect()
+}
+
+pub const KMEANS_ITERATIONS: usize = 24;
+
+// Evenly strided pixels so every image contributes a similar number of samples.
+pub fn sample_pixels(image: &RgbImage, max_samples: usize) -> Vec<Color> {
+    let total: usize = (image.width() * image.height()) as usize;
+    let stride: usize = total.div_ceil(max_samples.max(1)).max(1);
+    image.pixels().step_by(stride).map(Color::from_rgb).collect()
+}
+
+fn squared_distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn nearest_center(point: &[f32; 3], centers: &[[f32; 3]]) -> usize {
+    let mut best: (usize, f32) = (0, f32::MAX);
+    for (index, center) in centers.iter().enumerate() {
+        let d: f32 = squared_distance(point, center);
+        if d < best.1 {
+            best = (index, d);
+        }
+    }
+    best.0
+}
+
+// k-means++ seeding with an xorshift sequence started from `seed`, so the same inputs and seed
+// give the same palette.
+fn seed_centers(points: &[[f32; 3]], count: usize, seed: u64) -> Vec<[f32; 3]> {
+    let mut state: u64 = (0x9E37_79B9_7F4A_7C15 ^ seed).max(1);
+    let mut next_unit = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    };
+
+    let mut centers: Vec<[f32; 3]> = vec![points[(next_unit() * points.len() as f64) as usize % points.len()]];
+    let mut distances: Vec<f32> = points.iter().map(|point| squared_distance(point, &centers[0])).collect();
+    while centers.len() < count {
+        let total: f64 = distances.iter().map(|&d| d as f64).sum();
+        if total <= 0.0 {
+            break;
+        }
+        let mut target: f64 = next_unit() * total;
+        let mut chosen: usize = points.len() - 1;
+        for (index, &d) in distances.iter().enumerate() {
+            target -= d as f64;
+            if target <= 0.0 {
+                chosen = index;
+                break;
+            }
+        }
+        centers.push(points[chosen]);
+        for (distance, point) in distances.iter_mut().zip(points) {
+            *distance = distance.min(squared_distance(point, &points[chosen]));
+        }
+    }
+    centers
+}
+
+// Clusters the samples into at most max_colors colors. With `linear` the centroids are averaged
+// in linear light, which keeps mixes of bright and dark samples from coming out too dark.
+// Stops after `iterations` rounds (at least one) or once no sample changes cluster.
+pub fn kmeans_palette(samples: &[Color], max_colors: usize, linear: bool, iterations: usize, seed: u64) -> Vec<Color> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let encode = |color: &Color| -> [f32; 3] {
+        if linear {
+            [srgb_to_linear(color.r), srgb_to_linear(color.g), srgb_to_linear(color.b)]
+        } else {
+            [color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0]
+        }
+    };
+    let points: Vec<[f32; 3]> = samples.iter().map(encode).collect();
+    let mut centers: Vec<[f32; 3]> = seed_centers(&points, max_colors.max(1), seed);
+    let mut assignments: Vec<usize> = vec![usize::MAX; points.len()];
+    let mut sizes: Vec<u64> = vec![0; centers.len()];
+
+    for _ in 0..iterations.max(1) {
+        let mut changed: bool = false;
+        for (assignment, point) in assignments.iter_mut().zip(&points) {
+            let nearest: usize = nearest_center(point, &centers);
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums: Vec<[f64; 3]> = vec![[0.0; 3]; centers.len()];
+        sizes = vec![0; centers.len()];
+        for (&assignment, point) in assignments.iter().zip(&points) {
+            for channel in 0..3 {
+                sums[assignment][channel] += point[channel] as f64;
+            }
+            sizes[assignment] += 1;
+        }
+        for (center, (sum, &size)) in centers.iter_mut().zip(sums.iter().zip(&sizes)) {
+            if size > 0 {
+                *center = [(sum[0] / size as f64) as f32, (sum[1] / size as f64) as f32, (sum[2] / size as f64) as f32];
+            }
+        }
+    }
+
+    let mut clusters: Vec<([f32; 3], u64)> = centers.into_iter().zip(sizes).filter(|&(_, size)| size > 0).collect();
+    clusters.sort_by_key(|&(_, size)| std::cmp::Reverse(size));
+    clusters.into_iter()
+        .map(|(center, _)| {
+            if linear {
+                Color::from_rgb_components(linear_to_srgb(center[0]), linear_to_srgb(center[1]), linear_to_srgb(center[2]))
+            } else {
+                Color::from_rgb_components(
+                    (center[0] * 255.0).round() as u8,
+                    (center[1] * 255.0).round() as u8,
+                    (center[2] * 255.0).round() as u8,
+                )
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn octree_returns_requested_color_count() {
+        let gradient: RgbImage = RgbImage::from_fn(32, 32, |x, y| Rgb([(x * 8) as u8, (y * 8) as u8, 128]));
+        for count in [1, 2, 6, 16] {
+            assert_eq!(octree_palette(&gradient, count).len(), count);
+        }
+
+        let two_colors: RgbImage = RgbImage::from_fn(8, 8, |x, _| if x < 4 { Rgb([255, 0, 0]) } else { Rgb([0, 0, 255]) });
+        let colors: Vec<Color> = octree_palette(&two_colors, 16);
+        assert_eq!(colors.len(), 2);
+        assert!(colors.iter().any(|c| (c.r, c.g, c.b) == (255, 0, 0)));
+        assert!(colors.iter().any(|c| (c.r, c.g, c.b) == (0, 0, 255)));
+    }
+
+    #[test]
+    fn median_cut_splits_at_the_median_pixel() {
+        let gradient: RgbImage = RgbImage::from_fn(32, 32, |x, y| Rgb([(x * 8) as u8, (y * 8) as u8, 128]));
+        for count in [1, 2, 6, 16] {
+            assert_eq!(median_cut_palette(&gradient, count).len(), count);
+        }
+
+        // Only two distinct colors, listed most common first
+        let mostly_red: RgbImage = RgbImage::from_fn(8, 8, |x, _| if x < 6 { Rgb([240, 0, 0]) } else { Rgb([0, 0, 240]) });
+        let colors: Vec<Color> = median_cut_palette(&mostly_red, 16);
+        assert_eq!(colors.len(), 2);
+        assert_eq!((colors[0].r, colors[0].b), (240, 0));
+        assert_eq!((colors[1].r, colors[1].b), (0, 240));
+    }
+
+    #[test]
+    fn kmeans_averages_in_linear_light() {
+        let samples: Vec<Color> = [0, 255].iter().map(|&v| Color::from_rgb_components(v, v, v)).collect();
+        let linear: Vec<Color> = kmeans_palette(&samples, 1, true, KMEANS_ITERATIONS, 0);
+        let gamma: Vec<Color> = kmeans_palette(&samples, 1, false, KMEANS_ITERATIONS, 0);
+        assert_eq!(linear[0].r, 188);
+        assert_eq!(gamma[0].r, 128);
+
+        let mixed: Vec<Color> = (0..40).map(|i| if i % 4 == 0 { Color::from_rgb_components(0, 0, 250) } else { Color::from_rgb_components(250, 0, 0) }).collect();
+        let colors: Vec<Color> = kmeans_palette(&mixed, 2, true, KMEANS_ITERATIONS, 0);
+        assert_eq!((colors[0].r, colors[0].b), (250, 0));
+        assert_eq!((colors[1].r, colors[1].b), (0, 250));
+
+        // Other seeds start from other samples but settle on the same two clusters
+        for seed in [1, 7, 0x9E37_79B9_7F4A_7C15] {
+            let reseeded: Vec<Color> = kmeans_palette(&mixed, 2, true, KMEANS_ITERATIONS, seed);
+            assert_eq!((reseeded[0].r, reseeded[0].b, reseeded[1].r, reseeded[1].b), (250, 0, 0, 250));
+        }
+        let gradient: Vec<Color> = (0..=255).map(|v| Color::from_rgb_components(v, v, v)).collect();
+        assert_eq!(kmeans_palette(&gradient, 4, false, 0, 3).len(), 4);
+        let reds = |seed: u64| -> Vec<u8> { kmeans_palette(&gradient, 4, false, 2, seed).iter().map(|color| color.r).collect() };
+        assert_eq!(reds(3), reds(3));
+    }
+}
@@ -0,0 +1,151 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+
+// Candidates within this factor of the best overlap error are equally good, which keeps the
+// result from repeating the same patch over and over.
+const TOLERANCE: f64 = 1.1;
+
+// Overlap errors are computed for about this many evenly spaced exemplar positions per patch.
+const CANDIDATES: u32 = 400;
+
+// Fixed xorshift sequence, so the same exemplar always gives the same texture.
+struct Sequence(u64);
+
+impl Sequence {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound.max(1) as u64) as usize
+    }
+}
+
+fn difference(a: &Rgba<u8>, b: &Rgba<u8>) -> f64 {
+    a.0.iter().zip(b.0.iter()).map(|(&a, &b)| (a as f64 - b as f64).powi(2)).sum()
+}
+
+// Cheapest top-to-bottom path through `costs` (rows of `width` values), moving at most one
+// column per row. Returns the column of the path in every row.
+pub(crate) fn cheapest_path(costs: &[Vec<f64>]) -> Vec<usize> {
+    let width: usize = costs[0].len();
+    let mut totals: Vec<Vec<f64>> = vec![costs[0].clone()];
+    for row in &costs[1..] {
+        let previous: &Vec<f64> = totals.last().unwrap();
+        let next: Vec<f64> = (0..width)
+            .map(|x| row[x] + previous[x.saturating_sub(1)..(x + 2).min(width)].iter().copied().fold(f64::MAX, f64::min))
+            .collect();
+        totals.push(next);
+    }
+
+    let last: &Vec<f64> = totals.last().unwrap();
+    let mut x: usize = (0..width).min_by(|&a, &b| last[a].total_cmp(&last[b])).unwrap_or(0);
+    let mut path: Vec<usize> = vec![x; costs.len()];
+    for y in (0..costs.len() - 1).rev() {
+        let range: std::ops::Range<usize> = x.saturating_sub(1)..(x + 2).min(width);
+        x = range.min_by(|&a, &b| totals[y][a].total_cmp(&totals[y][b])).unwrap_or(x);
+        path[y] = x;
+    }
+    path
+}
+
+// Image quilting (Efros and Freeman): grows a texture of the given size from square patches of
+// the exemplar. Each patch is picked so it matches what is already placed in the overlap, and
+// the seam inside the overlap follows the path of least difference, so no block edges show.
+// `patch` defaults to a third of the exemplar's shorter side.
+pub fn quilt(exemplar: &DynamicImage, width: u32, height: u32, patch: Option<u32>) -> Result<DynamicImage, String> {
+    let source: RgbaImage = exemplar.to_rgba8();
+    let shorter: u32 = source.width().min(source.height());
+    let patch: u32 = patch.unwrap_or(shorter / 3).min(shorter);
+    if patch < 3 {
+        return Err(format!("texture synthesis needs patches of at least 3 pixels, the exemplar allows {}", patch));
+    }
+    let overlap: u32 = (patch / 6).max(1);
+    let step: u32 = patch - overlap;
+    let columns: u32 = width.saturating_sub(overlap).div_ceil(step).max(1);
+    let rows: u32 = height.saturating_sub(overlap).div_ceil(step).max(1);
+    let mut canvas: RgbaImage = RgbaImage::new(columns * step + overlap, rows * step + overlap);
+
+    let positions_x: u32 = source.width() - patch + 1;
+    let positions_y: u32 = source.height() - patch + 1;
+    let stride: u32 = (((positions_x * positions_y) as f64 / CANDIDATES as f64).sqrt().ceil() as u32).max(1);
+    let candidates: Vec<(u32, u32)> = (0..positions_y).step_by(stride as usize)
+        .flat_map(|y| (0..positions_x).step_by(stride as usize).map(move |x| (x, y)))
+        .collect();
+    let mut sequence: Sequence = Sequence(0x9E37_79B9_7F4A_7C15);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let (left, top) = (column * step, row * step);
+            let (has_left, has_top) = (column > 0, row > 0);
+            // Squared difference between a candidate and the canvas at one patch pixel
+            let cost = |canvas: &RgbaImage, (sx, sy): (u32, u32), x: u32, y: u32| difference(source.get_pixel(sx + x, sy + y), canvas.get_pixel(left + x, top + y));
+
+            let (sx, sy) = if !has_left && !has_top {
+                candidates[sequence.below(candidates.len())]
+            } else {
+                let errors: Vec<f64> = candidates.iter()
+                    .map(|&candidate| {
+                        let mut error: f64 = 0.0;
+                        for y in 0..patch {
+                            for x in 0..patch {
+                                if (has_left && x < overlap) || (has_top && y < overlap) {
+                                    error += cost(&canvas, candidate, x, y);
+                                }
+                            }
+                        }
+                        error
+                    })
+                    .collect();
+                let best: f64 = errors.iter().copied().fold(f64::MAX, f64::min);
+                let good: Vec<usize> = (0..candidates.len()).filter(|&index| errors[index] <= best * TOLERANCE).collect();
+                candidates[good[sequence.below(good.len())]]
+            };
+
+            // Pixels on the placed side of a seam keep the canvas
+            let mut keep: Vec<bool> = vec![false; (patch * patch) as usize];
+            if has_left {
+                let costs: Vec<Vec<f64>> = (0..patch).map(|y| (0..overlap).map(|x| cost(&canvas, (sx, sy), x, y)).collect()).collect();
+                for (y, seam) in cheapest_path(&costs).into_iter().enumerate() {
+                    for x in 0..seam {
+                        keep[y * patch as usize + x] = true;
+                    }
+                }
+            }
+            if has_top {
+                let costs: Vec<Vec<f64>> = (0..patch).map(|x| (0..overlap).map(|y| cost(&canvas, (sx, sy), x, y)).collect()).collect();
+                for (x, seam) in cheapest_path(&costs).into_iter().enumerate() {
+                    for y in 0..seam {
+                        keep[y * patch as usize + x] = true;
+                    }
+                }
+            }
+            for y in 0..patch {
+                for x in 0..patch {
+                    if !keep[(y * patch + x) as usize] {
+                        canvas.put_pixel(left + x, top + y, *source.get_pixel(sx + x, sy + y));
+                    }
+                }
+            }
+        }
+    }
+
+    let texture: RgbaImage = image::imageops::crop_imm(&canvas, 0, 0, width, height).to_image();
+    Ok(if exemplar.color().has_alpha() { texture.into() } else { DynamicImage::ImageRgba8(texture).to_rgb8().into() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use std::collections::HashSet;
+
+    #[test]
+    fn quilting_grows_the_exemplar_from_its_own_pixels() {
+        let exemplar: DynamicImage = RgbImage::from_fn(24, 20, |x, y| Rgb([(x * 10) as u8, (y * 12) as u8, ((x + y) % 3) as u8 * 100])).into();
+        let colors: HashSet<[u8; 3]> = exemplar.to_rgb8().pixels().map(|pixel| pixel.0).collect();
+        let texture: DynamicImage = quilt(&exemplar, 70, 45, Some(8)).unwrap();
+        assert_eq!((texture.width(), texture.height()), (70, 45));
+        assert!(texture.to_rgb8().pixels().all(|pixel| colors.contains(&pixel.0)));
+        assert_eq!(quilt(&exemplar, 70, 45, Some(8)).unwrap().to_rgb8(), texture.to_rgb8());
+        assert!(quilt(&exemplar, 70, 45, Some(2)).is_err());
+    }
+}
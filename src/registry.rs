@@ -0,0 +1,583 @@
+use filter::blend::BlendMode;
+use filter::channels::{parse_channel_mask, ChannelSet, Subsampling};
+use filter::color_parse::{parse_color, parse_color_pair, split_color_list};
+use filter::convolve::Kernel;
+use filter::diffusion::{diffusion_kernel, DIFFUSION_KERNELS};
+use filter::filter::*;
+use filter::library::{resolve_palette, PaletteSource};
+use filter::remap::Remap;
+use filter::tone::ToneMapper;
+use std::path::{Path, PathBuf};
+use crate::{parse_geometry_size, parse_numbers, parse_size};
+
+// What operation parsers may need besides the flag value.
+pub struct ParseContext<'a> {
+    pub palette_dir: &'a Path,
+    // The palette named by -pal=NAME; a run can only use one.
+    pub named_palette: &'a mut Option<PaletteSource>,
+}
+
+type Parse = fn(Option<&str>, &mut ParseContext) -> Result<Vec<FilterOperation>, String>;
+
+// One command line operation. It is written -flag or --name, and every spelling also works with
+// the other prefix, so -floyd, --floyd, -fs and --fs all mean the same. The name matches the
+// operation's help page.
+pub struct OperationSpec {
+    pub flag: &'static str,
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    // Positions in the comma-separated value that take whole numbers, e.g. pixel sizes, counts
+    // and levels, so sweeps and --jitter never hand them a fraction.
+    pub whole: &'static [usize],
+    parse: Parse,
+}
+
+impl OperationSpec {
+    pub fn spellings(&self) -> impl Iterator<Item = &'static str> {
+        [self.flag, self.name].into_iter().chain(self.aliases.iter().copied())
+    }
+}
+
+fn single(operation: FilterOperation) -> Result<Vec<FilterOperation>, String> {
+    Ok(vec![operation])
+}
+
+fn no_value(value: Option<&str>, flag: &str, operation: FilterOperation) -> Result<Vec<FilterOperation>, String> {
+    match value {
+        None => single(operation),
+        Some(value) => Err(format!("-{} takes no value, got {}", flag, value)),
+    }
+}
+
+fn required<'a>(value: Option<&'a str>, flag: &str, form: &str) -> Result<&'a str, String> {
+    value.ok_or_else(|| format!("-{} needs a value: -{}={}", flag, flag, form))
+}
+
+fn palette(value: Option<&str>, context: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    if let Some(name) = value {
+        let source: PaletteSource = resolve_palette(name, context.palette_dir)?;
+        if context.named_palette.as_ref().is_some_and(|existing| *existing != source) {
+            return Err("Only one named palette can be used per run".to_string());
+        }
+        *context.named_palette = Some(source);
+    }
+    single(FilterOperation::Palette)
+}
+
+fn pixelate(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    match value.map(|size| (size, Length::parse(size))) {
+        None => single(FilterOperation::Pixelate(Length::Pixels(8))),
+        Some((_, Some(Length::Pixels(0)))) => Ok(Vec::new()),
+        Some((_, Some(size))) => single(FilterOperation::Pixelate(size)),
+        Some((size, None)) => Err(format!("Invalid pixel size: {}", size)),
+    }
+}
+
+fn geometry(value: Option<&str>, flag: &str, operation: fn(Length, Length) -> FilterOperation) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, flag, "WxH")?;
+    match parse_geometry_size(value) {
+        Some((width, height)) => single(operation(width, height)),
+        None => Err(format!("Invalid size: {}", value)),
+    }
+}
+
+fn warp(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "warp", "quad(x1,y1,x2,y2,x3,y3,x4,y4)")?;
+    match parse_numbers(value, "quad").as_deref() {
+        Some(&[x1, y1, x2, y2, x3, y3, x4, y4]) => single(FilterOperation::Warp([(x1, y1), (x2, y2), (x3, y3), (x4, y4)])),
+        _ => Err(format!("Invalid warp: {} (expected quad(x1,y1,x2,y2,x3,y3,x4,y4))", value)),
+    }
+}
+
+fn affine(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "affine", "a,b,c,d,e,f")?;
+    match parse_numbers(value, "matrix").as_deref() {
+        Some(&[a, b, c, d, e, f]) => single(FilterOperation::Affine([a, b, c, d, e, f])),
+        _ => Err(format!("Invalid affine matrix: {} (expected a,b,c,d,e,f)", value)),
+    }
+}
+
+fn lens(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "lens", "K1[,K2][,add]")?;
+    let (coefficients, add) = match value.strip_suffix(",add") {
+        Some(coefficients) => (coefficients, true),
+        None => (value, false),
+    };
+    match parse_numbers(coefficients, "").as_deref() {
+        Some(&[k1]) => single(FilterOperation::Lens(k1, 0.0, add)),
+        Some(&[k1, k2]) => single(FilterOperation::Lens(k1, k2, add)),
+        _ => Err(format!("Invalid lens coefficients: {} (expected k1[,k2][,add])", value)),
+    }
+}
+
+fn rotate(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "rotate", "DEGREES[,COLOR][,crop]")?;
+    let parts: Vec<&str> = split_color_list(value);
+    let degrees: f32 = match parts[0].trim().parse::<f32>() {
+        Ok(degrees) if degrees.is_finite() => degrees,
+        _ => return Err(format!("Invalid rotation: {} (expected DEGREES[,COLOR][,crop])", value)),
+    };
+    let (mut background, mut expand) = (None, true);
+    for part in &parts[1..] {
+        match part.trim() {
+            "crop" => expand = false,
+            "expand" => expand = true,
+            color => background = Some(parse_color(color).map_err(|e| format!("Invalid -rotate background: {}", e))?),
+        }
+    }
+    single(FilterOperation::Rotate(degrees, background, expand))
+}
+
+fn swirl(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "swirl", "DEGREES")?;
+    match value.parse::<f32>() {
+        Ok(degrees) if degrees.is_finite() => single(FilterOperation::Remap(Remap::Swirl(degrees))),
+        _ => Err(format!("Invalid swirl angle: {}", value)),
+    }
+}
+
+fn spherize(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "spherize", "AMOUNT")?;
+    match value.parse::<f32>() {
+        Ok(amount) if amount > -1.0 && amount <= 1.0 => single(FilterOperation::Remap(Remap::Spherize(amount))),
+        _ => Err(format!("Invalid spherize amount: {} (expected more than -1 and at most 1)", value)),
+    }
+}
+
+fn kaleidoscope(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "kaleido", "SEGMENTS")?;
+    match value.parse::<u32>() {
+        Ok(segments) if (2..=64).contains(&segments) => single(FilterOperation::Remap(Remap::Kaleidoscope(segments))),
+        _ => Err(format!("Invalid kaleidoscope segments: {} (expected 2 to 64)", value)),
+    }
+}
+
+fn mirror_tile(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "mirror-tile", "COLUMNSxROWS")?;
+    match parse_size(value) {
+        Some((columns, rows)) => single(FilterOperation::Remap(Remap::MirrorTile(columns, rows))),
+        None => Err(format!("Invalid tile grid: {} (expected COLUMNSxROWS, e.g. 2x2)", value)),
+    }
+}
+
+fn wave(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "wave", "AMPLITUDE,WAVELENGTH[,x|y]")?;
+    let parts: Vec<&str> = value.split(',').collect();
+    let vertical: Option<bool> = match parts.get(2) {
+        None | Some(&"x") => Some(false),
+        Some(&"y") => Some(true),
+        Some(_) => None,
+    };
+    match (parts.len(), parts[0].parse::<f32>(), parts.get(1).map(|wavelength| wavelength.parse::<f32>()), vertical) {
+        (2 | 3, Ok(amplitude), Some(Ok(wavelength)), Some(vertical)) if amplitude.is_finite() && wavelength.is_finite() && wavelength > 0.0 => {
+            single(FilterOperation::Remap(Remap::Wave(amplitude, wavelength, vertical)))
+        },
+        _ => Err(format!("Invalid wave: {} (expected AMPLITUDE,WAVELENGTH[,x|y] with a positive wavelength)", value)),
+    }
+}
+
+fn roll(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "roll", "DX,DY")?;
+    match value.split_once(',').map(|(dx, dy)| (dx.parse::<i32>(), dy.parse::<i32>())) {
+        Some((Ok(dx), Ok(dy))) => single(FilterOperation::Remap(Remap::Roll(dx, dy))),
+        _ => Err(format!("Invalid roll offset: {} (expected DX,DY in pixels, e.g. 10,-5)", value)),
+    }
+}
+
+fn crop(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "crop", "X,Y,W,H")?;
+    let parts: Vec<Option<Length>> = value.split(',').map(Length::parse).collect();
+    match parts.as_slice() {
+        [Some(x), Some(y), Some(width), Some(height)] if *width != Length::Pixels(0) && *height != Length::Pixels(0) => {
+            single(FilterOperation::Crop(*x, *y, *width, *height))
+        },
+        _ => Err(format!("Invalid crop rectangle: {}", value)),
+    }
+}
+
+fn canvas(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "canvas", "WxH|W:H")?;
+    let size: Option<CanvasSize> = match value.split_once(':') {
+        Some((ratio_width, ratio_height)) => match (ratio_width.parse::<u32>(), ratio_height.parse::<u32>()) {
+            (Ok(ratio_width), Ok(ratio_height)) if ratio_width > 0 && ratio_height > 0 => Some(CanvasSize::Aspect(ratio_width, ratio_height)),
+            _ => None,
+        },
+        None => parse_geometry_size(value).map(|(width, height)| CanvasSize::Exact(width, height)),
+    };
+    size.map(FilterOperation::Canvas).map(|operation| vec![operation]).ok_or_else(|| format!("Invalid canvas size: {}", value))
+}
+
+fn pad(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "pad", "N")?;
+    Length::parse(value).map(|size| vec![FilterOperation::Pad(size)]).ok_or_else(|| format!("Invalid padding: {}", value))
+}
+
+fn blur(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "blur", "SIGMA")?;
+    match value.parse::<f32>() {
+        Ok(sigma) if sigma > 0.0 && sigma <= 1000.0 => single(FilterOperation::Blur(sigma)),
+        _ => Err(format!("Invalid blur sigma: {} (expected more than 0 and at most 1000)", value)),
+    }
+}
+
+fn box_blur(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "boxblur", "RADIUS")?;
+    Length::parse(value).map(|radius| vec![FilterOperation::BoxBlur(radius)]).ok_or_else(|| format!("Invalid box blur radius: {}", value))
+}
+
+fn adaptive_threshold(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "athreshold", "RADIUS[,OFFSET]")?;
+    let (radius, offset) = value.split_once(',').unwrap_or((value, "10"));
+    match (Length::parse(radius), offset.parse::<i32>()) {
+        (Some(radius), Ok(offset)) => single(FilterOperation::AdaptiveThreshold(radius, offset)),
+        _ => Err(format!("Invalid adaptive threshold: {} (expected RADIUS[,OFFSET])", value)),
+    }
+}
+
+fn local_contrast(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "localcontrast", "RADIUS[,AMOUNT]")?;
+    let (radius, amount) = value.split_once(',').unwrap_or((value, "0.5"));
+    match (Length::parse(radius), amount.parse::<f32>()) {
+        (Some(radius), Ok(amount)) if amount.is_finite() => single(FilterOperation::LocalContrast(radius, amount)),
+        _ => Err(format!("Invalid local contrast: {} (expected RADIUS[,AMOUNT])", value)),
+    }
+}
+
+fn clahe(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = match value {
+        Some(value) => value,
+        None => return single(FilterOperation::Clahe(8, 2.0)),
+    };
+    let (tiles, clip) = value.split_once(',').unwrap_or((value, "2"));
+    match (tiles.parse::<u32>(), clip.parse::<f32>()) {
+        (Ok(tiles), Ok(clip)) if tiles > 0 && clip >= 1.0 && clip.is_finite() => single(FilterOperation::Clahe(tiles, clip)),
+        _ => Err(format!("Invalid CLAHE parameters: {} (expected TILES[,CLIP] with CLIP >= 1)", value)),
+    }
+}
+
+fn dehaze(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    match value.map(|value| (value, value.parse::<f32>())) {
+        None => single(FilterOperation::Dehaze(0.95)),
+        Some((_, Ok(strength))) if (0.0..=1.0).contains(&strength) => single(FilterOperation::Dehaze(strength)),
+        Some((value, _)) => Err(format!("Invalid dehaze strength: {} (expected 0 to 1)", value)),
+    }
+}
+
+fn tonemap(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "tonemap", "OPERATOR[,EV]")?;
+    let (name, exposure) = value.split_once(',').unwrap_or((value, "0"));
+    match (ToneMapper::from_name(name), exposure.parse::<f32>()) {
+        (Some(mapper), Ok(exposure)) if exposure.is_finite() => single(FilterOperation::Tonemap(mapper, exposure)),
+        _ => Err(format!("Invalid tone mapping: {} (expected reinhard, aces or filmic, then optionally ,EV)", value)),
+    }
+}
+
+fn exposure(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "exposure", "STOPS")?;
+    match value.parse::<f32>() {
+        Ok(stops) if stops.is_finite() => single(FilterOperation::Exposure(stops)),
+        _ => Err(format!("Invalid exposure: {} (expected stops, e.g. -0.5 or 1)", value)),
+    }
+}
+
+// -highlights, -shadows and -clarity all take an amount from -100 to 100.
+fn amount(value: Option<&str>, name: &str, operation: fn(f32) -> FilterOperation) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, name, "AMOUNT")?;
+    match value.parse::<f32>() {
+        Ok(amount) if (-100.0..=100.0).contains(&amount) => single(operation(amount)),
+        _ => Err(format!("Invalid {} amount: {} (expected -100 to 100)", name, value)),
+    }
+}
+
+fn dither(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "dither", "KERNEL")?;
+    match diffusion_kernel(value) {
+        Some(kernel) => single(FilterOperation::ErrorDiffusion(kernel)),
+        None => {
+            let names: Vec<&str> = DIFFUSION_KERNELS.iter().map(|kernel| kernel.name).collect();
+            Err(format!("Unknown diffusion kernel: {} (expected {})", value, names.join(", ")))
+        },
+    }
+}
+
+fn bayer(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    match value.map(|value| (value, value.parse::<u8>())) {
+        None => single(FilterOperation::Bayer(2)),
+        Some((_, Ok(levels))) if levels >= 2 => single(FilterOperation::Bayer(levels)),
+        Some((value, _)) => Err(format!("Invalid Bayer levels: {} (expected 2 to 255)", value)),
+    }
+}
+
+fn blue_noise(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    match value.map(|value| (value, value.parse::<u8>())) {
+        None => single(FilterOperation::BlueNoise(2)),
+        Some((_, Ok(levels))) if levels >= 2 => single(FilterOperation::BlueNoise(levels)),
+        Some((value, _)) => Err(format!("Invalid blue noise levels: {} (expected 2 to 255)", value)),
+    }
+}
+
+fn split(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "split", "DIR[,SET]")?;
+    let (directory, set) = match value.rsplit_once(',') {
+        Some((directory, name)) => match ChannelSet::from_name(name) {
+            Some(set) => (directory, set),
+            None => return Err(format!("Unknown channel set: {} (expected rgba, hsl or ycbcr)", name)),
+        },
+        None => (value, ChannelSet::Rgba),
+    };
+    single(FilterOperation::Split(PathBuf::from(directory), set))
+}
+
+fn combine(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "combine", "r.png,g.png,b.png[,a.png]")?;
+    let paths: Vec<PathBuf> = value.split(',').map(PathBuf::from).collect();
+    if paths.len() != 3 && paths.len() != 4 {
+        return Err(format!("Invalid channel list: {} (expected r.png,g.png,b.png[,a.png])", value));
+    }
+    single(FilterOperation::Combine(paths))
+}
+
+fn anaglyph(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "anaglyph", "[LEFT,]RIGHT")?;
+    let paths: Vec<PathBuf> = value.split(',').map(PathBuf::from).collect();
+    if paths.len() > 2 || value.is_empty() {
+        return Err(format!("Invalid views: {} (expected RIGHT or LEFT,RIGHT)", value));
+    }
+    single(FilterOperation::Anaglyph(paths))
+}
+
+fn depth3d(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "depth3d", "DEPTH[,SHIFT]")?;
+    let (path, shift) = value.split_once(',').unwrap_or((value, "8"));
+    match shift.parse::<f32>() {
+        Ok(shift) if shift >= 0.0 && shift.is_finite() && !path.is_empty() => single(FilterOperation::Depth3d(PathBuf::from(path), shift)),
+        _ => Err(format!("Invalid depth map: {} (expected DEPTH[,SHIFT] with SHIFT in pixels)", value)),
+    }
+}
+
+fn synthesize(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "synth", "WxH[,PATCH]")?;
+    let (size, patch) = match value.split_once(',') {
+        Some((size, patch)) => (size, patch.parse::<u32>().ok().filter(|&patch| patch >= 3).map(Some)),
+        None => (value, Some(None)),
+    };
+    match (parse_size(size), patch) {
+        (Some((width, height)), Some(patch)) => single(FilterOperation::Synthesize(width, height, patch)),
+        _ => Err(format!("Invalid texture size: {} (expected WxH[,PATCH] with a patch of at least 3 pixels)", value)),
+    }
+}
+
+fn blend_with(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "blendwith", "PATH[,MODE[,OPACITY]]")?;
+    let parts: Vec<&str> = value.split(',').collect();
+    let mode: Option<BlendMode> = parts.get(1).map_or(Some(BlendMode::Normal), |name| BlendMode::from_name(name));
+    let opacity: Option<f32> = parts.get(2).map_or(Some(1.0), |opacity| opacity.parse::<f32>().ok().filter(|opacity| (0.0..=1.0).contains(opacity)));
+    match (mode, opacity) {
+        (Some(mode), Some(opacity)) if parts.len() <= 3 && !parts[0].is_empty() => single(FilterOperation::BlendWith(PathBuf::from(parts[0]), mode, opacity)),
+        _ => Err(format!("Invalid blend: {} (expected PATH[,MODE[,OPACITY]] with an opacity from 0 to 1)", value)),
+    }
+}
+
+fn chroma_subsample(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "chromasub", "4:2:0")?;
+    Subsampling::from_name(value)
+        .map(|subsampling| vec![FilterOperation::ChromaSubsample(subsampling)])
+        .ok_or_else(|| format!("Unknown chroma subsampling: {} (expected 4:2:0, 4:2:2 or 4:1:1)", value))
+}
+
+fn jpegify(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "jpegify", "QUALITY[,PASSES]")?;
+    let (quality, passes) = value.split_once(',').unwrap_or((value, "1"));
+    match (quality.parse::<u8>(), passes.parse::<u32>()) {
+        (Ok(quality @ 1..=100), Ok(passes @ 1..)) => single(FilterOperation::Jpegify(quality, passes)),
+        _ => Err(format!("Invalid JPEG settings: {} (expected QUALITY 1-100 and optional PASSES of at least 1)", value)),
+    }
+}
+
+fn kernel(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    Kernel::from_file(required(value, "kernel", "FILE")?).map(|kernel| vec![FilterOperation::Convolve(kernel)])
+}
+
+fn swap_palette(value: Option<&str>, context: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "swap-palette", "FROM,TO")?;
+    let sources: Vec<PaletteSource> = value.split(',')
+        .map(|name| resolve_palette(name, context.palette_dir))
+        .collect::<Result<Vec<PaletteSource>, String>>()?;
+    match sources.as_slice() {
+        [from, to] => single(FilterOperation::SwapPalette(from.clone(), to.clone())),
+        _ => Err(format!("Invalid -swap-palette: {} (expected FROM,TO)", value)),
+    }
+}
+
+// -floyd2 and -duotone both take a dark and a light color.
+fn color_pair(value: Option<&str>, flag: &str, operation: fn(Color, Color) -> FilterOperation) -> Result<Vec<FilterOperation>, String> {
+    let (dark, light) = parse_color_pair(required(value, flag, "DARK,LIGHT")?).map_err(|e| format!("Invalid -{} colors: {}", flag, e))?;
+    single(operation(dark, light))
+}
+
+fn reverse(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    match value.map(|letters| (letters, parse_channel_mask(letters))) {
+        None => single(FilterOperation::Reverse),
+        Some((_, Some(mask))) => single(FilterOperation::ReverseChannels(mask)),
+        Some((letters, None)) => Err(format!("Invalid channels: {} (expected letters from r, g, b and a, e.g. -rev=a or -rev=rb)", letters)),
+    }
+}
+
+fn quantize(value: Option<&str>, _: &mut ParseContext) -> Result<Vec<FilterOperation>, String> {
+    let value: &str = required(value, "quant", "N[,dither]")?;
+    let (count, dither) = match value.strip_suffix(",dither") {
+        Some(count) => (count, true),
+        None => (value, false),
+    };
+    match count.parse::<u32>() {
+        Ok(count) if count > 0 => single(FilterOperation::Quantize(count, dither)),
+        _ => Err(format!("Invalid color count: {}", count)),
+    }
+}
+
+pub const OPERATIONS: &[OperationSpec] = &[
+    OperationSpec { flag: "pal", name: "palette", aliases: &[], whole: &[], parse: palette },
+    OperationSpec { flag: "pixpal", name: "pixpal", aliases: &[], whole: &[], parse: |value, _| match value {
+        None => Ok(vec![FilterOperation::Pixelate(Length::Pixels(8)), FilterOperation::Palette]),
+        Some(value) => Err(format!("-pixpal takes no value, got {}", value)),
+    } },
+    OperationSpec { flag: "pix", name: "pixelate", aliases: &[], whole: &[0], parse: pixelate },
+    OperationSpec { flag: "floyd", name: "floyd", aliases: &["fs", "floyd-steinberg"], whole: &[], parse: |value, _| no_value(value, "floyd", FilterOperation::FloydSteinberg) },
+    OperationSpec { flag: "atkinson", name: "atkinson", aliases: &[], whole: &[], parse: |value, _| no_value(value, "atkinson", FilterOperation::Atkinson) },
+    OperationSpec { flag: "dither", name: "dither", aliases: &["diffuse"], whole: &[], parse: dither },
+    OperationSpec { flag: "swap-palette", name: "swap-palette", aliases: &[], whole: &[], parse: swap_palette },
+    OperationSpec { flag: "floyd2", name: "floyd2", aliases: &[], whole: &[], parse: |value, _| color_pair(value, "floyd2", FilterOperation::FloydTwoColor) },
+    OperationSpec { flag: "bayer", name: "bayer", aliases: &["ordered"], whole: &[0], parse: bayer },
+    OperationSpec { flag: "bluenoise", name: "bluenoise", aliases: &["blue-noise"], whole: &[0], parse: blue_noise },
+    OperationSpec { flag: "rev", name: "reverse", aliases: &["invert", "negate"], whole: &[], parse: reverse },
+    OperationSpec { flag: "quant", name: "quantize", aliases: &[], whole: &[0], parse: quantize },
+    OperationSpec { flag: "resize", name: "resize", aliases: &["scale"], whole: &[], parse: |value, _| geometry(value, "resize", FilterOperation::Resize) },
+    OperationSpec { flag: "palresize", name: "palresize", aliases: &[], whole: &[], parse: |value, _| geometry(value, "palresize", FilterOperation::PaletteDownscale) },
+    OperationSpec { flag: "seamcarve", name: "seamcarve", aliases: &[], whole: &[], parse: |value, _| geometry(value, "seamcarve", FilterOperation::SeamCarve) },
+    OperationSpec { flag: "warp", name: "warp", aliases: &["perspective"], whole: &[], parse: warp },
+    OperationSpec { flag: "affine", name: "affine", aliases: &[], whole: &[], parse: affine },
+    OperationSpec { flag: "lens", name: "lens", aliases: &[], whole: &[], parse: lens },
+    OperationSpec { flag: "rotate", name: "rotate", aliases: &[], whole: &[], parse: rotate },
+    OperationSpec { flag: "polar", name: "polar", aliases: &[], whole: &[], parse: |value, _| no_value(value, "polar", FilterOperation::Remap(Remap::Polar)) },
+    OperationSpec { flag: "unpolar", name: "unpolar", aliases: &["depolar"], whole: &[], parse: |value, _| no_value(value, "unpolar", FilterOperation::Remap(Remap::Unpolar)) },
+    OperationSpec { flag: "swirl", name: "swirl", aliases: &["twirl"], whole: &[], parse: swirl },
+    OperationSpec { flag: "spherize", name: "spherize", aliases: &["bulge"], whole: &[], parse: spherize },
+    OperationSpec { flag: "kaleido", name: "kaleido", aliases: &["kaleidoscope"], whole: &[0], parse: kaleidoscope },
+    OperationSpec { flag: "mirror-tile", name: "mirror-tile", aliases: &["mirrortile"], whole: &[], parse: mirror_tile },
+    OperationSpec { flag: "wave", name: "wave", aliases: &[], whole: &[], parse: wave },
+    OperationSpec { flag: "roll", name: "roll", aliases: &[], whole: &[0, 1], parse: roll },
+    OperationSpec { flag: "crop", name: "crop", aliases: &[], whole: &[0, 1, 2, 3], parse: crop },
+    OperationSpec { flag: "trim", name: "trim", aliases: &["autocrop"], whole: &[], parse: |value, _| no_value(value, "trim", FilterOperation::Trim) },
+    OperationSpec { flag: "canvas", name: "canvas", aliases: &[], whole: &[], parse: canvas },
+    OperationSpec { flag: "pad", name: "pad", aliases: &[], whole: &[0], parse: pad },
+    OperationSpec { flag: "duotone", name: "duotone", aliases: &[], whole: &[], parse: |value, _| color_pair(value, "duotone", FilterOperation::Duotone) },
+    OperationSpec { flag: "blur", name: "blur", aliases: &["gaussian"], whole: &[], parse: blur },
+    OperationSpec { flag: "sharpen", name: "sharpen", aliases: &[], whole: &[], parse: |value, _| no_value(value, "sharpen", FilterOperation::Sharpen) },
+    OperationSpec { flag: "edge", name: "edge", aliases: &["edges"], whole: &[], parse: |value, _| no_value(value, "edge", FilterOperation::Edge) },
+    OperationSpec { flag: "emboss", name: "emboss", aliases: &[], whole: &[], parse: |value, _| no_value(value, "emboss", FilterOperation::Emboss) },
+    OperationSpec { flag: "kernel", name: "kernel", aliases: &["convolve"], whole: &[], parse: kernel },
+    OperationSpec { flag: "descreen", name: "descreen", aliases: &[], whole: &[], parse: |value, _| no_value(value, "descreen", FilterOperation::Descreen) },
+    OperationSpec { flag: "spectrum", name: "spectrum", aliases: &[], whole: &[], parse: |value, _| no_value(value, "spectrum", FilterOperation::Spectrum) },
+    OperationSpec { flag: "boxblur", name: "boxblur", aliases: &[], whole: &[0], parse: box_blur },
+    OperationSpec { flag: "athreshold", name: "athreshold", aliases: &["adaptive-threshold"], whole: &[0, 1], parse: adaptive_threshold },
+    OperationSpec { flag: "localcontrast", name: "localcontrast", aliases: &["local-contrast"], whole: &[0], parse: local_contrast },
+    OperationSpec { flag: "clahe", name: "clahe", aliases: &[], whole: &[0], parse: clahe },
+    OperationSpec { flag: "dehaze", name: "dehaze", aliases: &[], whole: &[], parse: dehaze },
+    OperationSpec { flag: "tonemap", name: "tonemap", aliases: &[], whole: &[], parse: tonemap },
+    OperationSpec { flag: "exposure", name: "exposure", aliases: &["ev"], whole: &[], parse: exposure },
+    OperationSpec { flag: "highlights", name: "highlights", aliases: &[], whole: &[], parse: |value, _| amount(value, "highlights", FilterOperation::Highlights) },
+    OperationSpec { flag: "shadows", name: "shadows", aliases: &[], whole: &[], parse: |value, _| amount(value, "shadows", FilterOperation::Shadows) },
+    OperationSpec { flag: "clarity", name: "clarity", aliases: &[], whole: &[], parse: |value, _| amount(value, "clarity", FilterOperation::Clarity) },
+    OperationSpec { flag: "split", name: "split", aliases: &[], whole: &[], parse: split },
+    OperationSpec { flag: "combine", name: "combine", aliases: &[], whole: &[], parse: combine },
+    OperationSpec { flag: "anaglyph", name: "anaglyph", aliases: &[], whole: &[], parse: anaglyph },
+    OperationSpec { flag: "depth3d", name: "depth3d", aliases: &[], whole: &[], parse: depth3d },
+    OperationSpec { flag: "synth", name: "synth", aliases: &["synthesize"], whole: &[1], parse: synthesize },
+    OperationSpec { flag: "blendwith", name: "blendwith", aliases: &["blend"], whole: &[], parse: blend_with },
+    OperationSpec { flag: "chromasub", name: "chromasub", aliases: &[], whole: &[], parse: chroma_subsample },
+    OperationSpec { flag: "jpegify", name: "jpegify", aliases: &[], whole: &[0, 1], parse: jpegify },
+];
+
+// Splits -name or --name[=value] into the name and value.
+fn split_flag(arg: &str) -> Option<(&str, Option<&str>)> {
+    let flag: &str = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-'))?;
+    match flag.split_once('=') {
+        Some((name, value)) => Some((name, Some(value))),
+        None => Some((flag, None)),
+    }
+}
+
+// The operation an argument spells, by flag, name or alias.
+pub fn find(arg: &str) -> Option<&'static OperationSpec> {
+    let (name, _) = split_flag(arg)?;
+    OPERATIONS.iter().find(|spec| spec.spellings().any(|spelling| spelling == name))
+}
+
+// None when the argument is not an operation, e.g. an --option.
+pub fn parse(arg: &str, context: &mut ParseContext) -> Option<Result<Vec<FilterOperation>, String>> {
+    let (_, value) = split_flag(arg)?;
+    find(arg).map(|spec| (spec.parse)(value, context))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution: usize = previous[j] + usize::from(a_char != b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// The closest operation spelling or option to an unknown argument, if any is close enough to be
+// a typo: at most two edits, and fewer than half the name.
+pub fn suggestion(arg: &str, options: &[&str]) -> Option<String> {
+    let (name, _) = split_flag(arg)?;
+    let operations = OPERATIONS.iter().flat_map(|spec| spec.spellings().map(|spelling| format!("-{}", spelling)));
+    let options = options.iter().map(|option| option.trim_end_matches('=').to_string());
+    operations.chain(options)
+        .map(|candidate| (edit_distance(name, candidate.trim_start_matches('-')), candidate))
+        .filter(|(distance, _)| *distance <= 2 && *distance * 2 < name.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::help_command;
+    use std::collections::HashSet;
+
+    #[test]
+    fn spellings_resolve_to_one_operation() {
+        let mut named_palette: Option<PaletteSource> = None;
+        let mut context: ParseContext = ParseContext { palette_dir: Path::new("."), named_palette: &mut named_palette };
+        for arg in ["-floyd", "--floyd", "-fs", "--floyd-steinberg"] {
+            assert!(matches!(parse(arg, &mut context), Some(Ok(operations)) if matches!(operations[..], [FilterOperation::FloydSteinberg])));
+        }
+        assert!(matches!(parse("--invert", &mut context), Some(Ok(operations)) if matches!(operations[..], [FilterOperation::Reverse])));
+        assert!(matches!(parse("--pixelate=3", &mut context), Some(Ok(operations)) if matches!(operations[..], [FilterOperation::Pixelate(Length::Pixels(3))])));
+        assert!(matches!(parse("-resize", &mut context), Some(Err(_))));
+        assert!(matches!(parse("-floyd=2", &mut context), Some(Err(_))));
+        assert!(matches!(parse("-blur=1e9", &mut context), Some(Err(_))));
+        assert!(parse("--explain", &mut context).is_none());
+
+        assert_eq!(suggestion("-flyod", &[]), Some("-floyd".to_string()));
+        assert_eq!(suggestion("--explian", &["--explain", "--stats"]), Some("--explain".to_string()));
+        assert_eq!(suggestion("-xyz", &[]), None);
+
+        // Every operation has a help page under its name, and no two operations share a spelling
+        let mut spellings: Vec<&str> = OPERATIONS.iter().flat_map(|spec| spec.spellings().collect::<HashSet<&str>>()).collect();
+        for spec in OPERATIONS {
+            assert!(help_command::find(spec.name).is_some(), "{} has no help page", spec.name);
+        }
+        let count: usize = spellings.len();
+        spellings.sort();
+        spellings.dedup();
+        assert_eq!(spellings.len(), count);
+    }
+}
@@ -0,0 +1,151 @@
+use image::DynamicImage;
+use std::f64::consts::TAU;
+use crate::geometry::resample;
+use crate::math::{atan2_f64, cos_f64, hypot_f64, powf_f64, sin_cos_f64, sin_f64};
+
+// Distortions that move every pixel to a new position and keep the image size. Each one is only
+// the inverse mapping from an output position to the source position it is sampled from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Remap {
+    // Unrolls the disc around the center: x becomes the angle clockwise from the top, y the radius.
+    Polar,
+    // The inverse of Polar, rolling the rows up into a disc.
+    Unpolar,
+    // Turns the inside of the centered circle, by the angle in degrees at the center and less
+    // toward the edge.
+    Swirl(f32),
+    // Bulges the centered circle outward for positive amounts, pinches it for negative ones.
+    Spherize(f32),
+    // Repeats the wedge of 360 / n degrees clockwise from the top around the center, every other
+    // copy mirrored. The wedge is scaled so its tip to the image corners stays inside the input.
+    Kaleidoscope(u32),
+    // Shrinks the image into a columns x rows grid of cells, mirrored in every other column and
+    // row so neighbouring cells meet edge to edge.
+    MirrorTile(u32, u32),
+    // Shifts row y sideways by amplitude * sin(2 pi y / wavelength) pixels, or each column up
+    // and down when vertical. What leaves one edge comes back in at the other.
+    Wave(f32, f32, bool),
+    // Translates by (dx, dy) pixels, wrapping around the edges.
+    Roll(i32, i32),
+}
+
+impl Remap {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Remap::Polar => "polar",
+            Remap::Unpolar => "unpolar",
+            Remap::Swirl(_) => "swirl",
+            Remap::Spherize(_) => "spherize",
+            Remap::Kaleidoscope(_) => "kaleido",
+            Remap::MirrorTile(..) => "mirror-tile",
+            Remap::Wave(..) => "wave",
+            Remap::Roll(..) => "roll",
+        }
+    }
+
+    // The source position sampled for output position (x, y) in a width x height image. The
+    // disc the polar maps and effects work on is the largest centered circle.
+    fn source(&self, x: f64, y: f64, width: f64, height: f64) -> (f64, f64) {
+        let (center_x, center_y) = (width / 2.0, height / 2.0);
+        let radius: f64 = center_x.min(center_y);
+        let (dx, dy) = (x - center_x, y - center_y);
+        let distance: f64 = hypot_f64(dx, dy);
+        match *self {
+            Remap::Polar => {
+                let (angle, distance) = (x / width * TAU, y / height * radius);
+                (center_x + distance * sin_f64(angle), center_y - distance * cos_f64(angle))
+            },
+            Remap::Unpolar => {
+                let angle: f64 = atan2_f64(dx, -dy).rem_euclid(TAU);
+                (angle / TAU * width, distance / radius * height)
+            },
+            Remap::Swirl(degrees) if distance < radius => {
+                let turn: f64 = -(degrees as f64).to_radians() * (1.0 - distance / radius).powi(2);
+                let (sin, cos) = sin_cos_f64(turn);
+                (center_x + dx * cos - dy * sin, center_y + dx * sin + dy * cos)
+            },
+            Remap::Spherize(amount) if distance < radius && distance > 0.0 => {
+                let factor: f64 = powf_f64(distance / radius, amount as f64);
+                (center_x + dx * factor, center_y + dy * factor)
+            },
+            Remap::Kaleidoscope(segments) => {
+                let wedge: f64 = TAU / segments.max(1) as f64;
+                let angle: f64 = atan2_f64(dx, -dy).rem_euclid(TAU);
+                let copy: f64 = (angle / wedge).floor();
+                let local: f64 = if copy % 2.0 == 1.0 { wedge * (copy + 1.0) - angle } else { angle - wedge * copy };
+                let distance: f64 = distance * radius / hypot_f64(center_x, center_y);
+                (center_x + distance * sin_f64(local), center_y - distance * cos_f64(local))
+            },
+            Remap::MirrorTile(columns, rows) => {
+                let fold = |position: f64, size: f64, cells: u32| {
+                    let cell_size: f64 = size / cells.max(1) as f64;
+                    let cell: f64 = (position / cell_size).floor();
+                    let offset: f64 = position / cell_size - cell;
+                    if cell % 2.0 == 1.0 { (1.0 - offset) * size } else { offset * size }
+                };
+                (fold(x, width, columns), fold(y, height, rows))
+            },
+            Remap::Wave(amplitude, wavelength, false) => {
+                let shift: f64 = amplitude as f64 * sin_f64(TAU * y.floor() / wavelength as f64);
+                ((x - shift).rem_euclid(width), y)
+            },
+            Remap::Wave(amplitude, wavelength, true) => {
+                let shift: f64 = amplitude as f64 * sin_f64(TAU * x.floor() / wavelength as f64);
+                (x, (y - shift).rem_euclid(height))
+            },
+            Remap::Roll(dx, dy) => ((x - dx as f64).rem_euclid(width), (y - dy as f64).rem_euclid(height)),
+            _ => (x, y),
+        }
+    }
+}
+
+pub fn remap(image: &DynamicImage, mapping: Remap) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    resample(image, width, height, |x, y| mapping.source(x, y, width as f64, height as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn remaps_move_pixels_and_round_trip() {
+        let image: DynamicImage = RgbImage::from_fn(40, 40, |x, y| Rgb([(x * 6) as u8, (y * 6) as u8, 128])).into();
+        for identity in [Remap::Swirl(0.0), Remap::Spherize(0.0), Remap::MirrorTile(1, 1), Remap::Wave(0.0, 10.0, false), Remap::Roll(40, -80)] {
+            assert_eq!(remap(&image, identity).to_rgb8(), image.to_rgb8());
+        }
+
+        // The corners lie outside the circle and do not move
+        let swirled: RgbImage = remap(&image, Remap::Swirl(180.0)).to_rgb8();
+        assert_eq!(swirled.get_pixel(1, 1), image.to_rgb8().get_pixel(1, 1));
+        assert_ne!(swirled.get_pixel(24, 20), image.to_rgb8().get_pixel(24, 20));
+
+        // Polar then unpolar returns the disc, away from the center where the samples are sparse
+        let round_trip: RgbImage = remap(&remap(&image, Remap::Polar), Remap::Unpolar).to_rgb8();
+        for (x, y) in [(30, 20), (20, 8), (12, 27)] {
+            let (a, b) = (round_trip.get_pixel(x, y), image.to_rgb8().get_pixel(x, y).to_owned());
+            assert!(a.0.iter().zip(b.0).all(|(&a, b)| a.abs_diff(b) <= 12), "{:?} {:?}", a, b);
+        }
+
+        // Neighbouring tiles and kaleidoscope copies are mirror images
+        let tiled: RgbImage = remap(&image, Remap::MirrorTile(2, 2)).to_rgb8();
+        assert_eq!(tiled.get_pixel(5, 7), tiled.get_pixel(34, 7));
+        assert_eq!(tiled.get_pixel(5, 7), tiled.get_pixel(5, 32));
+        let kaleidoscope: RgbImage = remap(&image, Remap::Kaleidoscope(4)).to_rgb8();
+        assert_eq!(kaleidoscope.get_pixel(14, 10), kaleidoscope.get_pixel(25, 10));
+        assert_eq!(kaleidoscope.get_pixel(14, 10), kaleidoscope.get_pixel(14, 29));
+
+        // Rolling moves whole pixels and wraps them around
+        let rolled: RgbImage = remap(&image, Remap::Roll(5, -3)).to_rgb8();
+        assert_eq!(rolled.get_pixel(5, 0), image.to_rgb8().get_pixel(0, 3));
+        assert_eq!(rolled.get_pixel(2, 38), image.to_rgb8().get_pixel(37, 1));
+        // A wave of amplitude 3 and wavelength 4 shifts the rows at y = 1 and y = 3 by 3 pixels each way
+        let waved: RgbImage = remap(&image, Remap::Wave(3.0, 4.0, false)).to_rgb8();
+        assert_eq!(waved.get_pixel(10, 0), image.to_rgb8().get_pixel(10, 0));
+        assert_eq!(waved.get_pixel(10, 1), image.to_rgb8().get_pixel(7, 1));
+        assert_eq!(waved.get_pixel(1, 3), image.to_rgb8().get_pixel(4, 3));
+        let columns: RgbImage = remap(&image, Remap::Wave(3.0, 4.0, true)).to_rgb8();
+        assert_eq!(columns.get_pixel(1, 10), image.to_rgb8().get_pixel(1, 7));
+    }
+}
@@ -0,0 +1,95 @@
+use filter::error::Error;
+use crate::report::{file_hash, report_path};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+fn print_usage() {
+    println!("Usage: cargo r replay REPORT.json|manifest.json...");
+    println!("Runs the command lines recorded by --report again, from the same working directory,");
+    println!("and checks that every output comes out byte-identical, exiting with code 1 when one does not.");
+    println!("A --name-by-hash manifest.json replays the report next to each output it lists (both flags");
+    println!("must have been used).");
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let text: String = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{} is not valid JSON: {}", path.display(), e))
+}
+
+// A report is replayed as it is; a manifest stands for the reports of all its outputs.
+fn reports(path: &Path) -> Result<Vec<PathBuf>, String> {
+    match read_json(path)? {
+        Value::Object(map) if map.contains_key("arguments") => Ok(vec![path.to_path_buf()]),
+        Value::Object(map) => map.values()
+            .map(|output| match output.as_str() {
+                Some(output) => Ok(report_path(Path::new(output))),
+                None => Err(format!("{} is neither a report nor a manifest", path.display())),
+            })
+            .collect(),
+        _ => Err(format!("{} is neither a report nor a manifest", path.display())),
+    }
+}
+
+fn replay(report: &Path) -> Result<bool, String> {
+    let report_json: Value = read_json(report)?;
+    let field = |pointer: &str| report_json.pointer(pointer).and_then(Value::as_str).map(str::to_string);
+    let (input, output) = match (field("/input/path"), field("/output/path")) {
+        (Some(input), Some(output)) => (input, output),
+        _ => return Err(format!("{} has no input or output path", report.display())),
+    };
+    let arguments: Vec<String> = match report_json["arguments"].as_array() {
+        Some(arguments) => arguments.iter().filter_map(|argument| argument.as_str().map(str::to_string)).collect(),
+        None => return Err(format!("{} has no recorded arguments", report.display())),
+    };
+
+    if file_hash(Path::new(&input)) != field("/input/sha256") {
+        eprintln!("Warning: {} changed since {} was written", input, report.display());
+    }
+    let mut args: Vec<String> = vec!["filter".to_string()];
+    args.extend(arguments);
+    args.push(input);
+    args.push(output.clone());
+    crate::apply(&args).map_err(|e| e.to_string())?;
+
+    Ok(file_hash(Path::new(&output)).is_some() && file_hash(Path::new(&output)) == field("/output/sha256"))
+}
+
+pub fn run(args: &[String]) -> Result<(), Error> {
+    if args.is_empty() {
+        print_usage();
+        return Ok(());
+    }
+
+    let mut reproduced: usize = 0;
+    let mut total: usize = 0;
+    let mut unreadable: usize = 0;
+    for path in args {
+        let reports: Vec<PathBuf> = match reports(Path::new(path)) {
+            Ok(reports) => reports,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                unreadable += 1;
+                continue;
+            }
+        };
+        for report in reports {
+            total += 1;
+            match replay(&report) {
+                Ok(true) => {
+                    reproduced += 1;
+                    println!("Reproduced: {}", report.display());
+                },
+                Ok(false) => eprintln!("Differs: {} no longer gives the recorded output", report.display()),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+    }
+    println!("{} of {} outputs reproduced", reproduced, total);
+    if reproduced < total {
+        return Err(Error::Operation(format!("{} of {} outputs were not reproduced", total - reproduced, total)));
+    }
+    if unreadable > 0 {
+        return Err(Error::Operation(format!("{} of {} reports or manifests could not be read", unreadable, args.len())));
+    }
+    Ok(())
+}
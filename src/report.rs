@@ -0,0 +1,50 @@
+use filter::determinism::sha256_hex;
+use filter::filter::FilterOperation;
+use image::{DynamicImage, GenericImageView};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use crate::stats::RunStats;
+
+// <output stem>.report.json next to the output.
+pub fn report_path(output_path: &Path) -> std::path::PathBuf {
+    let stem: String = output_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    output_path.with_file_name(format!("{}.report.json", stem))
+}
+
+pub fn file_hash(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|bytes| sha256_hex(&bytes))
+}
+
+// Provenance of one output: what went in, which operations with which final parameters, and
+// what came out, so build systems can tell when an asset has to be regenerated.
+// `arguments` are the operations and options of the command line, which replay runs again.
+pub fn write_report(arguments: &[String], input_path: &Path, output_path: &Path, image: &DynamicImage, palette: Option<String>, operations: &[FilterOperation], stats: &RunStats) -> Result<(), String> {
+    let colors: HashSet<[u8; 4]> = image.to_rgba8().pixels().map(|pixel| pixel.0).collect();
+    let steps: Vec<serde_json::Value> = operations.iter().enumerate()
+        .map(|(index, op)| {
+            let seconds: Option<f64> = stats.step_timings().get(index).map(Duration::as_secs_f64);
+            json!({ "operation": op.name(), "parameters": format!("{:?}", op), "seconds": seconds })
+        })
+        .collect();
+    let (width, height) = image.dimensions();
+    let trim: Option<serde_json::Value> = stats.trim().map(|(source_width, source_height, offset_x, offset_y)| {
+        json!({ "source_width": source_width, "source_height": source_height, "offset_x": offset_x, "offset_y": offset_y })
+    });
+    let report: serde_json::Value = json!({
+        "input": { "path": input_path.to_string_lossy(), "sha256": file_hash(input_path) },
+        "output": { "path": output_path.to_string_lossy(), "sha256": file_hash(output_path) },
+        "width": width,
+        "height": height,
+        "colors": colors.len(),
+        "palette": palette,
+        "trim": trim,
+        "operations": steps,
+        "arguments": arguments,
+    });
+
+    let path: std::path::PathBuf = report_path(output_path);
+    let text: String = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    std::fs::write(&path, text + "\n").map_err(|e| format!("Failed to write report {}: {}", path.display(), e))
+}
@@ -0,0 +1,128 @@
+use image::{DynamicImage, RgbaImage};
+use crate::convolve::sobel_energy;
+use crate::portable::luma;
+use crate::quilt::cheapest_path;
+
+// Rows of RGBA pixels that shrink or grow by one column per seam.
+#[derive(Clone)]
+struct Grid {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl Grid {
+    fn transposed(&self) -> Grid {
+        let pixels: Vec<[u8; 4]> = (0..self.width)
+            .flat_map(|x| (0..self.height).map(move |y| (x, y)))
+            .map(|(x, y)| self.pixels[y * self.width + x])
+            .collect();
+        Grid { width: self.height, height: self.width, pixels }
+    }
+
+    // The connected top-to-bottom path of least Sobel energy, one column per row.
+    fn cheapest_seam(&self) -> Vec<usize> {
+        let brightness: Vec<f32> = self.pixels.iter().map(|pixel| luma(pixel[0], pixel[1], pixel[2]) as f32).collect();
+        let energy: Vec<f32> = sobel_energy(&brightness, self.width, self.height);
+        let costs: Vec<Vec<f64>> = energy.chunks(self.width).map(|row| row.iter().map(|&value| value as f64).collect()).collect();
+        cheapest_path(&costs)
+    }
+
+    fn remove_seam(&mut self, seam: &[usize]) {
+        let width: usize = self.width;
+        let pixels: Vec<[u8; 4]> = self.pixels.chunks(width).zip(seam)
+            .flat_map(|(row, &cut)| row[..cut].iter().chain(&row[cut + 1..]).copied().collect::<Vec<[u8; 4]>>())
+            .collect();
+        self.pixels = pixels;
+        self.width -= 1;
+    }
+
+    fn narrowed(mut self, columns: usize) -> Grid {
+        for _ in 0..columns {
+            let seam: Vec<usize> = self.cheapest_seam();
+            self.remove_seam(&seam);
+        }
+        self
+    }
+
+    // Finds the `columns` cheapest seams by carving them out of a copy, then duplicates each of
+    // them in the original as the average of the seam pixel and its right neighbour.
+    fn widened(&self, columns: usize) -> Grid {
+        let mut carved: Grid = self.clone();
+        let mut positions: Vec<Vec<usize>> = vec![(0..self.width).collect(); self.height];
+        let mut copies: Vec<Vec<u32>> = vec![vec![0; self.width]; self.height];
+        for _ in 0..columns {
+            let seam: Vec<usize> = carved.cheapest_seam();
+            for (y, &cut) in seam.iter().enumerate() {
+                copies[y][positions[y].remove(cut)] += 1;
+            }
+            carved.remove_seam(&seam);
+        }
+
+        let mut pixels: Vec<[u8; 4]> = Vec::with_capacity((self.width + columns) * self.height);
+        for (row, row_copies) in self.pixels.chunks(self.width).zip(&copies) {
+            for (x, (&pixel, &count)) in row.iter().zip(row_copies).enumerate() {
+                pixels.push(pixel);
+                let next: [u8; 4] = row[(x + 1).min(self.width - 1)];
+                let average: [u8; 4] = std::array::from_fn(|channel| ((pixel[channel] as u16 + next[channel] as u16) / 2) as u8);
+                pixels.extend(std::iter::repeat_n(average, count as usize));
+            }
+        }
+        Grid { width: self.width + columns, height: self.height, pixels }
+    }
+
+    // Widening inserts at most half the current width per pass, so repeated stretching does
+    // not duplicate the same seam over and over.
+    fn with_width(self, width: usize) -> Grid {
+        let mut grid: Grid = self;
+        while grid.width < width {
+            let step: usize = (width - grid.width).min((grid.width / 2).max(1));
+            grid = grid.widened(step);
+        }
+        if grid.width > width {
+            let excess: usize = grid.width - width;
+            grid = grid.narrowed(excess);
+        }
+        grid
+    }
+}
+
+// Content-aware resize (Avidan and Shamir): removes or duplicates the connected seams of least
+// Sobel energy instead of scaling, so flat background gives way while detailed areas keep
+// their proportions. Width is carved first, then height.
+pub fn seam_carve(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let rgba: RgbaImage = image.to_rgba8();
+    let grid: Grid = Grid {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        pixels: rgba.pixels().map(|pixel| pixel.0).collect(),
+    };
+    let carved: Grid = grid.with_width(width.max(1) as usize).transposed().with_width(height.max(1) as usize).transposed();
+
+    let raw: Vec<u8> = carved.pixels.into_iter().flatten().collect();
+    // The buffer holds exactly width * height RGBA pixels
+    let output: RgbaImage = RgbaImage::from_raw(carved.width as u32, carved.height as u32, raw).expect("seam carved buffer size");
+    if image.color().has_alpha() { output.into() } else { DynamicImage::ImageRgba8(output).to_rgb8().into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn seams_avoid_the_detailed_area() {
+        // Flat gray with a checkered block on the right: removing columns must keep the block
+        let image: DynamicImage = RgbImage::from_fn(20, 8, |x, y| {
+            if (14..18).contains(&x) { if (x + y) % 2 == 0 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) } } else { Rgb([128, 128, 128]) }
+        }).into();
+        let narrow: RgbImage = seam_carve(&image, 12, 8).to_rgb8();
+        assert_eq!(narrow.dimensions(), (12, 8));
+        let checkered: usize = narrow.pixels().filter(|pixel| pixel[0] == 0 || pixel[0] == 255).count();
+        assert_eq!(checkered, 4 * 8);
+
+        let wide: RgbImage = seam_carve(&image, 30, 10).to_rgb8();
+        assert_eq!(wide.dimensions(), (30, 10));
+        assert_eq!(seam_carve(&image, 20, 8).to_rgb8(), image.to_rgb8());
+    }
+}
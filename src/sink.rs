@@ -0,0 +1,172 @@
+use image::{DynamicImage, ImageFormat};
+use std::io::Write;
+use std::path::PathBuf;
+use crate::encode::{encode, encode_with_quality, save_image};
+
+// Receives the result of a pipeline run, and optionally the image after every operation.
+pub trait OutputSink {
+    // Intermediate images are only produced (and cloned) for sinks that ask for them.
+    fn wants_intermediates(&self) -> bool {
+        false
+    }
+
+    // `step` counts operations from 1; `operation` is the short name from FilterOperation::name.
+    fn intermediate(&mut self, _step: usize, _operation: &str, _image: &DynamicImage) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn finish(&mut self, image: &DynamicImage) -> Result<(), String>;
+}
+
+// Keeps the images in memory.
+#[derive(Default)]
+pub struct MemorySink {
+    keep_intermediates: bool,
+    pub intermediates: Vec<(String, DynamicImage)>,
+    pub image: Option<DynamicImage>,
+}
+
+impl MemorySink {
+    pub fn new(keep_intermediates: bool) -> Self {
+        MemorySink { keep_intermediates, ..Default::default() }
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn wants_intermediates(&self) -> bool {
+        self.keep_intermediates
+    }
+
+    fn intermediate(&mut self, _step: usize, operation: &str, image: &DynamicImage) -> Result<(), String> {
+        self.intermediates.push((operation.to_string(), image.clone()));
+        Ok(())
+    }
+
+    fn finish(&mut self, image: &DynamicImage) -> Result<(), String> {
+        self.image = Some(image.clone());
+        Ok(())
+    }
+}
+
+// Encodes the final image, e.g. for an HTTP response body.
+pub struct EncodedSink {
+    format: ImageFormat,
+    pub bytes: Vec<u8>,
+}
+
+impl EncodedSink {
+    pub fn new(format: ImageFormat) -> Self {
+        EncodedSink { format, bytes: Vec::new() }
+    }
+}
+
+impl OutputSink for EncodedSink {
+    fn finish(&mut self, image: &DynamicImage) -> Result<(), String> {
+        self.bytes = encode(image, self.format).map_err(|e| format!("failed to encode image: {}", e))?;
+        Ok(())
+    }
+}
+
+// Streams the encoded final image to any writer. Encoding happens in memory first because
+// several encoders need to seek, which pipes and sockets cannot.
+pub struct WriterSink<W: Write> {
+    writer: W,
+    format: ImageFormat,
+    quality: u8,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W, format: ImageFormat) -> Self {
+        WriterSink { writer, format, quality: 75 }
+    }
+
+    // JPEG quality from 1 to 100 (default 75); other formats ignore it.
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> OutputSink for WriterSink<W> {
+    fn finish(&mut self, image: &DynamicImage) -> Result<(), String> {
+        let bytes: Vec<u8> = encode_with_quality(image, self.format, self.quality).map_err(|e| format!("failed to encode image: {}", e))?;
+        self.writer.write_all(&bytes).and_then(|_| self.writer.flush()).map_err(|e| format!("failed to write image: {}", e))
+    }
+}
+
+// Saves to a path, picking the format from the extension unless one is given.
+pub struct FileSink {
+    path: PathBuf,
+    format: Option<ImageFormat>,
+    quality: u8,
+}
+
+impl FileSink {
+    pub fn new<P: Into<PathBuf>>(path: P, format: Option<ImageFormat>, quality: u8) -> Self {
+        FileSink { path: path.into(), format, quality }
+    }
+}
+
+impl OutputSink for FileSink {
+    fn finish(&mut self, image: &DynamicImage) -> Result<(), String> {
+        save_image(image, &self.path.to_string_lossy(), self.format, self.quality)
+            .map_err(|e| format!("failed to save image {}: {}", self.path.display(), e))
+    }
+}
+
+// Writes every intermediate image as NN-operation.png into a directory; the final image is
+// already the last step, so it is not written again.
+pub struct StepsSink {
+    directory: PathBuf,
+}
+
+impl StepsSink {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Result<Self, String> {
+        let directory: PathBuf = directory.into();
+        std::fs::create_dir_all(&directory).map_err(|e| format!("failed to create {}: {}", directory.display(), e))?;
+        Ok(StepsSink { directory })
+    }
+}
+
+impl OutputSink for StepsSink {
+    fn wants_intermediates(&self) -> bool {
+        true
+    }
+
+    fn intermediate(&mut self, step: usize, operation: &str, image: &DynamicImage) -> Result<(), String> {
+        let path: PathBuf = self.directory.join(format!("{:02}-{}.png", step, operation));
+        image.save_with_format(&path, ImageFormat::Png).map_err(|e| format!("failed to save step {}: {}", path.display(), e))
+    }
+
+    fn finish(&mut self, _image: &DynamicImage) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn sinks_receive_the_same_image() {
+        let image: DynamicImage = RgbImage::from_fn(5, 3, |x, y| Rgb([x as u8 * 50, y as u8 * 80, 7])).into();
+
+        let mut memory: MemorySink = MemorySink::new(true);
+        memory.intermediate(1, "blur", &image).unwrap();
+        memory.finish(&image).unwrap();
+        assert_eq!(memory.intermediates[0].0, "blur");
+        assert_eq!(memory.image.unwrap().to_rgb8(), image.to_rgb8());
+
+        let mut encoded: EncodedSink = EncodedSink::new(ImageFormat::Png);
+        encoded.finish(&image).unwrap();
+        let mut writer: WriterSink<Vec<u8>> = WriterSink::new(Vec::new(), ImageFormat::Png);
+        writer.finish(&image).unwrap();
+        assert_eq!(writer.into_inner(), encoded.bytes);
+        assert_eq!(image::load_from_memory(&encoded.bytes).unwrap().to_rgb8(), image.to_rgb8());
+    }
+}
@@ -0,0 +1,122 @@
+use filter::observer::PipelineObserver;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+pub struct RunStats {
+    started: Instant,
+    pixels: u64,
+    largest_image_bytes: usize,
+    timings: Vec<(&'static str, Duration, u32)>,
+    step_timings: Vec<Duration>,
+    // Source width and height and offset of what -trim kept, in the coordinates of the input
+    trim: Option<(u32, u32, u32, u32)>,
+}
+
+// Resident set high-water mark; only available where /proc is.
+fn peak_memory_kb() -> Option<u64> {
+    let status: String = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line: &str = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        RunStats { started: Instant::now(), pixels: 0, largest_image_bytes: 0, timings: Vec::new(), step_timings: Vec::new(), trim: None }
+    }
+
+    pub fn add_pixels(&mut self, width: u32, height: u32) {
+        self.pixels += width as u64 * height as u64;
+    }
+
+    pub fn record(&mut self, operation: &'static str, elapsed: Duration) {
+        match self.timings.iter_mut().find(|(name, _, _)| *name == operation) {
+            Some((_, total, count)) => {
+                *total += elapsed;
+                *count += 1;
+            },
+            None => self.timings.push((operation, elapsed, 1)),
+        }
+    }
+
+    // Time of every operation of the last run, in order.
+    pub fn step_timings(&self) -> &[Duration] {
+        &self.step_timings
+    }
+
+    pub fn trim(&self) -> Option<(u32, u32, u32, u32)> {
+        self.trim
+    }
+
+    fn processing_time(&self) -> Duration {
+        self.timings.iter().map(|(_, elapsed, _)| *elapsed).sum()
+    }
+
+    fn megapixels_per_second(&self) -> f64 {
+        let seconds: f64 = self.processing_time().as_secs_f64();
+        if seconds > 0.0 { self.pixels as f64 / 1_000_000.0 / seconds } else { 0.0 }
+    }
+
+    pub fn report(&self) -> String {
+        let mut lines: Vec<String> = vec![
+            format!("Pixels processed: {} ({:.2} MP)", self.pixels, self.pixels as f64 / 1_000_000.0),
+            format!("Wall time: {:.3} s", self.started.elapsed().as_secs_f64()),
+        ];
+        for (name, elapsed, count) in &self.timings {
+            lines.push(format!("  {:<10} {:>9.3} s  ({} run{})", name, elapsed.as_secs_f64(), count, if *count == 1 { "" } else { "s" }));
+        }
+        lines.push(format!("Throughput: {:.2} MP/s", self.megapixels_per_second()));
+        lines.push(format!("Largest image buffer: {:.1} MB", self.largest_image_bytes as f64 / 1024.0 / 1024.0));
+        match peak_memory_kb() {
+            Some(kb) => lines.push(format!("Peak memory: {:.1} MB", kb as f64 / 1024.0)),
+            None => lines.push("Peak memory: unavailable".to_string()),
+        }
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        let operations: Vec<serde_json::Value> = self.timings.iter()
+            .map(|(name, elapsed, count)| json!({ "operation": name, "seconds": elapsed.as_secs_f64(), "runs": count }))
+            .collect();
+        json!({
+            "pixels": self.pixels,
+            "wall_seconds": self.started.elapsed().as_secs_f64(),
+            "operations": operations,
+            "megapixels_per_second": self.megapixels_per_second(),
+            "largest_image_bytes": self.largest_image_bytes,
+            "peak_memory_kb": peak_memory_kb(),
+        }).to_string()
+    }
+}
+
+// The CLI collects its --stats numbers as an observer of the pipeline and prints its warnings.
+impl PipelineObserver for RunStats {
+    fn operation_started(&mut self, step: usize, _operation: &'static str, width: u32, height: u32) {
+        // Every image of a batch starts over, so its report gets only its own steps
+        if step == 1 {
+            self.step_timings.clear();
+            self.trim = None;
+        }
+        self.add_pixels(width, height);
+    }
+
+    fn operation_finished(&mut self, _step: usize, operation: &'static str, elapsed: Duration) {
+        self.record(operation, elapsed);
+        self.step_timings.push(elapsed);
+    }
+
+    fn bytes_allocated(&mut self, _operation: &'static str, bytes: usize) {
+        self.largest_image_bytes = self.largest_image_bytes.max(bytes);
+    }
+
+    // A second trim cuts into the first one's result, so the offsets add up
+    fn trimmed(&mut self, source: (u32, u32), bounds: (u32, u32, u32, u32)) {
+        self.trim = Some(match self.trim {
+            Some((width, height, x, y)) => (width, height, x + bounds.0, y + bounds.1),
+            None => (source.0, source.1, bounds.0, bounds.1),
+        });
+    }
+
+    fn warning(&mut self, message: &str) {
+        eprintln!("Warning: {}", message);
+    }
+}
@@ -0,0 +1,189 @@
+use filter::config::Settings;
+use filter::error::Error;
+use filter::filter::{open_image, FilterOperation};
+use filter::font::{draw_text, text_width, GLYPH_HEIGHT};
+use filter::library::PaletteSource;
+use filter::pipeline::{run as run_pipeline, RunOptions};
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+use crate::registry::{self, OperationSpec, ParseContext};
+use crate::variations::{format_number, parse_reference, replace_value};
+
+// More cells than this make labels and results too small to compare.
+const MAX_CELLS: usize = 64;
+
+fn print_usage() {
+    println!("Usage: cargo r sweep [operations] --param OPERATION[.PARAMETER]=FROM..TO[:STEP] [--columns=N] input.png grid.png");
+    println!("Renders the operations once per value of one parameter into a labeled grid, e.g.");
+    println!("  cargo r sweep --param pix.size=2..16:2 input.png grid.png");
+    println!("  cargo r sweep -pal=pico8 -pix=4 -bayer --param bayer=2..8 input.png grid.png");
+    println!("The swept operation is added at the end when it is not among the operations.");
+    println!("PARAMETER is a name from the operation's help or a position from 1 (default: the first value).");
+    println!("STEP defaults to 1 for whole numbers and an eighth of the range otherwise; at most {} cells.", MAX_CELLS);
+    println!("Options:");
+    println!("  --columns=N: Cells per row (default: about square)");
+}
+
+// FROM..TO[:STEP], ascending or descending. Whole numbers when all three are.
+pub fn parse_range(text: &str) -> Result<(Vec<f64>, bool), String> {
+    let (range, step) = match text.split_once(':') {
+        Some((range, step)) => (range, Some(step)),
+        None => (text, None),
+    };
+    let (from, to) = range.split_once("..").ok_or_else(|| format!("Invalid range: {} (expected FROM..TO[:STEP])", text))?;
+    let number = |value: &str| value.trim().parse::<f64>().ok().filter(|value| value.is_finite()).ok_or_else(|| format!("Invalid number in range: {}", value));
+    let (first, last) = (number(from)?, number(to)?);
+    let whole: bool = [Some(from), Some(to), step].iter().flatten().all(|value| !value.contains(['.', 'e', 'E']));
+    let step: f64 = match step {
+        Some(step) => number(step)?.abs(),
+        None if whole => 1.0,
+        None => (last - first).abs() / 8.0,
+    };
+    if step == 0.0 {
+        return Ok((vec![first], whole));
+    }
+    let count: usize = ((last - first).abs() / step + 1e-9).floor() as usize + 1;
+    if count > MAX_CELLS {
+        return Err(format!("{} has {} values, more than {}; use a larger step", text, count, MAX_CELLS));
+    }
+    let direction: f64 = if last < first { -1.0 } else { 1.0 };
+    Ok(((0..count).map(|index| first + direction * step * index as f64).collect(), whole))
+}
+
+// The operations with the swept value in place, rounded only when the parameter takes whole
+// numbers.
+fn swept_arguments(operations: &[String], spec: &OperationSpec, index: usize, value: f64) -> Result<Vec<String>, String> {
+    operations.iter().map(|arg| match registry::find(arg) {
+        Some(found) if found.name == spec.name => replace_value(arg, index, spec.whole.contains(&index), |_| value),
+        _ => Ok(arg.clone()),
+    }).collect()
+}
+
+// The results side by side, `columns` to a row, each above its label.
+pub fn labeled_grid(cells: &[(String, DynamicImage)], columns: usize) -> RgbImage {
+    let columns: u32 = columns.clamp(1, cells.len().max(1)) as u32;
+    let rows: u32 = (cells.len() as u32).div_ceil(columns);
+    let cell_width: u32 = cells.iter().map(|(_, image)| image.width()).max().unwrap_or(1);
+    let cell_height: u32 = cells.iter().map(|(_, image)| image.height()).max().unwrap_or(1);
+    let scale: u32 = if cell_width >= 120 { 2 } else { 1 };
+    let label_height: u32 = (GLYPH_HEIGHT + 4) * scale;
+    let gap: u32 = 4;
+
+    let mut grid: RgbImage = RgbImage::from_pixel(columns * (cell_width + gap) + gap, rows * (cell_height + label_height + gap) + gap, Rgb([32, 32, 32]));
+    for (index, (label, image)) in cells.iter().enumerate() {
+        let (column, row) = (index as u32 % columns, index as u32 / columns);
+        let x: u32 = gap + column * (cell_width + gap);
+        let y: u32 = gap + row * (cell_height + label_height + gap);
+        imageops::overlay(&mut grid, &image.to_rgb8(), x as i64, y as i64);
+        let text_x: u32 = x + cell_width.saturating_sub(text_width(label, scale)) / 2;
+        draw_text(&mut grid, text_x, y + cell_height + 2 * scale, label, scale, Rgb([230, 230, 230]));
+    }
+    grid
+}
+
+pub fn run(args: &[String], settings: &Settings) -> Result<(), Error> {
+    let mut parameter: Option<&str> = None;
+    let mut columns: Option<usize> = None;
+    let mut operations: Vec<String> = Vec::new();
+    let mut paths: Vec<&String> = Vec::new();
+
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if let Some(value) = arg.strip_prefix("--param=") {
+            parameter = Some(value);
+        } else if arg == "--param" {
+            parameter = remaining.next().map(String::as_str);
+        } else if let Some(value) = arg.strip_prefix("--columns=") {
+            match value.parse::<usize>() {
+                Ok(count) if count > 0 => columns = Some(count),
+                _ => return Err(Error::InvalidArgument(format!("Invalid column count: {}", value))),
+            }
+        } else if registry::find(arg).is_some() {
+            operations.push(arg.clone());
+        } else if arg.starts_with('-') {
+            return Err(Error::InvalidArgument(format!("Unknown option: {}", arg)));
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    let (parameter, paths) = match parameter {
+        Some(parameter) if paths.len() == 2 => (parameter, paths),
+        _ => {
+            print_usage();
+            return Ok(());
+        }
+    };
+    let (reference, range) = parameter.split_once('=')
+        .ok_or_else(|| Error::InvalidArgument(format!("Invalid parameter: {} (expected OPERATION[.PARAMETER]=FROM..TO[:STEP])", parameter)))?;
+    let ((spec, index), (values, whole)): ((&OperationSpec, usize), (Vec<f64>, bool)) = parse_reference(reference)
+        .and_then(|reference| Ok((reference, parse_range(range)?)))
+        .map_err(Error::InvalidArgument)?;
+    if !operations.iter().any(|arg| registry::find(arg).is_some_and(|found| found.name == spec.name)) {
+        if index > 0 {
+            return Err(Error::InvalidArgument(format!("-{} is not among the operations, so only its first value can be swept", spec.flag)));
+        }
+        operations.push(format!("-{}=0", spec.flag));
+    }
+
+    let image: DynamicImage = open_image(paths[0])?;
+
+    let mut cells: Vec<(String, DynamicImage)> = Vec::with_capacity(values.len());
+    for value in values {
+        let text: String = format_number(value, whole);
+        let mut named_palette: Option<PaletteSource> = None;
+        let mut parsed: Vec<FilterOperation> = Vec::new();
+        for arg in swept_arguments(&operations, spec, index, value).map_err(Error::InvalidArgument)? {
+            let mut context: ParseContext = ParseContext { palette_dir: &settings.palette_dir.value, named_palette: &mut named_palette };
+            match registry::parse(&arg, &mut context) {
+                Some(Ok(operations)) => parsed.extend(operations),
+                Some(Err(e)) => return Err(Error::InvalidArgument(format!("{} = {}: {}", reference, text, e))),
+                None => {}
+            }
+        }
+        let palette: PaletteSource = named_palette.unwrap_or_else(|| PaletteSource::File(settings.palette_path()));
+        let mut options: RunOptions = RunOptions::new(palette);
+        options.distance = settings.distance.value;
+        options.messages_to_stderr = true;
+        println!("{} = {}", reference, text);
+        cells.push((format!("{}={}", reference, text), run_pipeline(image.clone(), &parsed, &options)?));
+    }
+
+    let columns: usize = columns.unwrap_or_else(|| (cells.len() as f64).sqrt().ceil() as usize);
+    let grid: RgbImage = labeled_grid(&cells, columns);
+    grid.save(paths[1]).map_err(|source| Error::Encode { path: paths[1].into(), source })?;
+    println!("The grid is saved: {} ({} cells)", paths[1], cells.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn ranges_step_through_values_into_a_grid() {
+        assert_eq!(parse_range("2..16:2").unwrap(), (vec![2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0, 16.0], true));
+        assert_eq!(parse_range("3..1").unwrap(), (vec![3.0, 2.0, 1.0], true));
+        assert_eq!(parse_range("0..1:0.25").unwrap(), (vec![0.0, 0.25, 0.5, 0.75, 1.0], false));
+        assert_eq!(parse_range("0.5..4.5").unwrap().0.len(), 9);
+        assert!(parse_range("1..1000").is_err());
+        assert!(parse_range("a..3").is_err());
+        assert!(parse_range("3").is_err());
+
+        // The swept argument carries the value itself, fractions included where the parameter takes them
+        let find = |name: &str| registry::find(&format!("-{}", name)).unwrap();
+        let operations: Vec<String> = vec!["-pix=4".to_string(), "-blur=0".to_string()];
+        assert_eq!(swept_arguments(&operations, find("blur"), 0, 0.75).unwrap(), ["-pix=4", "-blur=0.75"]);
+        assert_eq!(swept_arguments(&operations, find("pix"), 0, 2.5).unwrap(), ["-pix=3", "-blur=0"]);
+        assert_eq!(swept_arguments(&["-pix=10%".to_string()], find("pix"), 0, 2.5).unwrap(), ["-pix=2.5%"]);
+        assert_eq!(swept_arguments(&["-wave=4,30".to_string()], find("wave"), 1, 12.5).unwrap(), ["-wave=4,12.5"]);
+
+        let cells: Vec<(String, DynamicImage)> = (0..5).map(|index| (format!("pix={}", index), RgbImage::from_pixel(20 + index, 10, Rgb([200, 0, 0])).into())).collect();
+        let grid: RgbImage = labeled_grid(&cells, 3);
+        // 3 columns of 24 pixel cells and 2 rows of 10 pixel cells with 9 pixel labels, 4 pixel gaps
+        assert_eq!(grid.dimensions(), (3 * 28 + 4, 2 * 23 + 4));
+        assert_eq!(grid.get_pixel(4, 4), &Rgb([200, 0, 0]));
+        assert_eq!(grid.get_pixel(4 + 28 * 2 + 23, 4), &Rgb([32, 32, 32]));
+        assert!(grid.pixels().any(|pixel| pixel == &Rgb([230, 230, 230])));
+    }
+}
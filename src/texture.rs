@@ -0,0 +1,272 @@
+use image::{DynamicImage, GrayImage, RgbaImage};
+use std::path::Path;
+
+// GPU texture files with block compressed data: BC4 (one channel) for gray images, BC1 (color
+// with 1-bit alpha) otherwise. Each 4 x 4 block takes 8 bytes; blocks at the right and bottom
+// edges repeat the last column and row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Container {
+    Dds,
+    Ktx2,
+}
+
+impl Container {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension: String = Path::new(path).extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "dds" => Some(Container::Dds),
+            "ktx2" => Some(Container::Ktx2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockFormat {
+    Bc1,
+    Bc4,
+}
+
+// The 4 x 4 blocks of the image, row by row.
+fn blocks<P: image::Pixel, F: Fn(&P) -> T, T: Copy + Default>(image: &image::ImageBuffer<P, Vec<P::Subpixel>>, value: F) -> Vec<[T; 16]> {
+    let (width, height) = image.dimensions();
+    let mut result: Vec<[T; 16]> = Vec::new();
+    for block_y in 0..height.div_ceil(4) {
+        for block_x in 0..width.div_ceil(4) {
+            let mut block: [T; 16] = [T::default(); 16];
+            for (texel, entry) in block.iter_mut().enumerate() {
+                let x: u32 = (block_x * 4 + texel as u32 % 4).min(width - 1);
+                let y: u32 = (block_y * 4 + texel as u32 / 4).min(height - 1);
+                *entry = value(image.get_pixel(x, y));
+            }
+            result.push(block);
+        }
+    }
+    result
+}
+
+fn to_565(color: [u8; 3]) -> u16 {
+    ((color[0] as u16 >> 3) << 11) | ((color[1] as u16 >> 2) << 5) | (color[2] as u16 >> 3)
+}
+
+fn from_565(value: u16) -> [i32; 3] {
+    let (r, g, b) = ((value >> 11) & 31, (value >> 5) & 63, value & 31);
+    [((r << 3) | (r >> 2)) as i32, ((g << 2) | (g >> 4)) as i32, ((b << 3) | (b >> 2)) as i32]
+}
+
+fn squared_distance(a: [i32; 3], b: [i32; 3]) -> i32 {
+    (a[0] - b[0]).pow(2) + (a[1] - b[1]).pow(2) + (a[2] - b[2]).pow(2)
+}
+
+// Endpoints are the two opaque texels farthest apart, so blocks of one or two colors (as in
+// dithered and palettized art) come out exact up to the 5:6:5 precision.
+fn bc1_block(texels: &[[u8; 4]; 16]) -> [u8; 8] {
+    let opaque: Vec<[u8; 3]> = texels.iter().filter(|texel| texel[3] >= 128).map(|texel| [texel[0], texel[1], texel[2]]).collect();
+    let transparent: bool = opaque.len() < 16;
+    let mut ends: ([u8; 3], [u8; 3]) = (opaque.first().copied().unwrap_or([0; 3]), opaque.first().copied().unwrap_or([0; 3]));
+    let mut widest: i32 = -1;
+    for (i, &a) in opaque.iter().enumerate() {
+        for &b in &opaque[i..] {
+            let distance: i32 = squared_distance(a.map(|v| v as i32), b.map(|v| v as i32));
+            if distance > widest {
+                widest = distance;
+                ends = (a, b);
+            }
+        }
+    }
+
+    // c0 > c1 selects four colors, c0 <= c1 three colors and transparent
+    let (mut c0, mut c1) = (to_565(ends.0), to_565(ends.1));
+    if (c0 < c1) != transparent && c0 != c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    let (a, b) = (from_565(c0), from_565(c1));
+    let mix = |wa: i32, wb: i32, total: i32| -> [i32; 3] { std::array::from_fn(|channel| (a[channel] * wa + b[channel] * wb) / total) };
+    let colors: Vec<[i32; 3]> = if c0 > c1 { vec![a, b, mix(2, 1, 3), mix(1, 2, 3)] } else { vec![a, b, mix(1, 1, 2)] };
+
+    let mut indices: u32 = 0;
+    for (texel_index, texel) in texels.iter().enumerate() {
+        let code: u32 = if texel[3] < 128 {
+            3
+        } else {
+            let color: [i32; 3] = [texel[0] as i32, texel[1] as i32, texel[2] as i32];
+            (0..colors.len()).min_by_key(|&code| squared_distance(colors[code], color)).unwrap_or(0) as u32
+        };
+        indices |= code << (texel_index * 2);
+    }
+    let mut block: [u8; 8] = [0; 8];
+    block[0..2].copy_from_slice(&c0.to_le_bytes());
+    block[2..4].copy_from_slice(&c1.to_le_bytes());
+    block[4..8].copy_from_slice(&indices.to_le_bytes());
+    block
+}
+
+// Eight levels from the block's darkest to lightest value.
+fn bc4_block(texels: &[u8; 16]) -> [u8; 8] {
+    let (high, low) = (*texels.iter().max().unwrap_or(&0), *texels.iter().min().unwrap_or(&0));
+    let levels: [i32; 8] = std::array::from_fn(|code| match code {
+        0 => high as i32,
+        1 => low as i32,
+        _ => ((8 - code as i32) * high as i32 + (code as i32 - 1) * low as i32) / 7,
+    });
+    let mut indices: u64 = 0;
+    for (texel_index, &texel) in texels.iter().enumerate() {
+        let code: usize = (0..8).min_by_key(|&code| (levels[code] - texel as i32).abs()).unwrap_or(0);
+        indices |= (code as u64) << (texel_index * 3);
+    }
+    let mut block: [u8; 8] = [0; 8];
+    block[0] = high;
+    block[1] = low;
+    block[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+    block
+}
+
+pub fn compress_bc1(image: &RgbaImage) -> Vec<u8> {
+    blocks(image, |pixel| pixel.0).iter().flat_map(bc1_block).collect()
+}
+
+pub fn compress_bc4(image: &GrayImage) -> Vec<u8> {
+    blocks(image, |pixel| pixel[0]).iter().flat_map(bc4_block).collect()
+}
+
+fn dds(format: BlockFormat, width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+    let mut header: [u32; 31] = [0; 31];
+    header[0] = 124;
+    // Caps, height, width, pixel format and linear size are set
+    header[1] = 0x1 | 0x2 | 0x4 | 0x1000 | 0x80000;
+    header[2] = height;
+    header[3] = width;
+    header[4] = data.len() as u32;
+    // Pixel format: 32 bytes, a four-character code
+    header[18] = 32;
+    header[19] = 0x4;
+    header[20] = u32::from_le_bytes(*match format {
+        BlockFormat::Bc1 => b"DXT1",
+        BlockFormat::Bc4 => b"ATI1",
+    });
+    header[26] = 0x1000;
+
+    let mut bytes: Vec<u8> = b"DDS ".to_vec();
+    bytes.extend(header.iter().flat_map(|value| value.to_le_bytes()));
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn ktx2(format: BlockFormat, width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+    // Vulkan formats BC1_RGBA_SRGB_BLOCK and BC4_UNORM_BLOCK, and the matching data format
+    // descriptor models, transfer functions and channel
+    let (vk_format, model, transfer, channel): (u32, u8, u8, u8) = match format {
+        BlockFormat::Bc1 => (134, 128, 2, 0x1),
+        BlockFormat::Bc4 => (139, 131, 1, 0x0),
+    };
+    let mut descriptor: Vec<u8> = Vec::new();
+    descriptor.extend(44u32.to_le_bytes());
+    descriptor.extend(0u32.to_le_bytes());
+    descriptor.extend(2u16.to_le_bytes());
+    descriptor.extend(40u16.to_le_bytes());
+    // Model, BT.709 primaries, transfer function, straight alpha, 4 x 4 x 1 x 1 blocks of 8 bytes
+    descriptor.extend([model, 1, transfer, 0, 3, 3, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0]);
+    // One sample covering all 64 bits of the block
+    descriptor.extend(0u16.to_le_bytes());
+    descriptor.extend([63, channel, 0, 0, 0, 0]);
+    descriptor.extend(0u32.to_le_bytes());
+    descriptor.extend(u32::MAX.to_le_bytes());
+
+    let descriptor_offset: u32 = 12 + 9 * 4 + 32 + 24;
+    let data_offset: u64 = (descriptor_offset as u64 + descriptor.len() as u64).next_multiple_of(8);
+    let mut bytes: Vec<u8> = vec![0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+    for value in [vk_format, 1, width, height, 0, 0, 1, 1, 0] {
+        bytes.extend(value.to_le_bytes());
+    }
+    for value in [descriptor_offset, descriptor.len() as u32, 0, 0] {
+        bytes.extend(value.to_le_bytes());
+    }
+    for value in [0u64, 0, data_offset, data.len() as u64, 0] {
+        bytes.extend(value.to_le_bytes());
+    }
+    bytes.extend(descriptor);
+    bytes.resize(data_offset as usize, 0);
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+pub fn encode_texture(image: &DynamicImage, container: Container) -> Vec<u8> {
+    let (format, data): (BlockFormat, Vec<u8>) = if image.color().has_color() || image.color().has_alpha() {
+        (BlockFormat::Bc1, compress_bc1(&image.to_rgba8()))
+    } else {
+        (BlockFormat::Bc4, compress_bc4(&image.to_luma8()))
+    };
+    match container {
+        Container::Dds => dds(format, image.width(), image.height(), &data),
+        Container::Ktx2 => ktx2(format, image.width(), image.height(), &data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgba};
+
+    fn decode_bc1(block: &[u8]) -> [[u8; 4]; 16] {
+        let (c0, c1) = (u16::from_le_bytes([block[0], block[1]]), u16::from_le_bytes([block[2], block[3]]));
+        let (a, b) = (from_565(c0), from_565(c1));
+        let mix = |wa: i32, wb: i32, total: i32| -> [i32; 3] { std::array::from_fn(|channel| (a[channel] * wa + b[channel] * wb) / total) };
+        let colors: [[i32; 3]; 4] = if c0 > c1 { [a, b, mix(2, 1, 3), mix(1, 2, 3)] } else { [a, b, mix(1, 1, 2), [0; 3]] };
+        let indices: u32 = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        std::array::from_fn(|texel| {
+            let code: usize = (indices >> (texel * 2) & 3) as usize;
+            let alpha: u8 = if c0 <= c1 && code == 3 { 0 } else { 255 };
+            [colors[code][0] as u8, colors[code][1] as u8, colors[code][2] as u8, alpha]
+        })
+    }
+
+    #[test]
+    fn blocks_round_trip_and_containers_have_headers() {
+        // Two colors per block, one of them transparent in the second block
+        let image: RgbaImage = RgbaImage::from_fn(8, 4, |x, y| match (x < 4, (x + y) % 2 == 0) {
+            (true, true) => Rgba([255, 0, 0, 255]),
+            (true, false) => Rgba([0, 0, 255, 255]),
+            (false, true) => Rgba([255, 255, 255, 255]),
+            (false, false) => Rgba([0, 0, 0, 0]),
+        });
+        let compressed: Vec<u8> = compress_bc1(&image);
+        assert_eq!(compressed.len(), 16);
+        for (block_index, block) in compressed.chunks(8).enumerate() {
+            for (texel, decoded) in decode_bc1(block).iter().enumerate() {
+                let expected: &Rgba<u8> = image.get_pixel(block_index as u32 * 4 + texel as u32 % 4, texel as u32 / 4);
+                assert_eq!(decoded[3], expected[3]);
+                if expected[3] == 255 {
+                    assert_eq!(&decoded[..3], &expected.0[..3]);
+                }
+            }
+        }
+
+        // A 6 x 5 gray ramp needs 2 x 2 blocks; BC4 keeps it within a level step
+        let gray: GrayImage = GrayImage::from_fn(6, 5, |x, y| Luma([(x * 40 + y * 3) as u8]));
+        let compressed: Vec<u8> = compress_bc4(&gray);
+        assert_eq!(compressed.len(), 32);
+        let first: &[u8] = &compressed[..8];
+        let indices: u64 = u64::from_le_bytes([first[2], first[3], first[4], first[5], first[6], first[7], 0, 0]);
+        let (high, low) = (first[0] as i32, first[1] as i32);
+        for texel in 0..16 {
+            let code: i32 = (indices >> (texel * 3) & 7) as i32;
+            let value: i32 = match code { 0 => high, 1 => low, _ => ((8 - code) * high + (code - 1) * low) / 7 };
+            let expected: i32 = gray.get_pixel(texel as u32 % 4, texel as u32 / 4)[0] as i32;
+            assert!((value - expected).abs() <= (high - low) / 14 + 1, "{} {}", value, expected);
+        }
+
+        let dds_file: Vec<u8> = encode_texture(&DynamicImage::ImageRgba8(image.clone()), Container::Dds);
+        assert_eq!(&dds_file[..4], b"DDS ");
+        assert_eq!(&dds_file[84..88], b"DXT1");
+        assert_eq!(dds_file.len(), 128 + 16);
+        let decoded: RgbaImage = image::load_from_memory_with_format(&dds_file, image::ImageFormat::Dds).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0).0[..3], [255, 0, 0]);
+        assert_eq!(decoded.get_pixel(1, 0).0[..3], [0, 0, 255]);
+        assert_eq!(decoded.get_pixel(5, 1).0[..3], [255, 255, 255]);
+        let gray_file: Vec<u8> = encode_texture(&DynamicImage::ImageLuma8(gray), Container::Ktx2);
+        assert_eq!(&gray_file[..12], &[0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n']);
+        assert_eq!(u32::from_le_bytes(gray_file[12..16].try_into().unwrap()), 139);
+        assert_eq!(&gray_file[gray_file.len() - 32..], &compressed[..]);
+        assert_eq!(Container::from_path("out/Texture.KTX2"), Some(Container::Ktx2));
+    }
+}
@@ -0,0 +1,345 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use crate::color::{linear_to_srgb, oklab_to_rgb, rgb_to_oklab, srgb_to_linear};
+use crate::filter::Color;
+use crate::integral::IntegralImage;
+use crate::math;
+
+const LEVELS: usize = 256;
+// Blur radii of the clarity detail layers as fractions of the shorter image side.
+const CLARITY_SCALES: [f32; 3] = [0.005, 0.02, 0.06];
+// Half size of the square the dark channel is taken over, as in He et al.'s 15x15 patches.
+const DEHAZE_PATCH_RADIUS: u32 = 7;
+// Some haze is kept so distant objects still read as distant and noise is not amplified.
+const DEHAZE_MIN_TRANSMISSION: f32 = 0.1;
+
+fn with_alpha_of(image: &DynamicImage, output: RgbaImage) -> DynamicImage {
+    if image.color().has_alpha() { output.into() } else { DynamicImage::ImageRgba8(output).to_rgb8().into() }
+}
+
+// OKLab lightness of every pixel as a 0-255 level, so hue and chroma can be kept when it changes.
+fn lightness_levels(image: &RgbaImage) -> Vec<u8> {
+    image.pixels().map(|pixel| {
+        let lab: [f32; 3] = rgb_to_oklab(Color::from_rgb_components(pixel[0], pixel[1], pixel[2]));
+        (lab[0] * 255.0).round().clamp(0.0, 255.0) as u8
+    }).collect()
+}
+
+// Replaces the OKLab lightness of every pixel; `lightness` gets the pixel position and its old level.
+fn map_lightness(image: &DynamicImage, lightness: impl Fn(u32, u32, u8) -> f32) -> DynamicImage {
+    let mut source: RgbaImage = image.to_rgba8();
+    let levels: Vec<u8> = lightness_levels(&source);
+    let width: u32 = source.width();
+    for (x, y, pixel) in source.enumerate_pixels_mut() {
+        let old: u8 = levels[y as usize * width as usize + x as usize];
+        let new: f32 = lightness(x, y, old);
+        if (new - old as f32).abs() < 0.5 {
+            continue;
+        }
+        let mut lab: [f32; 3] = rgb_to_oklab(Color::from_rgb_components(pixel[0], pixel[1], pixel[2]));
+        lab[0] = new.clamp(0.0, 255.0) / 255.0;
+        let color: Color = oklab_to_rgb(lab);
+        *pixel = Rgba([color.r, color.g, color.b, pixel[3]]);
+    }
+    with_alpha_of(image, source)
+}
+
+// Equalization curve of one tile. Histogram bins are capped at `clip` times the average bin and
+// the excess is spread over all levels, which limits how much noise in flat areas gets amplified.
+fn tile_curve(levels: &[u8], width: u32, bounds: (u32, u32, u32, u32), clip: f32) -> [f32; LEVELS] {
+    let (x0, y0, x1, y1) = bounds;
+    let mut histogram: [f32; LEVELS] = [0.0; LEVELS];
+    for y in y0..y1 {
+        for x in x0..x1 {
+            histogram[levels[y as usize * width as usize + x as usize] as usize] += 1.0;
+        }
+    }
+    let pixels: f32 = ((x1 - x0) * (y1 - y0)).max(1) as f32;
+    let limit: f32 = (clip * pixels / LEVELS as f32).max(1.0);
+    let mut excess: f32 = 0.0;
+    for count in histogram.iter_mut() {
+        if *count > limit {
+            excess += *count - limit;
+            *count = limit;
+        }
+    }
+    let mut curve: [f32; LEVELS] = [0.0; LEVELS];
+    let mut total: f32 = 0.0;
+    for (level, count) in histogram.iter().enumerate() {
+        total += count + excess / LEVELS as f32;
+        curve[level] = total / pixels * 255.0;
+    }
+    curve
+}
+
+// Contrast-limited adaptive histogram equalization of the lightness on a `tiles` x `tiles` grid.
+// Each pixel blends the curves of the four nearest tile centers, so no tile seams show.
+pub fn clahe(image: &DynamicImage, tiles: u32, clip: f32) -> DynamicImage {
+    let source: RgbaImage = image.to_rgba8();
+    let (width, height) = source.dimensions();
+    let levels: Vec<u8> = lightness_levels(&source);
+    let (tiles_x, tiles_y) = (tiles.clamp(1, width.max(1)), tiles.clamp(1, height.max(1)));
+    let (tile_width, tile_height) = (width as f32 / tiles_x as f32, height as f32 / tiles_y as f32);
+
+    let mut curves: Vec<[f32; LEVELS]> = Vec::with_capacity((tiles_x * tiles_y) as usize);
+    for tile_y in 0..tiles_y {
+        for tile_x in 0..tiles_x {
+            let bounds: (u32, u32, u32, u32) = (
+                (tile_x as f32 * tile_width) as u32,
+                (tile_y as f32 * tile_height) as u32,
+                ((tile_x + 1) as f32 * tile_width) as u32,
+                ((tile_y + 1) as f32 * tile_height) as u32,
+            );
+            curves.push(tile_curve(&levels, width, bounds, clip));
+        }
+    }
+
+    // Position in tile-center coordinates, split into the lower tile index and the blend weight.
+    let locate = |position: u32, size: f32, count: u32| -> (u32, u32, f32) {
+        let center: f32 = ((position as f32 + 0.5) / size - 0.5).clamp(0.0, (count - 1) as f32);
+        let low: u32 = center.floor() as u32;
+        (low, (low + 1).min(count - 1), center - low as f32)
+    };
+
+    map_lightness(image, |x, y, level| {
+        let (left, right, tx) = locate(x, tile_width, tiles_x);
+        let (top, bottom, ty) = locate(y, tile_height, tiles_y);
+        let value = |tile_x: u32, tile_y: u32| curves[(tile_y * tiles_x + tile_x) as usize][level as usize];
+        let upper: f32 = value(left, top) * (1.0 - tx) + value(right, top) * tx;
+        let lower: f32 = value(left, bottom) * (1.0 - tx) + value(right, bottom) * tx;
+        upper * (1.0 - ty) + lower * ty
+    })
+}
+
+// Minimum over a square of `radius` around every pixel, as two one-dimensional passes.
+fn min_filter(values: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    let mut rows: Vec<f32> = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            rows[y * width + x] = values[y * width + x0..y * width + x1].iter().cloned().fold(f32::MAX, f32::min);
+        }
+    }
+    let mut output: Vec<f32> = vec![0.0; values.len()];
+    for y in 0..height {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for x in 0..width {
+            output[y * width + x] = (y0..y1).map(|row| rows[row * width + x]).fold(f32::MAX, f32::min);
+        }
+    }
+    output
+}
+
+// Darkest channel in the neighbourhood of every pixel, with colors divided by `light`.
+fn dark_channel(image: &RgbaImage, light: [f32; 3]) -> Vec<f32> {
+    let minimums: Vec<f32> = image.pixels()
+        .map(|pixel| (0..3).map(|channel| pixel[channel] as f32 / light[channel]).fold(f32::MAX, f32::min))
+        .collect();
+    min_filter(&minimums, image.width() as usize, image.height() as usize, DEHAZE_PATCH_RADIUS as usize)
+}
+
+// Color of the haze: the average of the 0.1% pixels with the brightest dark channel, which in
+// a hazy photo are the most haze-covered, not merely the brightest objects.
+fn atmospheric_light(image: &RgbaImage, dark: &[f32]) -> [f32; 3] {
+    let mut order: Vec<usize> = (0..dark.len()).collect();
+    order.sort_by(|&a, &b| dark[b].total_cmp(&dark[a]));
+    let count: usize = (dark.len() / 1000).max(1);
+    let mut sum: [f32; 3] = [0.0; 3];
+    for &index in &order[..count] {
+        let pixel: &Rgba<u8> = image.get_pixel((index % image.width() as usize) as u32, (index / image.width() as usize) as u32);
+        for channel in 0..3 {
+            sum[channel] += pixel[channel] as f32;
+        }
+    }
+    sum.map(|total| (total / count as f32).max(1.0))
+}
+
+// Dark channel prior dehazing (He, Sun and Tang). Haze-free regions almost always have a channel
+// near zero, so a bright dark channel measures how much haze covers a pixel. `strength` is the
+// share of haze removed, where 1 removes all of it.
+pub fn dehaze(image: &DynamicImage, strength: f32) -> DynamicImage {
+    let source: RgbaImage = image.to_rgba8();
+    let (width, height) = source.dimensions();
+    let light: [f32; 3] = atmospheric_light(&source, &dark_channel(&source, [1.0; 3]));
+    let transmission: Vec<f32> = dark_channel(&source, light).iter().map(|dark| 1.0 - strength * dark).collect();
+
+    // Smoothing the transmission hides the blocky patch edges of the dark channel.
+    let table: IntegralImage = IntegralImage::from_fn(width, height, |x, y| {
+        (transmission[y as usize * width as usize + x as usize].clamp(0.0, 1.0) * 65535.0) as u64
+    });
+    let output: RgbaImage = RgbaImage::from_fn(width, height, |x, y| {
+        let t: f32 = (table.mean(x, y, DEHAZE_PATCH_RADIUS) / 65535.0).max(DEHAZE_MIN_TRANSMISSION);
+        let pixel: &Rgba<u8> = source.get_pixel(x, y);
+        let clear = |channel: usize| ((pixel[channel] as f32 - light[channel]) / t + light[channel]).round().clamp(0.0, 255.0) as u8;
+        Rgba([clear(0), clear(1), clear(2), pixel[3]])
+    });
+    with_alpha_of(image, output)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapper {
+    // x / (1 + x): never clips, but flattens highlights.
+    Reinhard,
+    // Narkowicz's fit of the ACES reference rendering curve, with a toe and a soft shoulder.
+    Aces,
+    // Hable's filmic curve from Uncharted 2.
+    Filmic,
+}
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "aces" => Some(ToneMapper::Aces),
+            "filmic" => Some(ToneMapper::Filmic),
+            _ => None,
+        }
+    }
+
+    fn map(&self, x: f32) -> f32 {
+        match self {
+            ToneMapper::Reinhard => x / (1.0 + x),
+            ToneMapper::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMapper::Filmic => {
+                let curve = |x: f32| ((x * (0.15 * x + 0.05) + 0.004) / (x * (0.15 * x + 0.5) + 0.06)) - 0.02 / 0.3;
+                // Linear white at 11.2, with the customary exposure bias of 2
+                curve(2.0 * x) / curve(11.2)
+            }
+        }
+    }
+}
+
+// Applies `adjust` to linear-light RGB and encodes the result as 8-bit sRGB. Float images
+// (EXR, Radiance HDR) are already linear and may exceed 1; 8-bit images are decoded from sRGB.
+fn map_linear(image: &DynamicImage, adjust: impl Fn([f32; 3]) -> [f32; 3]) -> DynamicImage {
+    let linear: bool = matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+    let output: RgbaImage = if linear {
+        let source: image::Rgba32FImage = image.to_rgba32f();
+        RgbaImage::from_fn(source.width(), source.height(), |x, y| {
+            let pixel: &Rgba<f32> = source.get_pixel(x, y);
+            let [r, g, b] = adjust([pixel[0].max(0.0), pixel[1].max(0.0), pixel[2].max(0.0)]);
+            Rgba([linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), (pixel[3].clamp(0.0, 1.0) * 255.0).round() as u8])
+        })
+    } else {
+        let mut source: RgbaImage = image.to_rgba8();
+        for pixel in source.pixels_mut() {
+            let [r, g, b] = adjust([srgb_to_linear(pixel[0]), srgb_to_linear(pixel[1]), srgb_to_linear(pixel[2])]);
+            *pixel = Rgba([linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), pixel[3]]);
+        }
+        source
+    };
+    with_alpha_of(image, output)
+}
+
+// Brings scene-referred values into display range after scaling by 2^`exposure`.
+pub fn tonemap(image: &DynamicImage, mapper: ToneMapper, exposure: f32) -> DynamicImage {
+    let scale: f32 = math::exp2(exposure);
+    map_linear(image, |rgb| rgb.map(|value| mapper.map(value * scale)))
+}
+
+pub fn exposure(image: &DynamicImage, stops: f32) -> DynamicImage {
+    let scale: f32 = math::exp2(stops);
+    map_linear(image, |rgb| rgb.map(|value| value * scale))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t: f32 = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Scales each pixel by up to two stops at `amount` = +/-100, weighted by how far its perceptual
+// lightness lies inside the tonal range. Multiplying all channels alike keeps hue and saturation.
+fn adjust_range(image: &DynamicImage, amount: f32, weight: impl Fn(f32) -> f32) -> DynamicImage {
+    map_linear(image, |rgb| {
+        let luminance: f32 = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+        let lightness: f32 = linear_to_srgb(luminance) as f32 / 255.0;
+        let scale: f32 = math::exp2(2.0 * amount / 100.0 * weight(lightness));
+        rgb.map(|value| value * scale)
+    })
+}
+
+// Negative amounts pull blown highlights back into range; positive ones brighten them.
+pub fn highlights(image: &DynamicImage, amount: f32) -> DynamicImage {
+    adjust_range(image, amount, |lightness| smoothstep(0.4, 1.0, lightness))
+}
+
+// Positive amounts open up shadows without lifting the midtones much.
+pub fn shadows(image: &DynamicImage, amount: f32) -> DynamicImage {
+    adjust_range(image, amount, |lightness| 1.0 - smoothstep(0.0, 0.6, lightness))
+}
+
+// Multi-scale unsharp masking of the lightness. Detail is the difference from box blurs at a few
+// radii relative to the image size; it is added back mostly in the midtones, so shadows and
+// highlights do not clip. `amount` runs from -100 (softer) to 100.
+pub fn clarity(image: &DynamicImage, amount: f32) -> DynamicImage {
+    let source: RgbaImage = image.to_rgba8();
+    let (width, height) = source.dimensions();
+    let levels: Vec<u8> = lightness_levels(&source);
+    let table: IntegralImage = IntegralImage::from_fn(width, height, |x, y| levels[y as usize * width as usize + x as usize] as u64);
+    let radii: Vec<u32> = CLARITY_SCALES.iter().map(|scale| ((width.min(height) as f32 * scale).round() as u32).max(1)).collect();
+    let gain: f32 = amount / 100.0;
+
+    map_lightness(image, |x, y, level| {
+        let detail: f32 = radii.iter().map(|&radius| level as f32 - table.mean(x, y, radius)).sum::<f32>() / radii.len() as f32;
+        let midtone: f32 = 1.0 - (level as f32 / 127.5 - 1.0).powi(2);
+        level as f32 + gain * midtone * detail
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn tone_operations_keep_detail() {
+        // A dim and a bright low-contrast half; a global curve could not stretch both
+        let image: DynamicImage = RgbImage::from_fn(64, 32, |x, y| {
+            let base: u32 = if x < 32 { 40 } else { 190 };
+            Rgb([(base + (x + y) % 12) as u8; 3])
+        }).into();
+        let output: RgbImage = clahe(&image, 2, 4.0).to_rgb8();
+        let spread = |image: &RgbImage, left: bool| {
+            let values: Vec<u8> = image.enumerate_pixels().filter(|(x, _, _)| (*x < 32) == left).map(|(_, _, pixel)| pixel[0]).collect();
+            values.iter().max().unwrap() - values.iter().min().unwrap()
+        };
+        let input: RgbImage = image.to_rgb8();
+        assert!(spread(&output, true) > 2 * spread(&input, true));
+        assert!(spread(&output, false) > 2 * spread(&input, false));
+
+        // A scene with a dark channel of zero under uniform haze, with clear sky at the top
+        let scene: RgbImage = RgbImage::from_fn(64, 64, |x, y| {
+            if y < 16 { Rgb([220, 220, 220]) } else { Rgb([(x * 3) as u8, (y * 3) as u8, 0]) }
+        });
+        let hazy: DynamicImage = RgbImage::from_fn(64, 64, |x, y| {
+            Rgb(scene.get_pixel(x, y).0.map(|value| (value as f32 * 0.5 + 110.0).round() as u8))
+        }).into();
+        let clear: RgbImage = dehaze(&hazy, 0.95).to_rgb8();
+        let error = |image: &RgbImage| -> u32 {
+            (0..64).flat_map(|x| (32..64).map(move |y| (x, y)))
+                .map(|(x, y)| image.get_pixel(x, y)[0].abs_diff(scene.get_pixel(x, y)[0]) as u32)
+                .sum()
+        };
+        assert!(error(&clear) * 4 < error(&hazy.to_rgb8()));
+
+        // HDR values above 1 keep their order instead of clipping to white
+        let hdr: DynamicImage = image::Rgb32FImage::from_fn(4, 1, |x, _| Rgb([0.25 * 2f32.powi(x as i32); 3])).into();
+        for mapper in [ToneMapper::Reinhard, ToneMapper::Aces, ToneMapper::Filmic] {
+            let mapped: RgbImage = tonemap(&hdr, mapper, 0.0).to_rgb8();
+            assert!(mapped.pixels().zip(mapped.pixels().skip(1)).all(|(a, b)| a[0] < b[0]), "{:?}", mapper);
+        }
+
+        // One stop doubles linear light: sRGB 128 is 0.216 linear, 0.431 encodes to 176
+        let gray: DynamicImage = RgbImage::from_pixel(1, 1, Rgb([128; 3])).into();
+        assert_eq!(exposure(&gray, 1.0).to_rgb8().get_pixel(0, 0)[0], 176);
+        let steps: DynamicImage = RgbImage::from_fn(2, 1, |x, _| Rgb([if x == 0 { 30 } else { 220 }; 3])).into();
+        let lifted: RgbImage = shadows(&steps, 50.0).to_rgb8();
+        assert!(lifted.get_pixel(0, 0)[0] > 40 && lifted.get_pixel(1, 0)[0] == 220);
+        let recovered: RgbImage = highlights(&steps, -50.0).to_rgb8();
+        assert!(recovered.get_pixel(0, 0)[0] == 30 && recovered.get_pixel(1, 0)[0] < 200);
+
+        let edge: DynamicImage = RgbImage::from_fn(200, 20, |x, _| Rgb([if x < 100 { 100 } else { 150 }; 3])).into();
+        let crisp: RgbImage = clarity(&edge, 100.0).to_rgb8();
+        assert!(crisp.get_pixel(99, 10)[0] < 100 && crisp.get_pixel(100, 10)[0] > 150);
+        assert_eq!(crisp.get_pixel(20, 10)[0], 100);
+    }
+}
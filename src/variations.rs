@@ -0,0 +1,188 @@
+use std::path::Path;
+use crate::help_command;
+use crate::registry::{self, OperationSpec};
+
+// One --jitter entry: the value at `index` of every `operation` argument moves by up to `amount`
+// either way, or by up to `amount` percent of itself when `relative`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jitter {
+    pub operation: &'static str,
+    pub index: usize,
+    pub amount: f64,
+    pub relative: bool,
+}
+
+// Position of a parameter in an operation's comma-separated value: a number from 1, or a name
+// from the operation's help page. Operations with one parameter take any name, so pix.size and
+// blur.sigma both work.
+pub fn parameter_index(spec: &OperationSpec, parameter: Option<&str>) -> Result<usize, String> {
+    let parameter: &str = match parameter {
+        None => return Ok(0),
+        Some(parameter) => parameter,
+    };
+    if let Ok(position) = parameter.parse::<usize>() {
+        return if position > 0 { Ok(position - 1) } else { Err(format!("Parameter positions start at 1: {}.{}", spec.name, parameter)) };
+    }
+    let names: Vec<String> = help_command::OPERATIONS.iter()
+        .find(|doc| doc.name == spec.name)
+        .map(|doc| doc.parameters.iter().flat_map(|(names, _)| names.split(',').map(|name| name.trim().to_ascii_lowercase())).collect())
+        .unwrap_or_default();
+    match names.iter().position(|name| *name == parameter.to_ascii_lowercase()) {
+        Some(index) => Ok(index),
+        None if names.len() <= 1 => Ok(0),
+        None => Err(format!("{} has no parameter {} (parameters: {})", spec.name, parameter, names.join(", "))),
+    }
+}
+
+// OPERATION[.PARAMETER], e.g. blur, pix.size or wave.2.
+pub fn parse_reference(text: &str) -> Result<(&'static OperationSpec, usize), String> {
+    let (name, parameter) = match text.split_once('.') {
+        Some((name, parameter)) => (name, Some(parameter)),
+        None => (text, None),
+    };
+    let spec: &'static OperationSpec = registry::find(&format!("-{}", name)).ok_or_else(|| format!("Unknown operation: {}", name))?;
+    Ok((spec, parameter_index(spec, parameter)?))
+}
+
+// One --jitter entry such as blur:0.5 or exposure:20% — a reference and the largest change, in
+// the parameter's own units or as a percentage of its value.
+pub fn parse_jitter(entry: &str) -> Result<Jitter, String> {
+    let (reference, range) = entry.split_once(':').ok_or_else(|| format!("Invalid jitter: {} (expected OPERATION[.PARAMETER]:RANGE)", entry))?;
+    let (spec, index) = parse_reference(reference)?;
+    let (number, relative) = match range.strip_suffix('%') {
+        Some(percent) => (percent, true),
+        None => (range, false),
+    };
+    match number.parse::<f64>() {
+        Ok(amount) if amount >= 0.0 && amount.is_finite() => Ok(Jitter { operation: spec.name, index, amount, relative }),
+        _ => Err(format!("Invalid jitter range: {}", range)),
+    }
+}
+
+// Whole numbers stay whole; others keep at most three decimals.
+pub fn format_number(value: f64, whole: bool) -> String {
+    if whole {
+        return format!("{}", value.round() as i64);
+    }
+    let text: String = format!("{:.3}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+// The argument with the number at `index` of its comma-separated value changed, rounded when the
+// parameter is `whole` and not a percentage. A % suffix is kept, as are the other parts.
+pub fn replace_value(arg: &str, index: usize, whole: bool, change: impl Fn(f64) -> f64) -> Result<String, String> {
+    let (flag, value) = arg.split_once('=').ok_or_else(|| format!("{} has no value to change", arg))?;
+    let mut parts: Vec<String> = value.split(',').map(str::to_string).collect();
+    let part: &mut String = parts.get_mut(index).ok_or_else(|| format!("{} has no parameter {}", arg, index + 1))?;
+    let (number, suffix) = match part.strip_suffix('%') {
+        Some(number) => (number.to_string(), "%"),
+        None => (part.clone(), ""),
+    };
+    let original: f64 = number.parse().map_err(|_| format!("{} in {} is not a number", part, arg))?;
+    *part = format!("{}{}", format_number(change(original), whole && suffix.is_empty()), suffix);
+    Ok(format!("{}={}", flag, parts.join(",")))
+}
+
+// Fixed xorshift sequence per variation, so the same command gives the same variants.
+struct Sequence(u64);
+
+impl Sequence {
+    // Uniform in -1 to 1.
+    fn signed(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+}
+
+// output.png becomes output-3.png (output-03.png from 10 variations on).
+pub fn variation_path(output_path: &str, number: u32, count: u32) -> String {
+    let label: String = format!("{:0width$}", number, width = count.to_string().len());
+    let path: &Path = Path::new(output_path);
+    let stem: String = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension: String = path.extension().map(|extension| extension.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}-{}.{}", stem, label, extension)).to_string_lossy().into_owned()
+}
+
+// One of the --variations: its operation arguments, and the jittered ones among them to show.
+pub struct Variant {
+    pub arguments: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+// `count` variants of the operation arguments, each with the jittered parameters moved by its own
+// random amounts. Whole-number parameters stay whole.
+pub fn variants(operation_args: &[String], count: u32, jitters: &[Jitter]) -> Result<Vec<Variant>, String> {
+    for jitter in jitters {
+        if !operation_args.iter().any(|arg| registry::find(arg).is_some_and(|spec| spec.name == jitter.operation)) {
+            return Err(format!("--jitter names {}, which is not among the operations", jitter.operation));
+        }
+    }
+    (1..=count).map(|number| {
+        let mut sequence: Sequence = Sequence(0x9E37_79B9_7F4A_7C15 ^ (number as u64).wrapping_mul(0x2545_F491_4F6C_DD1D));
+        let mut variant: Variant = Variant { arguments: Vec::new(), changed: Vec::new() };
+        for arg in operation_args {
+            let spec: Option<&OperationSpec> = registry::find(arg);
+            let operation: Option<&str> = spec.map(|spec| spec.name);
+            let mut arg: String = arg.clone();
+            for jitter in jitters.iter().filter(|jitter| operation == Some(jitter.operation)) {
+                let offset: f64 = sequence.signed();
+                let whole: bool = spec.is_some_and(|spec| spec.whole.contains(&jitter.index));
+                arg = replace_value(&arg, jitter.index, whole, |value| {
+                    let moved: f64 = value + offset * if jitter.relative { value.abs() * jitter.amount / 100.0 } else { jitter.amount };
+                    // Values that start out positive stay so, e.g. sizes and radii
+                    if value >= 0.0 { moved.max(0.0) } else { moved }
+                })?;
+            }
+            if jitters.iter().any(|jitter| operation == Some(jitter.operation)) {
+                variant.changed.push(arg.clone());
+            }
+            variant.arguments.push(arg);
+        }
+        Ok(variant)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitters_change_one_parameter_of_their_operation() {
+        let parse_jitters = |value: &str| value.split(',').map(parse_jitter).collect::<Result<Vec<Jitter>, String>>();
+        let jitters: Vec<Jitter> = parse_jitters("blur:0.5,wave.wavelength:20%,pix.size:2").unwrap();
+        assert_eq!(jitters[0], Jitter { operation: "blur", index: 0, amount: 0.5, relative: false });
+        assert_eq!((jitters[1].operation, jitters[1].index, jitters[1].relative), ("wave", 1, true));
+        assert_eq!((jitters[2].operation, jitters[2].index), ("pixelate", 0));
+        assert!(parse_jitters("crop.depth:3").is_err());
+        assert!(parse_jitters("nosuch:3").is_err());
+        assert!(parse_jitters("blur").is_err());
+
+        assert_eq!(replace_value("-wave=4,30,y", 1, false, |value| value + 2.6).unwrap(), "-wave=4,32.6,y");
+        assert_eq!(replace_value("-blur=1.5", 0, false, |value| value * 2.0).unwrap(), "-blur=3");
+        assert_eq!(replace_value("-pix=4", 0, true, |value| value + 0.6).unwrap(), "-pix=5");
+        assert_eq!(replace_value("--pixelate=2%", 0, true, |value| value + 0.25).unwrap(), "--pixelate=2.25%");
+        assert_eq!(replace_value("-exposure=0.5", 0, false, |value| value + 0.1234).unwrap(), "-exposure=0.623");
+        assert!(replace_value("-rotate=90,white", 1, false, |value| value).is_err());
+
+        assert_eq!(variation_path("out/photo.png", 3, 8), "out/photo-3.png");
+        assert_eq!(variation_path("photo.png", 3, 12), "photo-03.png");
+
+        // Each variant moves the jittered value by its own amount, keeping fractions where the
+        // parameter takes them and rounding where it does not
+        let operation_args: Vec<String> = vec!["-blur=1".to_string(), "-pix=4".to_string(), "-floyd".to_string()];
+        let jittered: Vec<Variant> = variants(&operation_args, 6, &parse_jitters("blur:0.8,pix:3").unwrap()).unwrap();
+        assert_eq!(jittered.len(), 6);
+        for variant in &jittered {
+            assert_eq!(variant.arguments[2], "-floyd");
+            assert_eq!(variant.changed, variant.arguments[..2]);
+            let pixels: &str = variant.arguments[1].strip_prefix("-pix=").unwrap();
+            assert!(pixels.parse::<u32>().is_ok_and(|size| (1..=7).contains(&size)), "{}", pixels);
+        }
+        let sigmas: Vec<f64> = jittered.iter().map(|variant| variant.arguments[0].strip_prefix("-blur=").unwrap().parse().unwrap()).collect();
+        assert!(sigmas.iter().all(|sigma| (0.2..=1.8).contains(sigma)));
+        assert!(sigmas.iter().any(|sigma| sigma.fract() != 0.0), "{:?}", sigmas);
+        assert!(variants(&operation_args, 2, &parse_jitters("wave:2").unwrap()).is_err());
+    }
+}